@@ -0,0 +1,134 @@
+//! Measures the executor wakeups per received packet and how much another task on the same
+//! thread gets to run while packets arrive, for several `set_max_reads_per_wake` limits.
+//!
+//! Needs root: `cargo run --release --example recv_wakeups --features async_io`
+#[cfg(target_os = "linux")]
+fn main() -> std::io::Result<()> {
+    bench::run()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    unimplemented!()
+}
+
+#[cfg(target_os = "linux")]
+mod bench {
+    use std::cell::Cell;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::task::{Context, Poll};
+    use std::time::{Duration, Instant};
+
+    use futures::executor::LocalPool;
+    use futures::task::LocalSpawnExt;
+    use tun_rs::{AsyncDevice, DeviceBuilder};
+
+    const PACKETS: usize = 200_000;
+
+    /// Counts how often the wrapped future is polled.
+    struct Counted<'a, F> {
+        future: F,
+        polls: &'a Cell<u64>,
+    }
+
+    impl<F: Future + Unpin> Future for Counted<'_, F> {
+        type Output = F::Output;
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+            self.polls.set(self.polls.get() + 1);
+            Pin::new(&mut self.future).poll(cx)
+        }
+    }
+
+    /// Lets the other tasks of the executor run once.
+    async fn yield_now() {
+        let mut yielded = false;
+        std::future::poll_fn(|cx| {
+            if yielded {
+                return Poll::Ready(());
+            }
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+        .await
+    }
+
+    pub fn run() -> std::io::Result<()> {
+        let device = DeviceBuilder::new()
+            .ipv4("10.26.30.1", 24, None)
+            .build_async()?;
+        let device = Rc::new(device);
+        println!("limit    packets  wakeups/packet  other task runs/packet");
+        for limit in [0, 16, AsyncDevice::DEFAULT_MAX_READS_PER_WAKE] {
+            device.set_max_reads_per_wake(limit);
+            let (packets, wakeups, ticks) = measure(device.clone())?;
+            println!(
+                "{limit:>5} {packets:>10} {:>15.4} {:>23.4}",
+                wakeups as f64 / packets.max(1) as f64,
+                ticks as f64 / packets.max(1) as f64,
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the packets received, the wakeups of the receiving task and how often the
+    /// other task ran.
+    fn measure(device: Rc<AsyncDevice>) -> std::io::Result<(u64, u64, u64)> {
+        let sender = std::thread::spawn(|| -> std::io::Result<()> {
+            let socket = std::net::UdpSocket::bind("10.26.30.1:0")?;
+            let payload = [0u8; 64];
+            for _ in 0..PACKETS {
+                // The queue of the device overflows now and then, which is fine here.
+                _ = socket.send_to(&payload, "10.26.30.2:9");
+            }
+            Ok(())
+        });
+        let mut pool = LocalPool::new();
+        let done = Rc::new(Cell::new(false));
+        let ticks = Rc::new(Cell::new(0u64));
+        {
+            let (done, ticks) = (done.clone(), ticks.clone());
+            pool.spawner()
+                .spawn_local(async move {
+                    while !done.get() {
+                        ticks.set(ticks.get() + 1);
+                        yield_now().await;
+                    }
+                })
+                .unwrap();
+        }
+        let result = pool.run_until(async {
+            let polls = Cell::new(0);
+            let mut calls = 0u64;
+            let mut packets = 0u64;
+            let mut buf = vec![0; 65536];
+            let mut last = Instant::now();
+            loop {
+                let recv = Box::pin(device.recv(&mut buf));
+                let recv = Counted {
+                    future: recv,
+                    polls: &polls,
+                };
+                calls += 1;
+                let timeout = async_std::future::timeout(Duration::from_millis(200), recv);
+                match timeout.await {
+                    Ok(rs) => {
+                        rs?;
+                        packets += 1;
+                        last = Instant::now();
+                    }
+                    Err(_) if last.elapsed() >= Duration::from_millis(200) => break,
+                    Err(_) => {}
+                }
+            }
+            done.set(true);
+            // Every poll after the first of a call was a wakeup.
+            Ok::<_, std::io::Error>((packets, polls.get() - calls))
+        });
+        sender.join().unwrap()?;
+        let (packets, wakeups) = result?;
+        Ok((packets, wakeups, ticks.get()))
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::VecDeque;
+#[cfg(feature = "async_framed")]
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+#[cfg(feature = "async_framed")]
+use std::pin::Pin;
+use std::sync::Arc;
+#[cfg(feature = "async_framed")]
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::platform::DeviceImpl;
+use crate::AsyncDevice;
+
+/// How often the addresses of the interface are compared with the last seen ones when the
+/// OS change notifications are unavailable.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A change of the addresses assigned to a device, see
+/// [`AsyncDevice::addresses_changed`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AddressEvent {
+    /// An address was assigned to the interface.
+    Added { address: IpAddr, prefix: u8 },
+    /// An address was removed from the interface.
+    Removed { address: IpAddr, prefix: u8 },
+    /// The interface disappeared. This is the last event of the stream.
+    DeviceGone,
+}
+
+type Addresses = Vec<(IpAddr, u8)>;
+/// A pending comparison of the addresses, polled by the `Stream` implementation.
+#[cfg(feature = "async_framed")]
+type Changes<'a> = Pin<Box<dyn Future<Output = (Addresses, Vec<AddressEvent>)> + Send + 'a>>;
+
+/// The address changes of a device, returned by [`AsyncDevice::addresses_changed`].
+///
+/// Use [`next`](Self::next), or the [`Stream`] implementation when the `async_framed`
+/// feature is enabled.
+///
+/// [`Stream`]: https://docs.rs/futures/0.3/futures/stream/trait.Stream.html
+pub struct AddressEvents<'a> {
+    device: &'a DeviceImpl,
+    notifier: Option<Arc<ChangeNotifier>>,
+    known: Addresses,
+    pending: VecDeque<AddressEvent>,
+    done: bool,
+    #[cfg(feature = "async_framed")]
+    changes: Option<Changes<'a>>,
+}
+
+impl AsyncDevice {
+    /// Returns the changes to the addresses of the interface from now on, whoever makes
+    /// them: DHCP on a TAP device, an operator or a network manager.
+    ///
+    /// The addresses are compared with the previous ones whenever the OS reports an address
+    /// or link change: through `RTM_NEWADDR`/`RTM_DELADDR` netlink messages on Linux, the
+    /// routing socket on macOS and FreeBSD, and `NotifyUnicastIpAddressChange` on Windows.
+    /// If the notifications cannot be set up, the addresses are polled every second. An
+    /// address that is added and removed again before the comparison is not reported.
+    ///
+    /// When the interface disappears, [`AddressEvent::DeviceGone`] is yielded and the
+    /// stream ends.
+    pub fn addresses_changed(&self) -> io::Result<AddressEvents<'_>> {
+        let device: &DeviceImpl = self;
+        // Subscribe before taking the snapshot, so that no change falls in between.
+        let notifier = match ChangeNotifier::new() {
+            Ok(notifier) => Some(Arc::new(notifier)),
+            Err(e) => {
+                log::warn!("address change notifications are unavailable, polling: {e}");
+                None
+            }
+        };
+        Ok(AddressEvents {
+            device,
+            notifier,
+            known: device.list_addresses()?,
+            pending: VecDeque::new(),
+            done: false,
+            #[cfg(feature = "async_framed")]
+            changes: None,
+        })
+    }
+}
+
+impl AddressEvents<'_> {
+    /// Waits for the next address change. Returns `None` once the device is gone.
+    ///
+    /// Cancel safe: dropping the returned future loses no event.
+    pub async fn next(&mut self) -> Option<AddressEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            if self.done {
+                return None;
+            }
+            let notifier = self.notifier.clone();
+            let (known, events) = wait_for_changes(self.device, notifier, self.known.clone()).await;
+            self.push(known, events);
+        }
+    }
+    fn push(&mut self, known: Addresses, events: Vec<AddressEvent>) {
+        self.done = events.contains(&AddressEvent::DeviceGone);
+        self.known = known;
+        self.pending.extend(events);
+    }
+}
+
+#[cfg(feature = "async_framed")]
+impl futures_core::Stream for AddressEvents<'_> {
+    type Item = AddressEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(event) = this.pending.pop_front() {
+                return Poll::Ready(Some(event));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+            let (device, notifier) = (this.device, this.notifier.clone());
+            let changes = this.changes.get_or_insert_with(|| {
+                Box::pin(wait_for_changes(device, notifier, this.known.clone()))
+            });
+            let Poll::Ready((known, events)) = changes.as_mut().poll(cx) else {
+                return Poll::Pending;
+            };
+            this.changes = None;
+            this.push(known, events);
+        }
+    }
+}
+
+/// Polls the addresses of `device` until they differ from `known`, returning the new
+/// addresses and the events leading there.
+async fn wait_for_changes(
+    device: &DeviceImpl,
+    notifier: Option<Arc<ChangeNotifier>>,
+    known: Addresses,
+) -> (Addresses, Vec<AddressEvent>) {
+    loop {
+        match &notifier {
+            Some(notifier) => {
+                if let Err(e) = notifier.changed().await {
+                    log::warn!("failed to wait for address change notifications: {e}");
+                    sleep(POLL_INTERVAL).await;
+                }
+            }
+            None => sleep(POLL_INTERVAL).await,
+        }
+        let current = match device.list_addresses() {
+            Ok(current) => current,
+            // The interface can no longer be resolved, `DeviceGone` has the same kind.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return (known, vec![AddressEvent::DeviceGone])
+            }
+            Err(e) => {
+                log::warn!("failed to read the addresses of the device: {e}");
+                continue;
+            }
+        };
+        let removed = known
+            .iter()
+            .filter(|v| !current.contains(v))
+            .map(|&(address, prefix)| AddressEvent::Removed { address, prefix });
+        let added = current
+            .iter()
+            .filter(|v| !known.contains(v))
+            .map(|&(address, prefix)| AddressEvent::Added { address, prefix });
+        let events: Vec<_> = removed.chain(added).collect();
+        if !events.is_empty() {
+            return (current, events);
+        }
+    }
+}
+
+async fn sleep(duration: Duration) {
+    #[cfg(feature = "async_tokio")]
+    tokio::time::sleep(duration).await;
+    #[cfg(all(feature = "async_std", not(feature = "async_tokio")))]
+    async_io::Timer::after(duration).await;
+}
+
+/// Wakes up on address and link changes of any interface, reported by a netlink socket on
+/// Linux and a routing socket on macOS and FreeBSD.
+#[cfg(unix)]
+struct ChangeNotifier {
+    #[cfg(feature = "async_tokio")]
+    socket: ::tokio::io::unix::AsyncFd<std::os::fd::OwnedFd>,
+    #[cfg(all(feature = "async_std", not(feature = "async_tokio")))]
+    socket: ::async_io::Async<std::os::fd::OwnedFd>,
+}
+
+#[cfg(unix)]
+impl ChangeNotifier {
+    fn new() -> io::Result<Self> {
+        let socket = change_socket()?;
+        #[cfg(feature = "async_tokio")]
+        let socket = ::tokio::io::unix::AsyncFd::new(socket)?;
+        #[cfg(all(feature = "async_std", not(feature = "async_tokio")))]
+        let socket = ::async_io::Async::new(socket)?;
+        Ok(Self { socket })
+    }
+    /// Waits for notifications and discards them, only the fact that something changed is
+    /// used.
+    async fn changed(&self) -> io::Result<()> {
+        #[cfg(feature = "async_tokio")]
+        {
+            let mut guard = self.socket.readable().await?;
+            drain(guard.get_inner());
+            guard.clear_ready();
+        }
+        #[cfg(all(feature = "async_std", not(feature = "async_tokio")))]
+        {
+            self.socket.readable().await?;
+            drain(self.socket.get_ref());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn drain(socket: &std::os::fd::OwnedFd) {
+    use std::os::fd::AsRawFd;
+    let mut buf = [0u8; 8192];
+    // Stops at EAGAIN, or ENOBUFS if notifications were lost, which is fine as the
+    // addresses are read again anyway.
+    while unsafe { libc::recv(socket.as_raw_fd(), buf.as_mut_ptr() as _, buf.len(), 0) } > 0 {}
+}
+
+#[cfg(target_os = "linux")]
+fn change_socket() -> io::Result<std::os::fd::OwnedFd> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = OwnedFd::from_raw_fd(fd);
+        let mut addr: libc::sockaddr_nl = std::mem::zeroed();
+        addr.nl_family = libc::AF_NETLINK as _;
+        // Link messages report the removal of the interface.
+        addr.nl_groups =
+            (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+        if libc::bind(
+            socket.as_raw_fd(),
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as _,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+fn change_socket() -> io::Result<std::os::fd::OwnedFd> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    unsafe {
+        let fd = libc::socket(libc::PF_ROUTE, libc::SOCK_RAW, libc::AF_UNSPEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = OwnedFd::from_raw_fd(fd);
+        let flags = libc::fcntl(socket.as_raw_fd(), libc::F_GETFL);
+        if flags < 0
+            || libc::fcntl(socket.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0
+            || libc::fcntl(socket.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+/// Wakes up on unicast address and IP interface changes, reported through
+/// `NotifyUnicastIpAddressChange` and `NotifyIpInterfaceChange`.
+#[cfg(windows)]
+struct ChangeNotifier {
+    state: Arc<NotifyState>,
+    handles: [windows_sys::Win32::Foundation::HANDLE; 2],
+}
+
+// SAFETY: the notification handles are only used to cancel the notifications on drop.
+#[cfg(windows)]
+unsafe impl Send for ChangeNotifier {}
+#[cfg(windows)]
+unsafe impl Sync for ChangeNotifier {}
+
+#[cfg(windows)]
+#[derive(Default)]
+struct NotifyState(std::sync::Mutex<(bool, Option<std::task::Waker>)>);
+
+#[cfg(windows)]
+impl NotifyState {
+    fn notify(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.0 = true;
+        if let Some(waker) = state.1.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(windows)]
+impl ChangeNotifier {
+    fn new() -> io::Result<Self> {
+        use windows_sys::Win32::Foundation::NO_ERROR;
+        use windows_sys::Win32::NetworkManagement::IpHelper::{
+            NotifyIpInterfaceChange, NotifyUnicastIpAddressChange,
+        };
+        use windows_sys::Win32::Networking::WinSock::AF_UNSPEC;
+        let state = Arc::new(NotifyState::default());
+        let context = Arc::as_ptr(&state) as *const std::ffi::c_void;
+        let mut notifier = Self {
+            state,
+            handles: [std::ptr::null_mut(); 2],
+        };
+        // On failure, dropping `notifier` cancels the notification registered so far.
+        let rs = unsafe {
+            NotifyUnicastIpAddressChange(
+                AF_UNSPEC,
+                Some(address_changed),
+                context,
+                0,
+                &mut notifier.handles[0],
+            )
+        };
+        if rs != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(rs as i32));
+        }
+        let rs = unsafe {
+            NotifyIpInterfaceChange(
+                AF_UNSPEC,
+                Some(interface_changed),
+                context,
+                0,
+                &mut notifier.handles[1],
+            )
+        };
+        if rs != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(rs as i32));
+        }
+        Ok(notifier)
+    }
+    async fn changed(&self) -> io::Result<()> {
+        std::future::poll_fn(|cx| {
+            let mut state = self.state.0.lock().unwrap();
+            if std::mem::take(&mut state.0) {
+                return std::task::Poll::Ready(Ok(()));
+            }
+            state.1 = Some(cx.waker().clone());
+            std::task::Poll::Pending
+        })
+        .await
+    }
+}
+
+#[cfg(windows)]
+impl Drop for ChangeNotifier {
+    fn drop(&mut self) {
+        use windows_sys::Win32::NetworkManagement::IpHelper::CancelMibChangeNotify2;
+        // Waits for running callbacks, so the state outlives them.
+        for handle in self.handles {
+            if !handle.is_null() {
+                unsafe { CancelMibChangeNotify2(handle) };
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn address_changed(
+    context: *const std::ffi::c_void,
+    _row: *const windows_sys::Win32::NetworkManagement::IpHelper::MIB_UNICASTIPADDRESS_ROW,
+    _kind: windows_sys::Win32::NetworkManagement::IpHelper::MIB_NOTIFICATION_TYPE,
+) {
+    (*(context as *const NotifyState)).notify();
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn interface_changed(
+    context: *const std::ffi::c_void,
+    _row: *const windows_sys::Win32::NetworkManagement::IpHelper::MIB_IPINTERFACE_ROW,
+    _kind: windows_sys::Win32::NetworkManagement::IpHelper::MIB_NOTIFICATION_TYPE,
+) {
+    (*(context as *const NotifyState)).notify();
+}
@@ -0,0 +1,76 @@
+//! The `AsyncRead` and `AsyncWrite` traits of the enabled runtime, on top of `poll_recv` and
+//! `poll_send`.
+//!
+//! The device is packet oriented: every read returns exactly one packet and every write
+//! sends `buf` as one packet. The read buffer must hold the largest packet (the MTU plus any
+//! packet information or virtio-net header), a longer packet fails with
+//! [`Error::Truncated`](crate::Error::Truncated) where the platform reports it. A write that
+//! sends less than all of `buf` fails with [`Error::PartialSend`](crate::Error::PartialSend)
+//! instead of reporting a partial write, the rest cannot follow as a packet of its own.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use crate::error::check_sent;
+use crate::AsyncDevice;
+
+#[cfg(feature = "async_tokio")]
+impl tokio::io::AsyncRead for AsyncDevice {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n = ready!(self.poll_recv(cx, buf.initialize_unfilled()))?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "async_tokio")]
+impl tokio::io::AsyncWrite for AsyncDevice {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let sent = ready!(self.poll_send(cx, buf))?;
+        Poll::Ready(check_sent(sent, buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "async_std")]
+impl futures_io::AsyncRead for AsyncDevice {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_recv(cx, buf)
+    }
+}
+
+#[cfg(feature = "async_std")]
+impl futures_io::AsyncWrite for AsyncDevice {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let sent = ready!(self.poll_send(cx, buf))?;
+        Poll::Ready(check_sent(sent, buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -12,5 +12,134 @@ pub use windows::*;
 #[cfg(feature = "async_framed")]
 pub mod async_framed;
 
+mod io_traits;
+
+#[cfg(feature = "async_tokio")]
+mod multi_queue;
+#[cfg_attr(docsrs, doc(cfg(feature = "async_tokio")))]
+#[cfg(feature = "async_tokio")]
+pub use multi_queue::{MultiQueueRunner, RunnerStats};
+
+#[cfg(any(
+    target_os = "windows",
+    all(target_os = "linux", not(target_env = "ohos")),
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+mod address_events;
+#[cfg(any(
+    target_os = "windows",
+    all(target_os = "linux", not(target_env = "ohos")),
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+pub use address_events::*;
+
+/// The current time of the runtime, which follows the paused clock in tokio tests.
+fn now() -> std::time::Instant {
+    #[cfg(feature = "async_tokio")]
+    return ::tokio::time::Instant::now().into_std();
+    #[cfg(not(feature = "async_tokio"))]
+    std::time::Instant::now()
+}
+
+/// Returns `Pending` once, after waking the task, so that other tasks run first.
+async fn yield_now() {
+    let mut yielded = false;
+    std::future::poll_fn(|cx| {
+        if yielded {
+            return std::task::Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        std::task::Poll::Pending
+    })
+    .await
+}
+
+impl AsyncDevice {
+    /// Limits the rate of packets sent through this device, `None` removes the limit.
+    ///
+    /// The limit is a token bucket: up to `burst` bytes go out at once, beyond that `send`
+    /// futures are delayed so that the average stays at `bytes_per_sec`, and `try_send`
+    /// returns `WouldBlock` until enough budget has accumulated. `send_multiple` charges
+    /// the bytes of the whole batch. The packet sizes counted are the buffers as passed,
+    /// including any packet information or virtio-net header.
+    ///
+    /// A future dropped while it waits has already used up its budget. The `poll_send` family
+    /// and [`SyncDevice`](crate::SyncDevice) are not paced. Without a limit the cost is one
+    /// atomic load per send.
+    pub fn set_send_rate_limit(&self, limit: Option<crate::RateLimit>) -> crate::Result<()> {
+        self.send_pacer.set(limit, now())
+    }
+    /// Limits how many packets `recv` returns in a row before it lets other tasks run,
+    /// 0 for no limit.
+    ///
+    /// Under load a packet is always ready, so a task receiving in a loop would never
+    /// return to the executor. tokio limits this with its cooperative budget, async-io has
+    /// no such mechanism, so the `async_io` backend defaults to
+    /// [`DEFAULT_MAX_READS_PER_WAKE`](Self::DEFAULT_MAX_READS_PER_WAKE) and the tokio
+    /// backend to no limit. Each call reads until the device would block before it waits,
+    /// so only one wakeup is needed after the device ran empty.
+    ///
+    /// The count is kept per device, across all tasks receiving from it. It applies to
+    /// `recv`, `recv_vectored`, `recv_with_timestamp` and `recv_multiple`, not to the
+    /// `poll_recv` family nor to reads through io_uring.
+    pub fn set_max_reads_per_wake(&self, max: usize) {
+        self.read_budget.set_max(max)
+    }
+    /// Returns the limit set with [`set_max_reads_per_wake`](Self::set_max_reads_per_wake).
+    pub fn max_reads_per_wake(&self) -> usize {
+        self.read_budget.max()
+    }
+    /// The default of [`set_max_reads_per_wake`](Self::set_max_reads_per_wake) with the
+    /// `async_io` backend, the same as the budget of a tokio task.
+    pub const DEFAULT_MAX_READS_PER_WAKE: usize = 128;
+    /// Yields to the executor if the read budget is used up.
+    pub(crate) async fn read_budget(&self) {
+        if self.read_budget.should_yield() {
+            yield_now().await;
+        }
+    }
+    /// Waits until `len` bytes may be sent.
+    pub(crate) async fn pace(&self, len: usize) {
+        let Some(delay) = self.send_pacer.reserve(len, now()) else {
+            return;
+        };
+        #[cfg(feature = "async_tokio")]
+        ::tokio::time::sleep(delay).await;
+        #[cfg(not(feature = "async_tokio"))]
+        async_io::Timer::after(delay).await;
+    }
+    /// Sends `len` bytes with `op` after waiting for the rate limit, refunding them if the
+    /// send fails.
+    pub(crate) async fn paced<R>(
+        &self,
+        len: usize,
+        op: impl std::future::Future<Output = std::io::Result<R>>,
+    ) -> std::io::Result<R> {
+        self.pace(len).await;
+        let rs = op.await;
+        if rs.is_err() {
+            self.send_pacer.refund(len);
+        }
+        rs
+    }
+    /// Like [`paced`](Self::paced) for a nonblocking send, fails with `WouldBlock` if the
+    /// rate limit does not allow `len` bytes right now.
+    pub(crate) fn try_paced<R>(
+        &self,
+        len: usize,
+        op: impl FnOnce() -> std::io::Result<R>,
+    ) -> std::io::Result<R> {
+        self.send_pacer.try_reserve(len, now())?;
+        let rs = op();
+        if rs.is_err() {
+            self.send_pacer.refund(len);
+        }
+        rs
+    }
+}
+
 #[cfg(all(feature = "async_tokio", feature = "async_std", not(doc)))]
 compile_error! {"More than one asynchronous runtime is simultaneously specified in features"}
@@ -0,0 +1,193 @@
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use ::tokio::sync::watch;
+use ::tokio::task::JoinHandle;
+
+use crate::AsyncDevice;
+
+/// Packet and error counts of a [`MultiQueueRunner`], summed over all queues.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RunnerStats {
+    /// The packets handed to the handler.
+    pub packets: u64,
+    /// The bytes of these packets.
+    pub bytes: u64,
+    /// The packets dropped because they could not be received, e.g. truncated ones.
+    pub errors: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// Runs a handler for the packets of every queue of a multi-queue device, one tokio task per
+/// queue.
+///
+/// Errors that concern a single packet, like a packet longer than the receive buffer, are
+/// counted and the queue carries on. Any other error, e.g. [`Error::DeviceGone`], ends all
+/// queues and is returned by [`join`](Self::join) or [`shutdown`](Self::shutdown). A panic
+/// in the handler ends all queues as well and is resumed there.
+///
+/// The queues come from `DeviceBuilder::build_sync_multi_queue` on Linux, each wrapped with
+/// [`AsyncDevice::new`], but any set of devices works.
+///
+/// ```no_run
+/// # async fn run(queues: Vec<tun_rs::AsyncDevice>) -> tun_rs::Result<()> {
+/// use tun_rs::MultiQueueRunner;
+/// let runner = MultiQueueRunner::spawn(queues, |queue, packet| async move {
+///     println!("queue {queue}: {} bytes", packet.len());
+/// });
+/// // ...
+/// runner.shutdown().await
+/// # }
+/// ```
+///
+/// [`Error::DeviceGone`]: crate::Error::DeviceGone
+pub struct MultiQueueRunner {
+    tasks: Vec<JoinHandle<io::Result<()>>>,
+    stop: watch::Sender<bool>,
+    counters: Arc<Counters>,
+}
+
+impl MultiQueueRunner {
+    /// Spawns a task for every queue on the current tokio runtime, which calls `handler`
+    /// with the index of the queue and each packet it receives.
+    ///
+    /// A queue waits for the handler before it receives the next packet.
+    ///
+    /// # Panics
+    /// Panics if called outside of a tokio runtime.
+    pub fn spawn<F, Fut>(queues: Vec<AsyncDevice>, handler: F) -> Self
+    where
+        F: Fn(usize, Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (stop, _) = watch::channel(false);
+        let counters = Arc::new(Counters::default());
+        let handler = Arc::new(handler);
+        let tasks = queues
+            .into_iter()
+            .enumerate()
+            .map(|(index, queue)| {
+                let worker = Worker {
+                    index,
+                    queue,
+                    stop: stop.clone(),
+                    counters: counters.clone(),
+                };
+                ::tokio::spawn(worker.run(handler.clone()))
+            })
+            .collect();
+        MultiQueueRunner {
+            tasks,
+            stop,
+            counters,
+        }
+    }
+    /// Returns the counts so far.
+    pub fn stats(&self) -> RunnerStats {
+        RunnerStats {
+            packets: self.counters.packets.load(Ordering::Relaxed),
+            bytes: self.counters.bytes.load(Ordering::Relaxed),
+            errors: self.counters.errors.load(Ordering::Relaxed),
+        }
+    }
+    /// Stops receiving and waits for the handlers still running to finish.
+    ///
+    /// Returns the error that ended the queues before, if any.
+    pub async fn shutdown(self) -> crate::Result<()> {
+        self.stop.send_replace(true);
+        self.join().await
+    }
+    /// Waits until the queues end on their own, after an error.
+    pub async fn join(mut self) -> crate::Result<()> {
+        let mut result = Ok(());
+        for task in std::mem::take(&mut self.tasks) {
+            match task.await {
+                Ok(rs) => {
+                    if result.is_ok() {
+                        result = rs.map_err(crate::Error::from);
+                    }
+                }
+                Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                Err(e) => {
+                    if result.is_ok() {
+                        result = Err(io::Error::other(e).into());
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for MultiQueueRunner {
+    fn drop(&mut self) {
+        self.stop.send_replace(true);
+    }
+}
+
+struct Worker {
+    index: usize,
+    queue: AsyncDevice,
+    stop: watch::Sender<bool>,
+    counters: Arc<Counters>,
+}
+
+impl Worker {
+    async fn run<F, Fut>(self, handler: Arc<F>) -> io::Result<()>
+    where
+        F: Fn(usize, Vec<u8>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        // Ends the other queues if this one fails or its handler panics.
+        let _guard = StopOnDrop(&self.stop);
+        let mut stopped = self.stop.subscribe();
+        let mut buf = vec![0; crate::platform::MAX_PACKET_SIZE];
+        loop {
+            if *stopped.borrow_and_update() {
+                return Ok(());
+            }
+            let rs = ::tokio::select! {
+                biased;
+                _ = stopped.changed() => continue,
+                rs = self.queue.recv(&mut buf) => rs,
+            };
+            match rs {
+                Ok(len) => {
+                    self.counters.packets.fetch_add(1, Ordering::Relaxed);
+                    self.counters.bytes.fetch_add(len as u64, Ordering::Relaxed);
+                    handler(self.index, buf[..len].to_vec()).await;
+                }
+                Err(e) if is_packet_error(&e) => {
+                    log::debug!("queue {}: dropped a packet: {e}", self.index);
+                    self.counters.errors.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Whether the error concerns only the packet that was being received.
+fn is_packet_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::InvalidInput
+    )
+}
+
+struct StopOnDrop<'a>(&'a watch::Sender<bool>);
+
+impl Drop for StopOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.send_replace(true);
+    }
+}
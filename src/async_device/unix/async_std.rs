@@ -1,11 +1,16 @@
 use crate::platform::DeviceImpl;
 use ::async_io::Async;
 use std::io;
+use std::sync::Mutex;
 use std::task::{Context, Poll};
 
 /// An async Tun/Tap device wrapper around a Tun/Tap device.
 ///
+/// This is the `async_io` backend: the device is an `async_io::Async<DeviceImpl>`, so it can
+/// be used from smol, async-std or any other executor driving the `async-io` reactor.
+///
 /// This type does not provide a split method, because this functionality can be achieved by instead wrapping the socket in an Arc.
+/// `AsyncDevice` is `Send` and `Sync`, so one task can receive while others send.
 ///
 /// # Streams
 ///
@@ -14,7 +19,10 @@ use std::task::{Context, Poll};
 /// **Note:** `DeviceFramed` is only available when the `async_framed` feature is enabled.
 ///
 /// [`Stream`]: https://docs.rs/futures/0.3/futures/stream/trait.Stream.html
-pub struct AsyncDevice(pub(crate) Async<DeviceImpl>);
+pub struct AsyncDevice(
+    pub(crate) Async<DeviceImpl>,
+    pub(crate) Mutex<Option<io::Error>>,
+);
 impl AsyncDevice {
     /// Polls the I/O handle for readability.
     ///
@@ -127,7 +135,8 @@ impl AsyncDevice {
 }
 impl AsyncDevice {
     pub(crate) fn new_dev(device: DeviceImpl) -> io::Result<Self> {
-        Ok(Self(Async::new(device)?))
+        device.read_budget.set_max(Self::DEFAULT_MAX_READS_PER_WAKE);
+        Ok(Self(Async::new(device)?, Mutex::new(None)))
     }
     pub(crate) fn into_device(self) -> io::Result<DeviceImpl> {
         self.0.into_inner()
@@ -135,9 +144,17 @@ impl AsyncDevice {
 
     pub(crate) async fn read_with<R>(
         &self,
-        op: impl FnMut(&DeviceImpl) -> io::Result<R>,
+        mut op: impl FnMut(&DeviceImpl) -> io::Result<R>,
     ) -> io::Result<R> {
-        self.0.read_with(op).await
+        self.read_budget().await;
+        // Reads until the device would block before waiting for readability.
+        self.0
+            .read_with(|device| {
+                let rs = op(device);
+                device.read_budget.would_block(&rs);
+                rs
+            })
+            .await
     }
     pub(crate) async fn write_with<R>(
         &self,
@@ -161,4 +178,7 @@ impl AsyncDevice {
     pub(crate) fn get_ref(&self) -> &DeviceImpl {
         self.0.get_ref()
     }
+    pub(crate) fn batch_error(&self) -> &Mutex<Option<io::Error>> {
+        &self.1
+    }
 }
@@ -1,16 +1,19 @@
 #[cfg(target_os = "linux")]
 use crate::platform::offload::{handle_gro, VirtioNetHdr, VIRTIO_NET_HDR_LEN};
-use crate::platform::DeviceImpl;
 #[cfg(target_os = "linux")]
 use crate::platform::GROTable;
+use crate::platform::{DeviceImpl, MAX_PACKET_SIZE};
 use crate::SyncDevice;
 use std::io;
 use std::io::{IoSlice, IoSliceMut};
 use std::ops::Deref;
 use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::SystemTime;
 
 #[cfg(feature = "async_tokio")]
 mod tokio;
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+mod uring;
 #[cfg(feature = "async_tokio")]
 pub use self::tokio::AsyncDevice;
 
@@ -97,8 +100,36 @@ impl AsyncDevice {
     /// The function must be called with valid byte array `buf` of sufficient
     /// size to hold the message bytes. If a message is too long to fit in the
     /// supplied buffer, excess bytes may be discarded.
+    ///
+    /// With the `io_uring` feature the read is submitted through io_uring. Dropping the future
+    /// does not withdraw the read, so a packet may be consumed by a cancelled call.
     pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
-        self.read_with(|device| device.recv(buf)).await
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        let rs =
+            self.uring().recv(buf).await.and_then(|n| {
+                crate::error::check_truncated(buf, n, self.get_ref().ip_header_offset())
+            });
+        #[cfg(not(all(target_os = "linux", feature = "io_uring")))]
+        let rs = self.read_with(|device| device.recv(buf)).await;
+        self.mirror.recv(rs, buf, |n| *n)
+    }
+    /// Receives a single packet like [`recv`](Self::recv), with the time it was read, see
+    /// [`SyncDevice::recv_with_timestamp`](crate::SyncDevice::recv_with_timestamp).
+    ///
+    /// The time is taken inside the read, before the task yields. With the `io_uring`
+    /// feature it is taken when the completion is seen, which includes the wakeup latency.
+    pub async fn recv_with_timestamp(&self, buf: &mut [u8]) -> io::Result<(usize, SystemTime)> {
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        {
+            let n = self.uring().recv(buf).await?;
+            let n = crate::error::check_truncated(buf, n, self.get_ref().ip_header_offset())?;
+            Ok((n, SystemTime::now()))
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io_uring")))]
+        {
+            self.read_with(|device| device.recv_with_timestamp(buf))
+                .await
+        }
     }
     /// Tries to receive a single packet from the device.
     /// On success, returns the number of bytes read.
@@ -110,15 +141,29 @@ impl AsyncDevice {
     /// When there is no pending data, `Err(io::ErrorKind::WouldBlock)` is
     /// returned. This function is usually paired with `readable()`.
     pub fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize> {
-        self.try_read_io(|device| device.recv(buf))
+        let rs = self.try_read_io(|device| device.recv(buf));
+        self.mirror.recv(rs, buf, |n| *n)
     }
 
     /// Send a packet to the device
     ///
     /// # Return
     /// On success, the number of bytes sent is returned, otherwise, the encountered error is returned.
+    ///
+    /// Waits for the [send rate limit](Self::set_send_rate_limit) first, if one is set.
     pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
-        self.write_with(|device| device.send(buf)).await
+        let rs = self.paced(buf.len(), self.send_unpaced(buf)).await;
+        self.mirror.send(rs, buf)
+    }
+    async fn send_unpaced(&self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        {
+            self.uring().send(buf).await
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io_uring")))]
+        {
+            self.write_with(|device| device.send(buf)).await
+        }
     }
     /// Tries to send packet to the device.
     ///
@@ -129,9 +174,11 @@ impl AsyncDevice {
     ///
     /// If successful, `Ok(n)` is returned, where `n` is the number of bytes
     /// sent. If the device is not ready to send data,
-    /// `Err(ErrorKind::WouldBlock)` is returned.
+    /// `Err(ErrorKind::WouldBlock)` is returned, also while the
+    /// [send rate limit](Self::set_send_rate_limit) is exhausted.
     pub fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
-        self.try_write_io(|device| device.send(buf))
+        let rs = self.try_paced(buf.len(), || self.try_write_io(|device| device.send(buf)));
+        self.mirror.send(rs, buf)
     }
     /// Receives a packet into multiple buffers (scatter read).
     /// **Processes single packet per call**.
@@ -144,18 +191,57 @@ impl AsyncDevice {
     }
     /// Sends multiple buffers as a single packet (gather write).
     pub async fn send_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
-        self.write_with(|device| device.send_vectored(bufs)).await
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        self.paced(len, self.write_with(|device| device.send_vectored(bufs)))
+            .await
     }
     /// Non-blocking version of `send_vectored`.
     pub fn try_send_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
-        self.try_write_io(|device| device.send_vectored(bufs))
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        self.try_paced(len, || {
+            self.try_write_io(|device| device.send_vectored(bufs))
+        })
+    }
+    /// Receives up to `max` packets under a single readiness notification.
+    ///
+    /// Waits until the device becomes readable, then drains packets with nonblocking reads
+    /// until `max` packets have been collected or no further packet is immediately available.
+    /// This amortizes reactor wakeups when the tunnel is busy.
+    ///
+    /// An error is returned only if no packet could be read. An error encountered after at
+    /// least one packet has been read ends the batch early and is returned by the next call.
+    pub async fn recv_batch(&self, max: usize) -> io::Result<Vec<Vec<u8>>> {
+        let mut packets = Vec::new();
+        if max == 0 {
+            return Ok(packets);
+        }
+        if let Some(e) = self.batch_error().lock().unwrap().take() {
+            return Err(e);
+        }
+        let mut buf = vec![0; MAX_PACKET_SIZE];
+        self.read_with(|device| {
+            while packets.len() < max {
+                match device.recv(&mut buf) {
+                    Ok(n) => packets.push(buf[..n].to_vec()),
+                    Err(e) if packets.is_empty() => return Err(e),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        *self.batch_error().lock().unwrap() = Some(e);
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(packets)
     }
 }
 
 #[cfg(target_os = "linux")]
 impl AsyncDevice {
-    pub fn try_clone(&self) -> io::Result<Self> {
-        AsyncDevice::new_dev(self.get_ref().try_clone()?)
+    pub fn try_clone(&self) -> crate::Result<Self> {
+        Ok(AsyncDevice::new_dev(self.get_ref().try_clone()?)?)
     }
     /// Recv a packet from the device.
     /// If offload is enabled. This method can be used to obtain processed data.
@@ -170,13 +256,52 @@ impl AsyncDevice {
         bufs: &mut [B],
         sizes: &mut [usize],
         offset: usize,
+    ) -> io::Result<usize> {
+        self.recv_multiple_packets(original_buffer, bufs, sizes, offset, None)
+            .await
+    }
+    /// Like [`recv_multiple`](Self::recv_multiple), also setting `timestamps[i]` to the time
+    /// packet `i` was read, see [`recv_with_timestamp`](Self::recv_with_timestamp).
+    ///
+    /// The packets split from one offloaded read share its timestamp. `timestamps` must be
+    /// at least as long as `sizes`.
+    #[cfg(target_os = "linux")]
+    pub async fn recv_multiple_with_timestamps<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        original_buffer: &mut [u8],
+        bufs: &mut [B],
+        sizes: &mut [usize],
+        timestamps: &mut [SystemTime],
+        offset: usize,
+    ) -> io::Result<usize> {
+        if timestamps.len() < sizes.len() {
+            return Err(io::Error::new(io::ErrorKind::Other, "timestamps error"));
+        }
+        let mut timestamp = SystemTime::UNIX_EPOCH;
+        let n = self
+            .recv_multiple_packets(original_buffer, bufs, sizes, offset, Some(&mut timestamp))
+            .await?;
+        timestamps[..n].fill(timestamp);
+        Ok(n)
+    }
+    #[cfg(target_os = "linux")]
+    async fn recv_multiple_packets<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        original_buffer: &mut [u8],
+        bufs: &mut [B],
+        sizes: &mut [usize],
+        offset: usize,
+        timestamp: Option<&mut SystemTime>,
     ) -> io::Result<usize> {
         if bufs.is_empty() || bufs.len() != sizes.len() {
             return Err(io::Error::new(io::ErrorKind::Other, "bufs error"));
         }
         let tun = self.get_ref();
         if tun.vnet_hdr {
-            let len = self.recv(original_buffer).await?;
+            let (len, time) = self.recv_with_timestamp(original_buffer).await?;
+            if let Some(timestamp) = timestamp {
+                *timestamp = time;
+            }
             if len <= VIRTIO_NET_HDR_LEN {
                 Err(io::Error::new(
                     io::ErrorKind::Other,
@@ -194,7 +319,10 @@ impl AsyncDevice {
                 offset,
             )
         } else {
-            let len = self.recv(bufs[0].as_mut()).await?;
+            let (len, time) = self.recv_with_timestamp(bufs[0].as_mut()).await?;
+            if let Some(timestamp) = timestamp {
+                *timestamp = time;
+            }
             sizes[0] = len;
             Ok(1)
         }
@@ -209,6 +337,12 @@ impl AsyncDevice {
         bufs: &mut [B],
         mut offset: usize,
     ) -> io::Result<usize> {
+        // The batch is charged as a whole, before GRO merges its packets.
+        let len = bufs
+            .iter()
+            .map(|buf| buf.as_ref().len().saturating_sub(offset))
+            .sum();
+        self.pace(len).await;
         gro_table.reset();
         let tun = self.get_ref();
         if tun.vnet_hdr {
@@ -217,7 +351,7 @@ impl AsyncDevice {
                 offset,
                 &mut gro_table.tcp_gro_table,
                 &mut gro_table.udp_gro_table,
-                tun.udp_gso,
+                tun.udp_gso(),
                 &mut gro_table.to_write,
             )?;
             offset -= VIRTIO_NET_HDR_LEN;
@@ -230,7 +364,7 @@ impl AsyncDevice {
         let mut total = 0;
         let mut err = Ok(());
         for buf_idx in &gro_table.to_write {
-            match self.send(&bufs[*buf_idx].as_ref()[offset..]).await {
+            match self.send_unpaced(&bufs[*buf_idx].as_ref()[offset..]).await {
                 Ok(n) => {
                     total += n;
                 }
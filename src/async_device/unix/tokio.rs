@@ -1,4 +1,5 @@
 use std::io;
+use std::sync::Mutex;
 use std::task::{Context, Poll};
 
 use crate::platform::DeviceImpl;
@@ -8,6 +9,7 @@ use ::tokio::io::Interest;
 /// An async Tun/Tap device wrapper around a Tun/Tap device.
 ///
 /// This type does not provide a split method, because this functionality can be achieved by instead wrapping the socket in an Arc.
+/// `AsyncDevice` is `Send` and `Sync`, so one task can receive while others send.
 ///
 /// # Streams
 ///
@@ -16,7 +18,11 @@ use ::tokio::io::Interest;
 /// **Note:** `DeviceFramed` is only available when the `async_framed` feature is enabled.
 ///
 /// [`Stream`]: https://docs.rs/futures/0.3/futures/stream/trait.Stream.html
-pub struct AsyncDevice(pub(crate) TokioAsyncFd<DeviceImpl>);
+pub struct AsyncDevice(
+    pub(crate) TokioAsyncFd<DeviceImpl>,
+    pub(crate) Mutex<Option<io::Error>>,
+    #[cfg(all(target_os = "linux", feature = "io_uring"))] pub(crate) super::uring::Uring,
+);
 impl AsyncDevice {
     /// Polls the I/O handle for readability.
     ///
@@ -134,9 +140,17 @@ impl AsyncDevice {
 }
 
 impl AsyncDevice {
+    #[cfg(not(all(target_os = "linux", feature = "io_uring")))]
     pub(crate) fn new_dev(device: DeviceImpl) -> io::Result<Self> {
         device.set_nonblocking(true)?;
-        Ok(Self(TokioAsyncFd::new(device)?))
+        Ok(Self(TokioAsyncFd::new(device)?, Mutex::new(None)))
+    }
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    pub(crate) fn new_dev(device: DeviceImpl) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+        device.set_nonblocking(true)?;
+        let uring = super::uring::Uring::new(device.as_raw_fd())?;
+        Ok(Self(TokioAsyncFd::new(device)?, Mutex::new(None), uring))
     }
     pub(crate) fn into_device(self) -> io::Result<DeviceImpl> {
         Ok(self.0.into_inner())
@@ -146,8 +160,13 @@ impl AsyncDevice {
         &self,
         mut op: impl FnMut(&DeviceImpl) -> io::Result<R>,
     ) -> io::Result<R> {
+        self.read_budget().await;
         self.0
-            .async_io(Interest::READABLE.add(Interest::ERROR), |device| op(device))
+            .async_io(Interest::READABLE.add(Interest::ERROR), |device| {
+                let rs = op(device);
+                device.read_budget.would_block(&rs);
+                rs
+            })
             .await
     }
     pub(crate) async fn write_with<R>(
@@ -177,4 +196,11 @@ impl AsyncDevice {
     pub(crate) fn get_ref(&self) -> &DeviceImpl {
         self.0.get_ref()
     }
+    pub(crate) fn batch_error(&self) -> &Mutex<Option<io::Error>> {
+        &self.1
+    }
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    pub(crate) fn uring(&self) -> &super::uring::Uring {
+        &self.2
+    }
 }
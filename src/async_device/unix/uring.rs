@@ -0,0 +1,278 @@
+//! Submits the reads and writes of [`AsyncDevice`](super::AsyncDevice) through io_uring.
+//!
+//! A dedicated thread owns the ring. Requests reach it over a channel and an eventfd that is
+//! polled by the ring, completions are handed back through oneshot channels. The thread owns
+//! the buffers of in-flight operations, so dropping a future never leaves the kernel writing
+//! into freed memory. Finished buffers go back to a pool and are reused by later operations.
+
+use crate::error::check_sent;
+use crate::platform::unix::map_gone;
+use io_uring::{opcode, squeue, types, IoUring};
+use std::collections::HashMap;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::sync::oneshot;
+
+const ENTRIES: u32 = 256;
+/// `user_data` of the poll on the wakeup eventfd.
+const WAKER: u64 = u64::MAX;
+/// `user_data` of cancellation requests, whose completions are ignored.
+const CANCEL: u64 = u64::MAX - 1;
+/// Set in the `user_data` of the readiness poll linked in front of each operation.
+const POLL_BIT: u64 = 1 << 62;
+/// The most buffers kept for reuse, one per operation that fits in the ring.
+const POOLED: usize = ENTRIES as usize / 2;
+
+type Pool = Arc<Mutex<Vec<Vec<u8>>>>;
+
+enum Op {
+    Read(Vec<u8>),
+    Write(Vec<u8>),
+}
+
+struct Request {
+    op: Op,
+    done: oneshot::Sender<(io::Result<usize>, Vec<u8>)>,
+}
+
+pub(crate) struct Uring {
+    tx: Option<mpsc::Sender<Request>>,
+    wake: OwnedFd,
+    pool: Pool,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Uring {
+    pub(crate) fn new(fd: RawFd) -> io::Result<Self> {
+        // A duplicate keeps the file open until all in-flight operations are finished, even
+        // if the device is dropped first.
+        let fd = unsafe { libc::dup(fd) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let wake = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if wake < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let wake = unsafe { OwnedFd::from_raw_fd(wake) };
+        let ring = IoUring::new(ENTRIES)?;
+        let (tx, rx) = mpsc::channel();
+        let wake_fd = wake.as_raw_fd();
+        let pool = Pool::default();
+        let thread_pool = pool.clone();
+        let thread = std::thread::Builder::new()
+            .name("tun-rs-uring".into())
+            .spawn(move || run(ring, fd, wake_fd, rx, thread_pool))?;
+        Ok(Self {
+            tx: Some(tx),
+            wake,
+            pool,
+            thread: Some(thread),
+        })
+    }
+    pub(crate) async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut packet = self.buffer();
+        packet.resize(buf.len(), 0);
+        let (rs, packet) = self.submit(Op::Read(packet)).await;
+        if let Ok(n) = rs {
+            buf[..n].copy_from_slice(&packet[..n]);
+        }
+        recycle(&self.pool, packet);
+        rs
+    }
+    pub(crate) async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut packet = self.buffer();
+        packet.extend_from_slice(buf);
+        let (rs, packet) = self.submit(Op::Write(packet)).await;
+        recycle(&self.pool, packet);
+        check_sent(rs?, buf.len())
+    }
+    /// Takes an empty buffer from the pool.
+    fn buffer(&self) -> Vec<u8> {
+        let mut buf = self.pool.lock().unwrap().pop().unwrap_or_default();
+        buf.clear();
+        buf
+    }
+    async fn submit(&self, op: Op) -> (io::Result<usize>, Vec<u8>) {
+        let (done, rx) = oneshot::channel();
+        let sent = self
+            .tx
+            .as_ref()
+            .is_some_and(|tx| tx.send(Request { op, done }).is_ok());
+        if !sent || self.wake().is_err() {
+            return (Err(io::Error::from(io::ErrorKind::BrokenPipe)), Vec::new());
+        }
+        rx.await
+            .unwrap_or_else(|_| (Err(io::Error::from(io::ErrorKind::BrokenPipe)), Vec::new()))
+    }
+    fn wake(&self) -> io::Result<()> {
+        let buf = 1u64.to_ne_bytes();
+        let rs = unsafe {
+            libc::write(
+                self.wake.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+            )
+        };
+        if rs < 0 {
+            let err = io::Error::last_os_error();
+            // The counter is saturated, the thread is awake anyway.
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Uring {
+    fn drop(&mut self) {
+        // Disconnecting the channel makes the thread cancel what is in flight and exit.
+        self.tx.take();
+        _ = self.wake();
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
+
+/// Returns a finished buffer to the pool, unless the pool is full.
+fn recycle(pool: &Pool, buf: Vec<u8>) {
+    let mut pool = pool.lock().unwrap();
+    if pool.len() < POOLED && buf.capacity() > 0 {
+        pool.push(buf);
+    }
+}
+
+fn run(mut ring: IoUring, fd: OwnedFd, wake: RawFd, rx: mpsc::Receiver<Request>, pool: Pool) {
+    let fd = fd.as_raw_fd();
+    let mut pending: HashMap<u64, Request> = HashMap::new();
+    let mut next_id = 0u64;
+    let mut stopping = false;
+    let mut arm_waker = true;
+    loop {
+        if arm_waker {
+            let poll = opcode::PollAdd::new(types::Fd(wake), libc::POLLIN as _)
+                .build()
+                .user_data(WAKER);
+            push(&mut ring, &[poll]);
+            arm_waker = false;
+        }
+        while !stopping {
+            match rx.try_recv() {
+                Ok(mut req) => {
+                    let id = next_id;
+                    next_id = (next_id + 1) % POLL_BIT;
+                    push_op(&mut ring, fd, id, &mut req.op);
+                    pending.insert(id, req);
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    stopping = true;
+                    // Cancelling the readiness poll fails the linked operation as well.
+                    for id in pending.keys() {
+                        for user_data in [*id | POLL_BIT, *id] {
+                            let cancel = opcode::AsyncCancel::new(user_data)
+                                .build()
+                                .user_data(CANCEL);
+                            push(&mut ring, &[cancel]);
+                        }
+                    }
+                }
+            }
+        }
+        if stopping && pending.is_empty() {
+            return;
+        }
+        if let Err(e) = ring.submit_and_wait(1) {
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            log::error!("io_uring submission failed: {e}");
+            // The kernel may still write into the buffers of pending operations.
+            for (_, req) in pending.drain() {
+                std::mem::forget(req.op);
+            }
+            return;
+        }
+        let mut rearm = Vec::new();
+        for cqe in ring.completion() {
+            match cqe.user_data() {
+                WAKER => {
+                    let mut counter = [0u8; 8];
+                    unsafe {
+                        libc::read(wake, counter.as_mut_ptr() as *mut libc::c_void, 8);
+                    }
+                    arm_waker = true;
+                }
+                CANCEL => {}
+                id if id & POLL_BIT != 0 => {}
+                id => {
+                    let res = cqe.result();
+                    // Another reader, such as `try_recv`, can take the packet between the
+                    // readiness poll and the read. Wait for readiness again instead of
+                    // failing the operation.
+                    if res == -libc::EAGAIN && !stopping {
+                        rearm.push(id);
+                        continue;
+                    }
+                    let Some(req) = pending.remove(&id) else {
+                        continue;
+                    };
+                    let rs = if res < 0 {
+                        Err(map_gone(fd, io::Error::from_raw_os_error(-res)))
+                    } else {
+                        Ok(res as usize)
+                    };
+                    let buf = match req.op {
+                        Op::Read(buf) | Op::Write(buf) => buf,
+                    };
+                    if let Err((_, buf)) = req.done.send((rs, buf)) {
+                        recycle(&pool, buf);
+                    }
+                }
+            }
+        }
+        for id in rearm {
+            if let Some(req) = pending.get_mut(&id) {
+                push_op(&mut ring, fd, id, &mut req.op);
+            }
+        }
+    }
+}
+
+/// Pushes `op` behind a linked readiness poll, both tagged with `id`.
+fn push_op(ring: &mut IoUring, fd: RawFd, id: u64, op: &mut Op) {
+    // The device is nonblocking, so wait for readiness before the operation.
+    let (events, entry) = match op {
+        Op::Read(buf) => (
+            libc::POLLIN,
+            opcode::Read::new(types::Fd(fd), buf.as_mut_ptr(), buf.len() as _).build(),
+        ),
+        Op::Write(buf) => (
+            libc::POLLOUT,
+            opcode::Write::new(types::Fd(fd), buf.as_ptr(), buf.len() as _).build(),
+        ),
+    };
+    let poll = opcode::PollAdd::new(types::Fd(fd), events as _)
+        .build()
+        .flags(squeue::Flags::IO_LINK)
+        .user_data(id | POLL_BIT);
+    push(ring, &[poll, entry.user_data(id)]);
+}
+
+/// Pushes linked entries together, flushing the queue first if they do not fit.
+fn push(ring: &mut IoUring, entries: &[squeue::Entry]) {
+    loop {
+        let rs = unsafe { ring.submission().push_multiple(entries) };
+        if rs.is_ok() {
+            return;
+        }
+        if let Err(e) = ring.submit() {
+            log::warn!("io_uring submission failed: {e}");
+        }
+    }
+}
@@ -5,12 +5,13 @@ use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
-use crate::platform::DeviceImpl;
+use crate::platform::{DeviceImpl, MAX_PACKET_SIZE};
 use crate::SyncDevice;
 
 /// An async Tun/Tap device wrapper around a Tun/Tap device.
 ///
 /// This type does not provide a split method, because this functionality can be achieved by instead wrapping the socket in an Arc.
+/// `AsyncDevice` is `Send` and `Sync`, so one task can receive while others send.
 ///
 /// # Streams
 ///
@@ -23,6 +24,8 @@ pub struct AsyncDevice {
     inner: Arc<DeviceImpl>,
     recv_task_lock: Arc<Mutex<Option<RecvTask>>>,
     send_task_lock: Arc<Mutex<Option<SendTask>>>,
+    /// An error that ended a [`recv_batch`](Self::recv_batch) early, returned by the next call.
+    batch_error: Mutex<Option<io::Error>>,
 }
 type RecvTask = blocking::Task<io::Result<(Vec<u8>, usize)>>;
 type SendTask = blocking::Task<io::Result<usize>>;
@@ -43,12 +46,15 @@ impl AsyncDevice {
     }
     /// Create a new `AsyncDevice` wrapping around a `Device`.
     pub(crate) fn new_dev(device: DeviceImpl) -> io::Result<AsyncDevice> {
+        #[cfg(not(feature = "async_tokio"))]
+        device.read_budget.set_max(Self::DEFAULT_MAX_READS_PER_WAKE);
         let inner = Arc::new(device);
 
         Ok(AsyncDevice {
             inner,
             recv_task_lock: Arc::new(Mutex::new(None)),
             send_task_lock: Arc::new(Mutex::new(None)),
+            batch_error: Mutex::new(None),
         })
     }
     /// Attempts to receive a single packet from the device
@@ -157,7 +163,10 @@ impl AsyncDevice {
 
     /// Recv a packet from the device
     pub async fn recv(&self, mut buf: &mut [u8]) -> io::Result<usize> {
-        match self.try_recv(buf) {
+        self.read_budget().await;
+        let rs = self.try_recv(buf);
+        self.read_budget.would_block(&rs);
+        match rs {
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
             rs => return rs,
         }
@@ -170,6 +179,7 @@ impl AsyncDevice {
         })
         .await?;
         let mut packet: &[u8] = &packet[..n];
+        self.mirror.inbound(packet);
 
         match io::copy(&mut packet, &mut buf) {
             Ok(n) => Ok(n as usize),
@@ -177,20 +187,131 @@ impl AsyncDevice {
         }
     }
     pub fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner.try_recv(buf)
+        let rs = self.inner.try_recv(buf);
+        self.mirror.recv(rs, buf, |n| *n)
+    }
+    /// Receives a packet like [`recv`](Self::recv), with the time it was taken from the
+    /// driver, see [`SyncDevice::recv_with_timestamp`](crate::SyncDevice::recv_with_timestamp).
+    ///
+    /// The time is taken on the thread that reads the packet, before it is handed back to
+    /// the task.
+    pub async fn recv_with_timestamp(
+        &self,
+        mut buf: &mut [u8],
+    ) -> io::Result<(usize, std::time::SystemTime)> {
+        self.read_budget().await;
+        let rs = self.try_recv(buf);
+        self.read_budget.would_block(&rs);
+        match rs {
+            Ok(n) => return Ok((n, std::time::SystemTime::now())),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        let device = self.inner.clone();
+        let size = buf.len();
+        let (packet, n, time) = blocking::unblock(move || {
+            let mut in_buf = vec![0; size];
+            let (n, time) = device.recv_with_timestamp(&mut in_buf)?;
+            Ok::<_, io::Error>((in_buf, n, time))
+        })
+        .await?;
+        self.mirror.inbound(&packet[..n]);
+        let n = io::copy(&mut &packet[..n], &mut buf)?;
+        Ok((n as usize, time))
+    }
+    /// Receives up to `max` packets per wakeup.
+    ///
+    /// Waits for the first packet, then drains further packets with `try_recv`
+    /// until `max` packets have been collected or no further packet is immediately available.
+    /// This amortizes wakeups when the tunnel is busy.
+    ///
+    /// An error is returned only if no packet could be read. An error encountered after at
+    /// least one packet has been read ends the batch early and is returned by the next call.
+    pub async fn recv_batch(&self, max: usize) -> io::Result<Vec<Vec<u8>>> {
+        let mut packets = Vec::new();
+        if max == 0 {
+            return Ok(packets);
+        }
+        if let Some(e) = self.batch_error.lock().unwrap().take() {
+            return Err(e);
+        }
+        let mut buf = vec![0; MAX_PACKET_SIZE];
+        let n = self.recv(&mut buf).await?;
+        packets.push(buf[..n].to_vec());
+        while packets.len() < max {
+            match self.try_recv(&mut buf) {
+                Ok(n) => packets.push(buf[..n].to_vec()),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    *self.batch_error.lock().unwrap() = Some(e);
+                    break;
+                }
+            }
+        }
+        Ok(packets)
     }
 
     /// Send a packet to the device
+    ///
+    /// Waits for the [send rate limit](Self::set_send_rate_limit) first, if one is set.
     pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
-        match self.inner.try_send(buf) {
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-            rs => return rs,
+        let rs = self.paced(buf.len(), async {
+            match self.inner.try_send(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                rs => return rs,
+            }
+            let buf = buf.to_vec();
+            let device = self.inner.clone();
+            blocking::unblock(move || device.send(&buf)).await
+        });
+        self.mirror.send(rs.await, buf)
+    }
+    /// Receives a burst of packets, see
+    /// [`DeviceImpl::recv_multiple`](crate::DeviceImpl::recv_multiple).
+    ///
+    /// Waits for the first packet like [`recv`](Self::recv), then takes the packets already
+    /// queued without waiting.
+    pub async fn recv_multiple<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        bufs: &mut [B],
+        sizes: &mut [usize],
+        offset: usize,
+    ) -> io::Result<usize> {
+        DeviceImpl::check_batch(bufs, sizes, offset)?;
+        sizes[0] = self.recv(&mut bufs[0].as_mut()[offset..]).await?;
+        Ok(1 + self.inner.drain(&mut bufs[1..], &mut sizes[1..], offset))
+    }
+    /// Sends the packets `bufs[i][offset..]` in order like [`send`](Self::send), returns
+    /// the number of bytes sent.
+    ///
+    /// An error is returned only if the first packet could not be sent, a later error ends
+    /// the batch early.
+    pub async fn send_multiple<B: AsRef<[u8]>>(
+        &self,
+        bufs: &[B],
+        offset: usize,
+    ) -> io::Result<usize> {
+        let mut total = 0;
+        for (i, buf) in bufs.iter().enumerate() {
+            let rs = match buf.as_ref().get(offset..) {
+                Some(packet) => self.send(packet).await,
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "offset out of buffer",
+                )),
+            };
+            match rs {
+                Ok(n) => total += n,
+                Err(e) if i == 0 => return Err(e),
+                Err(_) => break,
+            }
         }
-        let buf = buf.to_vec();
-        let device = self.inner.clone();
-        blocking::unblock(move || device.send(&buf)).await
+        Ok(total)
     }
+    /// Tries to send a packet to the device, failing with `WouldBlock` if it cannot take the
+    /// packet right now or the [send rate limit](Self::set_send_rate_limit) is exhausted.
     pub fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
-        self.inner.try_send(buf)
+        let rs = self.try_paced(buf.len(), || self.inner.try_send(buf));
+        self.mirror.send(rs, buf)
     }
 }
@@ -2,7 +2,10 @@ use std::io;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 
+#[cfg(feature = "config")]
 use crate::platform::{DeviceImpl, SyncDevice};
+#[cfg(feature = "config")]
+use crate::{Error, Result};
 
 /// Represents the OSI layer at which the TUN interface operates.
 ///
@@ -16,14 +19,180 @@ pub enum Layer {
     L3,
 }
 
+/// Longest interface name accepted by the kernel, `IFNAMSIZ` minus the trailing nul.
+#[cfg(unix)]
+#[cfg(feature = "config")]
+const MAX_NAME_LEN: usize = 15;
+#[cfg(unix)]
+#[cfg(feature = "config")]
+const NAME_TOO_LONG: &str = "longer than 15 bytes (IFNAMSIZ - 1)";
+/// Longest adapter name accepted by wintun, `MAX_ADAPTER_NAME` minus the trailing nul.
+#[cfg(windows)]
+#[cfg(feature = "config")]
+const MAX_NAME_LEN: usize = 127;
+#[cfg(windows)]
+#[cfg(feature = "config")]
+const NAME_TOO_LONG: &str = "longer than 127 UTF-16 code units (MAX_ADAPTER_NAME - 1)";
+
+/// Checks that `name` can be used as an interface name on the current platform.
+///
+/// On Linux a single `%d` is accepted and asks the kernel to pick the next free number,
+/// e.g. `tun%d` becomes `tun0`, `tun1`, ...
+#[cfg(feature = "config")]
+fn validate_name(name: &str) -> Result<()> {
+    let invalid = |reason| {
+        Err(Error::InvalidName {
+            name: name.to_string(),
+            reason,
+        })
+    };
+    if name.is_empty() {
+        return invalid("the name is empty");
+    }
+    if name.contains('\0') {
+        return invalid("the name contains a nul byte");
+    }
+    #[cfg(unix)]
+    let len = name.len();
+    #[cfg(windows)]
+    let len = name.encode_utf16().count();
+    if len > MAX_NAME_LEN {
+        return invalid(NAME_TOO_LONG);
+    }
+    #[cfg(unix)]
+    {
+        if name == "." || name == ".." {
+            return invalid("the name is reserved");
+        }
+        if name.contains('/') || name.chars().any(char::is_whitespace) {
+            return invalid("the name contains '/' or whitespace");
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        if name.contains(':') {
+            return invalid("the name contains ':'");
+        }
+        if name.contains('%') && (name.matches('%').count() > 1 || !name.contains("%d")) {
+            return invalid("'%' is only allowed once, as the \"%d\" numbering placeholder");
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if name.contains('%') {
+        return invalid("\"%d\" numbering is only supported on Linux");
+    }
+    Ok(())
+}
+
+/// A snapshot of an interface's settings, taken by [`DeviceImpl::identity`].
+///
+/// [`DeviceBuilder::from_identity`] turns it back into a builder, e.g. to recreate the
+/// interface after it reported [`Error::DeviceGone`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+#[cfg(feature = "config")]
+pub struct DeviceIdentity {
+    /// The interface name.
+    pub name: String,
+    /// Whether the interface is a TUN or a TAP device.
+    pub layer: Layer,
+    /// The MTU, on Windows the IPv4 MTU.
+    pub mtu: u16,
+    /// The IPv6 MTU, `None` if IPv6 is disabled on the interface.
+    #[cfg(windows)]
+    pub mtu_v6: Option<u16>,
+    /// The IPv4 addresses and their prefix lengths, in the order the OS lists them.
+    pub ipv4: Vec<(Ipv4Addr, u8)>,
+    /// The IPv6 addresses and their prefix lengths, without link-local addresses.
+    pub ipv6: Vec<(Ipv6Addr, u8)>,
+    /// The MAC address of L2 devices.
+    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "freebsd"))]
+    pub mac_addr: Option<[u8; 6]>,
+    /// The adapter GUID of wintun devices, which keeps the registry settings of the adapter.
+    #[cfg(windows)]
+    pub device_guid: Option<u128>,
+}
+
+/// The desired configuration of an existing interface, applied by [`DeviceImpl::apply`].
+///
+/// Only settings that can be read back are included, so that applying compares them with
+/// the current state.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg(feature = "config")]
+pub struct DeviceSpec {
+    /// The IPv4 addresses and their prefix lengths.
+    pub ipv4: Vec<(Ipv4Addr, u8)>,
+    /// The IPv6 addresses and their prefix lengths. Link-local addresses are left alone.
+    pub ipv6: Vec<(Ipv6Addr, u8)>,
+    /// The MTU, on Windows the IPv4 MTU. `None` keeps the current one.
+    pub mtu: Option<u16>,
+    /// The IPv6 MTU. `None` keeps the current one.
+    #[cfg(windows)]
+    pub mtu_v6: Option<u16>,
+}
+
+/// The changes made by [`DeviceImpl::apply`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+#[cfg(feature = "config")]
+pub struct ApplyReport {
+    /// The addresses that were added.
+    pub added: Vec<(IpAddr, u8)>,
+    /// The addresses that were removed.
+    pub removed: Vec<(IpAddr, u8)>,
+    /// The old and the new MTU, if it was changed.
+    pub mtu: Option<(u16, u16)>,
+    /// The old and the new IPv6 MTU, if it was changed.
+    #[cfg(windows)]
+    pub mtu_v6: Option<(u16, u16)>,
+}
+
+#[cfg(feature = "config")]
+impl ApplyReport {
+    /// Returns whether nothing had to be changed.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Splits a parsed address and prefix into the separately stored results of the builder,
+/// an error is kept with the address.
+#[cfg(feature = "config")]
+fn split_result<A>(result: io::Result<(A, u8)>) -> (io::Result<A>, io::Result<u8>) {
+    match result {
+        Ok((address, prefix)) => (Ok(address), Ok(prefix)),
+        Err(e) => (Err(e), Ok(0)),
+    }
+}
+
+/// Wraps the error of a configuration step in [`Error::Config`].
+#[cfg(feature = "config")]
+fn step(
+    device: &DeviceImpl,
+    op: &'static str,
+    value: impl std::fmt::Display,
+    result: Result<()>,
+) -> Result<()> {
+    result.map_err(|source| Error::Config {
+        op,
+        device: device.name().unwrap_or_default(),
+        value: value.to_string(),
+        source: Box::new(source),
+    })
+}
+
 /// Configuration for a TUN/TAP interface.
 ///
 /// This structure stores settings such as the device name, operating layer,
 /// and platform-specific parameters (e.g., GUID, wintun file, ring capacity on Windows).
 #[derive(Clone, Default, Debug)]
+#[cfg(feature = "config")]
 pub(crate) struct DeviceConfig {
     /// The name of the device/interface.
     pub dev_name: Option<String>,
+    /// Prefix of the generated name when `dev_name` is not set.
+    #[cfg(any(target_os = "windows", target_os = "freebsd"))]
+    pub name_prefix: Option<String>,
     /// Specifies whether the interface operates at L2 or L3.
     #[allow(dead_code)]
     pub layer: Option<Layer>,
@@ -46,25 +215,44 @@ pub(crate) struct DeviceConfig {
     /// Enable multi queue support
     #[cfg(target_os = "linux")]
     pub multi_queue: Option<bool>,
+    /// Additional `IFF_*` flags passed to `TUNSETIFF`.
+    #[cfg(target_os = "linux")]
+    pub extra_tun_flags: Option<u16>,
+    /// Bring the interface down on drop if the device brought it up.
+    #[cfg(target_os = "linux")]
+    pub cleanup_on_drop: Option<bool>,
 }
+#[cfg(feature = "config")]
 type IPV4 = (
     io::Result<Ipv4Addr>,
     io::Result<u8>,
     Option<io::Result<Ipv4Addr>>,
 );
+#[cfg(feature = "config")]
+type OnCreated = Box<dyn FnOnce(&DeviceImpl) -> io::Result<()> + Send>;
 /// A builder for configuring a TUN/TAP interface.
 ///
 /// This builder allows you to set parameters such as device name, MTU,
 /// IPv4/IPv6 addresses, MAC address, and other platform-specific options.
 #[derive(Default)]
+#[cfg(feature = "config")]
 pub struct DeviceBuilder {
     dev_name: Option<String>,
+    name_prefix: Option<String>,
     enabled: Option<bool>,
     mtu: Option<u16>,
     #[cfg(windows)]
     mtu_v6: Option<u16>,
-    ipv4: Option<IPV4>,
+    allow_small_mtu: bool,
+    ipv4: Option<Vec<IPV4>>,
     ipv6: Option<Vec<(io::Result<Ipv6Addr>, io::Result<u8>)>>,
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd"
+    ))]
+    ipv6_default_route: Option<io::Result<Ipv6Addr>>,
     layer: Option<Layer>,
     #[cfg(any(target_os = "windows", target_os = "linux", target_os = "freebsd"))]
     mac_addr: Option<[u8; 6]>,
@@ -74,7 +262,6 @@ pub struct DeviceBuilder {
     wintun_file: Option<String>,
     #[cfg(windows)]
     ring_capacity: Option<u32>,
-    #[cfg(windows)]
     metric: Option<u16>,
     /// switch of Enable/Disable packet information for network driver
     #[cfg(any(target_os = "ios", target_os = "macos", target_os = "linux"))]
@@ -88,19 +275,85 @@ pub struct DeviceBuilder {
     /// Enable multi queue support
     #[cfg(target_os = "linux")]
     multi_queue: Option<bool>,
+    #[cfg(target_os = "linux")]
+    extra_tun_flags: Option<u16>,
+    #[cfg(target_os = "linux")]
+    config_lock: Option<bool>,
+    #[cfg(target_os = "linux")]
+    num_queues: Option<usize>,
+    #[cfg(target_os = "linux")]
+    cleanup_on_drop: Option<bool>,
+    #[cfg(target_os = "linux")]
+    persist: Option<bool>,
+    #[cfg(target_os = "linux")]
+    protocol_filter: Option<crate::Protocol>,
+    #[cfg(target_os = "linux")]
+    capture_all: Option<bool>,
+    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+    dns_servers: Option<Vec<IpAddr>>,
+    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+    restore_dns_on_drop: Option<bool>,
+    on_created: Option<OnCreated>,
 }
 
+#[cfg(feature = "config")]
 impl DeviceBuilder {
     /// Creates a new DeviceBuilder instance with default settings.
     pub fn new() -> Self {
         Self::default()
     }
+    /// Creates a builder that recreates the interface captured by
+    /// [`DeviceImpl::identity`].
+    ///
+    /// The MTU is applied as captured, even if it is below the minimums of
+    /// [`mtu`](Self::mtu). Routes and point-to-point destinations are not part of the
+    /// identity and have to be re-installed by the caller.
+    pub fn from_identity(identity: &DeviceIdentity) -> Self {
+        let mut builder = DeviceBuilder::new()
+            .name(identity.name.clone())
+            .layer(identity.layer)
+            .mtu(identity.mtu)
+            .allow_small_mtu();
+        #[cfg(windows)]
+        {
+            builder.mtu_v6 = identity.mtu_v6;
+            builder.device_guid = identity.device_guid;
+        }
+        for &(address, prefix) in &identity.ipv4 {
+            builder = builder.ipv4(address, prefix, None);
+        }
+        for &(address, prefix) in &identity.ipv6 {
+            builder = builder.ipv6(address, prefix);
+        }
+        #[cfg(any(target_os = "windows", target_os = "linux", target_os = "freebsd"))]
+        {
+            builder.mac_addr = identity.mac_addr;
+        }
+        builder
+    }
     /// Sets the device name.
+    ///
+    /// The name is validated by [`build_sync`](Self::build_sync). On Linux it may contain a
+    /// single `%d`, which the kernel replaces with the next free number.
     pub fn name<S: Into<String>>(mut self, dev_name: S) -> Self {
         self.dev_name = Some(dev_name.into());
         self
     }
+    /// Sets the prefix of the generated device name, used when no [`name`](Self::name) is set.
+    ///
+    /// The OS appends a number, e.g. `myvpn` becomes `myvpn0`, `myvpn1`, ...
+    /// Not supported on macOS, where utun devices are always named `utunN`.
+    pub fn name_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.name_prefix = Some(prefix.into());
+        self
+    }
     /// Sets the device MTU (Maximum Transmission Unit).
+    ///
+    /// [`build_sync`](Self::build_sync) rejects values below 576, the datagram size every
+    /// IPv4 host must accept, and below 1280 when an IPv6 address is configured, as IPv6
+    /// does not run on smaller links. Values above what the TUN driver supports are rejected
+    /// as well. After setting it the effective MTU is read back and a clamped value is
+    /// reported as an error.
     pub fn mtu(mut self, mtu: u16) -> Self {
         self.mtu = Some(mtu);
         #[cfg(windows)]
@@ -111,35 +364,86 @@ impl DeviceBuilder {
         self
     }
     /// Sets the IPv4 MTU specifically for Windows.
+    ///
+    /// Windows keeps an MTU per address family on the interface. It is applied to the IPv4
+    /// subinterface with [`set_mtu`](DeviceImpl::set_mtu) and read back like the MTU set by
+    /// [`mtu`](Self::mtu).
     #[cfg(windows)]
     pub fn mtu_v4(mut self, mtu: u16) -> Self {
         self.mtu = Some(mtu);
         self
     }
-    /// Sets the IPv6 MTU specifically for Windows.
+    /// Sets the IPv6 MTU specifically for Windows, applied to the IPv6 subinterface with
+    /// [`set_mtu_v6`](DeviceImpl::set_mtu_v6).
     #[cfg(windows)]
     pub fn mtu_v6(mut self, mtu: u16) -> Self {
         self.mtu_v6 = Some(mtu);
         self
     }
+    /// Accepts MTUs below the minimums enforced by [`mtu`](Self::mtu).
+    ///
+    /// Meant for encapsulation experiments, IPv6 will not work on such an interface.
+    pub fn allow_small_mtu(mut self) -> Self {
+        self.allow_small_mtu = true;
+        self
+    }
     /// Sets the MAC address for the device (effective only in L2 mode).
+    ///
+    /// [`build_sync`](Self::build_sync) rejects all-zero and multicast addresses and warns
+    /// if the locally administered bit is not set. [`random_mac`](crate::random_mac)
+    /// generates a suitable address.
     #[cfg(any(target_os = "windows", target_os = "linux", target_os = "freebsd"))]
     pub fn mac_addr(mut self, mac_addr: [u8; 6]) -> Self {
         self.mac_addr = Some(mac_addr);
         self
     }
-    /// Configures the IPv4 address for the device.
+    /// Configures an IPv4 address for the device.
     ///
     /// - `address`: The IPv4 address of the device.
     /// - `mask`: The subnet mask or prefix length.
     /// - `destination`: Optional destination address for point-to-point links.
+    ///
+    /// Calling it again adds another address. The first one is the primary address, set with
+    /// [`set_network_address`](DeviceImpl::set_network_address), the others are added with
+    /// [`add_address_v4`](DeviceImpl::add_address_v4).
     pub fn ipv4<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
-        mut self,
+        self,
         address: IPv4,
         mask: Netmask,
         destination: Option<IPv4>,
     ) -> Self {
-        self.ipv4 = Some((address.ipv4(), mask.prefix(), destination.map(|v| v.ipv4())));
+        self.ipv4_tuple(&[(address, mask, destination)])
+    }
+    /// Configures multiple IPv4 addresses in batch, see [`ipv4`](Self::ipv4).
+    ///
+    /// Accepts a slice of (IPv4 address, netmask, destination) tuples.
+    pub fn ipv4_tuple<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
+        mut self,
+        addrs: &[(IPv4, Netmask, Option<IPv4>)],
+    ) -> Self {
+        let v = self.ipv4.get_or_insert_with(Vec::new);
+        for (address, mask, destination) in addrs {
+            v.push((
+                address.ipv4(),
+                mask.prefix(),
+                destination.as_ref().map(|v| v.ipv4()),
+            ));
+        }
+        self
+    }
+    /// Configures an IPv4 address given with its prefix length, e.g. `"10.0.0.1/24"`, see
+    /// [`ipv4`](Self::ipv4).
+    ///
+    /// An invalid value is reported by [`build_sync`](Self::build_sync) as an `InvalidData`
+    /// error.
+    /// ```
+    /// let builder = tun_rs::DeviceBuilder::new().ipv4_cidr("10.0.0.1/24");
+    /// ```
+    pub fn ipv4_cidr<Cidr: ToIpv4Cidr>(mut self, cidr: Cidr) -> Self {
+        let (address, prefix) = split_result(cidr.ipv4_cidr());
+        self.ipv4
+            .get_or_insert_with(Vec::new)
+            .push((address, prefix, None));
         self
     }
     /// Configures an IPv6 address for the device.
@@ -159,6 +463,18 @@ impl DeviceBuilder {
 
         self
     }
+    /// Configures an IPv6 address given with its prefix length, e.g. `"fd00::1/64"`, see
+    /// [`ipv6`](Self::ipv6).
+    ///
+    /// An invalid value is reported by [`build_sync`](Self::build_sync) as an `InvalidData`
+    /// error.
+    pub fn ipv6_cidr<Cidr: ToIpv6Cidr>(mut self, cidr: Cidr) -> Self {
+        let (address, prefix) = split_result(cidr.ipv6_cidr());
+        self.ipv6
+            .get_or_insert_with(Vec::new)
+            .push((address, prefix));
+        self
+    }
     /// Configures multiple IPv6 addresses in batch.
     ///
     /// Accepts a slice of (IPv6 address, netmask) tuples.
@@ -180,6 +496,21 @@ impl DeviceBuilder {
         }
         self
     }
+    /// Routes all IPv6 traffic through the device once it is enabled.
+    ///
+    /// `server` is the address of the VPN server, it keeps its current route so the tunnel
+    /// does not carry its own traffic. See
+    /// [`set_ipv6_default_route`](DeviceImpl::set_ipv6_default_route) for the routes installed.
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd"
+    ))]
+    pub fn ipv6_default_route<IPv6: ToIpv6Address>(mut self, server: IPv6) -> Self {
+        self.ipv6_default_route = Some(server.ipv6());
+        self
+    }
     /// Sets the operating layer (L2 or L3) for the device.
     pub fn layer(mut self, layer: Layer) -> Self {
         self.layer = Some(layer);
@@ -192,19 +523,27 @@ impl DeviceBuilder {
         self
     }
     /// Sets the wintun file path on Windows.
+    ///
+    /// Applies to this build only, without it the path set by
+    /// [`reload_wintun`](crate::reload_wintun) or `wintun.dll` is used.
     #[cfg(windows)]
     pub fn wintun_file(mut self, wintun_file: String) -> Self {
         self.wintun_file = Some(wintun_file);
         self
     }
     /// Sets the ring capacity on Windows.
+    ///
+    /// Only L3 (wintun) devices have rings to size. tap0901 reads its MTU, media status and
+    /// MAC address from the registry, but the queues of an L2 device have a fixed size
+    /// compiled into the driver.
     #[cfg(windows)]
     pub fn ring_capacity(mut self, ring_capacity: u32) -> Self {
         self.ring_capacity = Some(ring_capacity);
         self
     }
-    /// Sets the routing metric on Windows.
-    #[cfg(windows)]
+    /// Sets the routing metric of the interface.
+    ///
+    /// See [`DeviceImpl::set_metric`] for what the metric affects on each platform.
     pub fn metric(mut self, metric: u16) -> Self {
         self.metric = Some(metric);
         self
@@ -228,6 +567,96 @@ impl DeviceBuilder {
         self.multi_queue = Some(multi_queue);
         self
     }
+    /// ORs additional `IFF_*` flags into the `ifr_flags` passed to `TUNSETIFF` on Linux.
+    ///
+    /// Gives access to flags the builder does not expose, e.g. `IFF_TUN_EXCL`, which makes
+    /// the build fail if an interface with the requested name already exists instead of
+    /// attaching to it. The flags are passed as they are, the kernel rejects unknown ones.
+    #[cfg(target_os = "linux")]
+    pub fn extra_tun_flags(mut self, flags: u16) -> Self {
+        self.extra_tun_flags = Some(flags);
+        self
+    }
+    /// Sets how many queues [`build_sync_multi_queue`](Self::build_sync_multi_queue) opens
+    /// on Linux.
+    ///
+    /// Must be between 1 and 256, the kernel's `MAX_TAP_QUEUES`. Defaults to 1.
+    #[cfg(target_os = "linux")]
+    pub fn num_queues(mut self, num_queues: usize) -> Self {
+        self.num_queues = Some(num_queues);
+        self
+    }
+    /// Routes all IP traffic into the device once it is enabled on Linux, see
+    /// [`set_capture_all`](DeviceImpl::set_capture_all) for the policy rules installed.
+    ///
+    /// Traffic of the proxy itself must be marked with
+    /// [`CAPTURE_ALL_FWMARK`](crate::CAPTURE_ALL_FWMARK) to bypass the device. On the other
+    /// platforms [`ipv6_default_route`](Self::ipv6_default_route) and the equivalent IPv4
+    /// routes (`0.0.0.0/1` and `128.0.0.0/1` via the device) have the same effect, with the
+    /// route to the upstream server kept outside the tunnel.
+    #[cfg(target_os = "linux")]
+    pub fn capture_all(mut self, capture_all: bool) -> Self {
+        self.capture_all = Some(capture_all);
+        self
+    }
+    /// Takes an advisory lock on the interface before configuring it on Linux.
+    ///
+    /// The lock is a `flock` on `/run/lock/tun-rs-<name>.lock`, so that processes opening
+    /// the same persistent interface do not apply their configuration concurrently. If
+    /// another process holds it, the build fails with an error of kind `AddrInUse` naming
+    /// that process. The lock is released when the device is dropped, see
+    /// [`is_config_locked`](DeviceImpl::is_config_locked).
+    #[cfg(target_os = "linux")]
+    pub fn config_lock(mut self, config_lock: bool) -> Self {
+        self.config_lock = Some(config_lock);
+        self
+    }
+    /// Undoes the configuration applied through the device when it is dropped on Linux, see
+    /// [`SyncDevice::close`](crate::SyncDevice::close): routes first, then addresses, then
+    /// the interface is brought down again.
+    ///
+    /// Only changes of the device are undone. The interface is only brought down if it was
+    /// down before the device brought it up, and addresses that were already assigned are
+    /// kept, so adopting a persistent interface never removes configuration of its other
+    /// users. Failures are logged.
+    #[cfg(target_os = "linux")]
+    pub fn cleanup_on_drop(mut self, cleanup_on_drop: bool) -> Self {
+        self.cleanup_on_drop = Some(cleanup_on_drop);
+        self
+    }
+    /// Only receives frames of `protocol` on a Linux TAP device, the kernel drops the other
+    /// family and non-IP frames.
+    ///
+    /// The filter covers every queue of the device, see [`DeviceImpl::set_protocol_filter`].
+    /// Building a TUN device with a filter fails with an error of kind `Unsupported`.
+    #[cfg(target_os = "linux")]
+    pub fn protocol_filter(mut self, protocol: crate::Protocol) -> Self {
+        self.protocol_filter = Some(protocol);
+        self
+    }
+    /// Sets the DNS servers of the interface once it is enabled, see
+    /// [`set_dns_servers`](DeviceImpl::set_dns_servers). IPv4 and IPv6 servers may be mixed.
+    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+    pub fn dns_servers(mut self, servers: &[IpAddr]) -> Self {
+        self.dns_servers = Some(servers.to_vec());
+        self
+    }
+    /// Puts the DNS servers the interface had before the device first set them back when
+    /// the device is dropped, also if they were cleared with an empty
+    /// [`set_dns_servers`](DeviceImpl::set_dns_servers). Failures are logged.
+    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+    pub fn restore_dns_on_drop(mut self, restore: bool) -> Self {
+        self.restore_dns_on_drop = Some(restore);
+        self
+    }
+    /// Makes the interface persistent on Linux, see
+    /// [`set_persist`](DeviceImpl::set_persist): it stays after the device is dropped, and a
+    /// later build with the same [`name`](Self::name) attaches to it.
+    #[cfg(target_os = "linux")]
+    pub fn persist(mut self, persist: bool) -> Self {
+        self.persist = Some(persist);
+        self
+    }
     /// Enables or disables packet information for the network driver
     /// on iOS, macOS, and Linux.
     #[cfg(any(target_os = "ios", target_os = "macos", target_os = "linux"))]
@@ -235,6 +664,18 @@ impl DeviceBuilder {
         self.packet_information = Some(packet_information);
         self
     }
+    /// Sets a hook that runs after the device has been created and addressed,
+    /// but before it is enabled.
+    ///
+    /// This is the place for platform-specific setup such as firewall rules or extra ioctls.
+    /// An error returned by the hook aborts the build.
+    pub fn on_created<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&DeviceImpl) -> io::Result<()> + Send + 'static,
+    {
+        self.on_created = Some(Box::new(f));
+        self
+    }
     /// Enables or disables the device. Defaults to enabled.
     pub fn enable(mut self, enable: bool) -> Self {
         self.enabled = Some(enable);
@@ -242,7 +683,16 @@ impl DeviceBuilder {
     }
     pub(crate) fn build_config(&mut self) -> DeviceConfig {
         DeviceConfig {
+            // The kernel replaces "%d" with the next free number.
+            #[cfg(target_os = "linux")]
+            dev_name: self
+                .dev_name
+                .take()
+                .or_else(|| self.name_prefix.take().map(|prefix| format!("{prefix}%d"))),
+            #[cfg(not(target_os = "linux"))]
             dev_name: self.dev_name.take(),
+            #[cfg(any(target_os = "windows", target_os = "freebsd"))]
+            name_prefix: self.name_prefix.take(),
             layer: self.layer.take(),
             #[cfg(windows)]
             device_guid: self.device_guid.take(),
@@ -256,62 +706,386 @@ impl DeviceBuilder {
             offload: self.offload.take(),
             #[cfg(target_os = "linux")]
             multi_queue: self.multi_queue.take(),
+            #[cfg(target_os = "linux")]
+            extra_tun_flags: self.extra_tun_flags.take(),
+            #[cfg(target_os = "linux")]
+            cleanup_on_drop: self.cleanup_on_drop.take(),
         }
     }
-    pub(crate) fn config(self, device: &DeviceImpl) -> io::Result<()> {
+    /// Rejects option combinations that would produce a broken device.
+    fn validate(&self) -> Result<()> {
+        if let Some(name) = &self.dev_name {
+            validate_name(name)?;
+        }
+        if let Some(prefix) = &self.name_prefix {
+            if self.dev_name.is_some() {
+                return Err(Error::InvalidConfig(
+                    "name and name_prefix are mutually exclusive, set only one of them",
+                ));
+            }
+            #[cfg(target_os = "macos")]
+            {
+                _ = prefix;
+                return Err(Error::Unsupported(
+                    "name_prefix on macOS, utun devices are always named utunN",
+                ));
+            }
+            #[cfg(target_os = "linux")]
+            validate_name(&format!("{prefix}%d"))?;
+            #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+            validate_name(&format!("{prefix}0"))?;
+        }
         if let Some(mtu) = self.mtu {
-            device.set_mtu(mtu)?;
+            #[cfg(not(windows))]
+            let ipv6 = self.ipv6.is_some();
+            // The Windows MTU set by `mtu` only applies to IPv4, IPv6 has its own.
+            #[cfg(windows)]
+            let ipv6 = false;
+            crate::platform::check_mtu(mtu, ipv6, self.allow_small_mtu)?;
         }
         #[cfg(windows)]
         if let Some(mtu) = self.mtu_v6 {
-            device.set_mtu_v6(mtu)?;
+            crate::platform::check_mtu(mtu, true, self.allow_small_mtu)?;
+        }
+        #[cfg(any(
+            target_os = "windows",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "freebsd"
+        ))]
+        if self.ipv6_default_route.is_some() && self.enabled == Some(false) {
+            return Err(Error::InvalidConfig(
+                "ipv6_default_route needs an enabled device, routes cannot be added while it is down",
+            ));
+        }
+        #[cfg(target_os = "linux")]
+        if self.capture_all == Some(true) && self.enabled == Some(false) {
+            return Err(Error::InvalidConfig(
+                "capture_all needs an enabled device, routes cannot be added while it is down",
+            ));
+        }
+        #[cfg(any(target_os = "windows", target_os = "linux", target_os = "freebsd"))]
+        if let Some(mac_addr) = &self.mac_addr {
+            crate::platform::check_mac(mac_addr)?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(num_queues) = self.num_queues {
+            use crate::platform::linux::MAX_TAP_QUEUES;
+            if !(1..=MAX_TAP_QUEUES).contains(&num_queues) {
+                return Err(Error::InvalidConfig(
+                    "num_queues must be between 1 and 256, the kernel's MAX_TAP_QUEUES",
+                ));
+            }
+            if num_queues > 1 && self.multi_queue == Some(false) {
+                return Err(Error::InvalidConfig(
+                    "num_queues above 1 needs multi_queue, which is explicitly disabled",
+                ));
+            }
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let packet_information = self.packet_information.unwrap_or(false);
+            if packet_information && self.offload.unwrap_or(false) {
+                return Err(Error::InvalidConfig(
+                    "offload and packet_information both prepend a header to every packet, \
+                     disable one of them",
+                ));
+            }
+            if packet_information && self.layer == Some(Layer::L2) {
+                return Err(Error::InvalidConfig(
+                    "packet_information is not supported on L2 devices, \
+                     the Ethernet header already carries the protocol",
+                ));
+            }
         }
         #[cfg(windows)]
-        if let Some(metric) = self.metric {
-            device.set_metric(metric)?;
+        if let Some(ring_capacity) = self.ring_capacity {
+            use crate::platform::windows::{MAX_RING_CAPACITY, MIN_RING_CAPACITY};
+            if !(MIN_RING_CAPACITY..=MAX_RING_CAPACITY).contains(&ring_capacity)
+                || !ring_capacity.is_power_of_two()
+            {
+                return Err(Error::InvalidConfig(
+                    "ring_capacity must be a power of two between 128 KiB and 64 MiB",
+                ));
+            }
+        }
+        Ok(())
+    }
+    pub(crate) fn config(self, device: &DeviceImpl) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        if self.config_lock.unwrap_or(false) {
+            device.lock_config()?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(persist) = self.persist {
+            step(device, "set_persist", persist, device.set_persist(persist))?;
+        }
+        // The bounds were checked by `validate`, before any address was assigned.
+        if let Some(mtu) = self.mtu {
+            step(device, "set_mtu", mtu, device.set_mtu_unchecked(mtu))?;
+        }
+        #[cfg(windows)]
+        if let Some(mtu) = self.mtu_v6 {
+            step(device, "set_mtu_v6", mtu, device.set_mtu_v6_unchecked(mtu))?;
         }
         #[cfg(target_os = "linux")]
         if let Some(tx_queue_len) = self.tx_queue_len {
-            device.set_tx_queue_len(tx_queue_len)?;
+            step(
+                device,
+                "set_tx_queue_len",
+                tx_queue_len,
+                device.set_tx_queue_len(tx_queue_len),
+            )?;
         }
         #[cfg(any(target_os = "windows", target_os = "linux", target_os = "freebsd"))]
         if let Some(mac_addr) = self.mac_addr {
             if self.layer.unwrap_or_default() == Layer::L2 {
-                device.set_mac_address(mac_addr)?;
+                let value = mac_addr.map(|b| format!("{b:02x}")).join(":");
+                step(
+                    device,
+                    "set_mac_address",
+                    value,
+                    device.set_mac_address(mac_addr),
+                )?;
             }
         }
 
-        if let Some((address, prefix, destination)) = self.ipv4 {
-            let prefix = prefix?;
-            let address = address?;
-            let destination = destination.transpose()?;
-            device.set_network_address(address, prefix, destination)?;
+        if let Some(ipv4) = self.ipv4 {
+            for (i, (address, prefix, destination)) in ipv4.into_iter().enumerate() {
+                let prefix = prefix?;
+                let address = address?;
+                let destination = destination.transpose()?;
+                if i == 0 {
+                    step(
+                        device,
+                        "set_network_address",
+                        format_args!("{address}/{prefix}"),
+                        device.set_network_address(address, prefix, destination),
+                    )?;
+                } else {
+                    step(
+                        device,
+                        "add_address_v4",
+                        format_args!("{address}/{prefix}"),
+                        device.add_address_v4(address, prefix, destination),
+                    )?;
+                }
+            }
         }
         if let Some(ipv6) = self.ipv6 {
             for (address, prefix) in ipv6 {
                 let prefix = prefix?;
                 let address = address?;
-                device.add_address_v6(address, prefix)?;
+                step(
+                    device,
+                    "add_address_v6",
+                    format_args!("{address}/{prefix}"),
+                    device.add_address_v6(address, prefix),
+                )?;
             }
         }
-        device.enabled(self.enabled.unwrap_or(true))?;
+        if let Some(metric) = self.metric {
+            step(device, "set_metric", metric, device.set_metric(metric))?;
+        }
+        if let Some(on_created) = self.on_created {
+            step(
+                device,
+                "on_created",
+                "",
+                on_created(device).map_err(Error::from),
+            )?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(protocol) = self.protocol_filter {
+            step(
+                device,
+                "set_protocol_filter",
+                format_args!("{protocol:?}"),
+                device.set_protocol_filter(Some(protocol)),
+            )?;
+        }
+        let enabled = self.enabled.unwrap_or(true);
+        step(device, "enabled", enabled, device.enabled(enabled))?;
+        #[cfg(any(
+            target_os = "windows",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "freebsd"
+        ))]
+        if let Some(server) = self.ipv6_default_route {
+            let server = server?;
+            step(
+                device,
+                "set_ipv6_default_route",
+                server,
+                device.set_ipv6_default_route(server),
+            )?;
+        }
+        #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+        if self.restore_dns_on_drop == Some(true) {
+            device.dns_restore.enable();
+        }
+        #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+        if let Some(servers) = &self.dns_servers {
+            step(
+                device,
+                "set_dns_servers",
+                format_args!("{servers:?}"),
+                device.set_dns_servers(servers),
+            )?;
+        }
+        #[cfg(target_os = "linux")]
+        if self.capture_all == Some(true) {
+            step(
+                device,
+                "set_capture_all",
+                true,
+                device.set_capture_all(true),
+            )?;
+        }
         Ok(())
     }
     /// Builds a synchronous device instance and applies all configuration parameters.
-    pub fn build_sync(mut self) -> io::Result<SyncDevice> {
+    ///
+    /// A failing configuration step is reported as [`Error::Config`], naming the step, the
+    /// device and the value. The half-configured device is then closed, which removes the
+    /// interface unless it is persistent.
+    pub fn build_sync(mut self) -> Result<SyncDevice> {
+        self.validate()?;
         let device = DeviceImpl::new(self.build_config())?;
         self.config(&device)?;
         Ok(SyncDevice(device))
     }
+    /// Creates the device without applying addresses, MTU, metric or the enabled state.
+    ///
+    /// The remaining settings are returned in a [`Configurator`], which can apply them later,
+    /// e.g. after the file descriptor has been handed to another process.
+    pub fn build_sync_unconfigured(mut self) -> Result<(SyncDevice, Configurator)> {
+        self.validate()?;
+        let device = DeviceImpl::new(self.build_config())?;
+        Ok((SyncDevice(device), Configurator { builder: self }))
+    }
+    /// Creates a multi-queue device and opens [`num_queues`](Self::num_queues) queues of it
+    /// on Linux.
+    ///
+    /// Enables [`multi_queue`](Self::multi_queue) unless it was disabled. The configuration
+    /// is applied once through the first queue, the others are attached with
+    /// [`try_clone`](DeviceImpl::try_clone). Each queue receives a share of the packets.
+    #[cfg(target_os = "linux")]
+    pub fn build_sync_multi_queue(mut self) -> Result<Vec<SyncDevice>> {
+        self.multi_queue.get_or_insert(true);
+        self.validate()?;
+        let num_queues = self.num_queues.unwrap_or(1);
+        let device = DeviceImpl::new(self.build_config())?;
+        self.config(&device)?;
+        let mut queues = Vec::with_capacity(num_queues);
+        for _ in 1..num_queues {
+            queues.push(SyncDevice(device.try_clone()?));
+        }
+        queues.insert(0, SyncDevice(device));
+        Ok(queues)
+    }
     /// Builds an asynchronous device instance.
     ///
-    /// This method is available only when the async_std or async_tokio features are enabled.
+    /// This method is available only when the async_tokio or async_io (async_std) features
+    /// are enabled. The device is registered with the reactor of the enabled backend, which
+    /// for async_io is the global `async-io` reactor that smol also runs on.
     #[cfg(any(feature = "async_std", feature = "async_tokio"))]
-    pub fn build_async(self) -> io::Result<crate::AsyncDevice> {
+    pub fn build_async(self) -> Result<crate::AsyncDevice> {
         let sync_device = self.build_sync()?;
         let device = crate::AsyncDevice::new_dev(sync_device.0)?;
         Ok(device)
     }
+    /// Wraps an existing TUN file descriptor, such as the one returned by Android's
+    /// `VpnService.Builder.establish()`, instead of creating the device.
+    ///
+    /// The interface and its addresses, routes and MTU are left as they are, the
+    /// corresponding settings of the builder are ignored: on a descriptor handed out by a
+    /// VPN framework the interface belongs to the system, and configuring it usually fails
+    /// with a permission error. Configure it through the framework instead, or call the
+    /// setters of the device afterwards where they are allowed.
+    ///
+    /// On Linux the descriptor must be a TUN/TAP queue, its framing is read from the queue
+    /// and a [`packet_information`](Self::packet_information) or [`offload`](Self::offload)
+    /// setting that does not match it is an [`Error::InvalidConfig`]. With `offload(true)`
+    /// the TCP and UDP offloads are negotiated as for a created device. On macOS,
+    /// `packet_information` selects the framing as it does for created devices.
+    ///
+    /// A negative or closed descriptor is an error. On error the descriptor is not closed
+    /// and stays with the caller; on success the device owns it and closes it on drop.
+    /// [`into_raw_fd`](std::os::fd::IntoRawFd::into_raw_fd) hands it back, e.g. for the
+    /// Java side to close it.
+    ///
+    /// On Android and iOS, where `DeviceBuilder` is not available, use
+    /// [`SyncDevice::from_fd`].
+    ///
+    /// # Safety
+    /// `fd` must be owned by the caller and must not be used or closed by it afterwards,
+    /// unless it is handed back.
+    #[cfg(unix)]
+    pub unsafe fn build_from_fd(self, fd: std::os::fd::RawFd) -> Result<SyncDevice> {
+        let device = DeviceImpl::adopt_fd(fd)?;
+        #[cfg(target_os = "linux")]
+        {
+            let uses_packet_information = device.flags as libc::c_int & libc::IFF_NO_PI == 0;
+            let mismatch = if self
+                .packet_information
+                .is_some_and(|v| v != uses_packet_information)
+            {
+                Some("packet_information does not match the IFF_NO_PI flag of the descriptor")
+            } else if self.offload.is_some_and(|v| v != device.vnet_hdr) {
+                Some("offload does not match the IFF_VNET_HDR flag of the descriptor")
+            } else {
+                None
+            };
+            if let Some(mismatch) = mismatch {
+                use std::os::fd::IntoRawFd;
+                _ = device.into_raw_fd();
+                return Err(Error::InvalidConfig(mismatch));
+            }
+            if self.offload == Some(true) {
+                if let Err(err) =
+                    device.set_offloads(crate::OffloadFlags::TCP | crate::OffloadFlags::UDP)
+                {
+                    log::warn!("unsupported offload: {err:?}");
+                }
+            }
+        }
+        #[cfg(target_os = "macos")]
+        if let Some(packet_information) = self.packet_information {
+            device.tun.set_ignore_packet_info(!packet_information);
+        }
+        Ok(SyncDevice(device))
+    }
+    /// Like [`build_from_fd`](Self::build_from_fd), for an asynchronous device.
+    ///
+    /// The descriptor is put into non-blocking mode. It is closed if registering it with the
+    /// runtime fails.
+    ///
+    /// # Safety
+    /// See [`build_from_fd`](Self::build_from_fd).
+    #[cfg(all(unix, any(feature = "async_std", feature = "async_tokio")))]
+    pub unsafe fn build_async_from_fd(self, fd: std::os::fd::RawFd) -> Result<crate::AsyncDevice> {
+        let sync_device = self.build_from_fd(fd)?;
+        let device = crate::AsyncDevice::new_dev(sync_device.0)?;
+        Ok(device)
+    }
+}
+
+/// The configuration of a device created with
+/// [`DeviceBuilder::build_sync_unconfigured`], applied on demand.
+#[cfg(feature = "config")]
+pub struct Configurator {
+    builder: DeviceBuilder,
+}
+
+#[cfg(feature = "config")]
+impl Configurator {
+    /// Applies the stored MTU, MAC address, addresses and metric, runs the
+    /// [`on_created`](DeviceBuilder::on_created) hook and finally enables the device.
+    pub fn apply(self, device: &DeviceImpl) -> Result<()> {
+        self.builder.config(device)
+    }
 }
 
 /// Trait for converting various types into an IPv4 address.
@@ -499,3 +1273,354 @@ impl ToIpv6Netmask for &str {
         }
     }
 }
+
+/// Trait for converting an address with its prefix length, such as `"10.0.0.1/24"`, into
+/// an IPv4 address and prefix.
+pub trait ToIpv4Cidr {
+    /// Returns the address and the prefix length, an `InvalidData` error if either is
+    /// invalid.
+    fn ipv4_cidr(&self) -> io::Result<(Ipv4Addr, u8)>;
+}
+impl<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask> ToIpv4Cidr for (IPv4, Netmask) {
+    fn ipv4_cidr(&self) -> io::Result<(Ipv4Addr, u8)> {
+        Ok((self.0.ipv4()?, self.1.prefix()?))
+    }
+}
+impl ToIpv4Cidr for String {
+    fn ipv4_cidr(&self) -> io::Result<(Ipv4Addr, u8)> {
+        self.as_str().ipv4_cidr()
+    }
+}
+impl ToIpv4Cidr for &str {
+    /// Parses `address/prefix`, the prefix may also be given as a netmask.
+    fn ipv4_cidr(&self) -> io::Result<(Ipv4Addr, u8)> {
+        let (address, prefix) = split_cidr(self)?;
+        let prefix = match u8::from_str(prefix) {
+            Ok(prefix) => ToIpv4Netmask::prefix(&prefix)?,
+            Err(_) => ToIpv4Netmask::prefix(&prefix)?,
+        };
+        Ok((address.ipv4()?, prefix))
+    }
+}
+/// Trait for converting an address with its prefix length, such as `"fd00::1/64"`, into an
+/// IPv6 address and prefix.
+pub trait ToIpv6Cidr {
+    /// Returns the address and the prefix length, an `InvalidData` error if either is
+    /// invalid.
+    fn ipv6_cidr(&self) -> io::Result<(Ipv6Addr, u8)>;
+}
+impl<IPv6: ToIpv6Address, Netmask: ToIpv6Netmask> ToIpv6Cidr for (IPv6, Netmask) {
+    fn ipv6_cidr(&self) -> io::Result<(Ipv6Addr, u8)> {
+        Ok((self.0.ipv6()?, self.1.prefix()?))
+    }
+}
+impl ToIpv6Cidr for String {
+    fn ipv6_cidr(&self) -> io::Result<(Ipv6Addr, u8)> {
+        self.as_str().ipv6_cidr()
+    }
+}
+impl ToIpv6Cidr for &str {
+    /// Parses `address/prefix`, the prefix may also be given as a netmask.
+    fn ipv6_cidr(&self) -> io::Result<(Ipv6Addr, u8)> {
+        let (address, prefix) = split_cidr(self)?;
+        let prefix = match u8::from_str(prefix) {
+            Ok(prefix) => ToIpv6Netmask::prefix(&prefix)?,
+            Err(_) => ToIpv6Netmask::prefix(&prefix)?,
+        };
+        Ok((address.ipv6()?, prefix))
+    }
+}
+fn split_cidr(cidr: &str) -> io::Result<(&str, &str)> {
+    cidr.split_once('/').ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid CIDR str, expected address/prefix",
+        )
+    })
+}
+
+/// The IPv4 address family of a [`Prefix`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum V4 {}
+/// The IPv6 address family of a [`Prefix`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum V6 {}
+
+/// An address family a [`Prefix`] can be built for.
+pub trait PrefixFamily {
+    /// The longest prefix of the family.
+    const MAX: u8;
+}
+impl PrefixFamily for V4 {
+    const MAX: u8 = 32;
+}
+impl PrefixFamily for V6 {
+    const MAX: u8 = 128;
+}
+
+/// A prefix length that was checked against its address family when it was built, so using
+/// it as a netmask cannot fail.
+///
+/// Build it from a constant with [`prefix!`](crate::prefix), which rejects out of range
+/// values at compile time, or at runtime with [`Prefix::new`].
+/// ```
+/// let builder = tun_rs::DeviceBuilder::new().ipv4("10.0.0.1", tun_rs::prefix!(24), None);
+/// ```
+pub struct Prefix<V>(u8, std::marker::PhantomData<V>);
+
+impl<V: PrefixFamily> Prefix<V> {
+    /// Returns the prefix, `None` if it is longer than the address family allows.
+    pub const fn new(prefix: u8) -> Option<Self> {
+        if prefix <= V::MAX {
+            Some(Prefix(prefix, std::marker::PhantomData))
+        } else {
+            None
+        }
+    }
+    /// Returns the prefix length.
+    pub const fn get(self) -> u8 {
+        self.0
+    }
+    #[doc(hidden)]
+    pub const fn checked<const N: u8>() -> Self {
+        struct Check<V, const N: u8>(std::marker::PhantomData<V>);
+        impl<V: PrefixFamily, const N: u8> Check<V, N> {
+            const PREFIX: Prefix<V> = match Prefix::new(N) {
+                Some(prefix) => prefix,
+                None => panic!("prefix length out of range for the address family"),
+            };
+        }
+        Check::<V, N>::PREFIX
+    }
+}
+
+// Implemented by hand, deriving would require the uninhabited family types to implement them.
+impl<V> Clone for Prefix<V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<V> Copy for Prefix<V> {}
+impl<V> PartialEq for Prefix<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+impl<V> Eq for Prefix<V> {}
+impl<V> std::hash::Hash for Prefix<V> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+impl<V> std::fmt::Debug for Prefix<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Prefix({})", self.0)
+    }
+}
+
+impl ToIpv4Netmask for Prefix<V4> {
+    fn prefix(&self) -> io::Result<u8> {
+        Ok(self.0)
+    }
+}
+impl ToIpv6Netmask for Prefix<V6> {
+    fn prefix(&self) -> io::Result<u8> {
+        Ok(self.0)
+    }
+}
+
+/// Builds a [`Prefix`] from a constant, failing to compile if it is out of range for the
+/// address family it is used with.
+/// ```compile_fail
+/// let builder = tun_rs::DeviceBuilder::new().ipv4("10.0.0.1", tun_rs::prefix!(33), None);
+/// ```
+#[macro_export]
+macro_rules! prefix {
+    ($prefix:expr) => {
+        $crate::Prefix::checked::<{ $prefix }>()
+    };
+}
+
+#[cfg(all(test, feature = "config"))]
+mod test {
+    use super::DeviceBuilder;
+    use crate::Error;
+
+    #[test]
+    fn rejects_invalid_names() {
+        let err = DeviceBuilder::new().name("").validate().unwrap_err();
+        assert!(matches!(err, Error::InvalidName { .. }));
+        #[cfg(unix)]
+        {
+            let err = DeviceBuilder::new().name("tun 0").validate().unwrap_err();
+            assert!(matches!(err, Error::InvalidName { .. }));
+        }
+        let err = DeviceBuilder::new()
+            .name("a".repeat(200))
+            .validate()
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidName { .. }));
+    }
+
+    #[test]
+    fn prefix_edge_cases() {
+        use super::{ToIpv4Netmask, ToIpv6Netmask};
+        use std::net::{Ipv4Addr, Ipv6Addr};
+
+        for (prefix, netmask) in [
+            (0u8, Ipv4Addr::UNSPECIFIED),
+            (31, Ipv4Addr::new(255, 255, 255, 254)),
+            (32, Ipv4Addr::BROADCAST),
+        ] {
+            assert_eq!(ToIpv4Netmask::netmask(&prefix).unwrap(), netmask);
+            assert_eq!(ToIpv4Netmask::prefix(&netmask).unwrap(), prefix);
+        }
+        assert!(ToIpv4Netmask::prefix(&33u8).is_err());
+
+        for (prefix, netmask) in [
+            (0u8, Ipv6Addr::UNSPECIFIED),
+            (127, Ipv6Addr::from(u128::MAX - 1)),
+            (128, Ipv6Addr::from(u128::MAX)),
+        ] {
+            assert_eq!(ToIpv6Netmask::netmask(&prefix).unwrap(), netmask);
+            assert_eq!(ToIpv6Netmask::prefix(&netmask).unwrap(), prefix);
+        }
+        assert!(ToIpv6Netmask::prefix(&129u8).is_err());
+
+        use super::{Prefix, V4, V6};
+        let v4: Prefix<V4> = crate::prefix!(24);
+        assert_eq!(
+            ToIpv4Netmask::netmask(&v4).unwrap(),
+            Ipv4Addr::new(255, 255, 255, 0)
+        );
+        let v6: Prefix<V6> = crate::prefix!(128);
+        assert_eq!(
+            ToIpv6Netmask::netmask(&v6).unwrap(),
+            Ipv6Addr::from(u128::MAX)
+        );
+        assert!(Prefix::<V4>::new(33).is_none());
+        assert_eq!(Prefix::<V6>::new(64).map(Prefix::get), Some(64));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn rejects_conflicting_linux_options() {
+        use crate::Layer;
+        let err = DeviceBuilder::new()
+            .offload(true)
+            .packet_information(true)
+            .validate()
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)));
+        let err = DeviceBuilder::new()
+            .layer(Layer::L2)
+            .packet_information(true)
+            .validate()
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)));
+        assert!(DeviceBuilder::new().name("tun%d").validate().is_ok());
+        assert!(DeviceBuilder::new().name("tun%s").validate().is_err());
+        for num_queues in [0, 257] {
+            let err = DeviceBuilder::new()
+                .num_queues(num_queues)
+                .validate()
+                .unwrap_err();
+            assert!(matches!(err, Error::InvalidConfig(_)));
+        }
+        let err = DeviceBuilder::new()
+            .multi_queue(false)
+            .num_queues(2)
+            .validate()
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)));
+    }
+
+    #[test]
+    fn rejects_out_of_range_mtu() {
+        let err = DeviceBuilder::new().mtu(100).validate().unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)));
+        assert!(DeviceBuilder::new()
+            .mtu(100)
+            .allow_small_mtu()
+            .validate()
+            .is_ok());
+        #[cfg(not(windows))]
+        {
+            assert!(DeviceBuilder::new().mtu(1000).validate().is_ok());
+            let err = DeviceBuilder::new()
+                .mtu(1000)
+                .ipv6("fd00::1", 64)
+                .validate()
+                .unwrap_err();
+            assert!(matches!(err, Error::InvalidConfig(_)));
+        }
+        #[cfg(windows)]
+        {
+            let err = DeviceBuilder::new().mtu(1000).validate().unwrap_err();
+            assert!(matches!(err, Error::InvalidConfig(_)));
+        }
+        #[cfg(target_os = "freebsd")]
+        {
+            let err = DeviceBuilder::new().mtu(20000).validate().unwrap_err();
+            assert!(matches!(err, Error::InvalidConfig(_)));
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn rejects_invalid_ring_capacity() {
+        for ring_capacity in [0x1_0000, 0x3_0000, 0x800_0000] {
+            let err = DeviceBuilder::new()
+                .ring_capacity(ring_capacity)
+                .validate()
+                .unwrap_err();
+            assert!(matches!(err, Error::InvalidConfig(_)));
+        }
+        assert!(DeviceBuilder::new()
+            .ring_capacity(0x20_0000)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn parses_cidr() {
+        use super::{ToIpv4Cidr, ToIpv6Cidr};
+        use std::io::ErrorKind;
+        use std::net::{Ipv4Addr, Ipv6Addr};
+
+        assert_eq!(
+            (Ipv4Addr::new(192, 168, 1, 10), 24),
+            "192.168.1.10/24".ipv4_cidr().unwrap()
+        );
+        assert_eq!(
+            (Ipv4Addr::new(10, 0, 0, 1), 16),
+            "10.0.0.1/255.255.0.0".to_string().ipv4_cidr().unwrap()
+        );
+        assert_eq!(
+            (Ipv4Addr::new(10, 0, 0, 1), 8),
+            ("10.0.0.1", 8).ipv4_cidr().unwrap()
+        );
+        for invalid in [
+            "10.0.0.1",
+            "10.0.0.1/33",
+            "10.0.0/24",
+            "10.0.0.1/",
+            "fd00::1/64",
+        ] {
+            let err = invalid.ipv4_cidr().unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, err.kind(), "{invalid}");
+        }
+        assert_eq!(
+            ("fd00::1".parse::<Ipv6Addr>().unwrap(), 64),
+            "fd00::1/64".ipv6_cidr().unwrap()
+        );
+        for invalid in ["fd00::1", "fd00::1/129", "fd00::g/64", "10.0.0.1/24"] {
+            let err = invalid.ipv6_cidr().unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, err.kind(), "{invalid}");
+        }
+
+        let builder = DeviceBuilder::new().ipv4_cidr("10.0.0.1/33");
+        let ipv4 = builder.ipv4.unwrap();
+        assert!(ipv4[0].0.is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::io;
+
+/// A specialized `Result` type for device creation and configuration.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors returned by the builder and by device configuration methods.
+///
+/// Packet I/O (`recv`/`send` and friends) keeps returning `std::io::Result` so the device
+/// can be used with I/O traits. Every `Error` converts into an `io::Error` with a matching
+/// [`io::ErrorKind`], and converting such an `io::Error` back recovers the original variant.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// An operating system or I/O error that has no more specific variant.
+    #[error(transparent)]
+    Io(io::Error),
+    /// The wintun driver or its DLL reported a failure (Windows only).
+    #[error("wintun: {0}")]
+    Wintun(String),
+    /// A `netsh` invocation exited unsuccessfully (Windows only).
+    #[error("netsh {op} failed with code {code:?}: {output}")]
+    Netsh {
+        /// The command that was run.
+        op: String,
+        /// The exit code of the process, if any.
+        code: Option<i32>,
+        /// The error output of the process.
+        output: String,
+    },
+    /// The requested configuration is invalid.
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(&'static str),
+    /// The requested device name cannot be used on this platform.
+    #[error("invalid device name {name:?}: {reason}")]
+    InvalidName {
+        /// The offending name.
+        name: String,
+        /// Why the name was rejected.
+        reason: &'static str,
+    },
+    /// The operation is not supported on this platform or by this kind of device.
+    #[error("unsupported: {0}")]
+    Unsupported(&'static str),
+    /// The caller lacks the privileges required for the operation.
+    #[error("permission denied: {0}")]
+    PermissionDenied(io::Error),
+    /// The underlying interface no longer exists.
+    #[error("the device is gone")]
+    DeviceGone,
+    /// Only part of a packet was written to the device.
+    ///
+    /// Packets are all-or-nothing, a partially written packet is never retried.
+    #[error("partial send: {sent} of {expected} bytes written")]
+    PartialSend {
+        /// The number of bytes written.
+        sent: usize,
+        /// The length of the packet.
+        expected: usize,
+    },
+    /// A packet passed to `send` does not start with an IPv4 or IPv6 header where one is
+    /// expected, e.g. because the packet information header is missing.
+    #[error("invalid packet: {0}")]
+    InvalidPacket(&'static str),
+    /// A configuration step of the builder failed on the created device.
+    ///
+    /// [`kind`](Error::kind) reports the kind of `source`.
+    #[error("{op}({value}) on '{device}': {source}")]
+    Config {
+        /// The step that failed, e.g. `set_mtu`.
+        op: &'static str,
+        /// The name of the device, empty if it could not be read.
+        device: String,
+        /// The value that was being applied.
+        value: String,
+        /// The underlying error.
+        source: Box<Error>,
+    },
+    /// A received packet did not fit into the provided buffer.
+    #[error("packet truncated, a buffer of {needed} bytes is needed")]
+    Truncated {
+        /// The size of the packet.
+        needed: usize,
+    },
+}
+
+impl Error {
+    /// Returns the [`io::ErrorKind`] this error maps to.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::Io(e) => e.kind(),
+            Error::Wintun(_) | Error::Netsh { .. } => io::ErrorKind::Other,
+            Error::InvalidConfig(_)
+            | Error::InvalidName { .. }
+            | Error::InvalidPacket(_)
+            | Error::Truncated { .. } => io::ErrorKind::InvalidInput,
+            Error::Unsupported(_) => io::ErrorKind::Unsupported,
+            Error::PermissionDenied(_) => io::ErrorKind::PermissionDenied,
+            Error::DeviceGone => io::ErrorKind::NotFound,
+            Error::PartialSend { .. } => io::ErrorKind::WriteZero,
+            Error::Config { source, .. } => source.kind(),
+        }
+    }
+}
+
+/// Turns a short write of a packet into [`Error::PartialSend`].
+pub(crate) fn check_sent(sent: usize, expected: usize) -> io::Result<usize> {
+    if sent == expected {
+        Ok(sent)
+    } else {
+        Err(Error::PartialSend { sent, expected }.into())
+    }
+}
+
+/// Turns a packet longer than the receive buffer into [`Error::Truncated`].
+#[allow(dead_code)]
+pub(crate) fn check_received(len: usize, capacity: usize) -> io::Result<usize> {
+    if len > capacity {
+        Err(Error::Truncated { needed: len }.into())
+    } else {
+        Ok(len)
+    }
+}
+
+/// Turns a read that filled `buf` with a cut IP packet into [`Error::Truncated`].
+///
+/// Unix kernels silently cut a packet to the buffer and return the buffer length, so a full
+/// buffer is checked against the length in the IP header at `offset`. Without an offset,
+/// e.g. on TAP devices or with a virtio-net header, the packet is returned as read.
+pub(crate) fn check_truncated(buf: &[u8], len: usize, offset: Option<usize>) -> io::Result<usize> {
+    let Some(offset) = offset else {
+        return Ok(len);
+    };
+    if len < buf.len() {
+        return Ok(len);
+    }
+    match buf.get(offset..).and_then(crate::packet::total_len) {
+        Some(total) if offset + total > len => Err(Error::Truncated {
+            needed: offset + total,
+        }
+        .into()),
+        _ => Ok(len),
+    }
+}
+
+/// Replaces an OS error meaning that the interface has disappeared with [`Error::DeviceGone`],
+/// so packet I/O reports removal the same way on every platform.
+pub(crate) fn map_device_gone(err: io::Error) -> io::Error {
+    if err.raw_os_error().is_some_and(is_device_gone) {
+        Error::DeviceGone.into()
+    } else {
+        err
+    }
+}
+
+/// Whether the raw OS error means that the interface has disappeared.
+fn is_device_gone(code: i32) -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        code == libc::ENODEV || code == libc::ENXIO || code == libc::EBADFD
+    }
+    #[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+    {
+        code == libc::ENODEV || code == libc::ENXIO
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Foundation::{ERROR_DEVICE_NOT_CONNECTED, ERROR_DEV_NOT_EXIST};
+        code == ERROR_DEVICE_NOT_CONNECTED as i32 || code == ERROR_DEV_NOT_EXIST as i32
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if err.get_ref().is_some_and(|e| e.is::<Error>()) {
+            let inner = err.into_inner().expect("checked above");
+            return *inner.downcast::<Error>().expect("checked above");
+        }
+        if err.raw_os_error().is_some_and(is_device_gone) {
+            return Error::DeviceGone;
+        }
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            return Error::PermissionDenied(err);
+        }
+        Error::Io(err)
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "android",
+    target_os = "freebsd"
+))]
+impl From<nix::errno::Errno> for Error {
+    fn from(err: nix::errno::Errno) -> Self {
+        io::Error::from(err).into()
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(e) | Error::PermissionDenied(e) => e,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_truncated, Error};
+    use std::io;
+
+    #[test]
+    fn detects_cut_ip_packets() {
+        let mut buf = [0u8; 24];
+        buf[4] = 0x45;
+        buf[6..8].copy_from_slice(&1000u16.to_be_bytes());
+        let err = check_truncated(&buf, 24, Some(4)).unwrap_err();
+        assert!(matches!(
+            Error::from(err),
+            Error::Truncated { needed: 1004 }
+        ));
+        assert_eq!(20, check_truncated(&buf, 20, Some(4)).unwrap());
+        assert_eq!(24, check_truncated(&buf, 24, None).unwrap());
+        buf[6..8].copy_from_slice(&20u16.to_be_bytes());
+        assert_eq!(24, check_truncated(&buf, 24, Some(4)).unwrap());
+    }
+
+    #[test]
+    fn round_trip_through_io_error() {
+        let err: io::Error = Error::Truncated { needed: 1500 }.into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(matches!(
+            Error::from(err),
+            Error::Truncated { needed: 1500 }
+        ));
+
+        let err: io::Error = Error::InvalidConfig("bad").into();
+        assert!(matches!(Error::from(err), Error::InvalidConfig("bad")));
+
+        let err = Error::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(err, Error::PermissionDenied(_)));
+
+        let err = Error::Config {
+            op: "set_mtu",
+            device: "corp0".to_string(),
+            value: "9000".to_string(),
+            source: Box::new(io::Error::from_raw_os_error(libc::EINVAL).into()),
+        };
+        assert!(err.to_string().starts_with("set_mtu(9000) on 'corp0': "));
+        let err: io::Error = err.into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(matches!(
+            Error::from(err),
+            Error::Config { op: "set_mtu", .. }
+        ));
+    }
+}
@@ -58,7 +58,20 @@ pub use async_device::*;
     target_os = "freebsd"
 ))]
 mod builder;
+mod error;
+
+#[cfg(all(target_os = "windows", not(feature = "config")))]
+compile_error!(
+    "the `config` feature is required on Windows, devices can only be created with DeviceBuilder"
+);
+#[cfg_attr(docsrs, doc(cfg(feature = "ndp")))]
+#[cfg(feature = "ndp")]
+pub mod ndp;
+pub mod packet;
 mod platform;
+pub use error::{Error, Result};
+/// The length of the packet information header (`struct tun_pi` on Linux, the address family
+/// on macOS) in front of the packets of devices that use one.
 pub const PACKET_INFORMATION_LENGTH: usize = 4;
 
 /// Runs a command and returns an error if the command fails, just convenience for users.
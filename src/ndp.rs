@@ -0,0 +1,369 @@
+//! Minimal IPv6 neighbor discovery (RFC 4861) helpers for tunnels.
+//!
+//! Packets are plain IPv6 packets for L3 devices, without any packet information header,
+//! or Ethernet frames for L2 devices. Every message is sent with a hop limit of 255 and a
+//! checksum over the IPv6 pseudo-header, as receivers drop anything else.
+//!
+//! ```
+//! use std::net::Ipv6Addr;
+//! use tun_rs::ndp::RouterAdvertisement;
+//!
+//! let ra = RouterAdvertisement::new("fe80::1".parse().unwrap())
+//!     .prefix("2001:db8:1::".parse().unwrap(), 64)
+//!     .mtu(1400)
+//!     .build();
+//! assert_eq!(ra[40], 134);
+//! ```
+
+use crate::{Error, Result, ETHERNET_HEADER_LEN};
+use std::net::Ipv6Addr;
+
+const IPV6_HEADER_LEN: usize = 40;
+const ETHERTYPE_IPV6: [u8; 2] = [0x86, 0xdd];
+const NEXT_HEADER_ICMPV6: u8 = 58;
+const HOP_LIMIT: u8 = 255;
+
+const ROUTER_ADVERTISEMENT: u8 = 134;
+const NEIGHBOR_SOLICITATION: u8 = 135;
+const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+const OPTION_SOURCE_LINK_ADDR: u8 = 1;
+const OPTION_TARGET_LINK_ADDR: u8 = 2;
+const OPTION_PREFIX_INFORMATION: u8 = 3;
+const OPTION_MTU: u8 = 5;
+
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+/// Answers neighbor solicitations.
+pub struct NeighborAdvertisement;
+
+impl NeighborAdvertisement {
+    /// Builds the neighbor advertisement answering `ns_packet`.
+    ///
+    /// With `our_mac` set, `ns_packet` is an Ethernet frame and the reply is a frame from
+    /// `our_mac` carrying it as the target link-layer address. Without it, both are IPv6
+    /// packets as read from and written to an L3 device.
+    ///
+    /// The advertisement is sent from the solicited target address. A solicitation from the
+    /// unspecified address, i.e. duplicate address detection, is answered to all nodes.
+    pub fn reply_to(ns_packet: &[u8], our_mac: Option<[u8; 6]>) -> Result<Vec<u8>> {
+        let (peer_mac, packet) = match our_mac {
+            Some(_) => {
+                let (peer_mac, packet) = split_ethernet(ns_packet)?;
+                (Some(peer_mac), packet)
+            }
+            None => (None, ns_packet),
+        };
+        let (source, _, icmp) = parse_icmpv6(packet)?;
+        if icmp[0] != NEIGHBOR_SOLICITATION || icmp[1] != 0 {
+            return Err(Error::InvalidPacket("not a neighbor solicitation"));
+        }
+        if icmp.len() < 24 {
+            return Err(Error::InvalidPacket("neighbor solicitation too short"));
+        }
+        let target = Ipv6Addr::from(<[u8; 16]>::try_from(&icmp[8..24]).unwrap());
+        if target.is_multicast() {
+            return Err(Error::InvalidPacket("multicast solicitation target"));
+        }
+
+        let solicited = !source.is_unspecified();
+        let destination = if solicited { source } else { ALL_NODES };
+        let mut message = vec![NEIGHBOR_ADVERTISEMENT, 0, 0, 0];
+        // Router = 0, Solicited, Override: our answer is authoritative for the target.
+        message.push(if solicited { 0x60 } else { 0x20 });
+        message.extend_from_slice(&[0, 0, 0]);
+        message.extend_from_slice(&target.octets());
+        if let Some(mac) = our_mac {
+            message.extend_from_slice(&[OPTION_TARGET_LINK_ADDR, 1]);
+            message.extend_from_slice(&mac);
+        }
+        let packet = ipv6_packet(target, destination, message);
+        Ok(match (our_mac, peer_mac) {
+            (Some(mac), Some(peer_mac)) => {
+                let peer_mac = if solicited {
+                    peer_mac
+                } else {
+                    multicast_mac(destination)
+                };
+                ethernet_frame(peer_mac, mac, packet)
+            }
+            _ => packet,
+        })
+    }
+}
+
+/// Builds router advertisements announcing on-link prefixes for SLAAC.
+#[derive(Clone, Debug)]
+pub struct RouterAdvertisement {
+    source: Ipv6Addr,
+    destination: Ipv6Addr,
+    source_mac: Option<[u8; 6]>,
+    hop_limit: u8,
+    managed: bool,
+    other: bool,
+    router_lifetime: u16,
+    reachable_time: u32,
+    retrans_timer: u32,
+    mtu: Option<u32>,
+    prefixes: Vec<(Ipv6Addr, u8)>,
+}
+
+impl RouterAdvertisement {
+    /// Creates an advertisement from `source`, which must be a link-local address of the
+    /// router, sent to all nodes with a router lifetime of 1800 seconds.
+    pub fn new(source: Ipv6Addr) -> Self {
+        Self {
+            source,
+            destination: ALL_NODES,
+            source_mac: None,
+            hop_limit: 64,
+            managed: false,
+            other: false,
+            router_lifetime: 1800,
+            reachable_time: 0,
+            retrans_timer: 0,
+            mtu: None,
+            prefixes: Vec::new(),
+        }
+    }
+    /// Sends the advertisement to `destination` instead of all nodes, e.g. in answer to a
+    /// router solicitation.
+    pub fn destination(mut self, destination: Ipv6Addr) -> Self {
+        self.destination = destination;
+        self
+    }
+    /// Includes `mac` as the source link-layer address. Required for
+    /// [`build_frame`](Self::build_frame).
+    pub fn source_mac(mut self, mac: [u8; 6]) -> Self {
+        self.source_mac = Some(mac);
+        self
+    }
+    /// Sets the hop limit hosts should use for outgoing packets, 0 leaves it unspecified.
+    pub fn hop_limit(mut self, hop_limit: u8) -> Self {
+        self.hop_limit = hop_limit;
+        self
+    }
+    /// Sets the managed (M) and other configuration (O) flags, telling hosts to use DHCPv6.
+    pub fn dhcpv6(mut self, managed: bool, other: bool) -> Self {
+        self.managed = managed;
+        self.other = other;
+        self
+    }
+    /// Sets how long, in seconds, hosts may use the sender as default router. 0 announces
+    /// prefixes without offering a default route.
+    pub fn router_lifetime(mut self, seconds: u16) -> Self {
+        self.router_lifetime = seconds;
+        self
+    }
+    /// Sets the reachable time and retransmission timer in milliseconds, 0 leaves them
+    /// unspecified.
+    pub fn timers(mut self, reachable_time: u32, retrans_timer: u32) -> Self {
+        self.reachable_time = reachable_time;
+        self.retrans_timer = retrans_timer;
+        self
+    }
+    /// Announces the link MTU.
+    pub fn mtu(mut self, mtu: u32) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+    /// Announces an on-link prefix that hosts may autoconfigure addresses from, with
+    /// infinite lifetimes. Host bits of `prefix` are cleared.
+    pub fn prefix(mut self, prefix: Ipv6Addr, prefix_len: u8) -> Self {
+        let prefix_len = prefix_len.min(128);
+        let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+        self.prefixes
+            .push((Ipv6Addr::from(u128::from(prefix) & mask), prefix_len));
+        self
+    }
+    /// Builds the advertisement as an IPv6 packet, for L3 devices.
+    pub fn build(&self) -> Vec<u8> {
+        let mut message = vec![ROUTER_ADVERTISEMENT, 0, 0, 0, self.hop_limit];
+        message.push(((self.managed as u8) << 7) | ((self.other as u8) << 6));
+        message.extend_from_slice(&self.router_lifetime.to_be_bytes());
+        message.extend_from_slice(&self.reachable_time.to_be_bytes());
+        message.extend_from_slice(&self.retrans_timer.to_be_bytes());
+        if let Some(mac) = self.source_mac {
+            message.extend_from_slice(&[OPTION_SOURCE_LINK_ADDR, 1]);
+            message.extend_from_slice(&mac);
+        }
+        if let Some(mtu) = self.mtu {
+            message.extend_from_slice(&[OPTION_MTU, 1, 0, 0]);
+            message.extend_from_slice(&mtu.to_be_bytes());
+        }
+        for (prefix, prefix_len) in &self.prefixes {
+            // On-link (L) and autonomous (A) flags.
+            message.extend_from_slice(&[OPTION_PREFIX_INFORMATION, 4, *prefix_len, 0xc0]);
+            message.extend_from_slice(&u32::MAX.to_be_bytes());
+            message.extend_from_slice(&u32::MAX.to_be_bytes());
+            message.extend_from_slice(&[0; 4]);
+            message.extend_from_slice(&prefix.octets());
+        }
+        ipv6_packet(self.source, self.destination, message)
+    }
+    /// Builds the advertisement as an Ethernet frame, for L2 devices. Fails unless
+    /// [`source_mac`](Self::source_mac) was set.
+    ///
+    /// The frame goes to the multicast MAC of a multicast destination, otherwise to
+    /// `destination_mac`.
+    pub fn build_frame(&self, destination_mac: Option<[u8; 6]>) -> Result<Vec<u8>> {
+        let Some(source_mac) = self.source_mac else {
+            return Err(Error::InvalidConfig(
+                "router advertisement frame needs source_mac",
+            ));
+        };
+        let destination_mac = match destination_mac {
+            _ if self.destination.is_multicast() => multicast_mac(self.destination),
+            Some(mac) => mac,
+            None => return Err(Error::InvalidConfig("unicast destination needs its mac")),
+        };
+        Ok(ethernet_frame(destination_mac, source_mac, self.build()))
+    }
+}
+
+/// Returns the source MAC and the IPv6 packet of an Ethernet frame.
+fn split_ethernet(frame: &[u8]) -> Result<([u8; 6], &[u8])> {
+    if frame.len() < ETHERNET_HEADER_LEN || frame[12..14] != ETHERTYPE_IPV6 {
+        return Err(Error::InvalidPacket("not an IPv6 Ethernet frame"));
+    }
+    Ok((
+        frame[6..12].try_into().unwrap(),
+        &frame[ETHERNET_HEADER_LEN..],
+    ))
+}
+
+/// Validates an ICMPv6 neighbor discovery packet and returns its source, destination and
+/// ICMPv6 message.
+fn parse_icmpv6(packet: &[u8]) -> Result<(Ipv6Addr, Ipv6Addr, &[u8])> {
+    if packet.len() < IPV6_HEADER_LEN + 8 || packet[0] >> 4 != 6 {
+        return Err(Error::InvalidPacket("not an IPv6 packet"));
+    }
+    if packet[6] != NEXT_HEADER_ICMPV6 {
+        return Err(Error::InvalidPacket("not an ICMPv6 packet"));
+    }
+    if packet[7] != HOP_LIMIT {
+        return Err(Error::InvalidPacket(
+            "neighbor discovery hop limit is not 255",
+        ));
+    }
+    let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+    let Some(icmp) = packet[IPV6_HEADER_LEN..].get(..payload_len) else {
+        return Err(Error::InvalidPacket(
+            "IPv6 payload length exceeds the packet",
+        ));
+    };
+    let source = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[8..24]).unwrap());
+    let destination = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[24..40]).unwrap());
+    if checksum(source, destination, icmp) != 0 {
+        return Err(Error::InvalidPacket("bad ICMPv6 checksum"));
+    }
+    Ok((source, destination, icmp))
+}
+
+/// Wraps an ICMPv6 message in an IPv6 header and fills in its checksum.
+fn ipv6_packet(source: Ipv6Addr, destination: Ipv6Addr, mut message: Vec<u8>) -> Vec<u8> {
+    message[2..4].fill(0);
+    let sum = checksum(source, destination, &message);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+
+    let mut packet = Vec::with_capacity(IPV6_HEADER_LEN + message.len());
+    packet.extend_from_slice(&[0x60, 0, 0, 0]);
+    packet.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&[NEXT_HEADER_ICMPV6, HOP_LIMIT]);
+    packet.extend_from_slice(&source.octets());
+    packet.extend_from_slice(&destination.octets());
+    packet.extend_from_slice(&message);
+    packet
+}
+
+fn ethernet_frame(destination: [u8; 6], source: [u8; 6], packet: Vec<u8>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ETHERNET_HEADER_LEN + packet.len());
+    frame.extend_from_slice(&destination);
+    frame.extend_from_slice(&source);
+    frame.extend_from_slice(&ETHERTYPE_IPV6);
+    frame.extend_from_slice(&packet);
+    frame
+}
+
+/// The Ethernet address an IPv6 multicast address maps to (RFC 2464).
+fn multicast_mac(address: Ipv6Addr) -> [u8; 6] {
+    let o = address.octets();
+    [0x33, 0x33, o[12], o[13], o[14], o[15]]
+}
+
+/// The ICMPv6 checksum of `message` over the IPv6 pseudo-header. Returns 0 when verifying
+/// a message that carries a correct checksum.
+fn checksum(source: Ipv6Addr, destination: Ipv6Addr, message: &[u8]) -> u16 {
+    use crate::packet::sum;
+    let pseudo = sum(&source.octets())
+        + sum(&destination.octets())
+        + sum(&(message.len() as u32).to_be_bytes())
+        + u32::from(NEXT_HEADER_ICMPV6);
+    crate::packet::checksum(pseudo, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // Neighbor solicitation from fe80::2 (02:00:00:00:00:02) for fe80::1.
+    const NS_FRAME: &str = "3333ff000001 020000000002 86dd
+        6000000000203aff fe800000000000000000000000000002 ff0200000000000000000001ff000001
+        8700 7a97 00000000 fe800000000000000000000000000001 0101020000000002";
+
+    #[test]
+    fn answers_solicitation_on_l2() {
+        let ns = hex(NS_FRAME);
+        let mac = [0x02, 0, 0, 0, 0, 0x01];
+        let na = NeighborAdvertisement::reply_to(&ns, Some(mac)).unwrap();
+        let expected = hex("020000000002 020000000001 86dd
+            6000000000203aff fe800000000000000000000000000001 fe800000000000000000000000000002
+            8800 181c 60000000 fe800000000000000000000000000001 0201020000000001");
+        assert_eq!(expected, na);
+    }
+
+    #[test]
+    fn answers_solicitation_on_l3() {
+        let ns = hex(NS_FRAME);
+        let na = NeighborAdvertisement::reply_to(&ns[ETHERNET_HEADER_LEN..], None).unwrap();
+        let (source, destination, icmp) = parse_icmpv6(&na).unwrap();
+        assert_eq!("fe80::1".parse::<Ipv6Addr>().unwrap(), source);
+        assert_eq!("fe80::2".parse::<Ipv6Addr>().unwrap(), destination);
+        assert_eq!(NEIGHBOR_ADVERTISEMENT, icmp[0]);
+        assert_eq!(24, icmp.len());
+    }
+
+    #[test]
+    fn rejects_bad_solicitations() {
+        let mut ns = hex(NS_FRAME);
+        ns[ETHERNET_HEADER_LEN + 7] = 64;
+        assert!(NeighborAdvertisement::reply_to(&ns, Some([2, 0, 0, 0, 0, 1])).is_err());
+        let mut ns = hex(NS_FRAME);
+        ns[ETHERNET_HEADER_LEN + 42] ^= 1;
+        assert!(NeighborAdvertisement::reply_to(&ns, Some([2, 0, 0, 0, 0, 1])).is_err());
+    }
+
+    #[test]
+    fn builds_router_advertisement() {
+        let ra = RouterAdvertisement::new("fe80::1".parse().unwrap())
+            .source_mac([0x02, 0, 0, 0, 0, 0x01])
+            .mtu(1400)
+            .prefix("2001:db8:1::5".parse().unwrap(), 64)
+            .build_frame(None)
+            .unwrap();
+        let expected = hex("333300000001 020000000001 86dd
+            6000000000403aff fe800000000000000000000000000001 ff020000000000000000000000000001
+            8600 b5fd 40000708 00000000 00000000
+            0101020000000001 0501000000000578
+            0304 40c0 ffffffff ffffffff 00000000 20010db8000100000000000000000000");
+        assert_eq!(expected, ra);
+    }
+}
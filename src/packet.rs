@@ -0,0 +1,286 @@
+//! Reading and rewriting the TOS byte of IP packets in place.
+//!
+//! The TOS byte of IPv4 and the traffic class of IPv6 hold the DSCP in their upper six bits
+//! and the ECN codepoint in the lower two. Tunnels copy them between the inner packets and
+//! the outer transport, so congestion marks and QoS classes survive encapsulation.
+//!
+//! Every setter needs only the start of the header, up to and including the IPv4 header
+//! checksum, which is updated incrementally (RFC 1624).
+//!
+//! ```
+//! let mut packet = [0u8; 20];
+//! packet[0] = 0x45;
+//! tun_rs::packet::set_dscp(&mut packet, 46).unwrap();
+//! tun_rs::packet::set_ecn(&mut packet, 0b11).unwrap();
+//! assert_eq!(Some(46), tun_rs::packet::dscp(&packet));
+//! assert_eq!(Some(0b11), tun_rs::packet::ecn(&packet));
+//! ```
+
+use crate::{Error, Protocol, Result};
+
+/// How many bytes of the IP header the setters need, up to the IPv4 checksum.
+pub(crate) const TOS_HEADER_LEN: usize = 12;
+
+/// Returns the TOS byte of an IPv4 packet or the traffic class of an IPv6 packet, `None` if
+/// `packet` does not start with an IP header.
+pub fn tos(packet: &[u8]) -> Option<u8> {
+    match Protocol::of(packet)? {
+        Protocol::Ipv4 => packet.get(1).copied(),
+        Protocol::Ipv6 => {
+            let second = packet.get(1)?;
+            Some((packet[0] << 4) | (second >> 4))
+        }
+    }
+}
+
+/// The length of the IP packet according to its header, `None` if `packet` does not start with
+/// an IP header.
+pub(crate) fn total_len(packet: &[u8]) -> Option<usize> {
+    let field = |at: usize| {
+        packet
+            .get(at..at + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+    };
+    match Protocol::of(packet)? {
+        Protocol::Ipv4 => field(2).map(usize::from),
+        Protocol::Ipv6 => field(4).map(|len| 40 + usize::from(len)),
+    }
+}
+
+/// Returns the DSCP of the IP packet, see [`tos`].
+pub fn dscp(packet: &[u8]) -> Option<u8> {
+    tos(packet).map(|tos| tos >> 2)
+}
+
+/// Returns the ECN codepoint of the IP packet, see [`tos`].
+pub fn ecn(packet: &[u8]) -> Option<u8> {
+    tos(packet).map(|tos| tos & 0x03)
+}
+
+/// Sets the TOS byte of an IPv4 packet or the traffic class of an IPv6 packet.
+///
+/// `packet` holds at least the first 12 bytes of the header. The IPv4 header checksum is
+/// updated to match.
+pub fn set_tos(packet: &mut [u8], tos: u8) -> Result<()> {
+    if packet.len() < TOS_HEADER_LEN {
+        return Err(Error::InvalidPacket("too short for an IP header"));
+    }
+    match Protocol::of(packet) {
+        Some(Protocol::Ipv4) => {
+            let old = u16::from_be_bytes([packet[0], packet[1]]);
+            packet[1] = tos;
+            let new = u16::from_be_bytes([packet[0], packet[1]]);
+            let checksum = u16::from_be_bytes([packet[10], packet[11]]);
+            packet[10..12].copy_from_slice(&update_checksum(checksum, old, new).to_be_bytes());
+        }
+        Some(Protocol::Ipv6) => {
+            // The traffic class straddles the first two bytes, after the version nibble.
+            packet[0] = 0x60 | (tos >> 4);
+            packet[1] = (tos << 4) | (packet[1] & 0x0f);
+        }
+        None => return Err(Error::InvalidPacket("not an IPv4 or IPv6 packet")),
+    }
+    Ok(())
+}
+
+/// Sets the DSCP of the IP packet and keeps its ECN bits, see [`set_tos`].
+pub fn set_dscp(packet: &mut [u8], dscp: u8) -> Result<()> {
+    if dscp > 0x3f {
+        return Err(Error::InvalidConfig("DSCP must be below 64"));
+    }
+    let ecn = ecn(packet).ok_or(Error::InvalidPacket("not an IPv4 or IPv6 packet"))?;
+    set_tos(packet, (dscp << 2) | ecn)
+}
+
+/// Sets the ECN codepoint of the IP packet and keeps its DSCP, see [`set_tos`].
+pub fn set_ecn(packet: &mut [u8], ecn: u8) -> Result<()> {
+    if ecn > 0x03 {
+        return Err(Error::InvalidConfig("ECN must be below 4"));
+    }
+    let dscp = dscp(packet).ok_or(Error::InvalidPacket("not an IPv4 or IPv6 packet"))?;
+    set_tos(packet, (dscp << 2) | ecn)
+}
+
+/// Adjusts a ones' complement checksum for a 16-bit word changing from `old` to `new`,
+/// `HC' = ~(~HC + ~m + m')` from RFC 1624.
+fn update_checksum(checksum: u16, old: u16, new: u16) -> u16 {
+    let mut sum = u32::from(!checksum) + u32::from(!old) + u32::from(new);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Builds an IPv4 or IPv6 packet holding a UDP datagram, with valid checksums.
+///
+/// `src` and `dst` are of the same family.
+#[allow(dead_code)]
+pub(crate) fn udp_packet(
+    src: std::net::SocketAddr,
+    dst: std::net::SocketAddr,
+    payload: &[u8],
+) -> Vec<u8> {
+    use std::net::IpAddr;
+    let udp_len = 8 + payload.len();
+    let mut udp = Vec::with_capacity(udp_len);
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+    let mut packet = match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let mut header = vec![0x45, 0];
+            header.extend_from_slice(&((20 + udp_len) as u16).to_be_bytes());
+            // No identification, don't fragment, TTL 64, UDP.
+            header.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+            let checksum = checksum(0, &header);
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+            header
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            let mut header = vec![0x60, 0, 0, 0];
+            header.extend_from_slice(&(udp_len as u16).to_be_bytes());
+            header.extend_from_slice(&[17, 64]);
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+            header
+        }
+        _ => panic!("mixed address families"),
+    };
+    // The pseudo header: both addresses, the protocol and the UDP length.
+    let addresses = if packet.len() == 20 {
+        &packet[12..20]
+    } else {
+        &packet[8..40]
+    };
+    let pseudo = sum(addresses) + 17 + udp_len as u32;
+    let checksum = match checksum(pseudo, &udp) {
+        // Zero means no checksum in UDP.
+        0 => 0xffff,
+        v => v,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&udp);
+    packet
+}
+
+/// Adds up `data` as big endian 16-bit words, padding an odd length with zero.
+pub(crate) fn sum(data: &[u8]) -> u32 {
+    data.chunks(2)
+        .map(|w| u32::from(u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)])))
+        .sum()
+}
+
+/// The ones' complement of the ones' complement sum of `data` and `initial`, the internet
+/// checksum (RFC 1071). `initial` carries the sum of a pseudo-header.
+pub(crate) fn checksum(initial: u32, data: &[u8]) -> u16 {
+    let mut sum = u64::from(initial) + u64::from(sum(data));
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_checksum(header: &[u8]) -> u16 {
+        let mut sum: u32 = header
+            .chunks(2)
+            .map(|w| u32::from(u16::from_be_bytes([w[0], w[1]])))
+            .sum();
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    #[test]
+    fn rewrites_ipv4_dscp_and_checksum() {
+        let mut header = [
+            0x45, 0x01, 0x00, 0x54, 0x12, 0x34, 0x40, 0x00, 0x40, 0x01, 0, 0, 10, 0, 0, 1, 10, 0,
+            0, 2,
+        ];
+        let checksum = ipv4_checksum(&header);
+        header[10..12].copy_from_slice(&checksum.to_be_bytes());
+        set_dscp(&mut header, 46).unwrap();
+        assert_eq!((46 << 2) | 0x01, header[1]);
+        assert_eq!(0, ipv4_checksum(&header));
+    }
+
+    #[test]
+    fn rewrites_ipv6_traffic_class() {
+        let mut header = [0u8; 40];
+        header[..4].copy_from_slice(&[0x60, 0x1a, 0xbc, 0xde]);
+        set_dscp(&mut header, 0x3f).unwrap();
+        assert_eq!([0x6f, 0xda, 0xbc, 0xde], header[..4]);
+        assert_eq!(Some(0xfd), tos(&header));
+        set_ecn(&mut header, 0).unwrap();
+        assert_eq!([0x6f, 0xca, 0xbc, 0xde], header[..4]);
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        assert!(set_dscp(&mut [0x45; 20], 64).is_err());
+        assert!(set_ecn(&mut [0x45; 20], 4).is_err());
+        assert!(set_dscp(&mut [0x45; 8], 0).is_err());
+        assert!(set_tos(&mut [0x00; 20], 0).is_err());
+        assert_eq!(None, dscp(&[0x45]));
+    }
+
+    #[test]
+    fn builds_udp_packets() {
+        let payload = b"hello";
+        let v4 = udp_packet(
+            "10.0.0.1:1000".parse().unwrap(),
+            "10.0.0.2:9".parse().unwrap(),
+            payload,
+        );
+        assert_eq!(33, v4.len());
+        assert_eq!(0, ipv4_checksum(&v4[..20]));
+        let pseudo = sum(&v4[12..20]) + 17 + 13;
+        assert_eq!(0, checksum(pseudo, &v4[20..]));
+        let v6 = udp_packet(
+            "[fd00::1]:1000".parse().unwrap(),
+            "[fd00::2]:9".parse().unwrap(),
+            payload,
+        );
+        assert_eq!(Some(Protocol::Ipv6), Protocol::of(&v6));
+        assert_eq!(53, v6.len());
+        let pseudo = sum(&v6[8..40]) + 17 + 13;
+        assert_eq!(0, checksum(pseudo, &v6[40..]));
+        assert_eq!(payload, &v6[48..]);
+    }
+
+    /// The incremental checksum always matches a full recomputation, over random headers
+    /// and every TOS value.
+    #[test]
+    fn incremental_checksum_matches_full() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..64 {
+            let mut header = [0u8; 20];
+            for byte in header.iter_mut() {
+                *byte = next() as u8;
+            }
+            header[0] = 0x45;
+            header[10..12].copy_from_slice(&[0, 0]);
+            let checksum = ipv4_checksum(&header);
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+            for tos_value in 0..=u8::MAX {
+                set_tos(&mut header, tos_value).unwrap();
+                assert_eq!(Some(tos_value), tos(&header));
+                assert_eq!(0, ipv4_checksum(&header), "{header:02x?}");
+            }
+        }
+    }
+}
@@ -1,18 +1,24 @@
+#[cfg(feature = "config")]
+use crate::builder::DeviceConfig;
 use crate::{
-    builder::{DeviceConfig, Layer},
+    builder::Layer,
     platform::freebsd::sys::*,
     platform::{
         unix::{sockaddr_union, Fd, Tun},
         ETHER_ADDR_LEN,
     },
-    ToIpv4Address, ToIpv4Netmask, ToIpv6Address, ToIpv6Netmask,
+    Error, Result, ToIpv4Address, ToIpv4Netmask, ToIpv6Address, ToIpv6Netmask,
 };
 
 use crate::platform::unix::device::{ctl, ctl_v6};
+use crate::platform::{DnsRestore, IoHook, Mirror, NameCache};
+#[cfg(any(feature = "async_tokio", feature = "async_std"))]
+use crate::platform::{ReadBudget, SendPacer};
 use libc::{
     self, c_char, c_short, fcntl, ifreq, kinfo_file, AF_LINK, F_KINFO, IFF_RUNNING, IFF_UP,
     IFNAMSIZ, KINFO_FILE_SIZE, O_RDWR,
 };
+#[cfg(feature = "config")]
 use mac_address::mac_address_by_name;
 use std::io::ErrorKind;
 use std::{ffi::CStr, io, mem, net::IpAddr, os::unix::io::AsRawFd, ptr, sync::Mutex};
@@ -27,13 +33,22 @@ struct Route {
 
 /// A TUN device using the TUN/TAP Linux driver.
 pub struct DeviceImpl {
+    pub(crate) dns_restore: DnsRestore,
     pub(crate) tun: Tun,
     alias_lock: Mutex<()>,
+    name_cache: NameCache,
+    pub(crate) io_hook: IoHook,
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    pub(crate) send_pacer: SendPacer,
+    pub(crate) mirror: Mirror,
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    pub(crate) read_budget: ReadBudget,
 }
 
 impl DeviceImpl {
     /// Create a new `Device` for the given `Configuration`.
-    pub(crate) fn new(config: DeviceConfig) -> std::io::Result<Self> {
+    #[cfg(feature = "config")]
+    pub(crate) fn new(config: DeviceConfig) -> Result<Self> {
         let layer = config.layer.unwrap_or(Layer::L3);
         let device_prefix = if layer == Layer::L3 {
             "tun".to_string()
@@ -46,23 +61,14 @@ impl DeviceImpl {
                     let tun_name = tun_name.clone();
 
                     if tun_name.len() > IFNAMSIZ {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidInput,
-                            "device name too long",
-                        ));
+                        return Err(Error::InvalidConfig("device name too long"));
                     }
 
                     if layer == Layer::L3 && !tun_name.starts_with("tun") {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidInput,
-                            "device name must start with tun",
-                        ));
+                        return Err(Error::InvalidConfig("device name must start with tun"));
                     }
                     if layer == Layer::L2 && !tun_name.starts_with("tap") {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidInput,
-                            "device name must start with tap",
-                        ));
+                        return Err(Error::InvalidConfig("device name must start with tap"));
                     }
                     Some(
                         tun_name[3..]
@@ -75,7 +81,7 @@ impl DeviceImpl {
                 None => None,
             };
 
-            let (tun, _tun_name) = {
+            let (tun, tun_name) = {
                 if let Some(name_index) = dev_index.as_ref() {
                     let device_name = format!("{}{}", device_prefix, name_index);
                     let device_path = format!("/dev/{}\0", device_name);
@@ -96,24 +102,46 @@ impl DeviceImpl {
                         return Err(std::io::Error::new(
                             std::io::ErrorKind::AlreadyExists,
                             "no available file descriptor",
-                        ));
+                        )
+                        .into());
                     };
                     (tun, device_name)
                 }
             };
 
-            DeviceImpl {
+            let device = DeviceImpl {
+                dns_restore: DnsRestore::default(),
                 tun: Tun::new(tun),
                 alias_lock: Mutex::new(()),
+                name_cache: NameCache::default(),
+                io_hook: IoHook::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                send_pacer: SendPacer::default(),
+                mirror: Mirror::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                read_budget: ReadBudget::default(),
+            };
+            if let Some(prefix) = config.name_prefix.as_ref() {
+                // The kernel only clones tunN/tapN, so keep the unit number under the new prefix.
+                device.set_name(&format!("{prefix}{}", &tun_name[3..]))?;
             }
+            device
         };
 
         Ok(device)
     }
     pub(crate) fn from_tun(tun: Tun) -> Self {
         Self {
+            dns_restore: DnsRestore::default(),
             tun,
             alias_lock: Mutex::new(()),
+            name_cache: NameCache::default(),
+            io_hook: IoHook::default(),
+            #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+            send_pacer: SendPacer::default(),
+            mirror: Mirror::default(),
+            #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+            read_budget: ReadBudget::default(),
         }
     }
     // fn current_route(&self) -> Option<Route> {
@@ -158,7 +186,7 @@ impl DeviceImpl {
                     req.mask = crate::platform::unix::sockaddr_union::from((mask, 0)).addr;
 
                     if let Err(err) = siocaifaddr(ctl.as_raw_fd(), &req) {
-                        return Err(io::Error::from(err));
+                        return Err(err.into());
                     }
                 }
                 IpAddr::V6(_) => {
@@ -178,7 +206,7 @@ impl DeviceImpl {
                     req.in6_addrlifetime.ia6t_pltime = 0xffffffff_u32;
                     req.ifra_flags = IN6_IFF_NODAD;
                     if let Err(err) = siocaifaddr_in6(ctl_v6()?.as_raw_fd(), &req) {
-                        return Err(io::Error::from(err));
+                        return Err(err.into());
                     }
                 }
             }
@@ -280,14 +308,24 @@ impl DeviceImpl {
     //     Ok(())
     // }
 
+    /// Setting DNS servers is not supported on FreeBSD, this fails with
+    /// [`Error::Unsupported`].
+    #[cfg(feature = "config")]
+    pub fn dns_servers(&self) -> Result<Vec<IpAddr>> {
+        Err(Error::Unsupported("DNS servers on FreeBSD"))
+    }
+    #[cfg(feature = "config")]
+    pub(crate) fn dns_setter(&self) -> Result<crate::platform::DnsSetter> {
+        Err(Error::Unsupported("DNS servers on FreeBSD"))
+    }
     /// Retrieves the name of the network interface.
-    pub fn name(&self) -> std::io::Result<String> {
+    pub fn name(&self) -> Result<String> {
         use std::path::PathBuf;
         unsafe {
             let mut path_info: kinfo_file = std::mem::zeroed();
             path_info.kf_structsize = KINFO_FILE_SIZE;
             if fcntl(self.tun.as_raw_fd(), F_KINFO, &mut path_info as *mut _) < 0 {
-                return Err(io::Error::last_os_error());
+                return Err(io::Error::last_os_error().into());
             }
             let dev_path = CStr::from_ptr(path_info.kf_path.as_ptr() as *const c_char)
                 .to_string_lossy()
@@ -304,18 +342,25 @@ impl DeviceImpl {
             Ok(device_name)
         }
     }
+    /// Returns the name of the device without querying the system.
+    ///
+    /// The name is looked up once and then only updated by [`set_name`](Self::set_name),
+    /// so it is cheap enough for hot logging paths. A rename by another process is not
+    /// noticed, use [`name`](Self::name) for the authoritative value.
+    pub fn cached_name(&self) -> Result<std::sync::Arc<str>> {
+        self.name_cache.get_or(|| self.name())
+    }
     /// Sets a new name for the network interface.
-    pub fn set_name(&self, value: &str) -> std::io::Result<()> {
+    #[cfg(feature = "config")]
+    pub fn set_name(&self, value: &str) -> Result<()> {
         use std::ffi::CString;
         unsafe {
             if value.len() > IFNAMSIZ {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    "device name too long",
-                ));
+                return Err(Error::InvalidConfig("device name too long"));
             }
             let mut req = self.request()?;
-            let tun_name = CString::new(value)?;
+            let tun_name = CString::new(value)
+                .map_err(|_| Error::InvalidConfig("device name contains a nul byte"))?;
             let mut tun_name: Vec<i8> = tun_name
                 .into_bytes_with_nul()
                 .into_iter()
@@ -323,19 +368,21 @@ impl DeviceImpl {
                 .collect::<_>();
             req.ifr_ifru.ifru_data = tun_name.as_mut_ptr();
             if let Err(err) = siocsifname(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
+            self.name_cache.set(value);
             Ok(())
         }
     }
     /// Enables or disables the network interface.
-    pub fn enabled(&self, value: bool) -> std::io::Result<()> {
+    #[cfg(feature = "config")]
+    pub fn enabled(&self, value: bool) -> Result<()> {
         unsafe {
             let mut req = self.request()?;
             let ctl = ctl()?;
             if let Err(err) = siocgifflags(ctl.as_raw_fd(), &mut req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
             if value {
@@ -345,7 +392,7 @@ impl DeviceImpl {
             }
 
             if let Err(err) = siocsifflags(ctl.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
             Ok(())
@@ -378,13 +425,33 @@ impl DeviceImpl {
     //         Ok(())
     //     }
     // }
+    /// Sets an explicit IPv4 broadcast address on a TAP interface, replacing the one derived
+    /// from the netmask.
+    ///
+    /// [`set_network_address`](Self::set_network_address) derives it again, so call this
+    /// afterwards. TUN devices have no broadcast address and are rejected with
+    /// [`Error::Unsupported`].
+    #[cfg(feature = "config")]
+    pub fn set_broadcast_address(&self, addr: std::net::Ipv4Addr) -> Result<()> {
+        if self.detect_layer()? != Layer::L2 {
+            return Err(Error::Unsupported("the interface has no broadcast address"));
+        }
+        unsafe {
+            let mut req = self.request()?;
+            req.ifr_ifru.ifru_broadaddr = sockaddr_union::from((IpAddr::V4(addr), 0)).addr;
+            if let Err(err) = siocsifbrdaddr(ctl()?.as_raw_fd(), &req) {
+                return Err(err.into());
+            }
+            Ok(())
+        }
+    }
     /// Retrieves the current MTU (Maximum Transmission Unit) for the interface.
-    pub fn mtu(&self) -> std::io::Result<u16> {
+    pub fn mtu(&self) -> Result<u16> {
         unsafe {
             let mut req = self.request()?;
 
             if let Err(err) = siocgifmtu(ctl()?.as_raw_fd(), &mut req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
             let r: u16 = req
@@ -396,24 +463,53 @@ impl DeviceImpl {
         }
     }
     /// Sets the MTU (Maximum Transmission Unit) for the interface.
-    pub fn set_mtu(&self, value: u16) -> std::io::Result<()> {
+    ///
+    /// Values below 576, or below 1280 while the interface has an IPv6 address, are rejected
+    /// with [`Error::InvalidConfig`], use [`set_mtu_unchecked`](Self::set_mtu_unchecked) to
+    /// apply them anyway. Fails if the OS applied a different value than requested.
+    #[cfg(feature = "config")]
+    pub fn set_mtu(&self, value: u16) -> Result<()> {
+        let ipv6 = self.addresses()?.iter().any(|addr| addr.is_ipv6());
+        crate::platform::check_mtu(value, ipv6, false)?;
+        self.set_mtu_unchecked(value)
+    }
+    /// Sets the MTU without the lower bounds checked by [`set_mtu`](Self::set_mtu).
+    #[cfg(feature = "config")]
+    pub fn set_mtu_unchecked(&self, value: u16) -> Result<()> {
         unsafe {
             let mut req = self.request()?;
             req.ifr_ifru.ifru_mtu = value as i32;
 
             if let Err(err) = siocsifmtu(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
+            }
+        }
+        crate::platform::check_mtu_applied(value, self.mtu()?)
+    }
+    /// Sets the interface metric.
+    ///
+    /// The kernel does not use the metric for route selection, it is exported to routing
+    /// daemons and tools such as `ifconfig`.
+    #[cfg(feature = "config")]
+    pub fn set_metric(&self, metric: u16) -> Result<()> {
+        unsafe {
+            let mut req = self.request()?;
+            req.ifr_ifru.ifru_metric = metric as _;
+
+            if let Err(err) = siocsifmetric(ctl()?.as_raw_fd(), &req) {
+                return Err(err.into());
             }
             Ok(())
         }
     }
     /// Sets the IPv4 network address, netmask, and an optional destination address.
+    #[cfg(feature = "config")]
     pub fn set_network_address<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
         &self,
         address: IPv4,
         netmask: Netmask,
         destination: Option<IPv4>,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
         let addr = address.ipv4()?.into();
         let netmask = netmask.netmask()?.into();
         let default_dest = self.calc_dest_addr(addr, netmask)?;
@@ -425,22 +521,34 @@ impl DeviceImpl {
         self.set_alias(addr, dest, netmask)?;
         Ok(())
     }
-    /// Removes an IP address from the interface.
-    pub fn remove_address(&self, addr: IpAddr) -> io::Result<()> {
+    /// Adds an IPv4 address to the interface. `SIOCAIFADDR` keeps the addresses already
+    /// assigned, so this is the same as [`set_network_address`](Self::set_network_address).
+    #[cfg(feature = "config")]
+    pub fn add_address_v4<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
+        &self,
+        address: IPv4,
+        netmask: Netmask,
+        destination: Option<IPv4>,
+    ) -> Result<()> {
+        self.set_network_address(address, netmask, destination)
+    }
+    /// Removes an IP address from the interface without checking that it is assigned.
+    #[cfg(feature = "config")]
+    pub(crate) fn remove_address_unchecked(&self, addr: IpAddr) -> Result<()> {
         unsafe {
             match addr {
                 IpAddr::V4(addr) => {
                     let mut req_v4 = self.request()?;
                     req_v4.ifr_ifru.ifru_addr = sockaddr_union::from((addr, 0)).addr;
                     if let Err(err) = siocdifaddr(ctl()?.as_raw_fd(), &req_v4) {
-                        return Err(io::Error::from(err));
+                        return Err(err.into());
                     }
                 }
                 IpAddr::V6(addr) => {
                     let mut req_v6 = self.request_v6()?;
                     req_v6.ifr_ifru.ifru_addr = sockaddr_union::from((addr, 0)).addr6;
                     if let Err(err) = siocdifaddr_in6(ctl_v6()?.as_raw_fd(), &req_v6) {
-                        return Err(io::Error::from(err));
+                        return Err(err.into());
                     }
                 }
             }
@@ -448,11 +556,12 @@ impl DeviceImpl {
         }
     }
     /// Adds an IPv6 address to the interface.
+    #[cfg(feature = "config")]
     pub fn add_address_v6<IPv6: ToIpv6Address, Netmask: ToIpv6Netmask>(
         &self,
         addr: IPv6,
         netmask: Netmask,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
         let addr = addr.ipv6()?;
         unsafe {
             let tun_name = self.name()?;
@@ -471,7 +580,7 @@ impl DeviceImpl {
             req.in6_addrlifetime.ia6t_pltime = 0xffffffff_u32;
             req.ifra_flags = IN6_IFF_NODAD;
             if let Err(err) = siocaifaddr_in6(ctl_v6()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             let Ok(dest) = self.calc_dest_addr(addr.into(), mask) else {
                 return Ok(());
@@ -490,8 +599,11 @@ impl DeviceImpl {
     ///
     /// This function constructs an interface request and copies the provided MAC address
     /// into the hardware address field. It then applies the change via a system call.
-    /// This operation is typically supported only for TAP devices.
-    pub fn set_mac_address(&self, eth_addr: [u8; ETHER_ADDR_LEN as usize]) -> std::io::Result<()> {
+    /// This operation is typically supported only for TAP devices. All-zero and multicast
+    /// addresses are rejected with [`Error::InvalidConfig`].
+    #[cfg(feature = "config")]
+    pub fn set_mac_address(&self, eth_addr: [u8; ETHER_ADDR_LEN as usize]) -> Result<()> {
+        crate::platform::check_mac(&eth_addr)?;
         unsafe {
             let mut req = self.request()?;
             req.ifr_ifru.ifru_addr.sa_len = ETHER_ADDR_LEN;
@@ -499,7 +611,7 @@ impl DeviceImpl {
             req.ifr_ifru.ifru_addr.sa_data[0..ETHER_ADDR_LEN as usize]
                 .copy_from_slice(eth_addr.map(|c| c as i8).as_slice());
             if let Err(err) = siocsiflladdr(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             Ok(())
         }
@@ -508,7 +620,8 @@ impl DeviceImpl {
     ///
     /// This function queries the MAC address by the interface name using a helper function.
     /// An error is returned if the MAC address cannot be found.
-    pub fn mac_address(&self) -> std::io::Result<[u8; ETHER_ADDR_LEN as usize]> {
+    #[cfg(feature = "config")]
+    pub fn mac_address(&self) -> Result<[u8; ETHER_ADDR_LEN as usize]> {
         let mac = mac_address_by_name(&self.name()?)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
             .ok_or(std::io::Error::new(
@@ -517,6 +630,19 @@ impl DeviceImpl {
             ))?;
         Ok(mac.bytes())
     }
+    /// Determines whether the interface operates at L2 or L3 from the interface type,
+    /// `IFT_ETHER` for tap and `IFT_PROPVIRTUAL` for tun devices.
+    ///
+    /// Meant for devices adopted from a raw fd, whose layer is not known otherwise.
+    pub fn detect_layer(&self) -> Result<Layer> {
+        const IFT_ETHER: u8 = 0x06;
+        let data = crate::platform::unix::device::link_data(&self.name()?)?;
+        if data.ifi_type == IFT_ETHER {
+            Ok(Layer::L2)
+        } else {
+            Ok(Layer::L3)
+        }
+    }
 }
 
 impl From<Layer> for c_short {
@@ -1,5 +1,8 @@
+// Without `config` only the I/O path is reachable, the configuration helpers stay unused.
+#[cfg_attr(not(feature = "config"), allow(dead_code, unused_imports))]
 mod sys;
 
+#[cfg_attr(not(feature = "config"), allow(dead_code, unused_imports))]
 mod device;
 
 pub use self::device::DeviceImpl;
@@ -219,6 +219,8 @@ ioctl_readwrite!(siocgifnetmask, b'i', 37, ifreq);
 ioctl_write_ptr!(siocsifmtu, b'i', 52, ifreq);
 ioctl_readwrite!(siocgifmtu, b'i', 51, ifreq);
 
+ioctl_write_ptr!(siocsifmetric, b'i', 24, ifreq);
+
 ioctl_write_ptr!(siocaifaddr, b'i', 43, ifaliasreq);
 ioctl_write_ptr!(siocdifaddr, b'i', 25, ifreq);
 
@@ -1,55 +1,84 @@
-use crate::platform::linux::offload::{
-    gso_none_checksum, gso_split, handle_gro, VirtioNetHdr, VIRTIO_NET_HDR_F_NEEDS_CSUM,
-    VIRTIO_NET_HDR_GSO_NONE, VIRTIO_NET_HDR_GSO_TCPV4, VIRTIO_NET_HDR_GSO_TCPV6,
-    VIRTIO_NET_HDR_GSO_UDP_L4, VIRTIO_NET_HDR_LEN,
-};
+#[cfg(feature = "config")]
+use crate::builder::DeviceConfig;
+use crate::platform::linux::offload::{handle_gro, OffloadFlags, VirtioNetHdr, VIRTIO_NET_HDR_LEN};
+use crate::platform::linux::teardown::Applied;
+#[cfg(feature = "config")]
+use crate::platform::linux::teardown::{TeardownReport, TeardownStep};
 use crate::platform::unix::device::{ctl, ctl_v6};
-use crate::platform::{ExpandBuffer, GROTable};
+use crate::platform::{
+    DnsRestore, ExpandBuffer, GROTable, IoEvent, IoHook, Mirror, NameCache, Protocol,
+};
+#[cfg(any(feature = "async_tokio", feature = "async_std"))]
+use crate::platform::{ReadBudget, SendPacer};
 use crate::{
-    builder::{DeviceConfig, Layer},
+    builder::Layer,
     platform::linux::sys::*,
     platform::{
         unix::{ipaddr_to_sockaddr, sockaddr_union, Fd, Tun},
         ETHER_ADDR_LEN,
     },
-    ToIpv4Address, ToIpv4Netmask, ToIpv6Address, ToIpv6Netmask,
+    Error, Result, ToIpv4Address, ToIpv4Netmask, ToIpv6Address, ToIpv6Netmask,
 };
 use libc::{
-    self, c_char, c_short, ifreq, in6_ifreq, ARPHRD_ETHER, IFF_MULTI_QUEUE, IFF_NO_PI, IFF_RUNNING,
-    IFF_TAP, IFF_TUN, IFF_UP, IFNAMSIZ, O_RDWR,
+    self, c_char, c_short, ifreq, in6_ifreq, ARPHRD_ETHER, ARPHRD_NONE, IFF_ATTACH_QUEUE,
+    IFF_BROADCAST, IFF_DETACH_QUEUE, IFF_MULTI_QUEUE, IFF_NO_PI, IFF_RUNNING, IFF_TAP, IFF_TUN,
+    IFF_TUN_EXCL, IFF_UP, IFNAMSIZ, O_RDWR,
 };
+#[cfg(feature = "config")]
 use mac_address::mac_address_by_name;
 use std::net::Ipv6Addr;
 use std::{
     ffi::CString,
+    fs::File,
     io, mem,
     net::{IpAddr, Ipv4Addr},
+    ops::Range,
     os::unix::io::{AsRawFd, RawFd},
     ptr,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    sync::OnceLock,
+    time::SystemTime,
 };
 
 const OVERWRITE_SIZE: usize = mem::size_of::<libc::__c_anonymous_ifr_ifru>();
 
 /// A TUN device using the TUN/TAP Linux driver.
 pub struct DeviceImpl {
+    pub(crate) dns_restore: DnsRestore,
     pub(crate) tun: Tun,
     pub(crate) vnet_hdr: bool,
-    pub(crate) udp_gso: bool,
-    flags: c_short,
+    /// The [`OffloadFlags`] last accepted by `TUNSETOFFLOAD`.
+    offloads: AtomicU32,
+    /// The flags passed to `TUNSETIFF`, read with `TUNGETIFF` for adopted descriptors and 0
+    /// for devices created with `from_fd`.
+    pub(crate) flags: c_short,
+    pub(crate) cleanup_on_drop: bool,
+    /// Whether `enabled(true)` on this device took the interface from down to up.
+    brought_up: AtomicBool,
+    name_cache: NameCache,
+    pub(crate) io_hook: IoHook,
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    pub(crate) send_pacer: SendPacer,
+    pub(crate) mirror: Mirror,
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    pub(crate) read_budget: ReadBudget,
+    /// The routes and addresses applied through this device, undone by the teardown.
+    applied: Applied,
+    /// The lock file taken by `config_lock`, held until the device is dropped.
+    config_lock: OnceLock<File>,
 }
 
 impl DeviceImpl {
     /// Create a new `Device` for the given `Configuration`.
-    pub(crate) fn new(config: DeviceConfig) -> std::io::Result<Self> {
+    #[cfg(feature = "config")]
+    pub(crate) fn new(config: DeviceConfig) -> Result<Self> {
         let dev_name = match config.dev_name.as_ref() {
             Some(tun_name) => {
-                let tun_name = CString::new(tun_name.clone())?;
+                let tun_name = CString::new(tun_name.clone())
+                    .map_err(|_| Error::InvalidConfig("device name contains a nul byte"))?;
 
                 if tun_name.as_bytes_with_nul().len() > IFNAMSIZ {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::InvalidInput,
-                        "device name too long",
-                    ));
+                    return Err(Error::InvalidConfig("device name too long"));
                 }
 
                 Some(tun_name)
@@ -74,109 +103,174 @@ impl DeviceImpl {
             let iff_multi_queue = IFF_MULTI_QUEUE as c_short;
             let packet_information = config.packet_information.unwrap_or(false);
             let offload = config.offload.unwrap_or(false);
+            let extra_flags = config.extra_tun_flags.unwrap_or(0) as c_short;
             req.ifr_ifru.ifru_flags = device_type
                 | if packet_information { 0 } else { iff_no_pi }
                 | if multi_queue { iff_multi_queue } else { 0 }
-                | if offload { iff_vnet_hdr } else { 0 };
+                | if offload { iff_vnet_hdr } else { 0 }
+                | extra_flags;
 
             let fd = libc::open(c"/dev/net/tun".as_ptr() as *const _, O_RDWR, 0);
             let tun_fd = Fd::new(fd)?;
             if let Err(err) = tunsetiff(tun_fd.inner, &mut req as *mut _ as *mut _) {
-                return Err(io::Error::from(err));
+                if err == nix::errno::Errno::EBUSY && extra_flags & IFF_TUN_EXCL as c_short != 0 {
+                    return Err(Error::InvalidConfig(
+                        "the interface already exists and IFF_TUN_EXCL was requested",
+                    ));
+                }
+                return Err(err.into());
             }
-            let (vnet_hdr, udp_gso) = if offload && libc::IFF_VNET_HDR != 0 {
+            let (vnet_hdr, offloads) = if offload && libc::IFF_VNET_HDR != 0 {
                 // tunTCPOffloads were added in Linux v2.6. We require their support if IFF_VNET_HDR is set.
-                let tun_tcp_offloads = libc::TUN_F_CSUM | libc::TUN_F_TSO4 | libc::TUN_F_TSO6;
-                let tun_udp_offloads = libc::TUN_F_USO4 | libc::TUN_F_USO6;
-                if let Err(err) = tunsetoffload(tun_fd.inner, tun_tcp_offloads as _) {
-                    log::warn!("unsupported offload: {err:?}");
-                    (false, false)
-                } else {
-                    // tunUDPOffloads were added in Linux v6.2. We do not return an
-                    // error if they are unsupported at runtime.
-                    let rs =
-                        tunsetoffload(tun_fd.inner, (tun_tcp_offloads | tun_udp_offloads) as _);
-                    (true, rs.is_ok())
+                match negotiate_offloads(tun_fd.inner, OffloadFlags::TCP | OffloadFlags::UDP) {
+                    Ok(offloads) => (true, offloads),
+                    Err(err) => {
+                        log::warn!("unsupported offload: {err:?}");
+                        (false, OffloadFlags::empty())
+                    }
                 }
             } else {
-                (false, false)
+                (false, OffloadFlags::empty())
             };
 
             let device = DeviceImpl {
+                dns_restore: DnsRestore::default(),
                 tun: Tun::new(tun_fd),
                 vnet_hdr,
-                udp_gso,
+                offloads: AtomicU32::new(offloads.bits()),
                 flags: req.ifr_ifru.ifru_flags,
+                cleanup_on_drop: config.cleanup_on_drop.unwrap_or(false),
+                brought_up: AtomicBool::new(false),
+                name_cache: NameCache::default(),
+                io_hook: IoHook::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                send_pacer: SendPacer::default(),
+                mirror: Mirror::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                read_budget: ReadBudget::default(),
+                applied: Applied::default(),
+                config_lock: OnceLock::new(),
             };
             Ok(device)
         }
     }
-    unsafe fn set_tcp_offloads(&self) -> io::Result<()> {
-        let tun_tcp_offloads = libc::TUN_F_CSUM | libc::TUN_F_TSO4 | libc::TUN_F_TSO6;
-        tunsetoffload(self.as_raw_fd(), tun_tcp_offloads as _)
-            .map(|_| ())
-            .map_err(|e| e.into())
-    }
-    unsafe fn set_tcp_udp_offloads(&self) -> io::Result<()> {
-        let tun_tcp_offloads = libc::TUN_F_CSUM | libc::TUN_F_TSO4 | libc::TUN_F_TSO6;
-        let tun_udp_offloads = libc::TUN_F_USO4 | libc::TUN_F_USO6;
-        tunsetoffload(self.as_raw_fd(), (tun_tcp_offloads | tun_udp_offloads) as _)
-            .map(|_| ())
-            .map_err(|e| e.into())
-    }
     pub(crate) fn from_tun(tun: Tun) -> Self {
         Self {
+            dns_restore: DnsRestore::default(),
             tun,
             vnet_hdr: false,
-            udp_gso: false,
+            offloads: AtomicU32::new(0),
             flags: 0,
+            cleanup_on_drop: false,
+            brought_up: AtomicBool::new(false),
+            name_cache: NameCache::default(),
+            io_hook: IoHook::default(),
+            #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+            send_pacer: SendPacer::default(),
+            mirror: Mirror::default(),
+            #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+            read_budget: ReadBudget::default(),
+            applied: Applied::default(),
+            config_lock: OnceLock::new(),
         }
     }
 
+    /// Takes over the TUN/TAP descriptor `fd` with the flags of its queue, so that the
+    /// framing (`IFF_NO_PI`, `IFF_VNET_HDR`) matches the interface.
+    ///
+    /// # Safety
+    /// `fd` must be an open descriptor owned by the caller.
+    pub(crate) unsafe fn from_tun_fd(fd: RawFd) -> io::Result<Self> {
+        let mut req: ifreq = mem::zeroed();
+        if let Err(err) = tungetiff(fd, &mut req as *mut _ as *mut _) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("file descriptor {fd} is not a TUN/TAP queue: {err}"),
+            ));
+        }
+        let flags = req.ifr_ifru.ifru_flags;
+        let mut device = DeviceImpl::from_fd(fd);
+        device.flags = flags;
+        device.vnet_hdr = flags as libc::c_int & libc::IFF_VNET_HDR != 0;
+        Ok(device)
+    }
+
     /// # Prerequisites
     /// - The `IFF_MULTI_QUEUE` flag must be enabled.
     /// - The system must support network interface multi-queue functionality.
     ///
     /// # Description
     /// When multi-queue is enabled, create a new queue by duplicating an existing one.
-    pub fn try_clone(&self) -> io::Result<DeviceImpl> {
+    pub fn try_clone(&self) -> Result<DeviceImpl> {
         let flags = self.flags;
         if flags & (IFF_MULTI_QUEUE as c_short) != IFF_MULTI_QUEUE as c_short {
-            return Err(io::Error::new(
-                io::ErrorKind::Unsupported,
-                "iff_multi_queue not enabled",
-            ));
+            return Err(Error::Unsupported("iff_multi_queue not enabled"));
         }
         unsafe {
             let mut req = self.request()?;
-            req.ifr_ifru.ifru_flags = flags;
+            // The queue attaches to the existing interface, which IFF_TUN_EXCL refuses.
+            req.ifr_ifru.ifru_flags = flags & !(IFF_TUN_EXCL as c_short);
             let fd = libc::open(c"/dev/net/tun".as_ptr() as *const _, O_RDWR);
             let tun_fd = Fd::new(fd)?;
             if let Err(err) = tunsetiff(tun_fd.inner, &mut req as *mut _ as *mut _) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             let dev = DeviceImpl {
+                dns_restore: DnsRestore::default(),
                 tun: Tun::new(tun_fd),
                 vnet_hdr: self.vnet_hdr,
-                udp_gso: self.udp_gso,
+                offloads: AtomicU32::new(self.offloads.load(Ordering::Relaxed)),
                 flags,
+                cleanup_on_drop: false,
+                brought_up: AtomicBool::new(false),
+                name_cache: NameCache::default(),
+                io_hook: IoHook::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                send_pacer: SendPacer::default(),
+                mirror: Mirror::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                read_budget: ReadBudget::default(),
+                applied: Applied::default(),
+                config_lock: OnceLock::new(),
             };
             if dev.vnet_hdr {
-                if dev.udp_gso {
-                    dev.set_tcp_udp_offloads()?
-                } else {
-                    dev.set_tcp_offloads()?;
-                }
+                tunsetoffload(dev.as_raw_fd(), dev.active_offloads().bits() as _)
+                    .map_err(io::Error::from)?;
             }
 
             Ok(dev)
         }
     }
+    /// Attaches this queue to the interface or detaches it from it (`TUNSETQUEUE`).
+    ///
+    /// The kernel only spreads packets over the attached queues, so a worker that is idle
+    /// can park its queue and attach it again when it resumes, without closing it. The
+    /// descriptor of a detached queue stays usable: the interface can still be queried
+    /// through it, e.g. with [`name`](Self::name), and packets written to it are still sent,
+    /// but the kernel no longer hands it received packets.
+    ///
+    /// Fails with [`Error::Unsupported`] unless the device was created with
+    /// [`multi_queue`](crate::DeviceBuilder::multi_queue).
+    pub fn set_queue_enabled(&self, enabled: bool) -> Result<()> {
+        if self.flags & (IFF_MULTI_QUEUE as c_short) == 0 {
+            return Err(Error::Unsupported("iff_multi_queue not enabled"));
+        }
+        unsafe {
+            let mut req: ifreq = mem::zeroed();
+            req.ifr_ifru.ifru_flags = if enabled {
+                IFF_ATTACH_QUEUE
+            } else {
+                IFF_DETACH_QUEUE
+            } as c_short;
+            tunsetqueue(self.as_raw_fd(), &req as *const _ as *const _).map_err(io::Error::from)?;
+        }
+        Ok(())
+    }
     /// Returns whether UDP Generic Segmentation Offload (GSO) is enabled.
     ///
-    /// This is determined by the `udp_gso` flag in the device.
+    /// This is determined by the [`active_offloads`](Self::active_offloads).
     pub fn udp_gso(&self) -> bool {
-        self.udp_gso
+        self.active_offloads().intersects(OffloadFlags::UDP)
     }
     /// Returns whether TCP Generic Segmentation Offload (GSO) is enabled.
     ///
@@ -184,18 +278,119 @@ impl DeviceImpl {
     pub fn tcp_gso(&self) -> bool {
         self.vnet_hdr
     }
+    /// Checks whether offload is active on the file descriptor right now.
+    ///
+    /// [`tcp_gso`](Self::tcp_gso) only reports what was negotiated when the device was
+    /// created. This additionally asks the kernel whether `IFF_VNET_HDR` is still set on the
+    /// queue, as `recv_multiple`/`send_multiple` parse every packet as starting with a
+    /// virtio-net header. The kernel has no way to read back the `TUNSETOFFLOAD` features,
+    /// so those are taken from the negotiation.
+    pub fn offload_enabled(&self) -> Result<bool> {
+        if !self.vnet_hdr {
+            return Ok(false);
+        }
+        let mut req: ifreq = unsafe { mem::zeroed() };
+        unsafe {
+            if let Err(err) = tungetiff(self.as_raw_fd(), &mut req as *mut _ as *mut _) {
+                return Err(err.into());
+            }
+        }
+        let flags = unsafe { req.ifr_ifru.ifru_flags } as libc::c_int;
+        Ok(flags & libc::IFF_VNET_HDR != 0)
+    }
+    /// Returns the offloads the kernel accepted last, empty without offload.
+    ///
+    /// `offload(true)` asks for [`OffloadFlags::TCP`] and [`OffloadFlags::UDP`] and falls
+    /// back to the TCP offloads on kernels before 6.2, which reject USO. The kernel cannot
+    /// report the offloads of a device, so this is what `TUNSETOFFLOAD` accepted when the
+    /// device was created or in the last [`set_offloads`](Self::set_offloads);
+    /// [`offload_enabled`](Self::offload_enabled) checks the queue itself.
+    ///
+    /// `send_multiple` only coalesces UDP packets while USO is active.
+    pub fn active_offloads(&self) -> OffloadFlags {
+        OffloadFlags::from_bits_retain(self.offloads.load(Ordering::Relaxed))
+    }
+    /// Changes the offloads of a device created with offload and returns those the kernel
+    /// accepted.
+    ///
+    /// The UDP offloads are dropped if the kernel rejects them. A change is logged and
+    /// reported as [`IoEvent::OffloadsChanged`] to the hook set with
+    /// [`SyncDevice::set_io_hook`](crate::SyncDevice::set_io_hook). The offloads belong to
+    /// the interface, so they change for all its queues, but only this queue sees the change.
+    pub fn set_offloads(&self, offloads: OffloadFlags) -> Result<OffloadFlags> {
+        if !self.vnet_hdr {
+            return Err(Error::Unsupported("the device was created without offload"));
+        }
+        let accepted = negotiate_offloads(self.as_raw_fd(), offloads)?;
+        let old = self.offloads.swap(accepted.bits(), Ordering::Relaxed);
+        if old != accepted.bits() {
+            log::info!(
+                "offloads changed from {:?} to {accepted:?}",
+                OffloadFlags::from_bits_retain(old)
+            );
+            self.io_hook
+                .emit(IoEvent::OffloadsChanged { offloads: accepted });
+        }
+        Ok(accepted)
+    }
+    /// Drops every frame sent to the TAP device that is not of `protocol`, `None` removes the
+    /// filter.
+    ///
+    /// The filter is a classic BPF program attached with `TUNATTACHFILTER`, matching the
+    /// EtherType of the frame in the kernel, so the other family and non-IP frames such as
+    /// ARP never reach `recv`. The kernel keeps one filter for the whole device: it covers
+    /// every queue, including queues opened later with [`try_clone`](Self::try_clone), and
+    /// setting it through one queue replaces it for all of them. Different families on
+    /// different queues need an eBPF steering program attached with `TUNSETSTEERINGEBPF`
+    /// instead.
+    ///
+    /// The kernel only filters TAP devices, on a TUN device an error of kind
+    /// `Unsupported` is returned.
+    pub fn set_protocol_filter(&self, protocol: Option<Protocol>) -> Result<()> {
+        const SKF_AD_PROTOCOL: u32 = (-0x1000i32) as u32;
+        if self.flags & IFF_TAP as c_short == 0 {
+            return Err(Error::Unsupported("protocol filters need a TAP device"));
+        }
+        let ether_type = match protocol {
+            Some(Protocol::Ipv4) => libc::ETH_P_IP,
+            Some(Protocol::Ipv6) => libc::ETH_P_IPV6,
+            None => {
+                let prog: libc::sock_fprog = unsafe { mem::zeroed() };
+                unsafe { tundetachfilter(self.as_raw_fd(), &prog) }.map_err(io::Error::from)?;
+                return Ok(());
+            }
+        };
+        let filter = |code, jt, jf, k| libc::sock_filter { code, jt, jf, k };
+        let mut program = [
+            // ldh [protocol]: the EtherType of the frame in host byte order
+            filter(0x28, 0, 0, SKF_AD_PROTOCOL),
+            // jeq #ether_type, accept, drop
+            filter(0x15, 0, 1, ether_type as u32),
+            // ret #-1: accept the whole packet
+            filter(0x06, 0, 0, u32::MAX),
+            // ret #0: drop
+            filter(0x06, 0, 0, 0),
+        ];
+        let prog = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_mut_ptr(),
+        };
+        unsafe { tunattachfilter(self.as_raw_fd(), &prog) }.map_err(io::Error::from)?;
+        Ok(())
+    }
     /// Sets the transmit queue length for the network interface.
     ///
     /// This method constructs an interface request (`ifreq`) structure,
     /// assigns the desired transmit queue length to the `ifru_metric` field,
     /// and calls the `change_tx_queue_len` function using the control file descriptor.
     /// If the underlying operation fails, an I/O error is returned.
-    pub fn set_tx_queue_len(&self, tx_queue_len: u32) -> io::Result<()> {
+    #[cfg(feature = "config")]
+    pub fn set_tx_queue_len(&self, tx_queue_len: u32) -> Result<()> {
         unsafe {
             let mut ifreq = self.request()?;
             ifreq.ifr_ifru.ifru_metric = tx_queue_len as _;
             if let Err(err) = change_tx_queue_len(ctl()?.as_raw_fd(), &ifreq) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
         }
         Ok(())
@@ -204,31 +399,39 @@ impl DeviceImpl {
     ///
     /// This function constructs an interface request structure and calls `tx_queue_len`
     /// to populate it with the current transmit queue length. The value is then returned.
-    pub fn tx_queue_len(&self) -> io::Result<u32> {
+    pub fn tx_queue_len(&self) -> Result<u32> {
         unsafe {
             let mut ifreq = self.request()?;
             if let Err(err) = tx_queue_len(ctl()?.as_raw_fd(), &mut ifreq) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             Ok(ifreq.ifr_ifru.ifru_metric as _)
         }
     }
     /// Make the device persistent.
-    pub fn persist(&self) -> io::Result<()> {
-        unsafe {
-            if let Err(err) = tunsetpersist(self.as_raw_fd(), &1) {
-                Err(io::Error::from(err))
-            } else {
-                Ok(())
-            }
-        }
+    #[cfg(feature = "config")]
+    pub fn persist(&self) -> Result<()> {
+        self.set_persist(true)
+    }
+    /// Sets whether the interface outlives its last open descriptor, with `TUNSETPERSIST`.
+    ///
+    /// A persistent interface stays in `ip link` after the device is dropped, and building a
+    /// device with its [`name`](crate::DeviceBuilder::name) attaches to it again instead of
+    /// creating a new one. Clearing the flag while no other descriptor is open removes the
+    /// interface once this device is dropped.
+    #[cfg(feature = "config")]
+    pub fn set_persist(&self, persist: bool) -> Result<()> {
+        // TUNSETPERSIST takes the flag as the argument itself, not behind a pointer.
+        unsafe { tunsetpersist(self.as_raw_fd(), libc::c_int::from(persist) as _) }?;
+        Ok(())
     }
 
     /// Set the owner of the device.
-    pub fn user(&self, value: i32) -> io::Result<()> {
+    #[cfg(feature = "config")]
+    pub fn user(&self, value: i32) -> Result<()> {
         unsafe {
             if let Err(err) = tunsetowner(self.as_raw_fd(), &value) {
-                Err(io::Error::from(err))
+                Err(err.into())
             } else {
                 Ok(())
             }
@@ -236,19 +439,69 @@ impl DeviceImpl {
     }
 
     /// Set the group of the device.
-    pub fn group(&self, value: i32) -> io::Result<()> {
+    #[cfg(feature = "config")]
+    pub fn group(&self, value: i32) -> Result<()> {
         unsafe {
             if let Err(err) = tunsetgroup(self.as_raw_fd(), &value) {
-                Err(io::Error::from(err))
+                Err(err.into())
             } else {
                 Ok(())
             }
         }
     }
+    /// Sends packets that live in a caller-owned memory region, such as an mmap'd AF_XDP UMEM
+    /// or a shared-memory queue, without staging them in an intermediate buffer.
+    ///
+    /// Each range in `packets` is one packet within `region`. If offload is enabled, every
+    /// packet must start with a `VirtioNetHdr`. The TUN driver still copies each packet into
+    /// a kernel buffer during the write, so `region` only has to stay valid and unmodified
+    /// until this call returns; afterwards the frames can be handed back to their producer.
+    ///
+    /// Returns the number of packets sent. An error is returned only if the first packet
+    /// could not be sent, a later error ends the batch early.
+    pub fn send_from_region(&self, region: &[u8], packets: &[Range<usize>]) -> io::Result<usize> {
+        for (sent, range) in packets.iter().enumerate() {
+            let rs = region
+                .get(range.clone())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "range out of region"))
+                .and_then(|packet| self.send(packet));
+            let rs = self.io_hook.send(rs);
+            if let Err(e) = rs {
+                if sent == 0 {
+                    return Err(e);
+                }
+                return Ok(sent);
+            }
+        }
+        Ok(packets.len())
+    }
     /// send multiple fragmented data packets.
     /// GROTable can be reused, as it is used to assist in data merging.
     /// Offset is the starting position of the data. Need to meet offset>=10.
     pub fn send_multiple<B: ExpandBuffer>(
+        &self,
+        gro_table: &mut GROTable,
+        bufs: &mut [B],
+        offset: usize,
+    ) -> io::Result<usize> {
+        // GRO merges packets, so their lengths are taken before it runs.
+        let lens: Vec<usize> = if self.io_hook.is_set() {
+            bufs.iter()
+                .map(|buf| buf.as_ref().len().saturating_sub(offset))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let rs = self.send_multiple_packets(gro_table, bufs, offset);
+        match &rs {
+            Ok(_) => lens
+                .into_iter()
+                .for_each(|len| self.io_hook.emit(IoEvent::Send { len })),
+            Err(e) => self.io_hook.error(e),
+        }
+        rs
+    }
+    fn send_multiple_packets<B: ExpandBuffer>(
         &self,
         gro_table: &mut GROTable,
         bufs: &mut [B],
@@ -261,7 +514,7 @@ impl DeviceImpl {
                 offset,
                 &mut gro_table.tcp_gro_table,
                 &mut gro_table.udp_gro_table,
-                self.udp_gso,
+                self.udp_gso(),
                 &mut gro_table.to_write,
             )?;
             offset -= VIRTIO_NET_HDR_LEN;
@@ -303,12 +556,63 @@ impl DeviceImpl {
         bufs: &mut [B],
         sizes: &mut [usize],
         offset: usize,
+    ) -> io::Result<usize> {
+        let rs = self.recv_multiple_packets(original_buffer, bufs, sizes, offset, None);
+        match &rs {
+            Ok(n) => sizes[..*n]
+                .iter()
+                .for_each(|&len| self.io_hook.emit(IoEvent::Recv { len })),
+            Err(e) => self.io_hook.error(e),
+        }
+        rs
+    }
+    /// Like [`recv_multiple`](Self::recv_multiple), also setting `timestamps[i]` to the time
+    /// packet `i` was read, see [`SyncDevice::recv_with_timestamp`](crate::SyncDevice::recv_with_timestamp).
+    ///
+    /// The packets split from one offloaded read share its timestamp. `timestamps` must be
+    /// at least as long as `sizes`.
+    pub fn recv_multiple_with_timestamps<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        original_buffer: &mut [u8],
+        bufs: &mut [B],
+        sizes: &mut [usize],
+        timestamps: &mut [SystemTime],
+        offset: usize,
+    ) -> io::Result<usize> {
+        if timestamps.len() < sizes.len() {
+            return Err(io::Error::new(io::ErrorKind::Other, "timestamps error"));
+        }
+        let mut timestamp = SystemTime::UNIX_EPOCH;
+        let rs =
+            self.recv_multiple_packets(original_buffer, bufs, sizes, offset, Some(&mut timestamp));
+        match &rs {
+            Ok(n) => {
+                timestamps[..*n].fill(timestamp);
+                sizes[..*n]
+                    .iter()
+                    .for_each(|&len| self.io_hook.emit(IoEvent::Recv { len }))
+            }
+            Err(e) => self.io_hook.error(e),
+        }
+        rs
+    }
+    /// Reads one packet and splits it, setting `timestamp` right after the read.
+    fn recv_multiple_packets<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        original_buffer: &mut [u8],
+        bufs: &mut [B],
+        sizes: &mut [usize],
+        offset: usize,
+        timestamp: Option<&mut SystemTime>,
     ) -> io::Result<usize> {
         if bufs.is_empty() || bufs.len() != sizes.len() {
             return Err(io::Error::new(io::ErrorKind::Other, "bufs error"));
         }
         if self.vnet_hdr {
             let len = self.recv(original_buffer)?;
+            if let Some(timestamp) = timestamp {
+                *timestamp = SystemTime::now();
+            }
             if len <= VIRTIO_NET_HDR_LEN {
                 Err(io::Error::new(
                     io::ErrorKind::Other,
@@ -327,6 +631,9 @@ impl DeviceImpl {
             )
         } else {
             let len = self.recv(bufs[0].as_mut())?;
+            if let Some(timestamp) = timestamp {
+                *timestamp = SystemTime::now();
+            }
             sizes[0] = len;
             Ok(1)
         }
@@ -337,124 +644,18 @@ impl DeviceImpl {
     /// and returns the number of packets read.
     pub(crate) fn handle_virtio_read<B: AsRef<[u8]> + AsMut<[u8]>>(
         &self,
-        mut hdr: VirtioNetHdr,
+        hdr: VirtioNetHdr,
         input: &mut [u8],
         bufs: &mut [B],
         sizes: &mut [usize],
         offset: usize,
     ) -> io::Result<usize> {
-        let len = input.len();
-        if hdr.gso_type == VIRTIO_NET_HDR_GSO_NONE {
-            if hdr.flags & VIRTIO_NET_HDR_F_NEEDS_CSUM != 0 {
-                // This means CHECKSUM_PARTIAL in skb context. We are responsible
-                // for computing the checksum starting at hdr.csumStart and placing
-                // at hdr.csumOffset.
-                gso_none_checksum(input, hdr.csum_start, hdr.csum_offset);
-            }
-            if bufs[0].as_ref()[offset..].len() < len {
-                Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "read len {len} overflows bufs element len {}",
-                        bufs[0].as_ref().len()
-                    ),
-                ))?
-            }
-            sizes[0] = len;
-            bufs[0].as_mut()[offset..offset + len].copy_from_slice(input);
-            return Ok(1);
-        }
-        if hdr.gso_type != VIRTIO_NET_HDR_GSO_TCPV4
-            && hdr.gso_type != VIRTIO_NET_HDR_GSO_TCPV6
-            && hdr.gso_type != VIRTIO_NET_HDR_GSO_UDP_L4
-        {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("unsupported virtio GSO type: {}", hdr.gso_type),
-            ))?
-        }
-        let ip_version = input[0] >> 4;
-        match ip_version {
-            4 => {
-                if hdr.gso_type != VIRTIO_NET_HDR_GSO_TCPV4
-                    && hdr.gso_type != VIRTIO_NET_HDR_GSO_UDP_L4
-                {
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("ip header version: 4, GSO type: {}", hdr.gso_type),
-                    ))?
-                }
-            }
-            6 => {
-                if hdr.gso_type != VIRTIO_NET_HDR_GSO_TCPV6
-                    && hdr.gso_type != VIRTIO_NET_HDR_GSO_UDP_L4
-                {
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("ip header version: 6, GSO type: {}", hdr.gso_type),
-                    ))?
-                }
-            }
-            ip_version => Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("invalid ip header version: {}", ip_version),
-            ))?,
-        }
-        // Don't trust hdr.hdrLen from the kernel as it can be equal to the length
-        // of the entire first packet when the kernel is handling it as part of a
-        // FORWARD path. Instead, parse the transport header length and add it onto
-        // csumStart, which is synonymous for IP header length.
-        if hdr.gso_type == VIRTIO_NET_HDR_GSO_UDP_L4 {
-            hdr.hdr_len = hdr.csum_start + 8
-        } else {
-            if len <= hdr.csum_start as usize + 12 {
-                Err(io::Error::new(io::ErrorKind::Other, "packet is too short"))?
-            }
-
-            let tcp_h_len = ((input[hdr.csum_start as usize + 12] as u16) >> 4) * 4;
-            if !(20..=60).contains(&tcp_h_len) {
-                // A TCP header must be between 20 and 60 bytes in length.
-                Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("tcp header len is invalid: {tcp_h_len}"),
-                ))?
-            }
-            hdr.hdr_len = hdr.csum_start + tcp_h_len
-        }
-        if len < hdr.hdr_len as usize {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "length of packet ({len}) < virtioNetHdr.hdr_len ({})",
-                    hdr.hdr_len
-                ),
-            ))?
-        }
-        if hdr.hdr_len < hdr.csum_start {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "virtioNetHdr.hdrLen ({}) < virtioNetHdr.csumStart ({})",
-                    hdr.hdr_len, hdr.csum_start
-                ),
-            ))?
-        }
-        let c_sum_at = (hdr.csum_start + hdr.csum_offset) as usize;
-        if c_sum_at + 1 >= len {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "end of checksum offset ({}) exceeds packet length ({len})",
-                    c_sum_at + 1,
-                ),
-            ))?
-        }
-        gso_split(input, hdr, bufs, sizes, offset, ip_version == 6)
+        crate::platform::linux::offload::virtio_split(hdr, input, bufs, sizes, offset)
     }
 }
 
 impl DeviceImpl {
-    /// Prepare a new request.
+    /// Prepare a new request for the interface's current name.
     unsafe fn request(&self) -> io::Result<ifreq> {
         request(&self.name()?)
     }
@@ -463,7 +664,7 @@ impl DeviceImpl {
             let mut req = self.request()?;
             ipaddr_to_sockaddr(addr, 0, &mut req.ifr_ifru.ifru_addr, OVERWRITE_SIZE);
             if let Err(err) = siocsifaddr(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
         }
         Ok(())
@@ -473,7 +674,7 @@ impl DeviceImpl {
             let mut req = self.request()?;
             ipaddr_to_sockaddr(value, 0, &mut req.ifr_ifru.ifru_netmask, OVERWRITE_SIZE);
             if let Err(err) = siocsifnetmask(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             Ok(())
         }
@@ -484,13 +685,14 @@ impl DeviceImpl {
             let mut req = self.request()?;
             ipaddr_to_sockaddr(value, 0, &mut req.ifr_ifru.ifru_dstaddr, OVERWRITE_SIZE);
             if let Err(err) = siocsifdstaddr(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             Ok(())
         }
     }
 
-    pub fn remove_address_v6(&self, addr: Ipv6Addr, prefix: u8) -> io::Result<()> {
+    #[cfg(feature = "config")]
+    fn delete_address_v6(&self, addr: Ipv6Addr, prefix: u8) -> Result<()> {
         unsafe {
             let if_index = self.if_index()?;
             let ctl = ctl_v6()?;
@@ -501,14 +703,26 @@ impl DeviceImpl {
                 .addr6
                 .sin6_addr;
             if let Err(err) = siocdifaddr_in6(ctl.as_raw_fd(), &ifrv6) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
         }
         Ok(())
     }
     /// Retrieves the name of the network interface.
-    pub fn name(&self) -> io::Result<String> {
-        unsafe { name(self.as_raw_fd()) }
+    ///
+    /// The name is queried from the file descriptor (`TUNGETIFF`) on every call rather than
+    /// cached, so it follows renames done by other processes (e.g. `ip link set ... name ...`).
+    /// Configuration methods resolve the name the same way before each request.
+    pub fn name(&self) -> Result<String> {
+        unsafe { Ok(name(self.as_raw_fd())?) }
+    }
+    /// Returns the name of the device without querying the system.
+    ///
+    /// The name is looked up once and then only updated by [`set_name`](Self::set_name),
+    /// so it is cheap enough for hot logging paths. A rename by another process is not
+    /// noticed, use [`name`](Self::name) for the authoritative value.
+    pub fn cached_name(&self) -> Result<std::sync::Arc<str>> {
+        self.name_cache.get_or(|| self.name())
     }
     /// Sets a new name for the network interface.
     ///
@@ -516,12 +730,14 @@ impl DeviceImpl {
     /// checks that its length does not exceed the maximum allowed (IFNAMSIZ),
     /// and then copies it into an interface request structure. It then uses a system call
     /// (via `siocsifname`) to apply the new name.
-    pub fn set_name(&self, value: &str) -> io::Result<()> {
+    #[cfg(feature = "config")]
+    pub fn set_name(&self, value: &str) -> Result<()> {
         unsafe {
-            let tun_name = CString::new(value)?;
+            let tun_name = CString::new(value)
+                .map_err(|_| Error::InvalidConfig("name contains a nul byte"))?;
 
             if tun_name.as_bytes_with_nul().len() > IFNAMSIZ {
-                return Err(io::Error::new(io::ErrorKind::InvalidInput, "name too long"));
+                return Err(Error::InvalidConfig("name too long"));
             }
 
             let mut req = self.request()?;
@@ -532,9 +748,10 @@ impl DeviceImpl {
             );
 
             if let Err(err) = siocsifname(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
+            self.name_cache.set(value);
             Ok(())
         }
     }
@@ -545,7 +762,7 @@ impl DeviceImpl {
             let mut req = self.request()?;
 
             if let Err(err) = siocgifflags(ctl.as_raw_fd(), &mut req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             Ok(req.ifr_ifru.ifru_flags)
         }
@@ -553,23 +770,363 @@ impl DeviceImpl {
     /// Checks whether the network interface is currently running.
     ///
     /// The interface is considered running if both the IFF_UP and IFF_RUNNING flags are set.
-    pub fn is_running(&self) -> io::Result<bool> {
+    pub fn is_running(&self) -> Result<bool> {
         let flags = self.ifru_flags()?;
         Ok(flags & (IFF_UP | IFF_RUNNING) as c_short == (IFF_UP | IFF_RUNNING) as c_short)
     }
+    /// Checks whether the interface has carrier, independently of whether it is up.
+    ///
+    /// Reads `/sys/class/net/<name>/carrier`. A TUN/TAP interface has carrier while a file
+    /// descriptor is attached to it. An interface that is down reports no carrier.
+    pub fn has_carrier(&self) -> Result<bool> {
+        let name = self.name()?;
+        match std::fs::read_to_string(format!("/sys/class/net/{name}/carrier")) {
+            Ok(carrier) => Ok(carrier.trim() == "1"),
+            // The kernel refuses to report the carrier of an interface that is down.
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+    /// Returns the unicast routes of all routing tables whose output interface is this
+    /// device, read with a netlink `RTM_GETROUTE` dump.
+    ///
+    /// Local and broadcast routes the kernel derives from the addresses are left out, as are
+    /// multipath routes.
+    #[cfg(feature = "config")]
+    pub fn routes(&self) -> Result<Vec<crate::platform::RouteEntry>> {
+        Ok(crate::platform::linux::route::routes(self.if_index()?)?)
+    }
+    /// Returns the DNS servers systemd-resolved has for the interface, see
+    /// [`set_dns_servers`](Self::set_dns_servers).
+    #[cfg(feature = "config")]
+    pub fn dns_servers(&self) -> Result<Vec<IpAddr>> {
+        crate::platform::linux::dns::servers(self.if_index()?)
+    }
+    #[cfg(feature = "config")]
+    pub(crate) fn dns_setter(&self) -> Result<crate::platform::DnsSetter> {
+        let index = self.if_index()?;
+        Ok(Box::new(move |servers| {
+            crate::platform::linux::dns::set_servers(index, servers)
+        }))
+    }
+    /// Routes `destination/prefix` out of this device in the `main` table, replacing a route
+    /// to the same destination, with netlink `RTM_NEWROUTE`.
+    ///
+    /// `pref_src` is the source address the kernel picks for packets that take the route
+    /// (`ip route ... src`). Without it the kernel may pick the address of another interface,
+    /// and replies to that address do not come back through the tunnel, see
+    /// [`add_route_via_device`](Self::add_route_via_device). Windows always takes the source
+    /// from the outgoing interface; macOS and FreeBSD have no preferred source for routes.
+    #[cfg(feature = "config")]
+    pub fn add_route(
+        &self,
+        destination: IpAddr,
+        prefix: u8,
+        pref_src: Option<IpAddr>,
+    ) -> Result<()> {
+        let entry = route_entry(destination, prefix, pref_src)?;
+        crate::platform::linux::route::change_route(true, self.if_index()?, &entry)?;
+        self.applied.route_added(entry);
+        Ok(())
+    }
+    /// Routes `destination/prefix` out of this device in the `main` table with `metric`, 0
+    /// for `None`.
+    ///
+    /// Unlike [`add_route`](Self::add_route) an existing route is not replaced: if the table
+    /// has a route to `destination/prefix` with the same metric, an error of kind
+    /// [`AlreadyExists`](io::ErrorKind::AlreadyExists) is returned. The route is bound to
+    /// the interface, the kernel removes it when the interface goes down.
+    /// [`remove_route`](Self::remove_route) removes it.
+    #[cfg(feature = "config")]
+    pub fn add_route_with_metric(
+        &self,
+        destination: IpAddr,
+        prefix: u8,
+        metric: Option<u32>,
+    ) -> Result<()> {
+        let mut entry = route_entry(destination, prefix, None)?;
+        entry.metric = metric.unwrap_or(0);
+        crate::platform::linux::route::create_route(self.if_index()?, &entry)?;
+        self.applied.route_added(entry);
+        Ok(())
+    }
+    /// Routes `destination/prefix` out of this device in the `main` table through
+    /// `gateway`, on-link for `None`.
+    ///
+    /// Like [`add_route_with_metric`](Self::add_route_with_metric), an existing route is not
+    /// replaced but fails with an error of kind [`AlreadyExists`](io::ErrorKind::AlreadyExists).
+    /// The gateway must be of the family of `destination`.
+    #[cfg(feature = "config")]
+    pub fn add_route_via(
+        &self,
+        destination: IpAddr,
+        prefix: u8,
+        gateway: Option<IpAddr>,
+    ) -> Result<()> {
+        crate::platform::check_gateway(destination, gateway)?;
+        let mut entry = route_entry(destination, prefix, None)?;
+        entry.gateway = gateway;
+        crate::platform::linux::route::create_route(self.if_index()?, &entry)?;
+        self.applied.route_added(entry);
+        Ok(())
+    }
+    /// Like [`add_route`](Self::add_route), with the first address of the device of the
+    /// same family as `destination` as the preferred source.
+    ///
+    /// Fails with [`Error::InvalidConfig`] if the device has no such address, IPv6
+    /// link-local addresses do not count.
+    #[cfg(feature = "config")]
+    pub fn add_route_via_device(&self, destination: IpAddr, prefix: u8) -> Result<()> {
+        let pref_src = self
+            .primary_address(destination.is_ipv4())?
+            .ok_or(Error::InvalidConfig(
+                "the device has no address of the family of the route",
+            ))?;
+        self.add_route(destination, prefix, Some(pref_src))
+    }
+    /// Removes the route to `destination/prefix` out of this device from the `main` table.
+    #[cfg(feature = "config")]
+    pub fn remove_route(&self, destination: IpAddr, prefix: u8) -> Result<()> {
+        let entry = route_entry(destination, prefix, None)?;
+        crate::platform::linux::route::change_route(false, self.if_index()?, &entry)?;
+        self.applied.route_removed(&entry);
+        Ok(())
+    }
+    /// Routes the host `peer` out of this device in the `main` table, see
+    /// [`add_peer_routes`](Self::add_peer_routes).
+    #[cfg(feature = "config")]
+    pub fn add_peer_route(&self, peer: Ipv4Addr) -> Result<()> {
+        self.add_peer_routes(&[peer])
+    }
+    /// Routes each host of `peers` out of this device in the `main` table, as a /32 route
+    /// without gateway, replacing routes to the same hosts.
+    ///
+    /// The requests are batched on one netlink socket, so thousands of routes take a few
+    /// syscalls rather than one round trip each. On an error the routes before it, and some
+    /// after it, may have been added.
+    #[cfg(feature = "config")]
+    pub fn add_peer_routes(&self, peers: &[Ipv4Addr]) -> Result<()> {
+        self.change_peer_routes(true, peers)
+    }
+    /// Removes the route to the host `peer` out of this device from the `main` table.
+    #[cfg(feature = "config")]
+    pub fn remove_peer_route(&self, peer: Ipv4Addr) -> Result<()> {
+        self.remove_peer_routes(&[peer])
+    }
+    /// Removes the routes to the hosts `peers` out of this device from the `main` table,
+    /// batched like [`add_peer_routes`](Self::add_peer_routes). Peers without a route are
+    /// skipped.
+    #[cfg(feature = "config")]
+    pub fn remove_peer_routes(&self, peers: &[Ipv4Addr]) -> Result<()> {
+        self.change_peer_routes(false, peers)
+    }
+    /// Returns the hosts with a /32 route without gateway out of this device in the `main`
+    /// table, as installed by [`add_peer_routes`](Self::add_peer_routes).
+    #[cfg(feature = "config")]
+    pub fn peer_routes(&self) -> Result<Vec<Ipv4Addr>> {
+        Ok(self
+            .routes()?
+            .into_iter()
+            .filter(|route| {
+                route.prefix == 32
+                    && route.gateway.is_none()
+                    && route.table == crate::platform::linux::route::RT_TABLE_MAIN
+            })
+            .filter_map(|route| match route.destination {
+                IpAddr::V4(peer) => Some(peer),
+                IpAddr::V6(_) => None,
+            })
+            .collect())
+    }
+    #[cfg(feature = "config")]
+    fn change_peer_routes(&self, add: bool, peers: &[Ipv4Addr]) -> Result<()> {
+        let routes = peers
+            .iter()
+            .map(|peer| route_entry((*peer).into(), 32, None))
+            .collect::<Result<Vec<_>>>()?;
+        let result = crate::platform::linux::route::change_routes(add, self.if_index()?, &routes);
+        // After an error some of the routes may have been changed, the teardown skips
+        // routes that are gone.
+        if add {
+            routes
+                .into_iter()
+                .for_each(|route| self.applied.route_added(route));
+        } else if result.is_ok() {
+            routes
+                .iter()
+                .for_each(|route| self.applied.route_removed(route));
+        }
+        Ok(result?)
+    }
+    /// Leaves the configuration applied through the device in place when it is dropped, even
+    /// if it was built with [`cleanup_on_drop`](crate::DeviceBuilder::cleanup_on_drop), e.g.
+    /// to inspect the interface after a failure. Does not affect
+    /// [`SyncDevice::close`](crate::SyncDevice::close).
+    #[cfg(feature = "config")]
+    pub fn leak_on_drop(&self, leak: bool) {
+        self.applied.set_leak(leak)
+    }
+    /// Undoes the configuration applied through this device, latest first: the routes, then
+    /// the addresses, then brings the interface down if this device brought it up.
+    ///
+    /// Every step is attempted. Routes and addresses that are already gone count as removed,
+    /// as does everything if the interface is gone.
+    #[cfg(feature = "config")]
+    pub(crate) fn teardown(&self) -> Result<TeardownReport> {
+        let mut report = TeardownReport::default();
+        let (routes, addresses) = self.applied.take();
+        let index = self.if_index()?;
+        if index == 0 {
+            return Ok(report);
+        }
+        // One batch in the common case, the routes are only removed one by one to tell which
+        // of them failed.
+        let batched = routes.is_empty()
+            || crate::platform::linux::route::change_routes(false, index, &routes).is_ok();
+        for route in &routes {
+            let removed = if batched {
+                Ok(())
+            } else {
+                match crate::platform::linux::route::change_route(false, index, route) {
+                    Err(e) if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+                    result => result.map_err(Error::from),
+                }
+            };
+            report.push(
+                TeardownStep::RemoveRoute(route.destination, route.prefix),
+                removed,
+            );
+        }
+        for address in addresses {
+            report.push(
+                TeardownStep::RemoveAddress(address),
+                self.remove_address_unchecked(address),
+            );
+        }
+        // Only undo our own change, an interface that was already up is left to its other users.
+        if self.brought_up.load(Ordering::Relaxed) {
+            report.push(TeardownStep::Down, self.enabled(false));
+        }
+        Ok(report)
+    }
+    /// The first address of the device of the given family, without IPv6 link-local ones.
+    #[cfg(feature = "config")]
+    pub(crate) fn primary_address(&self, ipv4: bool) -> Result<Option<IpAddr>> {
+        Ok(self.addresses()?.into_iter().find(|addr| match addr {
+            IpAddr::V4(_) => ipv4,
+            IpAddr::V6(v6) => !ipv4 && !crate::platform::is_link_local(v6),
+        }))
+    }
+    /// Checks whether the IP packet `packet`, about to be sent on a socket, would be routed
+    /// back into this device, e.g. the encapsulated traffic of the tunnel itself.
+    ///
+    /// The destination of the packet is looked up like `ip route get`, so policy rules and
+    /// every routing table are taken into account. The source address of the packet is not.
+    /// An unreachable destination does not loop.
+    #[cfg(feature = "config")]
+    pub fn would_loop(&self, packet: &[u8]) -> Result<bool> {
+        self.would_loop_with_mark(packet, 0)
+    }
+    /// Like [`would_loop`](Self::would_loop), for a packet sent on a socket with firewall
+    /// mark `mark` (`SO_MARK`), see [`set_loop_exception`](Self::set_loop_exception).
+    #[cfg(feature = "config")]
+    pub fn would_loop_with_mark(&self, packet: &[u8], mark: u32) -> Result<bool> {
+        use crate::platform::linux::route;
+        let destination = crate::platform::packet_destination(packet)?;
+        Ok(route::output_interface(destination, mark)? == Some(self.if_index()?))
+    }
+    /// Keeps traffic with firewall mark `mark` out of this device, whatever routes point at it.
+    ///
+    /// Installs the policy rule `fwmark <mark> lookup <mark>` with priority 31990, before the
+    /// `main` table and the rules of [`set_capture_all`](Self::set_capture_all), and copies
+    /// the default routes of the `main` table that do not go through this device into table
+    /// `mark`. Sockets of the VPN itself set `SO_MARK` to `mark` and keep using the underlay.
+    /// The copy is a snapshot: call this again when the default route of the host changes.
+    /// `false` removes the rule and the table.
+    ///
+    /// Rules and routes are changed through netlink, which needs `CAP_NET_ADMIN`. The mark is
+    /// used as the table number, so 0 and the reserved tables 253 to 255 are rejected.
+    #[cfg(feature = "config")]
+    pub fn set_loop_exception(&self, mark: u32, enable: bool) -> Result<()> {
+        if matches!(mark, 0 | 253..=255) {
+            return Err(Error::InvalidConfig(
+                "the loop exception mark is used as a routing table, 0 and 253 to 255 are reserved",
+            ));
+        }
+        let rs = self.loop_exception(mark, enable);
+        if enable && rs.is_err() {
+            _ = self.loop_exception(mark, false);
+        }
+        rs
+    }
+    #[cfg(feature = "config")]
+    fn loop_exception(&self, mark: u32, enable: bool) -> Result<()> {
+        use crate::platform::linux::route::{self, RT_TABLE_MAIN};
+        let index = self.if_index()?;
+        let mut families = vec![libc::AF_INET];
+        if std::path::Path::new("/proc/net/if_inet6").exists() {
+            families.push(libc::AF_INET6);
+        }
+        for &family in &families {
+            _ = route::change_rule(false, family, LOOP_EXCEPTION_PREF, mark, mark);
+        }
+        let routes = route::dump_routes()?;
+        for (oif, entry) in &routes {
+            if let (Some(oif), true) = (oif, entry.table == mark) {
+                _ = route::change_route(false, *oif, entry);
+            }
+        }
+        if !enable {
+            return Ok(());
+        }
+        for (oif, entry) in routes {
+            let Some(oif) = oif else {
+                continue;
+            };
+            if entry.table == RT_TABLE_MAIN && entry.prefix == 0 && oif != index {
+                let entry = crate::platform::RouteEntry {
+                    table: mark,
+                    ..entry
+                };
+                route::change_route(true, oif, &entry)?;
+            }
+        }
+        for family in families {
+            route::change_rule(true, family, LOOP_EXCEPTION_PREF, mark, mark)?;
+        }
+        Ok(())
+    }
+    /// Sets the carrier of the interface with `TUNSETCARRIER`, independently of whether it
+    /// is up.
+    ///
+    /// Marking the carrier down when the underlying transport is lost makes the kernel treat
+    /// the link as down: its routes stop being used and routing daemons withdraw them, while
+    /// the addresses and the configuration stay. Needs Linux 4.13 and `CAP_NET_ADMIN`.
+    /// Read the state back with [`has_carrier`](Self::has_carrier).
+    #[cfg(feature = "config")]
+    pub fn set_carrier(&self, up: bool) -> Result<()> {
+        unsafe {
+            if let Err(err) = tunsetcarrier(self.as_raw_fd(), &(up as libc::c_int)) {
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
     /// Enables or disables the network interface.
     ///
     /// If `value` is true, the interface is enabled by setting the IFF_UP and IFF_RUNNING flags.
     /// If false, the IFF_UP flag is cleared. The change is applied using a system call.
-    pub fn enabled(&self, value: bool) -> io::Result<()> {
+    #[cfg(feature = "config")]
+    pub fn enabled(&self, value: bool) -> Result<()> {
         unsafe {
             let ctl = ctl()?;
             let mut req = self.request()?;
 
             if let Err(err) = siocgifflags(ctl.as_raw_fd(), &mut req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
+            let was_up = req.ifr_ifru.ifru_flags & IFF_UP as c_short != 0;
             if value {
                 req.ifr_ifru.ifru_flags |= (IFF_UP | IFF_RUNNING) as c_short;
             } else {
@@ -577,7 +1134,12 @@ impl DeviceImpl {
             }
 
             if let Err(err) = siocsifflags(ctl.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
+            }
+            if value && !was_up {
+                self.brought_up.store(true, Ordering::Relaxed);
+            } else if !value {
+                self.brought_up.store(false, Ordering::Relaxed);
             }
 
             Ok(())
@@ -587,11 +1149,11 @@ impl DeviceImpl {
     ///
     /// This function populates an interface request with the broadcast address via a system call,
     /// converts it into a sockaddr structure, and then extracts the IP address.
-    pub fn broadcast(&self) -> io::Result<IpAddr> {
+    pub fn broadcast(&self) -> Result<IpAddr> {
         unsafe {
             let mut req = self.request()?;
             if let Err(err) = siocgifbrdaddr(ctl()?.as_raw_fd(), &mut req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             let sa = sockaddr_union::from(req.ifr_ifru.ifru_broadaddr);
             Ok(std::net::SocketAddr::try_from(sa)?.ip())
@@ -601,46 +1163,136 @@ impl DeviceImpl {
     ///
     /// This function converts the given IP address into a sockaddr structure (with a specified overwrite size)
     /// and then applies it to the interface via a system call.
-    pub fn set_broadcast(&self, value: IpAddr) -> io::Result<()> {
+    #[cfg(feature = "config")]
+    pub fn set_broadcast(&self, value: IpAddr) -> Result<()> {
         unsafe {
             let mut req = self.request()?;
             ipaddr_to_sockaddr(value, 0, &mut req.ifr_ifru.ifru_broadaddr, OVERWRITE_SIZE);
             if let Err(err) = siocsifbrdaddr(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             Ok(())
         }
     }
+    /// Sets an explicit IPv4 broadcast address on a TAP interface, replacing the one derived
+    /// from the netmask.
+    ///
+    /// [`set_network_address`](Self::set_network_address) derives it again, so call this
+    /// afterwards. Interfaces without `IFF_BROADCAST`, such as TUN devices, are rejected with
+    /// [`Error::Unsupported`].
+    #[cfg(feature = "config")]
+    pub fn set_broadcast_address(&self, addr: Ipv4Addr) -> Result<()> {
+        if self.ifru_flags()? & IFF_BROADCAST as c_short == 0 {
+            return Err(Error::Unsupported("the interface has no broadcast address"));
+        }
+        self.set_broadcast(addr.into())
+    }
     /// Sets the IPv4 network address, netmask, and an optional destination address.
     ///
     /// This function sets the interface's address, netmask, and if provided, the destination address.
     /// It calls the helper methods `set_address_v4`, `set_netmask`, and `set_destination` respectively.
+    #[cfg(feature = "config")]
     pub fn set_network_address<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
         &self,
         address: IPv4,
         netmask: Netmask,
         destination: Option<IPv4>,
-    ) -> io::Result<()> {
-        self.set_address_v4(address.ipv4()?)?;
-        self.set_netmask(netmask.netmask()?)?;
+    ) -> Result<()> {
+        let address = address.ipv4()?;
+        let prefix = netmask.prefix()?;
+        let netmask = netmask.netmask()?;
+        let assigned = self.addresses()?.contains(&address.into());
+        self.set_address_v4(address)?;
+        if !assigned {
+            self.applied.address_added(address.into());
+        }
+        self.set_netmask(netmask)?;
+        if self.ifru_flags()? & IFF_BROADCAST as c_short != 0 {
+            // SIOCSIFADDR derives a broadcast from the classful prefix, and the kernel only
+            // updates it for prefixes below /31. /31 (RFC 3021) and /32 have no broadcast.
+            let broadcast = if prefix >= 31 {
+                Ipv4Addr::UNSPECIFIED
+            } else {
+                Ipv4Addr::from(u32::from(address) | !u32::from(netmask))
+            };
+            self.set_broadcast(broadcast.into())?;
+        }
         if let Some(destination) = destination {
             self.set_destination(destination.ipv4()?)?;
         }
         Ok(())
     }
-    /// Removes an IP address from the interface.
+    /// Adds an IPv4 address to the interface with netlink `RTM_NEWADDR`, keeping the
+    /// addresses already assigned, unlike [`set_network_address`](Self::set_network_address)
+    /// which replaces the primary one.
+    ///
+    /// `destination` is the other end of a point-to-point link. Adding an address that is
+    /// already assigned with the same prefix succeeds, with a different prefix
+    /// [`Error::InvalidConfig`] is returned.
+    #[cfg(feature = "config")]
+    pub fn add_address_v4<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
+        &self,
+        address: IPv4,
+        netmask: Netmask,
+        destination: Option<IPv4>,
+    ) -> Result<()> {
+        let address = address.ipv4()?;
+        let prefix = netmask.prefix()?;
+        let destination = destination.map(|v| v.ipv4()).transpose()?;
+        match crate::platform::linux::route::change_address(
+            true,
+            self.if_index()?,
+            address.into(),
+            prefix,
+            destination.map(IpAddr::from),
+        ) {
+            Ok(()) => self.applied.address_added(address.into()),
+            Err(e) if e.raw_os_error() == Some(libc::EEXIST) => {
+                let addrs = crate::platform::get_if_addrs_by_name(self.name()?)?;
+                if crate::platform::assigned_prefix(&addrs, address.into()) != Some(prefix) {
+                    return Err(Error::InvalidConfig(
+                        "the address is already assigned with a different prefix",
+                    ));
+                }
+                log::debug!("{address}/{prefix} is already assigned to the interface");
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+    /// Removes an IP address from the interface without checking that it is assigned.
     ///
-    /// For IPv4 addresses, it iterates over the current addresses and if a match is found,
-    /// resets the address to `0.0.0.0` (unspecified).
-    /// For IPv6 addresses, it retrieves the interface addresses by name and removes the matching address,
-    /// taking into account its prefix length.
-    pub fn remove_address(&self, addr: IpAddr) -> io::Result<()> {
+    /// IPv4 addresses are removed with netlink `RTM_DELADDR`, so that removing an additional
+    /// address leaves the primary one alone. If the interface has several IPv4 addresses,
+    /// `promote_secondaries` is enabled for it first, so that removing the primary address
+    /// promotes the next one instead of removing the whole subnet. For IPv6 addresses, it retrieves the interface
+    /// addresses by name and removes the matching address, taking into account its prefix
+    /// length.
+    #[cfg(feature = "config")]
+    pub(crate) fn remove_address_unchecked(&self, addr: IpAddr) -> Result<()> {
+        self.applied.address_removed(addr);
         match addr {
             IpAddr::V4(_) => {
-                for x in self.addresses()? {
-                    if x == addr {
-                        return self.set_address_v4(Ipv4Addr::UNSPECIFIED);
+                let addrs = self.addresses()?;
+                if addrs.contains(&addr) {
+                    if addrs.iter().filter(|v| v.is_ipv4()).count() > 1 {
+                        // Otherwise removing the primary address also removes the
+                        // secondary addresses of its subnet.
+                        let path = format!(
+                            "/proc/sys/net/ipv4/conf/{}/promote_secondaries",
+                            self.name()?
+                        );
+                        if let Err(e) = std::fs::write(path, "1") {
+                            log::debug!("enabling promote_secondaries failed: {e}");
+                        }
                     }
+                    crate::platform::linux::route::change_address(
+                        false,
+                        self.if_index()?,
+                        addr,
+                        32,
+                        None,
+                    )?;
                 }
             }
             IpAddr::V6(addr_v6) => {
@@ -649,7 +1301,7 @@ impl DeviceImpl {
                     if x.address == addr {
                         if let Some(netmask) = x.netmask {
                             let prefix = ipnet::ip_mask_to_prefix(netmask).unwrap_or(0);
-                            self.remove_address_v6(addr_v6, prefix)?
+                            self.delete_address_v6(addr_v6, prefix)?
                         }
                     }
                 }
@@ -662,23 +1314,39 @@ impl DeviceImpl {
     /// This function creates an `in6_ifreq` structure, fills in the interface index,
     /// prefix length, and IPv6 address (converted into a sockaddr structure),
     /// and then applies it using a system call.
+    ///
+    /// Adding an address that is already assigned with the same prefix succeeds, so a
+    /// configuration can be re-applied. If it is assigned with a different prefix,
+    /// [`Error::InvalidConfig`] is returned.
+    #[cfg(feature = "config")]
     pub fn add_address_v6<IPv6: ToIpv6Address, Netmask: ToIpv6Netmask>(
         &self,
         addr: IPv6,
         netmask: Netmask,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
         unsafe {
             let if_index = self.if_index()?;
             let ctl = ctl_v6()?;
             let mut ifrv6: in6_ifreq = mem::zeroed();
+            let addr = addr.ipv6()?;
+            let prefix = netmask.prefix()?;
             ifrv6.ifr6_ifindex = if_index as i32;
-            ifrv6.ifr6_prefixlen = netmask.prefix()? as u32;
-            ifrv6.ifr6_addr =
-                sockaddr_union::from(std::net::SocketAddr::new(addr.ipv6()?.into(), 0))
-                    .addr6
-                    .sin6_addr;
-            if let Err(err) = siocsifaddr_in6(ctl.as_raw_fd(), &ifrv6) {
-                return Err(io::Error::from(err));
+            ifrv6.ifr6_prefixlen = prefix as u32;
+            ifrv6.ifr6_addr = sockaddr_union::from(std::net::SocketAddr::new(addr.into(), 0))
+                .addr6
+                .sin6_addr;
+            match siocsifaddr_in6(ctl.as_raw_fd(), &ifrv6) {
+                Ok(_) => self.applied.address_added(addr.into()),
+                Err(nix::errno::Errno::EEXIST) => {
+                    let addrs = crate::platform::get_if_addrs_by_name(self.name()?)?;
+                    if crate::platform::assigned_prefix(&addrs, addr.into()) != Some(prefix) {
+                        return Err(Error::InvalidConfig(
+                            "the address is already assigned with a different prefix",
+                        ));
+                    }
+                    log::debug!("{addr}/{prefix} is already assigned to the interface");
+                }
+                Err(err) => return Err(err.into()),
             }
         }
         Ok(())
@@ -687,48 +1355,99 @@ impl DeviceImpl {
     ///
     /// This function constructs an interface request and uses a system call (via `siocgifmtu`)
     /// to obtain the MTU. The result is then converted to a u16.
-    pub fn mtu(&self) -> io::Result<u16> {
+    pub fn mtu(&self) -> Result<u16> {
         unsafe {
             let mut req = self.request()?;
 
             if let Err(err) = siocgifmtu(ctl()?.as_raw_fd(), &mut req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
-            req.ifr_ifru
+            let mtu = req
+                .ifr_ifru
                 .ifru_mtu
                 .try_into()
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))?;
+            Ok(mtu)
         }
     }
     /// Sets the MTU (Maximum Transmission Unit) for the interface.
     ///
     /// This function creates an interface request, sets the `ifru_mtu` field to the new value,
     /// and then applies it via a system call.
-    pub fn set_mtu(&self, value: u16) -> io::Result<()> {
+    ///
+    /// The TUN/TAP driver accepts any value from 68 up to 65535, less the 14-byte Ethernet
+    /// header on TAP devices, so TAP devices can carry jumbo frames (e.g. 9000). A bridge uses the smallest MTU of its ports, so when a TAP
+    /// is enslaved to a bridge the other ports must be raised as well.
+    ///
+    /// Values below 576, or below 1280 while the interface has an IPv6 address, are rejected
+    /// with [`Error::InvalidConfig`], use [`set_mtu_unchecked`](Self::set_mtu_unchecked) to
+    /// apply them anyway. Fails if the OS applied a different value than requested.
+    #[cfg(feature = "config")]
+    pub fn set_mtu(&self, value: u16) -> Result<()> {
+        let ipv6 = self.addresses()?.iter().any(|addr| addr.is_ipv6());
+        crate::platform::check_mtu(value, ipv6, false)?;
+        self.set_mtu_unchecked(value)
+    }
+    /// Sets the MTU without the lower bounds checked by [`set_mtu`](Self::set_mtu).
+    #[cfg(feature = "config")]
+    pub fn set_mtu_unchecked(&self, value: u16) -> Result<()> {
         unsafe {
             let mut req = self.request()?;
             req.ifr_ifru.ifru_mtu = value as i32;
 
             if let Err(err) = siocsifmtu(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                if err == nix::errno::Errno::EINVAL {
+                    return Err(Error::InvalidConfig(
+                        "MTU is outside the range supported by the interface",
+                    ));
+                }
+                return Err(err.into());
             }
-            Ok(())
         }
+        crate::platform::check_mtu_applied(value, self.mtu()?)
+    }
+    /// Sets the metric of the prefix routes derived from the interface's addresses.
+    ///
+    /// A higher metric keeps the tunnel from winning over the physical interface for
+    /// overlapping routes. This runs `ip address change ... metric` for every configured
+    /// address, addresses added afterwards keep the default metric.
+    #[cfg(feature = "config")]
+    pub fn set_metric(&self, metric: u16) -> Result<()> {
+        let name = self.name()?;
+        let metric = metric.to_string();
+        for iface in crate::platform::get_if_addrs_by_name(name.clone())? {
+            let Some(netmask) = iface.netmask else {
+                continue;
+            };
+            let prefix = ipnet::ip_mask_to_prefix(netmask)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let address = format!("{}/{}", iface.address, prefix);
+            crate::run_command(
+                "ip",
+                &[
+                    "address", "change", &address, "dev", &name, "metric", &metric,
+                ],
+            )?;
+        }
+        Ok(())
     }
     /// Sets the MAC (hardware) address for the interface.
     ///
     /// This function constructs an interface request and copies the provided MAC address
     /// into the hardware address field. It then applies the change via a system call.
-    /// This operation is typically supported only for TAP devices.
-    pub fn set_mac_address(&self, eth_addr: [u8; ETHER_ADDR_LEN as usize]) -> io::Result<()> {
+    /// This operation is typically supported only for TAP devices. All-zero and multicast
+    /// addresses are rejected with [`Error::InvalidConfig`].
+    #[cfg(feature = "config")]
+    pub fn set_mac_address(&self, eth_addr: [u8; ETHER_ADDR_LEN as usize]) -> Result<()> {
+        crate::platform::check_mac(&eth_addr)?;
         unsafe {
             let mut req = self.request()?;
             req.ifr_ifru.ifru_hwaddr.sa_family = ARPHRD_ETHER;
             req.ifr_ifru.ifru_hwaddr.sa_data[0..ETHER_ADDR_LEN as usize]
                 .copy_from_slice(eth_addr.map(|c| c as _).as_slice());
             if let Err(err) = siocsifhwaddr(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             Ok(())
         }
@@ -737,18 +1456,40 @@ impl DeviceImpl {
     ///
     /// This function queries the MAC address by the interface name using a helper function.
     /// An error is returned if the MAC address cannot be found.
-    pub fn mac_address(&self) -> io::Result<[u8; ETHER_ADDR_LEN as usize]> {
+    #[cfg(feature = "config")]
+    pub fn mac_address(&self) -> Result<[u8; ETHER_ADDR_LEN as usize]> {
         let mac = mac_address_by_name(&self.name()?)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
             .ok_or(io::Error::from(io::ErrorKind::NotFound))?;
         Ok(mac.bytes())
     }
+    /// Determines whether the interface operates at L2 or L3 from its hardware type,
+    /// `ARPHRD_ETHER` for TAP and `ARPHRD_NONE` for TUN devices.
+    ///
+    /// Meant for devices adopted from a raw fd, whose layer is not known otherwise.
+    pub fn detect_layer(&self) -> Result<Layer> {
+        unsafe {
+            let mut req = self.request()?;
+            if let Err(err) = siocgifhwaddr(ctl()?.as_raw_fd(), &mut req) {
+                return Err(err.into());
+            }
+            match req.ifr_ifru.ifru_hwaddr.sa_family {
+                ARPHRD_ETHER => Ok(Layer::L2),
+                ARPHRD_NONE => Ok(Layer::L3),
+                family => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected hardware type {family}"),
+                )
+                .into()),
+            }
+        }
+    }
 }
 
 unsafe fn name(fd: RawFd) -> io::Result<String> {
     let mut req: ifreq = mem::zeroed();
     if let Err(err) = tungetiff(fd, &mut req as *mut _ as *mut _) {
-        return Err(io::Error::from(err));
+        return Err(err.into());
     }
     let c_str = std::ffi::CStr::from_ptr(req.ifr_name.as_ptr() as *const c_char);
     let tun_name = c_str.to_string_lossy().into_owned();
@@ -765,6 +1506,159 @@ unsafe fn request(name: &str) -> io::Result<ifreq> {
     Ok(req)
 }
 
+/// Where `config_lock` creates the per-interface lock files, the same for every process.
+const CONFIG_LOCK_DIR: &str = "/run/lock";
+
+impl DeviceImpl {
+    /// Takes the advisory configuration lock of the interface, see
+    /// [`DeviceBuilder::config_lock`](crate::DeviceBuilder::config_lock).
+    #[cfg(feature = "config")]
+    pub(crate) fn lock_config(&self) -> Result<()> {
+        use std::io::{Read, Write};
+        let name = self.name()?;
+        let path = format!("{CONFIG_LOCK_DIR}/tun-rs-{name}.lock");
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err.into());
+            }
+            let mut pid = String::new();
+            _ = file.read_to_string(&mut pid);
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!(
+                    "the configuration of {name} is locked by process {}",
+                    pid.trim()
+                ),
+            )
+            .into());
+        }
+        file.set_len(0)?;
+        file.write_all(std::process::id().to_string().as_bytes())?;
+        _ = self.config_lock.set(file);
+        Ok(())
+    }
+    /// Returns whether this device holds the configuration lock of the interface.
+    ///
+    /// The lock is taken by [`DeviceBuilder::config_lock`](crate::DeviceBuilder::config_lock)
+    /// and released when the device is dropped.
+    pub fn is_config_locked(&self) -> bool {
+        self.config_lock.get().is_some()
+    }
+}
+
+/// The firewall mark and routing table used by
+/// [`set_capture_all`](DeviceImpl::set_capture_all).
+///
+/// Sockets of the proxy itself must carry this mark (`SO_MARK`), otherwise their traffic is
+/// routed back into the device.
+#[cfg(feature = "config")]
+pub const CAPTURE_ALL_FWMARK: u32 = 0x7475;
+/// The priority of the policy rule installed by `set_loop_exception`, ahead of those of
+/// `set_capture_all`.
+#[cfg(feature = "config")]
+const LOOP_EXCEPTION_PREF: u32 = 31990;
+/// The priorities of the two policy rules installed by `set_capture_all`, below the `main`
+/// rule at 32766.
+#[cfg(feature = "config")]
+const CAPTURE_ALL_PREFS: [u32; 2] = [32000, 32001];
+
+impl DeviceImpl {
+    /// Routes all IP traffic that is not marked with [`CAPTURE_ALL_FWMARK`] into the device,
+    /// the "intercept everything" mode of transparent proxies.
+    ///
+    /// An L3 tun only receives packets the routing table sends to it. This installs, for IPv4
+    /// and, if the kernel has it, IPv6, the same policy routing `wg-quick` uses:
+    ///
+    /// - a default route via the device in table [`CAPTURE_ALL_FWMARK`];
+    /// - `ip rule add table main suppress_prefixlength 0`, so more specific routes of the main
+    ///   table, like the local subnets, keep working;
+    /// - `ip rule add not fwmark CAPTURE_ALL_FWMARK table CAPTURE_ALL_FWMARK`, which sends
+    ///   everything else to the device.
+    ///
+    /// `net.ipv4.conf.all.src_valid_mark` is set, so that reverse path filtering takes the mark
+    /// into account. Enabling twice does not add duplicate rules. `false` removes the rules and
+    /// the table again; the rules are not removed when the device is dropped, the routes are.
+    /// Rules and routes are changed through netlink, which needs `CAP_NET_ADMIN`.
+    #[cfg(feature = "config")]
+    pub fn set_capture_all(&self, enable: bool) -> Result<()> {
+        let rs = self.capture_all(enable);
+        if enable && rs.is_err() {
+            _ = self.capture_all(false);
+        }
+        rs
+    }
+    #[cfg(feature = "config")]
+    fn capture_all(&self, enable: bool) -> Result<()> {
+        use crate::platform::linux::route::{self, RT_TABLE_MAIN};
+        let index = self.if_index()?;
+        let table = CAPTURE_ALL_FWMARK;
+        let [suppress, capture] = CAPTURE_ALL_PREFS;
+        let mut families = vec![libc::AF_INET];
+        if std::path::Path::new("/proc/net/if_inet6").exists() {
+            families.push(libc::AF_INET6);
+        }
+        // Removed first, so that enabling twice does not stack duplicate rules.
+        for &family in &families {
+            _ = route::change_unmarked_rule(false, family, capture, table, table);
+            _ = route::change_suppress_rule(false, family, suppress, RT_TABLE_MAIN, 0);
+        }
+        if !enable {
+            for (oif, entry) in route::dump_routes()? {
+                if let (Some(oif), true) = (oif, entry.table == table) {
+                    _ = route::change_route(false, oif, &entry);
+                }
+            }
+            return Ok(());
+        }
+        for family in families {
+            let destination = if family == libc::AF_INET {
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+            } else {
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+            };
+            let entry = crate::platform::RouteEntry {
+                destination,
+                prefix: 0,
+                gateway: None,
+                metric: 0,
+                table,
+                pref_src: None,
+            };
+            route::change_route(true, index, &entry)?;
+            route::change_suppress_rule(true, family, suppress, RT_TABLE_MAIN, 0)?;
+            route::change_unmarked_rule(true, family, capture, table, table)?;
+        }
+        if enable {
+            std::fs::write("/proc/sys/net/ipv4/conf/all/src_valid_mark", "1")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "config")]
+impl Drop for DeviceImpl {
+    fn drop(&mut self) {
+        if !self.cleanup_on_drop || self.applied.leak() {
+            return;
+        }
+        match self.teardown() {
+            Ok(report) => {
+                for (step, e) in report.failed {
+                    log::warn!("failed to undo {step:?} on drop: {e}");
+                }
+            }
+            Err(e) => log::warn!("failed to clean up the interface on drop: {e}"),
+        }
+    }
+}
+
 impl From<Layer> for c_short {
     fn from(layer: Layer) -> Self {
         match layer {
@@ -773,3 +1667,44 @@ impl From<Layer> for c_short {
         }
     }
 }
+
+/// A route in the `main` table, checked for a valid prefix and a source of its family.
+#[cfg(feature = "config")]
+fn route_entry(
+    destination: IpAddr,
+    prefix: u8,
+    pref_src: Option<IpAddr>,
+) -> Result<crate::platform::RouteEntry> {
+    match destination {
+        IpAddr::V4(_) => ToIpv4Netmask::prefix(&prefix)?,
+        IpAddr::V6(_) => ToIpv6Netmask::prefix(&prefix)?,
+    };
+    if pref_src.is_some_and(|src| src.is_ipv4() != destination.is_ipv4()) {
+        return Err(Error::InvalidConfig(
+            "the preferred source must be of the family of the destination",
+        ));
+    }
+    Ok(crate::platform::RouteEntry {
+        destination,
+        prefix,
+        gateway: None,
+        metric: 0,
+        table: crate::platform::linux::route::RT_TABLE_MAIN,
+        pref_src,
+    })
+}
+
+/// Sets `offloads` with `TUNSETOFFLOAD`, without the UDP offloads if the kernel rejects them,
+/// as before Linux 6.2. Returns the offloads that were accepted.
+fn negotiate_offloads(fd: RawFd, offloads: OffloadFlags) -> io::Result<OffloadFlags> {
+    match unsafe { tunsetoffload(fd, offloads.bits() as _) } {
+        Ok(_) => Ok(offloads),
+        Err(err) if offloads.intersects(OffloadFlags::UDP) => {
+            let fallback = offloads - OffloadFlags::UDP;
+            unsafe { tunsetoffload(fd, fallback.bits() as _) }?;
+            log::debug!("UDP segmentation offload unsupported: {err}");
+            Ok(fallback)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,64 @@
+use std::io;
+use std::net::IpAddr;
+
+use crate::{Error, Result};
+
+/// Sets the DNS servers of the link `index` with `resolvectl dns`, reverting the link to
+/// its defaults with `resolvectl revert` if `servers` is empty.
+pub(crate) fn set_servers(index: u32, servers: &[IpAddr]) -> Result<()> {
+    let index = index.to_string();
+    let servers: Vec<String> = servers.iter().map(|v| v.to_string()).collect();
+    let mut args = vec![if servers.is_empty() { "revert" } else { "dns" }, &index];
+    args.extend(servers.iter().map(|v| v.as_str()));
+    resolvectl(&args)?;
+    Ok(())
+}
+
+/// Returns the DNS servers of the link `index` as reported by `resolvectl dns`.
+pub(crate) fn servers(index: u32) -> Result<Vec<IpAddr>> {
+    let out = resolvectl(&["dns", &index.to_string()])?;
+    Ok(parse_servers(&String::from_utf8_lossy(&out)))
+}
+
+fn resolvectl(args: &[&str]) -> Result<Vec<u8>> {
+    crate::run_command("resolvectl", args).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            Error::Unsupported("DNS servers without systemd-resolved")
+        } else {
+            e.into()
+        }
+    })
+}
+
+/// Parses `Link 5 (tun0): 10.0.0.1 fd00::1#dns.example`, dropping the port, interface and
+/// server name suffixes newer versions print.
+fn parse_servers(out: &str) -> Vec<IpAddr> {
+    let Some((_, servers)) = out.split_once("):") else {
+        return Vec::new();
+    };
+    servers
+        .split_whitespace()
+        .filter_map(|v| {
+            let v = v.split(['#', '%']).next()?;
+            v.parse()
+                .ok()
+                .or_else(|| v.parse::<std::net::SocketAddr>().ok().map(|v| v.ip()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_servers() {
+        let out = "Link 5 (tun0): 10.0.0.1 fd00::1 10.0.0.2#dns.example fe80::1%5\n";
+        let servers: Vec<IpAddr> = ["10.0.0.1", "fd00::1", "10.0.0.2", "fe80::1"]
+            .iter()
+            .map(|v| v.parse().unwrap())
+            .collect();
+        assert_eq!(servers, parse_servers(out));
+        assert!(parse_servers("Link 5 (tun0):\n").is_empty());
+    }
+}
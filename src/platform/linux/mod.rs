@@ -1,10 +1,30 @@
-mod sys;
+// Without `config` only the I/O path is reachable, the configuration helpers stay unused.
+#[cfg_attr(not(feature = "config"), allow(dead_code, unused_imports))]
+pub(crate) mod sys;
 
 mod checksum;
+#[cfg_attr(not(feature = "config"), allow(dead_code, unused_imports))]
 mod device;
-pub(crate) mod offload;
+#[cfg(feature = "config")]
+mod dns;
+pub mod offload;
+#[cfg(feature = "config")]
+pub(crate) mod route;
+mod teardown;
+#[cfg(feature = "config")]
+mod tunnel;
 pub use device::DeviceImpl;
+#[cfg(feature = "config")]
+pub use device::CAPTURE_ALL_FWMARK;
 pub use offload::ExpandBuffer;
 pub use offload::GROTable;
+pub use offload::OffloadFlags;
 pub use offload::IDEAL_BATCH_SIZE;
 pub use offload::VIRTIO_NET_HDR_LEN;
+pub use teardown::{TeardownReport, TeardownStep};
+#[cfg(feature = "config")]
+pub use tunnel::{Tunnel, TunnelBuilder};
+
+/// The most queues a multi-queue TUN/TAP device can have, `MAX_TAP_QUEUES` in the kernel.
+#[cfg(feature = "config")]
+pub(crate) const MAX_TAP_QUEUES: usize = 256;
@@ -1,4 +1,12 @@
-/// https://github.com/WireGuard/wireguard-go/blob/master/tun/offload_linux.go
+//! Virtio-net headers and the segmentation (GSO) and coalescing (GRO) of the packets of a
+//! device with [`offload`](crate::DeviceBuilder::offload) enabled.
+//!
+//! Every buffer read from or written to such a device with `recv`/`send` starts with a
+//! [`VirtioNetHdr`]. [`parse_virtio_hdr`] and [`gso_segment`] turn a buffer read into
+//! wire-sized IP packets, [`build_virtio_hdr`] and [`gro_coalesce`] turn IP packets into
+//! buffers to write. `recv_multiple` and `send_multiple` do the same internally.
+//!
+//! Based on <https://github.com/WireGuard/wireguard-go/blob/master/tun/offload_linux.go>.
 use crate::platform::linux::checksum::{checksum, pseudo_header_checksum_no_fold};
 use byteorder::{BigEndian, ByteOrder};
 use bytes::BytesMut;
@@ -13,6 +21,31 @@ pub const VIRTIO_NET_HDR_GSO_TCPV4: u8 = 1;
 pub const VIRTIO_NET_HDR_GSO_TCPV6: u8 = 4;
 pub const VIRTIO_NET_HDR_GSO_UDP_L4: u8 = 5;
 
+bitflags::bitflags! {
+    /// The offloads of a device, as passed to `TUNSETOFFLOAD`, see
+    /// [`DeviceImpl::active_offloads`](crate::DeviceImpl::active_offloads).
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct OffloadFlags: u32 {
+        /// Checksum offload, required by all others.
+        const CSUM = libc::TUN_F_CSUM;
+        /// TCP segmentation offload over IPv4.
+        const TSO4 = libc::TUN_F_TSO4;
+        /// TCP segmentation offload over IPv6.
+        const TSO6 = libc::TUN_F_TSO6;
+        /// UDP segmentation offload over IPv4, since Linux 6.2.
+        const USO4 = libc::TUN_F_USO4;
+        /// UDP segmentation offload over IPv6, since Linux 6.2.
+        const USO6 = libc::TUN_F_USO6;
+    }
+}
+
+impl OffloadFlags {
+    /// The TCP offloads, requested by `offload(true)`.
+    pub const TCP: Self = Self::CSUM.union(Self::TSO4).union(Self::TSO6);
+    /// The UDP segmentation offloads, also requested by `offload(true)` where supported.
+    pub const UDP: Self = Self::USO4.union(Self::USO6);
+}
+
 /// <https://github.com/WireGuard/wireguard-go/blob/master/conn/conn.go#L19>
 ///
 /// maximum number of packets handled per read and write
@@ -29,7 +62,7 @@ const TCP_FLAG_ACK: u8 = 0x10;
 ///
 /// https://github.com/torvalds/linux/blob/master/include/uapi/linux/virtio_net.h
 #[repr(C)]
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct VirtioNetHdr {
     // #define VIRTIO_NET_HDR_F_NEEDS_CSUM	1	/* Use csum_start, csum_offset */
     // #define VIRTIO_NET_HDR_F_DATA_VALID	2	/* Csum is valid */
@@ -86,7 +119,7 @@ pub const VIRTIO_NET_HDR_LEN: usize = std::mem::size_of::<VirtioNetHdr>();
 
 /// tcpFlowKey represents the key for a TCP flow.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct TcpFlowKey {
+pub(crate) struct TcpFlowKey {
     src_addr: [u8; 16],
     dst_addr: [u8; 16],
     src_port: u16,
@@ -96,7 +129,7 @@ pub struct TcpFlowKey {
 }
 
 /// tcpGROTable holds flow and coalescing information for the purposes of TCP GRO.
-pub struct TcpGROTable {
+pub(crate) struct TcpGROTable {
     items_by_flow: HashMap<TcpFlowKey, Vec<TcpGROItem>>,
     items_pool: Vec<Vec<TcpGROItem>>,
 }
@@ -214,7 +247,7 @@ impl TcpGROTable {
 /// tcpGROItem represents bookkeeping data for a TCP packet during the lifetime
 /// of a GRO evaluation across a vector of packets.
 #[derive(Debug, Clone, Copy)]
-pub struct TcpGROItem {
+pub(crate) struct TcpGROItem {
     key: TcpFlowKey,
     sent_seq: u32,   // the sequence number
     bufs_index: u16, // the index into the original bufs slice
@@ -241,7 +274,7 @@ impl TcpGROTable {
 
 /// udpFlowKey represents the key for a UDP flow.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct UdpFlowKey {
+pub(crate) struct UdpFlowKey {
     src_addr: [u8; 16], // srcAddr
     dst_addr: [u8; 16], // dstAddr
     src_port: u16,      // srcPort
@@ -250,7 +283,7 @@ pub struct UdpFlowKey {
 }
 
 ///  udpGROTable holds flow and coalescing information for the purposes of UDP GRO.
-pub struct UdpGROTable {
+pub(crate) struct UdpGROTable {
     items_by_flow: HashMap<UdpFlowKey, Vec<UdpGROItem>>,
     items_pool: Vec<Vec<UdpGROItem>>,
 }
@@ -361,7 +394,7 @@ impl UdpGROTable {
 /// udpGROItem represents bookkeeping data for a UDP packet during the lifetime
 /// of a GRO evaluation across a vector of packets.
 #[derive(Debug, Clone, Copy)]
-pub struct UdpGROItem {
+pub(crate) struct UdpGROItem {
     key: UdpFlowKey,           // udpFlowKey
     bufs_index: u16,           // the index into the original bufs slice
     num_merged: u16,           // the number of packets merged into this item
@@ -871,7 +904,7 @@ fn tcp_gro<B: ExpandBuffer>(
 
 /// applyTCPCoalesceAccounting updates bufs to account for coalescing based on the
 /// metadata found in table.
-pub fn apply_tcp_coalesce_accounting<B: ExpandBuffer>(
+pub(crate) fn apply_tcp_coalesce_accounting<B: ExpandBuffer>(
     bufs: &mut [B],
     offset: usize,
     table: &TcpGROTable,
@@ -901,7 +934,7 @@ pub fn apply_tcp_coalesce_accounting<B: ExpandBuffer>(
                     IPV4_SRC_ADDR_OFFSET
                 };
 
-                let src_addr_at = offset + addr_offset;
+                let src_addr_at = addr_offset;
                 let src_addr =
                     unsafe { &*(&pkt[src_addr_at..src_addr_at + addr_len] as *const [u8]) };
                 let dst_addr = unsafe {
@@ -949,7 +982,7 @@ pub fn apply_tcp_coalesce_accounting<B: ExpandBuffer>(
 
 // applyUDPCoalesceAccounting updates bufs to account for coalescing based on the
 // metadata found in table.
-pub fn apply_udp_coalesce_accounting<B: ExpandBuffer>(
+pub(crate) fn apply_udp_coalesce_accounting<B: ExpandBuffer>(
     bufs: &mut [B],
     offset: usize,
     table: &UdpGROTable,
@@ -979,7 +1012,7 @@ pub fn apply_udp_coalesce_accounting<B: ExpandBuffer>(
                     (4, IPV4_SRC_ADDR_OFFSET)
                 };
 
-                let src_addr_at = offset + addr_offset;
+                let src_addr_at = addr_offset;
                 let src_addr =
                     unsafe { &*(&pkt[src_addr_at..(src_addr_at + addr_len)] as *const [u8]) };
                 let dst_addr = unsafe {
@@ -1032,7 +1065,7 @@ pub fn apply_udp_coalesce_accounting<B: ExpandBuffer>(
 }
 
 #[derive(PartialEq, Eq)]
-pub enum GroCandidateType {
+pub(crate) enum GroCandidateType {
     NotGRO,
     Tcp4GRO,
     Tcp6GRO,
@@ -1040,7 +1073,7 @@ pub enum GroCandidateType {
     Udp6GRO,
 }
 
-pub fn packet_is_gro_candidate(b: &[u8], can_udp_gro: bool) -> GroCandidateType {
+pub(crate) fn packet_is_gro_candidate(b: &[u8], can_udp_gro: bool) -> GroCandidateType {
     if b.len() < 28 {
         return GroCandidateType::NotGRO;
     }
@@ -1174,7 +1207,7 @@ fn udp_gro<B: ExpandBuffer>(
 /// empty (but non-nil), and are passed in to save allocs as the caller may reset
 /// and recycle them across vectors of packets. canUDPGRO indicates if UDP GRO is
 /// supported.
-pub fn handle_gro<B: ExpandBuffer>(
+pub(crate) fn handle_gro<B: ExpandBuffer>(
     bufs: &mut [B],
     offset: usize,
     tcp_table: &mut TcpGROTable,
@@ -1223,7 +1256,7 @@ pub fn handle_gro<B: ExpandBuffer>(
 /// gsoSplit splits packets from in into outBuffs, writing the size of each
 /// element into sizes. It returns the number of buffers populated, and/or an
 /// error.
-pub fn gso_split<B: AsRef<[u8]> + AsMut<[u8]>>(
+pub(crate) fn gso_split<B: AsRef<[u8]> + AsMut<[u8]>>(
     input: &mut [u8],
     hdr: VirtioNetHdr,
     out_bufs: &mut [B],
@@ -1258,7 +1291,7 @@ pub fn gso_split<B: AsRef<[u8]> + AsMut<[u8]>>(
     let mut i = 0;
 
     while next_segment_data_at < input.len() {
-        if i == out_bufs.len() {
+        if i == out_bufs.len() || i == sizes.len() {
             return Err(io::Error::new(io::ErrorKind::Other, "ErrTooManySegments"));
         }
 
@@ -1270,7 +1303,16 @@ pub fn gso_split<B: AsRef<[u8]> + AsMut<[u8]>>(
         let total_len = hdr.hdr_len as usize + segment_data_len;
 
         sizes[i] = total_len;
-        let out = &mut out_bufs[i].as_mut()[out_offset..];
+        let out = out_bufs[i]
+            .as_mut()
+            .get_mut(out_offset..)
+            .filter(|out| out.len() >= total_len)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("segment of {total_len} bytes overflows the output buffer"),
+                )
+            })?;
 
         out[..iph_len].copy_from_slice(&input[..iph_len]);
 
@@ -1337,8 +1379,19 @@ pub fn gso_split<B: AsRef<[u8]> + AsMut<[u8]>>(
     Ok(i)
 }
 
-pub fn gso_none_checksum(in_buf: &mut [u8], csum_start: u16, csum_offset: u16) {
-    let csum_at = (csum_start + csum_offset) as usize;
+pub(crate) fn gso_none_checksum(
+    in_buf: &mut [u8],
+    csum_start: u16,
+    csum_offset: u16,
+) -> io::Result<()> {
+    let csum_at = csum_start as usize + csum_offset as usize;
+    if csum_at + 2 > in_buf.len() {
+        return Err(invalid_data(format!(
+            "end of checksum offset ({}) exceeds packet length ({})",
+            csum_at + 2,
+            in_buf.len()
+        )));
+    }
     // The initial value at the checksum offset should be summed with the
     // checksum we compute. This is typically the pseudo-header checksum.
     let initial = BigEndian::read_u16(&in_buf[csum_at..]);
@@ -1346,6 +1399,329 @@ pub fn gso_none_checksum(in_buf: &mut [u8], csum_start: u16, csum_offset: u16) {
     in_buf[csum_at + 1] = 0;
     let computed_checksum = checksum(&in_buf[csum_start as usize..], initial as u64);
     BigEndian::write_u16(&mut in_buf[csum_at..], !computed_checksum);
+    Ok(())
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Splits `input`, a packet read from a device after its virtio-net header `hdr`, into
+/// `bufs`, leaving `offset` bytes at the front of each buffer. It sets `sizes` to the size
+/// of each packet and returns the number of packets.
+///
+/// The header is checked against the packet first, a malformed one is an `InvalidData`
+/// error.
+pub(crate) fn virtio_split<B: AsRef<[u8]> + AsMut<[u8]>>(
+    mut hdr: VirtioNetHdr,
+    input: &mut [u8],
+    bufs: &mut [B],
+    sizes: &mut [usize],
+    offset: usize,
+) -> io::Result<usize> {
+    let len = input.len();
+    if hdr.gso_type == VIRTIO_NET_HDR_GSO_NONE {
+        if hdr.flags & VIRTIO_NET_HDR_F_NEEDS_CSUM != 0 {
+            // This means CHECKSUM_PARTIAL in skb context. We are responsible
+            // for computing the checksum starting at hdr.csumStart and placing
+            // at hdr.csumOffset.
+            gso_none_checksum(input, hdr.csum_start, hdr.csum_offset)?;
+        }
+        let (Some(buf), Some(size)) = (bufs.first_mut(), sizes.first_mut()) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no buffers"));
+        };
+        let Some(out) = buf.as_mut().get_mut(offset..offset + len) else {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "read len {len} overflows bufs element len {}",
+                    buf.as_ref().len()
+                ),
+            ));
+        };
+        out.copy_from_slice(input);
+        *size = len;
+        return Ok(1);
+    }
+    let is_v6 = check_gso(&mut hdr, input)?;
+    gso_split(input, hdr, bufs, sizes, offset, is_v6)
+}
+
+/// Checks the GSO header `hdr` against `input` before [`gso_split`] and returns whether the
+/// packet is IPv6. `hdr_len` is replaced by the length of the IP and transport headers.
+fn check_gso(hdr: &mut VirtioNetHdr, input: &[u8]) -> io::Result<bool> {
+    let len = input.len();
+    if hdr.gso_type != VIRTIO_NET_HDR_GSO_TCPV4
+        && hdr.gso_type != VIRTIO_NET_HDR_GSO_TCPV6
+        && hdr.gso_type != VIRTIO_NET_HDR_GSO_UDP_L4
+    {
+        return Err(invalid_data(format!(
+            "unsupported virtio GSO type: {}",
+            hdr.gso_type
+        )));
+    }
+    let (is_v6, min_iph_len) = match input.first().map(|v| v >> 4) {
+        Some(4) if hdr.gso_type != VIRTIO_NET_HDR_GSO_TCPV6 => (false, 20),
+        Some(6) if hdr.gso_type != VIRTIO_NET_HDR_GSO_TCPV4 => (true, 40),
+        ip_version => {
+            return Err(invalid_data(format!(
+                "ip header version: {ip_version:?}, GSO type: {}",
+                hdr.gso_type
+            )))
+        }
+    };
+    if (hdr.csum_start as usize) < min_iph_len {
+        return Err(invalid_data(format!(
+            "virtioNetHdr.csumStart ({}) is shorter than an IP header",
+            hdr.csum_start
+        )));
+    }
+    if hdr.gso_size == 0 {
+        return Err(invalid_data("virtioNetHdr.gsoSize is 0"));
+    }
+    // Don't trust hdr.hdrLen from the kernel as it can be equal to the length
+    // of the entire first packet when the kernel is handling it as part of a
+    // FORWARD path. Instead, parse the transport header length and add it onto
+    // csumStart, which is synonymous for IP header length.
+    let transport_len = if hdr.gso_type == VIRTIO_NET_HDR_GSO_UDP_L4 {
+        UDP_H_LEN as u16
+    } else {
+        if len <= hdr.csum_start as usize + 12 {
+            return Err(invalid_data("packet is too short"));
+        }
+        let tcp_h_len = ((input[hdr.csum_start as usize + 12] as u16) >> 4) * 4;
+        if !(20..=60).contains(&tcp_h_len) {
+            // A TCP header must be between 20 and 60 bytes in length.
+            return Err(invalid_data(format!(
+                "tcp header len is invalid: {tcp_h_len}"
+            )));
+        }
+        tcp_h_len
+    };
+    hdr.hdr_len = hdr
+        .csum_start
+        .checked_add(transport_len)
+        .ok_or_else(|| invalid_data("virtioNetHdr.csumStart is too large"))?;
+    if len < hdr.hdr_len as usize {
+        return Err(invalid_data(format!(
+            "length of packet ({len}) < virtioNetHdr.hdr_len ({})",
+            hdr.hdr_len
+        )));
+    }
+    let csum_end = hdr.csum_start as usize + hdr.csum_offset as usize + 2;
+    if csum_end > hdr.hdr_len as usize {
+        return Err(invalid_data(format!(
+            "end of checksum offset ({csum_end}) exceeds the headers ({})",
+            hdr.hdr_len
+        )));
+    }
+    Ok(is_v6)
+}
+
+/// Splits a buffer read from a device with offloads into its virtio-net header and the IP
+/// packet that follows it.
+///
+/// Returns an `InvalidData` error if `buf` is shorter than [`VIRTIO_NET_HDR_LEN`] or the
+/// header has an unsupported GSO type. The other fields are checked by [`gso_segment`].
+pub fn parse_virtio_hdr(buf: &[u8]) -> io::Result<(VirtioNetHdr, &[u8])> {
+    if buf.len() < VIRTIO_NET_HDR_LEN {
+        return Err(invalid_data(format!(
+            "length of buffer ({}) < VIRTIO_NET_HDR_LEN ({VIRTIO_NET_HDR_LEN})",
+            buf.len()
+        )));
+    }
+    let hdr = VirtioNetHdr::decode(buf)?;
+    match hdr.gso_type {
+        VIRTIO_NET_HDR_GSO_NONE
+        | VIRTIO_NET_HDR_GSO_TCPV4
+        | VIRTIO_NET_HDR_GSO_TCPV6
+        | VIRTIO_NET_HDR_GSO_UDP_L4 => Ok((hdr, &buf[VIRTIO_NET_HDR_LEN..])),
+        gso_type => Err(invalid_data(format!(
+            "unsupported virtio GSO type: {gso_type}"
+        ))),
+    }
+}
+
+/// Builds the virtio-net header to write the TCP or UDP packet `packet` in one buffer, for
+/// the kernel to split it into segments of `gso_size` payload bytes, as it does with the
+/// buffers of `send_multiple`.
+///
+/// The length fields of `packet` are set to its length, the IPv4 header checksum is
+/// recomputed and the pseudo-header checksum the kernel completes is written to the
+/// transport checksum. A packet whose payload fits into one segment, or a `gso_size` of 0,
+/// gets a header without segmentation that only asks for the checksum. IPv4 options are
+/// supported, IPv6 extension headers are not. A packet that is not TCP or UDP over IPv4 or
+/// IPv6 is an `InvalidData` error.
+///
+/// ```
+/// use tun_rs::offload::{build_virtio_hdr, VIRTIO_NET_HDR_LEN};
+/// # let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+/// # packet.extend([0; 8]);
+/// # packet.resize(4000, 0);
+/// let hdr = build_virtio_hdr(&mut packet, 1400)?;
+/// let mut buf = vec![0; VIRTIO_NET_HDR_LEN];
+/// hdr.encode(&mut buf)?;
+/// buf.extend_from_slice(&packet);
+/// // dev.send(&buf)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn build_virtio_hdr(packet: &mut [u8], gso_size: u16) -> io::Result<VirtioNetHdr> {
+    let len = packet.len();
+    let (is_v6, iph_len, protocol) = match packet.first().map(|v| v >> 4) {
+        Some(4) if len >= 20 => (false, (packet[0] & 0x0f) as usize * 4, packet[9]),
+        Some(6) if len >= 40 => (true, 40, packet[6]),
+        _ => return Err(invalid_data("not an IPv4 or IPv6 packet")),
+    };
+    if iph_len < 20 {
+        return Err(invalid_data("invalid IPv4 header length"));
+    }
+    let (transport_len, csum_offset, gso_type): (usize, u16, u8) = match protocol as i32 {
+        IPPROTO_TCP => {
+            let tcp_h_len = packet
+                .get(iph_len + 12)
+                .map_or(0, |v| (v >> 4) as usize * 4);
+            if tcp_h_len < 20 {
+                return Err(invalid_data(format!(
+                    "tcp header len is invalid: {tcp_h_len}"
+                )));
+            }
+            let gso_type = if is_v6 {
+                VIRTIO_NET_HDR_GSO_TCPV6
+            } else {
+                VIRTIO_NET_HDR_GSO_TCPV4
+            };
+            (tcp_h_len, 16, gso_type)
+        }
+        IPPROTO_UDP => (UDP_H_LEN, 6, VIRTIO_NET_HDR_GSO_UDP_L4),
+        _ => return Err(invalid_data("neither a TCP nor a UDP packet")),
+    };
+    let hdr_len = iph_len + transport_len;
+    if len < hdr_len {
+        return Err(invalid_data(format!(
+            "length of packet ({len}) < length of its headers ({hdr_len})"
+        )));
+    }
+    let transport_total = u16::try_from(len - iph_len)
+        .ok()
+        .filter(|_| is_v6 || len <= u16::MAX as usize)
+        .ok_or_else(|| invalid_data(format!("packet of {len} bytes is too long")))?;
+    let (addr_offset, addr_len) = if is_v6 {
+        (IPV6_SRC_ADDR_OFFSET, 16)
+    } else {
+        (IPV4_SRC_ADDR_OFFSET, 4)
+    };
+    if is_v6 {
+        BigEndian::write_u16(&mut packet[4..6], transport_total);
+    } else {
+        BigEndian::write_u16(&mut packet[2..4], len as u16);
+        packet[10] = 0;
+        packet[11] = 0;
+        let iph_csum = !checksum(&packet[..iph_len], 0);
+        BigEndian::write_u16(&mut packet[10..12], iph_csum);
+    }
+    if gso_type == VIRTIO_NET_HDR_GSO_UDP_L4 {
+        BigEndian::write_u16(&mut packet[iph_len + 4..iph_len + 6], transport_total);
+    }
+    let addrs = packet[addr_offset..addr_offset + 2 * addr_len].to_vec();
+    let psum = pseudo_header_checksum_no_fold(
+        protocol,
+        &addrs[..addr_len],
+        &addrs[addr_len..],
+        transport_total,
+    );
+    let csum_at = iph_len + csum_offset as usize;
+    BigEndian::write_u16(&mut packet[csum_at..csum_at + 2], checksum(&[], psum));
+    let segmented = gso_size > 0 && len - hdr_len > gso_size as usize;
+    Ok(VirtioNetHdr {
+        flags: VIRTIO_NET_HDR_F_NEEDS_CSUM,
+        gso_type: if segmented {
+            gso_type
+        } else {
+            VIRTIO_NET_HDR_GSO_NONE
+        },
+        hdr_len: hdr_len as u16,
+        gso_size: if segmented { gso_size } else { 0 },
+        csum_start: iph_len as u16,
+        csum_offset,
+    })
+}
+
+/// Turns `packet`, read from a device after its virtio-net header `hdr`, into wire-sized IP
+/// packets with complete checksums, as `recv_multiple` does.
+///
+/// A header without segmentation yields `packet` itself, with its checksum completed if
+/// the header asks for it. A header that does not match the packet, e.g. with a `gso_size`
+/// of 0, a checksum outside of the transport header or a GSO type of the other IP version,
+/// is an `InvalidData` error.
+///
+/// ```no_run
+/// use tun_rs::offload::{gso_segment, parse_virtio_hdr};
+/// # fn main() -> std::io::Result<()> {
+/// let dev = tun_rs::DeviceBuilder::new().offload(true).build_sync()?;
+/// let mut buf = vec![0; 65535 + tun_rs::VIRTIO_NET_HDR_LEN];
+/// let len = dev.recv(&mut buf)?;
+/// let (hdr, packet) = parse_virtio_hdr(&buf[..len])?;
+/// for segment in gso_segment(&hdr, packet)? {
+///     println!("{} bytes", segment.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn gso_segment(hdr: &VirtioNetHdr, packet: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut input = packet.to_vec();
+    if hdr.gso_type == VIRTIO_NET_HDR_GSO_NONE {
+        if hdr.flags & VIRTIO_NET_HDR_F_NEEDS_CSUM != 0 {
+            gso_none_checksum(&mut input, hdr.csum_start, hdr.csum_offset)?;
+        }
+        return Ok(vec![input]);
+    }
+    let mut hdr = *hdr;
+    let is_v6 = check_gso(&mut hdr, &input)?;
+    let count = (input.len() - hdr.hdr_len as usize).div_ceil(hdr.gso_size as usize);
+    let mut bufs = vec![vec![0; hdr.hdr_len as usize + hdr.gso_size as usize]; count];
+    let mut sizes = vec![0; count];
+    let n = gso_split(&mut input, hdr, &mut bufs, &mut sizes, 0, is_v6)?;
+    bufs.truncate(n);
+    for (buf, size) in bufs.iter_mut().zip(sizes) {
+        buf.truncate(size);
+    }
+    Ok(bufs)
+}
+
+/// Coalesces consecutive TCP segments of the same flow in `packets`, and UDP datagrams if
+/// `udp` is set, into as few buffers as possible, as `send_multiple` does.
+///
+/// Every returned buffer starts with a virtio-net header and can be written to a device with
+/// offloads enabled with `send`. Packets that cannot be coalesced, including those with
+/// invalid checksums, are returned unchanged behind a header without segmentation. UDP
+/// needs kernel support for UDP segmentation offload, see
+/// [`DeviceImpl::udp_gso`](crate::DeviceImpl::udp_gso).
+pub fn gro_coalesce(packets: &[&[u8]], udp: bool) -> io::Result<Vec<Vec<u8>>> {
+    // Coalescing only appends to a buffer that already has room for a maximum sized packet.
+    let capacity = 2 * VIRTIO_NET_HDR_LEN + u16::MAX as usize;
+    let mut bufs: Vec<Vec<u8>> = packets
+        .iter()
+        .map(|packet| {
+            let mut buf = Vec::with_capacity(capacity.max(VIRTIO_NET_HDR_LEN + packet.len()));
+            buf.resize(VIRTIO_NET_HDR_LEN, 0);
+            buf.extend_from_slice(packet);
+            buf
+        })
+        .collect();
+    let mut table = GROTable::new();
+    handle_gro(
+        &mut bufs,
+        VIRTIO_NET_HDR_LEN,
+        &mut table.tcp_gro_table,
+        &mut table.udp_gro_table,
+        udp,
+        &mut table.to_write,
+    )?;
+    Ok(table
+        .to_write
+        .iter()
+        .map(|&i| std::mem::take(&mut bufs[i]))
+        .collect())
 }
 
 /// `send_multiple` Using GROTable to assist in writing data
@@ -1429,3 +1805,184 @@ impl ExpandBuffer for &mut Vec<u8> {
         self.extend_from_slice(extend)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Headers as read from a TUN device with TUNSETOFFLOAD, in little-endian byte order.
+    const TCP4_HDR: [u8; VIRTIO_NET_HDR_LEN] = [1, 1, 40, 0, 0xa8, 0x05, 20, 0, 16, 0];
+    const TCP6_HDR: [u8; VIRTIO_NET_HDR_LEN] = [1, 4, 60, 0, 0x94, 0x05, 40, 0, 16, 0];
+    const UDP4_HDR: [u8; VIRTIO_NET_HDR_LEN] = [1, 5, 28, 0, 0xb4, 0x05, 20, 0, 6, 0];
+
+    /// A TCP or UDP packet with `payload_len` bytes of payload and a valid checksum.
+    fn packet(is_v6: bool, protocol: u8, payload_len: usize) -> Vec<u8> {
+        let mut pkt = if is_v6 {
+            let mut pkt = vec![0x60, 0, 0, 0, 0, 0, protocol, 64];
+            pkt.extend([0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+            pkt.extend([0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+            pkt
+        } else {
+            vec![
+                0x45, 0, 0, 0, 0, 1, 0x40, 0, 64, protocol, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+            ]
+        };
+        if protocol == IPPROTO_TCP as u8 {
+            // Ports 1000 -> 2000, sequence number 1, ACK.
+            pkt.extend([3, 0xe8, 7, 0xd0, 0, 0, 0, 1, 0, 0, 0, 1, 0x50, TCP_FLAG_ACK]);
+            pkt.extend([0xff, 0xff, 0, 0, 0, 0]);
+        } else {
+            pkt.extend([3, 0xe8, 7, 0xd0, 0, 0, 0, 0]);
+        }
+        pkt.extend((0..payload_len).map(|i| i as u8));
+        let hdr = build_virtio_hdr(&mut pkt, 0).unwrap();
+        gso_none_checksum(&mut pkt, hdr.csum_start, hdr.csum_offset).unwrap();
+        pkt
+    }
+
+    fn assert_valid(segment: &[u8], is_v6: bool, protocol: u8) {
+        let iph_len = if is_v6 { 40 } else { 20 };
+        assert!(checksum_valid(segment, iph_len, protocol, is_v6));
+        if !is_v6 {
+            assert_eq!(0, !checksum(&segment[..20], 0));
+            assert_eq!(segment.len(), BigEndian::read_u16(&segment[2..4]) as usize);
+        } else {
+            assert_eq!(
+                segment.len() - 40,
+                BigEndian::read_u16(&segment[4..6]) as usize
+            );
+        }
+    }
+
+    fn round_trip(is_v6: bool, protocol: u8, captured: [u8; VIRTIO_NET_HDR_LEN]) {
+        let (captured, rest) = parse_virtio_hdr(&captured).unwrap();
+        assert!(rest.is_empty());
+        let gso_size = 1000;
+        let original = packet(is_v6, protocol, 2500);
+        let mut pkt = original.clone();
+        let hdr = build_virtio_hdr(&mut pkt, gso_size).unwrap();
+        assert_eq!(
+            VirtioNetHdr {
+                gso_size,
+                ..captured
+            },
+            hdr
+        );
+
+        let segments = gso_segment(&hdr, &pkt).unwrap();
+        let headers = hdr.hdr_len as usize;
+        let lens: Vec<usize> = segments.iter().map(|v| v.len() - headers).collect();
+        assert_eq!(vec![1000, 1000, 500], lens);
+        for segment in &segments {
+            assert_valid(segment, is_v6, protocol);
+        }
+        let payload: Vec<u8> = segments
+            .iter()
+            .flat_map(|v| v[headers..].iter().copied())
+            .collect();
+        assert_eq!(original[headers..], payload[..]);
+
+        let segments: Vec<&[u8]> = segments.iter().map(|v| &v[..]).collect();
+        let coalesced = gro_coalesce(&segments, true).unwrap();
+        assert_eq!(1, coalesced.len());
+        let (coalesced_hdr, coalesced) = parse_virtio_hdr(&coalesced[0]).unwrap();
+        assert_eq!(hdr, coalesced_hdr);
+        assert_eq!(original[headers..], coalesced[headers..]);
+        // The kernel segments it again, which is what gso_segment does.
+        let again = gso_segment(&coalesced_hdr, coalesced).unwrap();
+        assert_eq!(3, again.len());
+        again.iter().for_each(|v| assert_valid(v, is_v6, protocol));
+    }
+
+    #[test]
+    fn tcp4_round_trip() {
+        round_trip(false, IPPROTO_TCP as u8, TCP4_HDR);
+    }
+
+    #[test]
+    fn tcp6_round_trip() {
+        round_trip(true, IPPROTO_TCP as u8, TCP6_HDR);
+    }
+
+    #[test]
+    fn udp4_round_trip() {
+        round_trip(false, IPPROTO_UDP as u8, UDP4_HDR);
+    }
+
+    #[test]
+    fn coalesced_ipv6_pseudo_header_checksum() {
+        // The addresses of the pseudo-header are read behind the virtio-net header, not
+        // `offset` bytes further into the IP header.
+        for protocol in [IPPROTO_TCP as u8, IPPROTO_UDP as u8] {
+            let mut pkt = packet(true, protocol, 2000);
+            let hdr = build_virtio_hdr(&mut pkt, 1000).unwrap();
+            let segments = gso_segment(&hdr, &pkt).unwrap();
+            let segments: Vec<&[u8]> = segments.iter().map(|v| &v[..]).collect();
+            let coalesced = gro_coalesce(&segments, true).unwrap();
+            assert_eq!(1, coalesced.len());
+            let (hdr, coalesced) = parse_virtio_hdr(&coalesced[0]).unwrap();
+            let mut coalesced = coalesced.to_vec();
+            gso_none_checksum(&mut coalesced, hdr.csum_start, hdr.csum_offset).unwrap();
+            assert!(checksum_valid(&coalesced, 40, protocol, true));
+        }
+    }
+
+    #[test]
+    fn single_segment() {
+        let mut pkt = packet(false, IPPROTO_UDP as u8, 100);
+        let original = pkt.clone();
+        let hdr = build_virtio_hdr(&mut pkt, 1000).unwrap();
+        assert_eq!(VIRTIO_NET_HDR_GSO_NONE, hdr.gso_type);
+        assert_eq!(vec![original], gso_segment(&hdr, &pkt).unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let (tcp4, _) = parse_virtio_hdr(&TCP4_HDR).unwrap();
+        let mut pkt = packet(false, IPPROTO_TCP as u8, 3000);
+        build_virtio_hdr(&mut pkt, 1000).unwrap();
+        let cases = [
+            VirtioNetHdr {
+                gso_size: 0,
+                ..tcp4
+            },
+            VirtioNetHdr {
+                csum_offset: 40,
+                ..tcp4
+            },
+            VirtioNetHdr {
+                csum_start: 10,
+                ..tcp4
+            },
+            VirtioNetHdr {
+                csum_start: u16::MAX,
+                ..tcp4
+            },
+            VirtioNetHdr {
+                gso_type: VIRTIO_NET_HDR_GSO_TCPV6,
+                ..tcp4
+            },
+            VirtioNetHdr {
+                gso_type: 3,
+                ..tcp4
+            },
+            VirtioNetHdr {
+                gso_type: VIRTIO_NET_HDR_GSO_NONE,
+                csum_offset: u16::MAX,
+                ..tcp4
+            },
+        ];
+        for hdr in cases {
+            let err = gso_segment(&hdr, &pkt).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind(), "{hdr:?}");
+        }
+        let err = gso_segment(&tcp4, &pkt[..30]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(parse_virtio_hdr(&TCP4_HDR[..4]).is_err());
+        assert!(parse_virtio_hdr(&[0, 3, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(build_virtio_hdr(&mut [0x45; 10], 1000).is_err());
+        let mut icmp = packet(false, IPPROTO_UDP as u8, 10);
+        icmp[9] = 1;
+        assert!(build_virtio_hdr(&mut icmp, 1000).is_err());
+    }
+}
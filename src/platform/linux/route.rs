@@ -0,0 +1,493 @@
+use crate::platform::RouteEntry;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+const NLMSG_HDR_LEN: usize = 16;
+const RTMSG_LEN: usize = 12;
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// The table the kernel puts routes in by default, `RT_TABLE_MAIN`.
+pub(crate) const RT_TABLE_MAIN: u32 = 254;
+
+/// Builds a netlink request of type `kind`, whose header is an `rtmsg`, a `fib_rule_hdr`
+/// or an `ifaddrmsg`.
+fn request(kind: u16, flags: libc::c_int, header: &[u8], attrs: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut req = vec![0u8; NLMSG_HDR_LEN];
+    req[4..6].copy_from_slice(&kind.to_ne_bytes());
+    req[6..8].copy_from_slice(&((libc::NLM_F_REQUEST | flags) as u16).to_ne_bytes());
+    req[8..12].copy_from_slice(&1u32.to_ne_bytes());
+    req.extend(header);
+    for (kind, data) in attrs {
+        req.extend((4 + data.len() as u16).to_ne_bytes());
+        req.extend(kind.to_ne_bytes());
+        req.extend(*data);
+        req.resize(align(req.len()), 0);
+    }
+    let len = req.len() as u32;
+    req[0..4].copy_from_slice(&len.to_ne_bytes());
+    req
+}
+
+fn socket() -> io::Result<OwnedFd> {
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+/// Receives the next datagram of replies into `buf`, returning its length.
+fn recv(socket: &OwnedFd, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let n = unsafe { libc::recv(socket.as_raw_fd(), buf.as_mut_ptr() as _, buf.len(), 0) };
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Passes the type and payload of every netlink message in `msgs` to `on_message`.
+fn for_each_message(
+    mut msgs: &[u8],
+    mut on_message: impl FnMut(libc::c_int, &[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    while msgs.len() >= NLMSG_HDR_LEN {
+        let len = u32::from_ne_bytes(msgs[0..4].try_into().unwrap()) as usize;
+        let kind = u16::from_ne_bytes(msgs[4..6].try_into().unwrap());
+        if len < NLMSG_HDR_LEN || len > msgs.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated netlink message",
+            ));
+        }
+        on_message(kind as libc::c_int, &msgs[NLMSG_HDR_LEN..len])?;
+        msgs = &msgs[align(len).min(msgs.len())..];
+    }
+    Ok(())
+}
+
+/// The error code of an `NLMSG_ERROR` message, 0 for an acknowledgement.
+fn errno(payload: &[u8]) -> i32 {
+    -payload
+        .get(0..4)
+        .map_or(0, |v| i32::from_ne_bytes(v.try_into().unwrap()))
+}
+
+/// Sends `req` on a new netlink socket and passes the payload of every `RTM_NEWROUTE`
+/// reply to `on_route`, until the dump is done or, for other requests, the first reply.
+fn transact(req: &[u8], mut on_route: impl FnMut(&[u8])) -> io::Result<()> {
+    // NLM_F_DUMP is two bits that mean NLM_F_REPLACE and NLM_F_EXCL on new requests.
+    let dump_flags = libc::NLM_F_DUMP as u16;
+    let dump = u16::from_ne_bytes([req[6], req[7]]) & dump_flags == dump_flags;
+    let socket = socket()?;
+    if unsafe { libc::send(socket.as_raw_fd(), req.as_ptr() as _, req.len(), 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut buf = vec![0u8; 32 * 1024];
+    loop {
+        let n = recv(&socket, &mut buf)?;
+        let mut done = false;
+        for_each_message(&buf[..n], |kind, payload| {
+            match kind {
+                libc::NLMSG_DONE => done = true,
+                libc::NLMSG_ERROR => match errno(payload) {
+                    0 => {}
+                    errno => return Err(io::Error::from_raw_os_error(errno)),
+                },
+                k if k == libc::RTM_NEWROUTE as libc::c_int && !done => on_route(payload),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        if done || !dump {
+            return Ok(());
+        }
+    }
+}
+
+/// Dumps the unicast routes of every table with their output interface, with
+/// `RTM_GETROUTE`.
+pub(crate) fn dump_routes() -> io::Result<Vec<(Option<u32>, RouteEntry)>> {
+    // An rtmsg of family AF_UNSPEC dumps the IPv4 and the IPv6 routes.
+    let req = request(libc::RTM_GETROUTE, libc::NLM_F_DUMP, &[0; RTMSG_LEN], &[]);
+    let mut routes = Vec::new();
+    transact(&req, |msg| {
+        if let Some((oif, libc::RTN_UNICAST, route)) = parse_route(msg) {
+            routes.push((oif, route));
+        }
+    })?;
+    Ok(routes)
+}
+
+/// Returns the unicast routes of every table whose output interface is `index`.
+pub(crate) fn routes(index: u32) -> io::Result<Vec<RouteEntry>> {
+    Ok(dump_routes()?
+        .into_iter()
+        .filter(|(oif, _)| *oif == Some(index))
+        .map(|(_, route)| route)
+        .collect())
+}
+
+fn family(addr: IpAddr) -> libc::c_int {
+    match addr {
+        IpAddr::V4(_) => libc::AF_INET,
+        IpAddr::V6(_) => libc::AF_INET6,
+    }
+}
+
+fn octets(addr: IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    }
+}
+
+/// Adds `route` out of interface `oif` to its table, replacing a route to the same
+/// destination (`RTM_NEWROUTE`), or removes it again (`RTM_DELROUTE`).
+pub(crate) fn change_route(add: bool, oif: u32, route: &RouteEntry) -> io::Result<()> {
+    transact(&route_request(add, oif, route), |_| {})
+}
+
+/// Adds `route` out of interface `oif` like [`change_route`], failing with `EEXIST` instead
+/// of replacing a route with the same destination and metric.
+pub(crate) fn create_route(oif: u32, route: &RouteEntry) -> io::Result<()> {
+    let mut req = route_request(true, oif, route);
+    let flags =
+        (libc::NLM_F_REQUEST | libc::NLM_F_ACK | libc::NLM_F_CREATE | libc::NLM_F_EXCL) as u16;
+    req[6..8].copy_from_slice(&flags.to_ne_bytes());
+    transact(&req, |_| {})
+}
+
+/// Adds or removes `routes` out of interface `oif` like [`change_route`], packing as many
+/// requests into one datagram as fit and reading their acknowledgements together.
+///
+/// Removing a route that does not exist is not an error. Returns the first error the
+/// kernel reports, the other routes of the same datagram are still changed.
+pub(crate) fn change_routes(add: bool, oif: u32, routes: &[RouteEntry]) -> io::Result<()> {
+    const BATCH_LEN: usize = 16 * 1024;
+    let socket = socket()?;
+    let mut buf = vec![0u8; 32 * 1024];
+    let mut routes = routes.iter().zip(1u32..).peekable();
+    let mut batch = Vec::with_capacity(BATCH_LEN + 256);
+    while routes.peek().is_some() {
+        batch.clear();
+        let mut pending = 0;
+        while let Some((route, seq)) = routes.next_if(|_| batch.len() < BATCH_LEN) {
+            let mut req = route_request(add, oif, route);
+            req[8..12].copy_from_slice(&seq.to_ne_bytes());
+            batch.extend(req);
+            pending += 1;
+        }
+        if unsafe { libc::send(socket.as_raw_fd(), batch.as_ptr() as _, batch.len(), 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        while pending > 0 {
+            let n = recv(&socket, &mut buf)?;
+            for_each_message(&buf[..n], |kind, payload| {
+                if kind != libc::NLMSG_ERROR {
+                    return Ok(());
+                }
+                pending -= 1;
+                match errno(payload) {
+                    0 => Ok(()),
+                    libc::ESRCH if !add => Ok(()),
+                    errno => Err(io::Error::from_raw_os_error(errno)),
+                }
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// The `RTM_NEWROUTE` or `RTM_DELROUTE` request of [`change_route`].
+fn route_request(add: bool, oif: u32, route: &RouteEntry) -> Vec<u8> {
+    // Tables above 255 only fit into RTA_TABLE.
+    let table = u8::try_from(route.table).unwrap_or(libc::RT_TABLE_UNSPEC);
+    let header = [
+        family(route.destination) as u8,
+        route.prefix,
+        0,
+        0,
+        table,
+        libc::RTPROT_BOOT,
+        libc::RT_SCOPE_UNIVERSE,
+        libc::RTN_UNICAST,
+        0,
+        0,
+        0,
+        0,
+    ];
+    let destination = octets(route.destination);
+    let gateway = route.gateway.map(octets);
+    let pref_src = route.pref_src.map(octets);
+    let oif = oif.to_ne_bytes();
+    let metric = route.metric.to_ne_bytes();
+    let table = route.table.to_ne_bytes();
+    let mut attrs = vec![
+        (libc::RTA_DST, &destination[..]),
+        (libc::RTA_OIF, &oif[..]),
+        (libc::RTA_PRIORITY, &metric[..]),
+        (libc::RTA_TABLE, &table[..]),
+    ];
+    if let Some(gateway) = &gateway {
+        attrs.push((libc::RTA_GATEWAY, gateway));
+    }
+    if let Some(pref_src) = &pref_src {
+        attrs.push((libc::RTA_PREFSRC, pref_src));
+    }
+    let (kind, flags) = if add {
+        (libc::RTM_NEWROUTE, libc::NLM_F_CREATE | libc::NLM_F_REPLACE)
+    } else {
+        (libc::RTM_DELROUTE, 0)
+    };
+    request(kind, flags | libc::NLM_F_ACK, &header, &attrs)
+}
+
+/// Adds (`RTM_NEWRULE`) or removes (`RTM_DELRULE`) the policy rule
+/// `pref <pref> fwmark <mark> lookup <table>` of `family`.
+pub(crate) fn change_rule(
+    add: bool,
+    family: libc::c_int,
+    pref: u32,
+    mark: u32,
+    table: u32,
+) -> io::Result<()> {
+    let mark = mark.to_ne_bytes();
+    // FRA_FWMARK
+    rule(add, family, 0, pref, table, &[(10, &mark)])
+}
+
+/// Like [`change_rule`] for `pref <pref> not fwmark <mark> lookup <table>`, which matches
+/// the traffic without the mark.
+pub(crate) fn change_unmarked_rule(
+    add: bool,
+    family: libc::c_int,
+    pref: u32,
+    mark: u32,
+    table: u32,
+) -> io::Result<()> {
+    const FIB_RULE_INVERT: u32 = 2;
+    let mark = mark.to_ne_bytes();
+    rule(add, family, FIB_RULE_INVERT, pref, table, &[(10, &mark)])
+}
+
+/// Like [`change_rule`] for `pref <pref> lookup <table> suppress_prefixlength <len>`, which
+/// ignores the routes of `table` with a prefix of at most `len`.
+pub(crate) fn change_suppress_rule(
+    add: bool,
+    family: libc::c_int,
+    pref: u32,
+    table: u32,
+    len: u32,
+) -> io::Result<()> {
+    let len = len.to_ne_bytes();
+    // FRA_SUPPRESS_PREFIXLEN
+    rule(add, family, 0, pref, table, &[(14, &len)])
+}
+
+/// Sends the `RTM_NEWRULE` or `RTM_DELRULE` request of a rule looking up `table`.
+fn rule(
+    add: bool,
+    family: libc::c_int,
+    flags: u32,
+    pref: u32,
+    table: u32,
+    attrs: &[(u16, &[u8])],
+) -> io::Result<()> {
+    // A fib_rule_hdr, its action is FR_ACT_TO_TBL.
+    let mut header = vec![family as u8, 0, 0, 0, 0, 0, 0, 1];
+    header.extend(flags.to_ne_bytes());
+    let pref = pref.to_ne_bytes();
+    let table = table.to_ne_bytes();
+    // FRA_PRIORITY and FRA_TABLE.
+    let attrs = [&[(6, &pref[..]), (15, &table[..])], attrs].concat();
+    let (kind, rule_flags) = if add {
+        (libc::RTM_NEWRULE, libc::NLM_F_CREATE | libc::NLM_F_EXCL)
+    } else {
+        (libc::RTM_DELRULE, 0)
+    };
+    transact(
+        &request(kind, rule_flags | libc::NLM_F_ACK, &header, &attrs),
+        |_| {},
+    )
+}
+
+/// Adds `address/prefix` to interface `index` (`RTM_NEWADDR`), failing with `EEXIST` if it
+/// is already assigned, or removes `address` from it (`RTM_DELADDR`). `peer` is the other end
+/// of a point-to-point link.
+pub(crate) fn change_address(
+    add: bool,
+    index: u32,
+    address: IpAddr,
+    prefix: u8,
+    peer: Option<IpAddr>,
+) -> io::Result<()> {
+    // An ifaddrmsg: family, prefix length, flags, scope and interface index.
+    let mut header = vec![family(address) as u8, prefix, 0, libc::RT_SCOPE_UNIVERSE];
+    header.extend(index.to_ne_bytes());
+    let local = octets(address);
+    let peer = octets(peer.unwrap_or(address));
+    let (kind, flags, attrs) = if add {
+        (
+            libc::RTM_NEWADDR,
+            libc::NLM_F_CREATE | libc::NLM_F_EXCL,
+            vec![
+                (libc::IFA_LOCAL, &local[..]),
+                (libc::IFA_ADDRESS, &peer[..]),
+            ],
+        )
+    } else {
+        // Without IFA_ADDRESS the kernel matches the address regardless of its prefix.
+        (libc::RTM_DELADDR, 0, vec![(libc::IFA_LOCAL, &local[..])])
+    };
+    transact(
+        &request(kind, flags | libc::NLM_F_ACK, &header, &attrs),
+        |_| {},
+    )
+}
+
+/// Returns the interface the kernel sends a packet to `destination` out of, like
+/// `ip route get`, for a socket with firewall mark `mark`. `None` if it is unreachable.
+pub(crate) fn output_interface(destination: IpAddr, mark: u32) -> io::Result<Option<u32>> {
+    let addr = octets(destination);
+    let mark = mark.to_ne_bytes();
+    let mut header = [0; RTMSG_LEN];
+    header[0] = family(destination) as u8;
+    header[1] = addr.len() as u8 * 8;
+    let req = request(
+        libc::RTM_GETROUTE,
+        0,
+        &header,
+        &[(libc::RTA_DST, &addr), (libc::RTA_MARK, &mark)],
+    );
+    let mut oif = None;
+    match transact(&req, |msg| {
+        oif = parse_route(msg).and_then(|(oif, _, _)| oif)
+    }) {
+        Ok(()) => Ok(oif),
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::ENETUNREACH | libc::EHOSTUNREACH)
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Parses the `rtmsg` and attributes of an `RTM_NEWROUTE` message into its output
+/// interface, its route type (`RTN_*`) and the route.
+fn parse_route(msg: &[u8]) -> Option<(Option<u32>, u8, RouteEntry)> {
+    if msg.len() < RTMSG_LEN {
+        return None;
+    }
+    let family = msg[0] as libc::c_int;
+    let prefix = msg[1];
+    let route_type = msg[7];
+    let mut table = msg[4] as u32;
+    let mut oif = None;
+    let mut destination = None;
+    let mut gateway = None;
+    let mut metric = 0;
+    let mut pref_src = None;
+    let mut attrs = &msg[RTMSG_LEN..];
+    while attrs.len() >= 4 {
+        let len = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+        let kind = u16::from_ne_bytes([attrs[2], attrs[3]]);
+        if len < 4 || len > attrs.len() {
+            break;
+        }
+        let data = &attrs[4..len];
+        let value = || data.try_into().ok().map(u32::from_ne_bytes);
+        match kind {
+            libc::RTA_DST => destination = parse_addr(family, data),
+            libc::RTA_GATEWAY => gateway = parse_addr(family, data),
+            libc::RTA_PREFSRC => pref_src = parse_addr(family, data),
+            libc::RTA_OIF => oif = value(),
+            libc::RTA_PRIORITY => metric = value().unwrap_or(0),
+            // The rtmsg only has room for tables below 256.
+            libc::RTA_TABLE => table = value().unwrap_or(table),
+            _ => {}
+        }
+        attrs = &attrs[align(len).min(attrs.len())..];
+    }
+    let destination = match (destination, family) {
+        (Some(destination), _) => destination,
+        (None, libc::AF_INET) => Ipv4Addr::UNSPECIFIED.into(),
+        (None, libc::AF_INET6) => Ipv6Addr::UNSPECIFIED.into(),
+        _ => return None,
+    };
+    Some((
+        oif,
+        route_type,
+        RouteEntry {
+            destination,
+            prefix,
+            gateway,
+            metric,
+            table,
+            pref_src,
+        },
+    ))
+}
+
+fn parse_addr(family: libc::c_int, data: &[u8]) -> Option<IpAddr> {
+    match family {
+        libc::AF_INET => <[u8; 4]>::try_from(data).ok().map(IpAddr::from),
+        libc::AF_INET6 => <[u8; 16]>::try_from(data).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_route_attributes() {
+        // 10.26.0.0/16 via 10.26.1.1 dev 7 src 10.26.1.2 metric 100 in table main.
+        let mut msg = vec![libc::AF_INET as u8, 16, 0, 0, 254, 3, 0, libc::RTN_UNICAST];
+        msg.extend([0; 4]);
+        for (kind, data) in [
+            (libc::RTA_DST, vec![10, 26, 0, 0]),
+            (libc::RTA_GATEWAY, vec![10, 26, 1, 1]),
+            (libc::RTA_OIF, 7u32.to_ne_bytes().to_vec()),
+            (libc::RTA_PREFSRC, vec![10, 26, 1, 2]),
+            (libc::RTA_PRIORITY, 100u32.to_ne_bytes().to_vec()),
+        ] {
+            msg.extend((4 + data.len() as u16).to_ne_bytes());
+            msg.extend(kind.to_ne_bytes());
+            msg.extend(data);
+        }
+        let (oif, route_type, route) = parse_route(&msg).unwrap();
+        assert_eq!(Some(7), oif);
+        assert_eq!(libc::RTN_UNICAST, route_type);
+        assert_eq!(
+            RouteEntry {
+                destination: Ipv4Addr::new(10, 26, 0, 0).into(),
+                prefix: 16,
+                gateway: Some(Ipv4Addr::new(10, 26, 1, 1).into()),
+                metric: 100,
+                table: 254,
+                pref_src: Some(Ipv4Addr::new(10, 26, 1, 2).into()),
+            },
+            route
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use libc::{c_int, ifreq, in6_ifreq};
+use libc::{c_int, ifreq, in6_ifreq, sock_fprog};
 use nix::{ioctl_read, ioctl_read_bad, ioctl_write_ptr, ioctl_write_ptr_bad};
 
 ioctl_read_bad!(siocgifflags, 0x8913, ifreq);
@@ -20,6 +20,7 @@ ioctl_write_ptr_bad!(siocsifmtu, 0x8922, ifreq);
 ioctl_write_ptr_bad!(siocsifname, 0x8923, ifreq);
 
 ioctl_write_ptr_bad!(siocsifhwaddr, 0x8924, ifreq);
+ioctl_read_bad!(siocgifhwaddr, 0x8927, ifreq);
 ioctl_read_bad!(tx_queue_len, 0x8942, ifreq);
 ioctl_write_ptr_bad!(change_tx_queue_len, 0x8943, ifreq);
 
@@ -30,4 +31,8 @@ ioctl_write_ptr!(tunsetpersist, b'T', 203, c_int);
 ioctl_write_ptr!(tunsetowner, b'T', 204, c_int);
 ioctl_write_ptr!(tunsetgroup, b'T', 206, c_int);
 ioctl_write_ptr!(tunsetoffload, b'T', 208, c_int);
+ioctl_write_ptr!(tunattachfilter, b'T', 213, sock_fprog);
+ioctl_write_ptr!(tundetachfilter, b'T', 214, sock_fprog);
 ioctl_write_ptr!(tunsetvnethdrsz, b'T', 216, c_int);
+ioctl_write_ptr!(tunsetqueue, b'T', 217, c_int);
+ioctl_write_ptr!(tunsetcarrier, b'T', 226, c_int);
@@ -0,0 +1,131 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::platform::RouteEntry;
+
+/// A step of the teardown of a device, see [`TeardownReport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TeardownStep {
+    /// Removes a route added with `add_route` or `add_peer_routes`.
+    RemoveRoute(IpAddr, u8),
+    /// Removes an address the device assigned.
+    RemoveAddress(IpAddr),
+    /// Brings the interface down, if the device brought it up.
+    Down,
+}
+
+/// The outcome of [`SyncDevice::close`](crate::SyncDevice::close) or of the cleanup when a
+/// device built with [`cleanup_on_drop`](crate::DeviceBuilder::cleanup_on_drop) is dropped.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct TeardownReport {
+    /// The steps that succeeded, in order.
+    pub completed: Vec<TeardownStep>,
+    /// The steps that failed with their errors, the configuration they undo is still in place.
+    pub failed: Vec<(TeardownStep, crate::Error)>,
+}
+
+impl TeardownReport {
+    /// Returns whether every step succeeded.
+    pub fn is_clean(&self) -> bool {
+        self.failed.is_empty()
+    }
+    #[cfg(feature = "config")]
+    pub(crate) fn push(&mut self, step: TeardownStep, result: crate::Result<()>) {
+        match result {
+            Ok(()) => self.completed.push(step),
+            Err(e) => self.failed.push((step, e)),
+        }
+    }
+}
+
+/// The configuration applied through a device, in the order it was applied.
+#[derive(Default)]
+pub(crate) struct Applied {
+    routes: Mutex<Vec<RouteEntry>>,
+    addresses: Mutex<Vec<IpAddr>>,
+    leak: AtomicBool,
+}
+
+#[cfg_attr(not(feature = "config"), allow(dead_code))]
+impl Applied {
+    pub(crate) fn route_added(&self, route: RouteEntry) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.retain(|v| !same_route(v, &route));
+        routes.push(route);
+    }
+    pub(crate) fn route_removed(&self, route: &RouteEntry) {
+        self.routes
+            .lock()
+            .unwrap()
+            .retain(|v| !same_route(v, route));
+    }
+    pub(crate) fn address_added(&self, address: IpAddr) {
+        let mut addresses = self.addresses.lock().unwrap();
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    pub(crate) fn address_removed(&self, address: IpAddr) {
+        self.addresses.lock().unwrap().retain(|v| *v != address);
+    }
+    /// Takes the routes and the addresses to undo, the latest first.
+    pub(crate) fn take(&self) -> (Vec<RouteEntry>, Vec<IpAddr>) {
+        let mut routes = std::mem::take(&mut *self.routes.lock().unwrap());
+        let mut addresses = std::mem::take(&mut *self.addresses.lock().unwrap());
+        routes.reverse();
+        addresses.reverse();
+        (routes, addresses)
+    }
+    pub(crate) fn set_leak(&self, leak: bool) {
+        self.leak.store(leak, Ordering::Relaxed)
+    }
+    pub(crate) fn leak(&self) -> bool {
+        self.leak.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether two routes are the same for the kernel, which replaces one with the other.
+fn same_route(a: &RouteEntry, b: &RouteEntry) -> bool {
+    a.destination == b.destination && a.prefix == b.prefix && a.table == b.table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(destination: &str, prefix: u8) -> RouteEntry {
+        RouteEntry {
+            destination: destination.parse().unwrap(),
+            prefix,
+            gateway: None,
+            metric: 0,
+            table: 254,
+            pref_src: None,
+        }
+    }
+
+    #[test]
+    fn undoes_latest_first() {
+        let applied = Applied::default();
+        applied.route_added(route("10.1.0.0", 16));
+        applied.route_added(route("10.2.0.0", 16));
+        applied.route_added(route("10.1.0.0", 16));
+        applied.route_added(route("10.3.0.0", 16));
+        applied.route_removed(&route("10.3.0.0", 16));
+        applied.address_added("10.0.0.2".parse().unwrap());
+        applied.address_added("fd00::2".parse().unwrap());
+        let (routes, addresses) = applied.take();
+        assert_eq!(vec![route("10.1.0.0", 16), route("10.2.0.0", 16)], routes);
+        assert_eq!(
+            vec![
+                "fd00::2".parse::<IpAddr>().unwrap(),
+                "10.0.0.2".parse().unwrap()
+            ],
+            addresses
+        );
+        assert!(applied.take().0.is_empty());
+    }
+}
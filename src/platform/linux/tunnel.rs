@@ -0,0 +1,235 @@
+use std::net::IpAddr;
+use std::ops::Deref;
+
+use crate::platform::linux::dns;
+use crate::platform::linux::route::{self, RT_TABLE_MAIN};
+use crate::platform::RouteEntry;
+use crate::{DeviceBuilder, Error, Result, SyncDevice, ToIpv4Address, ToIpv4Netmask};
+use crate::{ToIpv6Address, ToIpv6Netmask};
+
+/// Builds a [`Tunnel`], created by [`Tunnel::builder`] or from a configured
+/// [`DeviceBuilder`].
+#[derive(Default)]
+pub struct TunnelBuilder {
+    device: DeviceBuilder,
+    routes: Vec<(IpAddr, u8)>,
+    dns: Vec<IpAddr>,
+    mss_clamp: bool,
+}
+
+impl From<DeviceBuilder> for TunnelBuilder {
+    fn from(device: DeviceBuilder) -> Self {
+        TunnelBuilder {
+            device,
+            ..Default::default()
+        }
+    }
+}
+
+impl TunnelBuilder {
+    /// See [`DeviceBuilder::name`].
+    pub fn name<S: Into<String>>(mut self, dev_name: S) -> Self {
+        self.device = self.device.name(dev_name);
+        self
+    }
+    /// See [`DeviceBuilder::mtu`].
+    pub fn mtu(mut self, mtu: u16) -> Self {
+        self.device = self.device.mtu(mtu);
+        self
+    }
+    /// See [`DeviceBuilder::ipv4`].
+    pub fn ipv4<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
+        mut self,
+        address: IPv4,
+        mask: Netmask,
+        destination: Option<IPv4>,
+    ) -> Self {
+        self.device = self.device.ipv4(address, mask, destination);
+        self
+    }
+    /// See [`DeviceBuilder::ipv6`].
+    pub fn ipv6<IPv6: ToIpv6Address, Netmask: ToIpv6Netmask>(
+        mut self,
+        address: IPv6,
+        mask: Netmask,
+    ) -> Self {
+        self.device = self.device.ipv6(address, mask);
+        self
+    }
+    /// Routes `destination/prefix` into the tunnel, in the main table. A route to the same
+    /// destination is replaced, e.g. `0.0.0.0/0` replaces the default route.
+    ///
+    /// The first address of the tunnel of the same family is the preferred source of the
+    /// route, as with [`add_route_via_device`](crate::DeviceImpl::add_route_via_device).
+    pub fn route(mut self, destination: IpAddr, prefix: u8) -> Self {
+        self.routes.push((destination, prefix));
+        self
+    }
+    /// Sets the DNS servers of the interface with `resolvectl`, which needs
+    /// systemd-resolved.
+    pub fn dns(mut self, servers: &[IpAddr]) -> Self {
+        self.dns = servers.to_vec();
+        self
+    }
+    /// Clamps the MSS of TCP connections leaving through the tunnel to its path MTU with an
+    /// `iptables`/`ip6tables` `TCPMSS` rule in the mangle table.
+    pub fn mss_clamp(mut self, enable: bool) -> Self {
+        self.mss_clamp = enable;
+        self
+    }
+    /// Creates the device, then installs the routes, the DNS servers and the MSS clamp rule.
+    ///
+    /// If a step fails, what was installed so far is removed again and the device is
+    /// dropped.
+    pub fn build(self) -> Result<Tunnel> {
+        for &(destination, prefix) in &self.routes {
+            match destination {
+                IpAddr::V4(_) => ToIpv4Netmask::prefix(&prefix)?,
+                IpAddr::V6(_) => ToIpv6Netmask::prefix(&prefix)?,
+            };
+        }
+        let mut tunnel = Tunnel {
+            device: self.device.build_sync()?,
+            routes: Vec::new(),
+            dns: false,
+            mss_clamp: false,
+        };
+        let index = tunnel.device.if_index()?;
+        for (destination, prefix) in self.routes {
+            let entry = RouteEntry {
+                destination,
+                prefix,
+                gateway: None,
+                metric: 0,
+                table: RT_TABLE_MAIN,
+                pref_src: tunnel.device.primary_address(destination.is_ipv4())?,
+            };
+            route::change_route(true, index, &entry)?;
+            tunnel.routes.push(entry);
+        }
+        if !self.dns.is_empty() {
+            dns::set_servers(index, &self.dns)?;
+            tunnel.dns = true;
+        }
+        if self.mss_clamp {
+            tunnel.change_mss_clamp(true)?;
+            tunnel.mss_clamp = true;
+        }
+        Ok(tunnel)
+    }
+}
+
+/// A device together with the routes, DNS servers and MSS clamp rule installed for it, all
+/// of which are removed again when the tunnel is dropped.
+///
+/// Derefs to the [`SyncDevice`] for packet I/O. Errors while cleaning up on drop are logged,
+/// call [`close`](Self::close) to get them.
+///
+/// ```no_run
+/// use tun_rs::Tunnel;
+/// let tunnel = Tunnel::builder()
+///     .ipv4("10.0.0.2", 24, None)
+///     .route("10.1.0.0".parse().unwrap(), 16)
+///     .dns(&["10.0.0.1".parse().unwrap()])
+///     .mss_clamp(true)
+///     .build()
+///     .unwrap();
+/// let mut buf = [0; 65535];
+/// let len = tunnel.recv(&mut buf).unwrap();
+/// ```
+pub struct Tunnel {
+    device: SyncDevice,
+    routes: Vec<RouteEntry>,
+    dns: bool,
+    mss_clamp: bool,
+}
+
+impl Tunnel {
+    /// Returns a builder for a tunnel.
+    pub fn builder() -> TunnelBuilder {
+        TunnelBuilder::default()
+    }
+    /// Returns the device of the tunnel.
+    pub fn device(&self) -> &SyncDevice {
+        &self.device
+    }
+    /// Removes the routes, the DNS servers and the MSS clamp rule, then closes the device.
+    ///
+    /// Every step is attempted, the first error is returned.
+    pub fn close(mut self) -> Result<()> {
+        self.cleanup()
+    }
+    fn cleanup(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        if std::mem::take(&mut self.mss_clamp) {
+            result = result.and(self.change_mss_clamp(false));
+        }
+        if std::mem::take(&mut self.dns) {
+            // No servers reverts the link to its defaults.
+            let reverted = self
+                .device
+                .if_index()
+                .and_then(|index| dns::set_servers(index, &[]));
+            result = result.and(reverted);
+        }
+        let routes = std::mem::take(&mut self.routes);
+        if !routes.is_empty() {
+            // Routes out of an interface that is already gone have been removed with it.
+            if let Ok(index) = self.device.if_index() {
+                for entry in routes.iter().rev() {
+                    let removed = route::change_route(false, index, entry).map_err(Error::from);
+                    result = result.and(removed);
+                }
+            }
+        }
+        result
+    }
+    fn change_mss_clamp(&self, add: bool) -> Result<()> {
+        let name = self.device.name()?;
+        let action = if add { "-A" } else { "-D" };
+        let rule = [
+            "-t",
+            "mangle",
+            action,
+            "POSTROUTING",
+            "-o",
+            name.as_str(),
+            "-p",
+            "tcp",
+            "--tcp-flags",
+            "SYN,RST",
+            "SYN",
+            "-j",
+            "TCPMSS",
+            "--clamp-mss-to-pmtu",
+        ];
+        crate::run_command("iptables", &rule)?;
+        if std::path::Path::new("/proc/net/if_inet6").exists() {
+            if let Err(e) = crate::run_command("ip6tables", &rule) {
+                if add {
+                    _ = crate::run_command(
+                        "iptables",
+                        &[&rule[..2], &["-D"][..], &rule[3..]].concat(),
+                    );
+                }
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Deref for Tunnel {
+    type Target = SyncDevice;
+    fn deref(&self) -> &Self::Target {
+        &self.device
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        if let Err(e) = self.cleanup() {
+            log::warn!("failed to clean up the tunnel: {e}");
+        }
+    }
+}
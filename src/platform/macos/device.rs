@@ -1,13 +1,17 @@
+#[cfg(feature = "config")]
+use crate::builder::DeviceConfig;
 use crate::{
-    builder::DeviceConfig,
     platform::{macos::sys::*, unix::sockaddr_union},
-    ToIpv4Address, ToIpv4Netmask, ToIpv6Address, ToIpv6Netmask,
+    Error, Result, ToIpv4Address, ToIpv4Netmask, ToIpv6Address, ToIpv6Netmask,
 };
 
 //const OVERWRITE_SIZE: usize = std::mem::size_of::<libc::__c_anonymous_ifr_ifru>();
 
 use crate::platform::unix::device::{ctl, ctl_v6};
 use crate::platform::unix::Tun;
+use crate::platform::{DnsRestore, IoHook, Mirror, NameCache};
+#[cfg(any(feature = "async_tokio", feature = "async_std"))]
+use crate::platform::{ReadBudget, SendPacer};
 use getifaddrs::{self, Interface};
 use libc::{
     self, c_char, c_short, c_uint, c_void, sockaddr, socklen_t, AF_SYSTEM, AF_SYS_CONTROL,
@@ -26,26 +30,29 @@ struct Route {
 
 /// A TUN device using the TUN macOS driver.
 pub struct DeviceImpl {
+    pub(crate) dns_restore: DnsRestore,
     pub(crate) tun: Tun,
     alias_lock: Mutex<()>,
+    name_cache: NameCache,
+    pub(crate) io_hook: IoHook,
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    pub(crate) send_pacer: SendPacer,
+    pub(crate) mirror: Mirror,
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    pub(crate) read_budget: ReadBudget,
 }
 
 impl DeviceImpl {
     /// Create a new `Device` for the given `Configuration`.
-    pub(crate) fn new(config: DeviceConfig) -> io::Result<Self> {
+    #[cfg(feature = "config")]
+    pub(crate) fn new(config: DeviceConfig) -> Result<Self> {
         let id = if let Some(tun_name) = config.dev_name.as_ref() {
             if tun_name.len() > IFNAMSIZ {
-                return Err(io::Error::new(
-                    ErrorKind::InvalidInput,
-                    "device name too long",
-                ));
+                return Err(Error::InvalidConfig("device name too long"));
             }
 
             if !tun_name.starts_with("utun") {
-                return Err(io::Error::new(
-                    ErrorKind::InvalidInput,
-                    "device name must start with utun",
-                ));
+                return Err(Error::InvalidConfig("device name must start with utun"));
             }
             tun_name[4..]
                 .parse::<u32>()
@@ -71,7 +78,7 @@ impl DeviceImpl {
             };
 
             if let Err(err) = ctliocginfo(tun.inner, &mut info as *mut _ as *mut _) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
             let addr = libc::sockaddr_ctl {
@@ -85,7 +92,7 @@ impl DeviceImpl {
 
             let address = &addr as *const libc::sockaddr_ctl as *const sockaddr;
             if libc::connect(tun.inner, address, mem::size_of_val(&addr) as socklen_t) < 0 {
-                return Err(io::Error::last_os_error());
+                return Err(io::Error::last_os_error().into());
             }
 
             let mut tun_name = [0u8; 64];
@@ -94,12 +101,20 @@ impl DeviceImpl {
             let optval = &mut tun_name as *mut _ as *mut c_void;
             let optlen = &mut name_len as *mut socklen_t;
             if libc::getsockopt(tun.inner, SYSPROTO_CONTROL, UTUN_OPT_IFNAME, optval, optlen) < 0 {
-                return Err(io::Error::last_os_error());
+                return Err(io::Error::last_os_error().into());
             }
 
             DeviceImpl {
+                dns_restore: DnsRestore::default(),
                 tun: Tun::new(tun),
                 alias_lock: Mutex::new(()),
+                name_cache: NameCache::default(),
+                io_hook: IoHook::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                send_pacer: SendPacer::default(),
+                mirror: Mirror::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                read_budget: ReadBudget::default(),
             }
         };
         device
@@ -109,8 +124,16 @@ impl DeviceImpl {
     }
     pub(crate) fn from_tun(tun: Tun) -> Self {
         Self {
+            dns_restore: DnsRestore::default(),
             tun,
             alias_lock: Mutex::new(()),
+            name_cache: NameCache::default(),
+            io_hook: IoHook::default(),
+            #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+            send_pacer: SendPacer::default(),
+            mirror: Mirror::default(),
+            #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+            read_budget: ReadBudget::default(),
         }
     }
     /// Prepare a new request.
@@ -166,10 +189,17 @@ impl DeviceImpl {
             .broadcast())
     }
 
-    /// Set the IPv4 alias of the device.
-    fn set_alias(&self, addr: Ipv4Addr, dest: Ipv4Addr, mask: Ipv4Addr) -> std::io::Result<()> {
+    /// Set the IPv4 alias of the device. With `replace` the route of the first IPv4 address
+    /// is replaced by the one of the new address, otherwise it is kept.
+    fn set_alias(
+        &self,
+        addr: Ipv4Addr,
+        dest: Ipv4Addr,
+        mask: Ipv4Addr,
+        replace: bool,
+    ) -> std::io::Result<()> {
         let _guard = self.alias_lock.lock().unwrap();
-        let old_route = self.current_route();
+        let old_route = if replace { self.current_route() } else { None };
         let tun_name = self.name()?;
         unsafe {
             let mut req: ifaliasreq = mem::zeroed();
@@ -183,7 +213,7 @@ impl DeviceImpl {
             req.ifra_mask = sockaddr_union::from((mask, 0)).addr;
 
             if let Err(err) = siocaifaddr(ctl()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             let new_route = Route {
                 addr: addr.into(),
@@ -197,7 +227,7 @@ impl DeviceImpl {
         }
     }
 
-    fn remove_route(&self, route: Route) -> std::io::Result<()> {
+    fn remove_route_entry(&self, route: Route) -> std::io::Result<()> {
         let if_name = self.name()?;
         let prefix_len = ipnet::ip_mask_to_prefix(route.netmask)
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
@@ -352,8 +382,21 @@ impl DeviceImpl {
     //     }
     // }
 
+    /// Returns the DNS servers published for the interface with
+    /// [`set_dns_servers`](Self::set_dns_servers), read back with `scutil`.
+    #[cfg(feature = "config")]
+    pub fn dns_servers(&self) -> Result<Vec<IpAddr>> {
+        crate::platform::macos::dns::servers(&self.name()?)
+    }
+    #[cfg(feature = "config")]
+    pub(crate) fn dns_setter(&self) -> Result<crate::platform::DnsSetter> {
+        let name = self.name()?;
+        Ok(Box::new(move |servers| {
+            crate::platform::macos::dns::set_servers(&name, servers)
+        }))
+    }
     /// Retrieves the name of the network interface.
-    pub fn name(&self) -> std::io::Result<String> {
+    pub fn name(&self) -> Result<String> {
         let mut tun_name = [0u8; 64];
         let mut name_len: socklen_t = 64;
 
@@ -368,24 +411,36 @@ impl DeviceImpl {
                 optlen,
             ) < 0
             {
-                return Err(io::Error::last_os_error());
+                return Err(io::Error::last_os_error().into());
             }
             Ok(CStr::from_ptr(tun_name.as_ptr() as *const c_char)
                 .to_string_lossy()
                 .into())
         }
     }
+    /// Returns the name of the device without querying the system.
+    ///
+    /// The name is looked up once and then never refreshed, so it is cheap enough for hot
+    /// logging paths. Use [`name`](Self::name) for the authoritative value.
+    pub fn cached_name(&self) -> Result<std::sync::Arc<str>> {
+        self.name_cache.get_or(|| self.name())
+    }
+    /// Returns the layer of the device, always L3 as utun has no L2 mode.
+    pub fn detect_layer(&self) -> Result<crate::Layer> {
+        Ok(crate::Layer::L3)
+    }
     /// Enables or disables the network interface.
     ///
     /// If `value` is true, the interface is enabled by setting the IFF_UP and IFF_RUNNING flags.
     /// If false, the IFF_UP flag is cleared. The change is applied using a system call.
-    pub fn enabled(&self, value: bool) -> std::io::Result<()> {
+    #[cfg(feature = "config")]
+    pub fn enabled(&self, value: bool) -> Result<()> {
         unsafe {
             let ctl = ctl()?;
             let mut req = self.request()?;
 
             if let Err(err) = siocgifflags(ctl.as_raw_fd(), &mut req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
             if value {
@@ -395,7 +450,7 @@ impl DeviceImpl {
             }
 
             if let Err(err) = siocsifflags(ctl.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
             Ok(())
@@ -432,13 +487,13 @@ impl DeviceImpl {
     //     }
     // }
     /// Retrieves the current MTU (Maximum Transmission Unit) for the interface.
-    pub fn mtu(&self) -> std::io::Result<u16> {
+    pub fn mtu(&self) -> Result<u16> {
         unsafe {
             let ctl = ctl()?;
             let mut req = self.request()?;
 
             if let Err(err) = siocgifmtu(ctl.as_raw_fd(), &mut req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
 
             let r: u16 = req
@@ -450,31 +505,81 @@ impl DeviceImpl {
         }
     }
     /// Sets the MTU (Maximum Transmission Unit) for the interface.
-    pub fn set_mtu(&self, value: u16) -> std::io::Result<()> {
+    ///
+    /// Values below 576, or below 1280 while the interface has an IPv6 address, are rejected
+    /// with [`Error::InvalidConfig`], use [`set_mtu_unchecked`](Self::set_mtu_unchecked) to
+    /// apply them anyway. Fails if the OS applied a different value than requested.
+    #[cfg(feature = "config")]
+    pub fn set_mtu(&self, value: u16) -> Result<()> {
+        let ipv6 = self.addresses()?.iter().any(|addr| addr.is_ipv6());
+        crate::platform::check_mtu(value, ipv6, false)?;
+        self.set_mtu_unchecked(value)
+    }
+    /// Sets the MTU without the lower bounds checked by [`set_mtu`](Self::set_mtu).
+    #[cfg(feature = "config")]
+    pub fn set_mtu_unchecked(&self, value: u16) -> Result<()> {
         unsafe {
             let ctl = ctl()?;
             let mut req = self.request()?;
             req.ifr_ifru.ifru_mtu = value as i32;
 
             if let Err(err) = siocsifmtu(ctl.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
+            }
+        }
+        crate::platform::check_mtu_applied(value, self.mtu()?)
+    }
+    /// Sets the interface metric.
+    ///
+    /// The kernel does not use the metric for route selection, it is exported to routing
+    /// daemons and tools such as `ifconfig`.
+    #[cfg(feature = "config")]
+    pub fn set_metric(&self, metric: u16) -> Result<()> {
+        unsafe {
+            let ctl = ctl()?;
+            let mut req = self.request()?;
+            req.ifr_ifru.ifru_metric = metric as _;
+
+            if let Err(err) = siocsifmetric(ctl.as_raw_fd(), &req) {
+                return Err(err.into());
             }
             Ok(())
         }
     }
     /// Sets the IPv4 network address, netmask, and an optional destination address.
+    #[cfg(feature = "config")]
     pub fn set_network_address<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
         &self,
         address: IPv4,
         netmask: Netmask,
         destination: Option<IPv4>,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
+        self.alias(address, netmask, destination, true)
+    }
+    /// Adds an IPv4 address to the interface, keeping the addresses already assigned and the
+    /// route of the first one, unlike [`set_network_address`](Self::set_network_address).
+    #[cfg(feature = "config")]
+    pub fn add_address_v4<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
+        &self,
+        address: IPv4,
+        netmask: Netmask,
+        destination: Option<IPv4>,
+    ) -> Result<()> {
+        self.alias(address, netmask, destination, false)
+    }
+    #[cfg(feature = "config")]
+    fn alias<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
+        &self,
+        address: IPv4,
+        netmask: Netmask,
+        destination: Option<IPv4>,
+        replace: bool,
+    ) -> Result<()> {
         let netmask = netmask.netmask()?;
         let address = address.ipv4()?;
         let default_dest = self.calc_dest_addr(address.into(), netmask.into())?;
         let IpAddr::V4(default_dest) = default_dest else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
+            return Err(Error::InvalidConfig(
                 "invalid destination for address/netmask",
             ));
         };
@@ -482,25 +587,26 @@ impl DeviceImpl {
             .map(|v| v.ipv4())
             .transpose()?
             .unwrap_or(default_dest);
-        self.set_alias(address, dest, netmask)?;
+        self.set_alias(address, dest, netmask, replace)?;
         Ok(())
     }
-    /// Removes an IP address from the interface.
-    pub fn remove_address(&self, addr: IpAddr) -> io::Result<()> {
+    /// Removes an IP address from the interface without checking that it is assigned.
+    #[cfg(feature = "config")]
+    pub(crate) fn remove_address_unchecked(&self, addr: IpAddr) -> Result<()> {
         unsafe {
             match addr {
                 IpAddr::V4(addr) => {
                     let mut req_v4 = self.request()?;
                     req_v4.ifr_ifru.ifru_addr = sockaddr_union::from((addr, 0)).addr;
                     if let Err(err) = siocdifaddr(ctl()?.as_raw_fd(), &req_v4) {
-                        return Err(io::Error::from(err));
+                        return Err(err.into());
                     }
                 }
                 IpAddr::V6(addr) => {
                     let mut req_v6 = self.request_v6()?;
                     req_v6.ifr_ifru.ifru_addr = sockaddr_union::from((addr, 0)).addr6;
                     if let Err(err) = siocdifaddr_in6(ctl_v6()?.as_raw_fd(), &req_v6) {
-                        return Err(io::Error::from(err));
+                        return Err(err.into());
                     }
                 }
             }
@@ -512,7 +618,7 @@ impl DeviceImpl {
                     let Some(dest) = v.associated_address else {
                         return;
                     };
-                    if let Err(e) = self.remove_route(Route {
+                    if let Err(e) = self.remove_route_entry(Route {
                         addr,
                         netmask,
                         dest,
@@ -525,11 +631,12 @@ impl DeviceImpl {
         }
     }
     /// Adds an IPv6 address to the interface.
+    #[cfg(feature = "config")]
     pub fn add_address_v6<IPv6: ToIpv6Address, Netmask: ToIpv6Netmask>(
         &self,
         addr: IPv6,
         netmask: Netmask,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
         let addr = addr.ipv6()?;
         unsafe {
             let tun_name = self.name()?;
@@ -548,7 +655,7 @@ impl DeviceImpl {
             req.in6_addrlifetime.ia6t_pltime = 0xffffffff_u32;
             req.ifra_flags = IN6_IFF_NODAD;
             if let Err(err) = siocaifaddr_in6(ctl_v6()?.as_raw_fd(), &req) {
-                return Err(io::Error::from(err));
+                return Err(err.into());
             }
             let Ok(dest) = self.calc_dest_addr(addr.into(), mask) else {
                 return Ok(());
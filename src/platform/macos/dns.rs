@@ -0,0 +1,84 @@
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+
+use crate::Result;
+
+/// The dynamic store key the DNS servers of interface `name` are published under.
+fn key(name: &str) -> String {
+    format!("State:/Network/Service/tun-rs-{name}/DNS")
+}
+
+/// Publishes `servers` as the resolver of interface `name` for all domains, removing the
+/// entry if `servers` is empty.
+pub(crate) fn set_servers(name: &str, servers: &[IpAddr]) -> Result<()> {
+    let key = key(name);
+    let script = if servers.is_empty() {
+        format!("remove {key}\n")
+    } else {
+        let servers: Vec<String> = servers.iter().map(|v| v.to_string()).collect();
+        format!(
+            "d.init\n\
+             d.add ServerAddresses * {}\n\
+             d.add SupplementalMatchDomains * \"\"\n\
+             d.add InterfaceName {name}\n\
+             set {key}\n",
+            servers.join(" ")
+        )
+    };
+    scutil(&script)?;
+    Ok(())
+}
+
+/// Returns the DNS servers published for interface `name`, none if there is no entry.
+pub(crate) fn servers(name: &str) -> Result<Vec<IpAddr>> {
+    let out = scutil(&format!("show {}\n", key(name)))?;
+    Ok(parse_servers(&out))
+}
+
+/// Runs `scutil` with `script` on its standard input and returns its output.
+fn scutil(script: &str) -> io::Result<String> {
+    let mut child = Command::new("scutil")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(script.as_bytes())?;
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        let err = String::from_utf8_lossy(&out.stderr);
+        let info = format!("scutil failed with: \"{err}\"");
+        return Err(io::Error::new(io::ErrorKind::Other, info));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Parses the `ServerAddresses` array of a `show` of the dynamic store:
+///
+/// ```text
+/// <dictionary> {
+///   ServerAddresses : <array> {
+///     0 : 10.0.0.1
+///   }
+/// }
+/// ```
+fn parse_servers(out: &str) -> Vec<IpAddr> {
+    let mut servers = Vec::new();
+    let mut in_servers = false;
+    for line in out.lines().map(str::trim) {
+        if line.starts_with("ServerAddresses") {
+            in_servers = true;
+        } else if line == "}" {
+            in_servers = false;
+        } else if in_servers {
+            if let Some(server) = line.split_once(" : ").and_then(|(_, v)| v.parse().ok()) {
+                servers.push(server);
+            }
+        }
+    }
+    servers
+}
@@ -0,0 +1,221 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::sync::RwLock;
+
+use bytes::Bytes;
+
+use crate::platform::DeviceImpl;
+
+/// The packets copied by [`DeviceImpl::mirror`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MirrorDirection {
+    /// Packets received from the device.
+    Inbound,
+    /// Packets sent to the device.
+    Outbound,
+    /// Packets in both directions.
+    Both,
+}
+
+impl MirrorDirection {
+    fn includes(self, inbound: bool) -> bool {
+        match self {
+            MirrorDirection::Inbound => inbound,
+            MirrorDirection::Outbound => !inbound,
+            MirrorDirection::Both => true,
+        }
+    }
+}
+
+enum Sender {
+    Std(SyncSender<Bytes>),
+    #[cfg(feature = "async_tokio")]
+    Tokio(::tokio::sync::mpsc::Sender<Bytes>),
+}
+
+struct Sink {
+    direction: MirrorDirection,
+    snap_len: usize,
+    sender: Sender,
+}
+
+/// The mirror of a device, checked with a single atomic load when none is set.
+#[derive(Default)]
+pub(crate) struct Mirror {
+    set: AtomicBool,
+    sink: RwLock<Option<Sink>>,
+    /// Counts the mirrors set, to tell whether the sink is still the one that failed.
+    generation: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[allow(dead_code)]
+impl Mirror {
+    fn set(&self, sink: Option<Sink>) {
+        let mut guard = self.sink.write().unwrap();
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.set.store(sink.is_some(), Ordering::Relaxed);
+        *guard = sink;
+    }
+    /// Copies a received packet to the channel.
+    pub(crate) fn inbound(&self, packet: &[u8]) {
+        self.copy(true, packet)
+    }
+    /// Copies a sent packet to the channel.
+    pub(crate) fn outbound(&self, packet: &[u8]) {
+        self.copy(false, packet)
+    }
+    /// Copies the packet of a successful receive, the first `len` bytes of `buf`, see
+    /// [`IoHook::recv`](super::IoHook::recv).
+    pub(crate) fn recv<T>(
+        &self,
+        res: std::io::Result<T>,
+        buf: &[u8],
+        len: impl Fn(&T) -> usize,
+    ) -> std::io::Result<T> {
+        if let Ok(v) = &res {
+            self.inbound(&buf[..len(v)]);
+        }
+        res
+    }
+    /// Copies `buf` if the send succeeded.
+    pub(crate) fn send(&self, res: std::io::Result<usize>, buf: &[u8]) -> std::io::Result<usize> {
+        if res.is_ok() {
+            self.outbound(buf);
+        }
+        res
+    }
+    fn copy(&self, inbound: bool, packet: &[u8]) {
+        if !self.set.load(Ordering::Relaxed) {
+            return;
+        }
+        let guard = self.sink.read().unwrap();
+        let Some(sink) = guard.as_ref() else {
+            return;
+        };
+        if !sink.direction.includes(inbound) {
+            return;
+        }
+        let copy = Bytes::copy_from_slice(&packet[..packet.len().min(sink.snap_len)]);
+        let sent = match &sink.sender {
+            Sender::Std(sender) => match sender.try_send(copy) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => false,
+                Err(TrySendError::Disconnected(_)) => return self.disconnected(guard),
+            },
+            #[cfg(feature = "async_tokio")]
+            Sender::Tokio(sender) => match sender.try_send(copy) {
+                Ok(()) => true,
+                Err(::tokio::sync::mpsc::error::TrySendError::Full(_)) => false,
+                Err(::tokio::sync::mpsc::error::TrySendError::Closed(_)) => {
+                    return self.disconnected(guard)
+                }
+            },
+        };
+        if !sent {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    /// Removes the mirror after its receiver was dropped.
+    fn disconnected(&self, guard: std::sync::RwLockReadGuard<'_, Option<Sink>>) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        drop(guard);
+        let mut guard = self.sink.write().unwrap();
+        // Another thread may have set a new mirror in between.
+        if self.generation.load(Ordering::Relaxed) == generation {
+            self.set.store(false, Ordering::Relaxed);
+            *guard = None;
+        }
+    }
+}
+
+impl DeviceImpl {
+    /// Copies the packets received from or sent to the device in `direction` to `sender`,
+    /// replacing any previous mirror.
+    ///
+    /// Each copy is cut to `snap_len` bytes, `None` copies whole packets. The copies are the
+    /// buffers as passed to `send` or filled by `recv`, including any packet information or
+    /// virtio-net header. A copy that does not fit into the channel is dropped and counted,
+    /// see [`mirror_dropped`](Self::mirror_dropped), so the mirror never blocks the I/O path.
+    /// The mirror is removed when the receiver is dropped.
+    ///
+    /// `recv`, `try_recv`, `send` and `try_send` of [`SyncDevice`](crate::SyncDevice) and
+    /// [`AsyncDevice`](crate::AsyncDevice) are mirrored, as are `recv_owned`,
+    /// `recv_with_timestamp`, `recv_packet`, `send_packet` and `send_with_dscp` of
+    /// `SyncDevice`. Other calls, such as the vectored and batched ones, are not guaranteed to
+    /// be mirrored. While no mirror is set the cost is one atomic load per call.
+    ///
+    /// ```no_run
+    /// # fn main() -> tun_rs::Result<()> {
+    /// use tun_rs::MirrorDirection;
+    /// let dev = tun_rs::DeviceBuilder::new().build_sync()?;
+    /// let (tx, rx) = std::sync::mpsc::sync_channel(1024);
+    /// dev.mirror(MirrorDirection::Both, Some(128), tx);
+    /// std::thread::spawn(move || {
+    ///     for packet in rx {
+    ///         println!("{} bytes", packet.len());
+    ///     }
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn mirror(
+        &self,
+        direction: MirrorDirection,
+        snap_len: Option<usize>,
+        sender: SyncSender<Bytes>,
+    ) {
+        self.mirror.set(Some(Sink {
+            direction,
+            snap_len: snap_len.unwrap_or(usize::MAX),
+            sender: Sender::Std(sender),
+        }))
+    }
+    /// Like [`mirror`](Self::mirror), copying the packets to a tokio channel.
+    #[cfg(feature = "async_tokio")]
+    pub fn mirror_async(
+        &self,
+        direction: MirrorDirection,
+        snap_len: Option<usize>,
+        sender: ::tokio::sync::mpsc::Sender<Bytes>,
+    ) {
+        self.mirror.set(Some(Sink {
+            direction,
+            snap_len: snap_len.unwrap_or(usize::MAX),
+            sender: Sender::Tokio(sender),
+        }))
+    }
+    /// Removes the mirror set with [`mirror`](Self::mirror).
+    pub fn clear_mirror(&self) {
+        self.mirror.set(None)
+    }
+    /// Returns how many copies were dropped because the channel of the mirror was full.
+    pub fn mirror_dropped(&self) -> u64 {
+        self.mirror.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_and_counts_drops() {
+        let mirror = Mirror::default();
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        mirror.set(Some(Sink {
+            direction: MirrorDirection::Inbound,
+            snap_len: 4,
+            sender: Sender::Std(tx),
+        }));
+        mirror.outbound(&[1; 8]);
+        mirror.inbound(&[1, 2, 3, 4, 5, 6]);
+        mirror.inbound(&[7; 8]);
+        assert_eq!(Bytes::from_static(&[1, 2, 3, 4]), rx.try_recv().unwrap());
+        assert!(rx.try_recv().is_err());
+        assert_eq!(1, mirror.dropped.load(Ordering::Relaxed));
+        drop(rx);
+        mirror.inbound(&[1]);
+        assert!(!mirror.set.load(Ordering::Relaxed));
+    }
+}
@@ -28,7 +28,46 @@ pub use self::macos::DeviceImpl;
 #[cfg(target_os = "windows")]
 pub(crate) mod windows;
 #[cfg(target_os = "windows")]
-pub use self::windows::DeviceImpl;
+pub use self::windows::{
+    has_required_privileges, is_elevated, reload_wintun, wintun_dll_version, wintun_version,
+    DeviceImpl,
+};
+
+mod rate_limit;
+pub use self::rate_limit::RateLimit;
+#[cfg(any(feature = "async_tokio", feature = "async_std"))]
+pub(crate) use self::rate_limit::SendPacer;
+
+mod mirror;
+pub(crate) use self::mirror::Mirror;
+pub use self::mirror::MirrorDirection;
+
+/// Gates items on the platforms with a configuration backend, with the `config` feature.
+macro_rules! cfg_config {
+    ($($item:item)*) => {
+        $(
+            #[cfg(all(
+                any(
+                    target_os = "windows",
+                    all(target_os = "linux", not(target_env = "ohos")),
+                    target_os = "macos",
+                    target_os = "freebsd"
+                ),
+                feature = "config"
+            ))]
+            $item
+        )*
+    };
+}
+
+cfg_config! {
+    mod probe;
+    pub use self::probe::{probe, PlatformCapabilities, Support};
+    mod txn;
+    pub use self::txn::{ConfigTxn, TxnReport, TxnStep};
+    mod self_test;
+    pub use self::self_test::{Probe, SelfTestReport};
+}
 
 use getifaddrs::Interface;
 #[cfg(unix)]
@@ -36,9 +75,222 @@ use std::io::{IoSlice, IoSliceMut};
 use std::ops::Deref;
 #[cfg(unix)]
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::SystemTime;
 
 #[allow(dead_code)]
 pub(crate) const ETHER_ADDR_LEN: u8 = 6;
+/// Rejects MAC addresses no Ethernet interface can use as its own address.
+///
+/// Warns if the address is not locally administered, since it may clash with real hardware
+/// carrying the same OUI.
+#[allow(dead_code)]
+pub(crate) fn check_mac(mac: &[u8; ETHER_ADDR_LEN as usize]) -> crate::Result<()> {
+    if mac.iter().all(|&b| b == 0) {
+        return Err(crate::Error::InvalidConfig("the MAC address is all zeros"));
+    }
+    if mac[0] & 0x01 != 0 {
+        return Err(crate::Error::InvalidConfig(
+            "the MAC address is a multicast address, the lowest bit of the first octet must be clear",
+        ));
+    }
+    if mac[0] & 0x02 == 0 {
+        log::warn!(
+            "the MAC address {mac:02x?} uses a globally unique OUI, \
+             set the locally administered bit (0x02 in the first octet) to avoid clashes"
+        );
+    }
+    Ok(())
+}
+
+/// Generates a random locally administered unicast MAC address for TAP devices.
+pub fn random_mac() -> [u8; ETHER_ADDR_LEN as usize] {
+    use std::hash::{BuildHasher, Hasher};
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+        .to_le_bytes();
+    let mut mac = [0u8; ETHER_ADDR_LEN as usize];
+    mac.copy_from_slice(&random[..ETHER_ADDR_LEN as usize]);
+    mac[0] = (mac[0] & !0x01) | 0x02;
+    mac
+}
+/// The length of the Ethernet header in front of the packets of L2 (TAP) devices.
+pub const ETHERNET_HEADER_LEN: usize = 14;
+/// A buffer of this size can hold any packet, including an Ethernet header on TAP devices.
+pub(crate) const MAX_PACKET_SIZE: usize = ETHERNET_HEADER_LEN + 65536;
+
+/// The smallest MTU accepted without `allow_small_mtu`, every IPv4 host must accept
+/// datagrams of this size (RFC 791).
+pub(crate) const MIN_MTU_V4: u16 = 576;
+/// The smallest MTU IPv6 runs on (RFC 8200), below it the kernel disables IPv6 on the link.
+pub(crate) const MIN_MTU_V6: u16 = 1280;
+/// The largest MTU the TUN driver accepts, `TUNMRU` in `if_tun.c`.
+#[cfg(target_os = "freebsd")]
+pub(crate) const MAX_MTU: u16 = 16384;
+#[cfg(not(target_os = "freebsd"))]
+pub(crate) const MAX_MTU: u16 = u16::MAX;
+
+/// Checks `mtu` against the bounds documented on `DeviceBuilder::mtu`.
+///
+/// `ipv6` tells whether the interface carries IPv6, `allow_small` skips the lower bounds.
+#[allow(dead_code)]
+pub(crate) fn check_mtu(mtu: u16, ipv6: bool, allow_small: bool) -> crate::Result<()> {
+    #[allow(clippy::absurd_extreme_comparisons)]
+    if mtu > MAX_MTU {
+        return Err(crate::Error::InvalidConfig(
+            "MTU is above the maximum supported by the TUN driver",
+        ));
+    }
+    if allow_small {
+        return Ok(());
+    }
+    if mtu < MIN_MTU_V4 {
+        return Err(crate::Error::InvalidConfig(
+            "MTU is below 576, the datagram size every IPv4 host must accept, \
+             use allow_small_mtu to override",
+        ));
+    }
+    if ipv6 && mtu < MIN_MTU_V6 {
+        return Err(crate::Error::InvalidConfig(
+            "MTU is below 1280, the minimum link MTU of IPv6, \
+             use allow_small_mtu to override",
+        ));
+    }
+    Ok(())
+}
+
+/// Fails if the OS silently applied a different MTU than the one requested.
+#[allow(dead_code)]
+pub(crate) fn check_mtu_applied(requested: u16, effective: u16) -> crate::Result<()> {
+    if requested != effective {
+        return Err(crate::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("the OS clamped the requested MTU {requested} to {effective}"),
+        )));
+    }
+    Ok(())
+}
+
+/// A tunnel encapsulation, see [`encapsulation_overhead`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EncapKind {
+    /// WireGuard: UDP (8) plus the data message header and authentication tag (32).
+    WireGuard,
+    /// IPsec ESP in tunnel mode with AES-GCM and NAT traversal: UDP (8), ESP header (8),
+    /// IV (8), at most 3 bytes of padding, trailer (2) and ICV (16).
+    IpsecEsp,
+    /// GRE without optional fields: GRE header (4).
+    Gre,
+    /// VXLAN: UDP (8), VXLAN header (8) and the inner Ethernet header (14).
+    Vxlan,
+}
+
+/// Returns the bytes `kind` adds to every packet, including the outer IP header.
+///
+/// The values assume an IPv6 underlay, which is the worst case. Over IPv4 the overhead is
+/// 20 bytes less.
+pub const fn encapsulation_overhead(kind: EncapKind) -> u16 {
+    const OUTER_IPV6: u16 = 40;
+    OUTER_IPV6
+        + match kind {
+            EncapKind::WireGuard => 8 + 32,
+            EncapKind::IpsecEsp => 8 + 8 + 8 + 3 + 2 + 16,
+            EncapKind::Gre => 4,
+            EncapKind::Vxlan => 8 + 8 + ETHERNET_HEADER_LEN as u16,
+        }
+}
+
+/// Returns the MTU for a tunnel whose packets are sent over a link with `underlay_mtu`,
+/// with `overhead` bytes of encapsulation, e.g. from [`encapsulation_overhead`].
+///
+/// ```
+/// use tun_rs::{encapsulation_overhead, mtu_for_underlay, EncapKind};
+/// assert_eq!(1420, mtu_for_underlay(1500, encapsulation_overhead(EncapKind::WireGuard)));
+/// ```
+pub const fn mtu_for_underlay(underlay_mtu: u16, overhead: u16) -> u16 {
+    underlay_mtu.saturating_sub(overhead)
+}
+
+/// The two halves of the IPv6 address space, together they override `::/0` without replacing it.
+#[allow(dead_code)]
+pub(crate) const IPV6_DEFAULT_HALVES: [&str; 2] = ["::/1", "8000::/1"];
+
+/// The network protocol of an L3 packet, see [`SyncDevice::send_packet`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Protocol {
+    /// An IPv4 packet.
+    Ipv4,
+    /// An IPv6 packet.
+    Ipv6,
+}
+
+impl Protocol {
+    /// Determines the protocol from the version field of the IP header in `packet`.
+    pub fn of(packet: &[u8]) -> Option<Protocol> {
+        match packet.first()? >> 4 {
+            4 => Some(Protocol::Ipv4),
+            6 => Some(Protocol::Ipv6),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the destination address of the IP packet `packet`.
+#[allow(dead_code)]
+pub(crate) fn packet_destination(packet: &[u8]) -> crate::Result<std::net::IpAddr> {
+    match Protocol::of(packet) {
+        Some(Protocol::Ipv4) if packet.len() >= 20 => {
+            Ok(<[u8; 4]>::try_from(&packet[16..20]).unwrap().into())
+        }
+        Some(Protocol::Ipv6) if packet.len() >= 40 => {
+            Ok(<[u8; 16]>::try_from(&packet[24..40]).unwrap().into())
+        }
+        _ => Err(crate::Error::InvalidPacket("not an IPv4 or IPv6 packet")),
+    }
+}
+
+/// The interface error counters exposed by `rx_errors()` and friends.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum ErrorCounter {
+    RxErrors,
+    TxErrors,
+    RxDropped,
+    TxDropped,
+}
+
+/// A snapshot of the interface counters, as returned by `stats()`.
+///
+/// The counters are cumulative since the interface was created; compute deltas between
+/// two snapshots for rates. A counter the platform does not keep is `None`.
+#[cfg(any(
+    target_os = "windows",
+    all(target_os = "linux", not(target_env = "ohos")),
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct InterfaceStats {
+    /// Packets received.
+    pub rx_packets: Option<u64>,
+    /// Packets transmitted.
+    pub tx_packets: Option<u64>,
+    /// Bytes received.
+    pub rx_bytes: Option<u64>,
+    /// Bytes transmitted.
+    pub tx_bytes: Option<u64>,
+    /// Receive errors.
+    pub rx_errors: Option<u64>,
+    /// Transmit errors.
+    pub tx_errors: Option<u64>,
+    /// Received packets dropped.
+    pub rx_dropped: Option<u64>,
+    /// Packets dropped on transmit.
+    pub tx_dropped: Option<u64>,
+}
 
 #[allow(dead_code)]
 pub(crate) fn get_if_addrs_by_name(if_name: String) -> std::io::Result<Vec<Interface>> {
@@ -47,13 +299,669 @@ pub(crate) fn get_if_addrs_by_name(if_name: String) -> std::io::Result<Vec<Inter
     Ok(ifs)
 }
 
+/// The interface name as last seen by this device, for `cached_name`.
+#[allow(dead_code)]
+#[derive(Default)]
+pub(crate) struct NameCache(std::sync::RwLock<Option<std::sync::Arc<str>>>);
+
+#[allow(dead_code)]
+impl NameCache {
+    /// Returns the cached name, filling the cache with `lookup` on first use.
+    pub(crate) fn get_or(
+        &self,
+        lookup: impl FnOnce() -> crate::Result<String>,
+    ) -> crate::Result<std::sync::Arc<str>> {
+        if let Some(name) = self.0.read().unwrap().as_ref() {
+            return Ok(name.clone());
+        }
+        let name: std::sync::Arc<str> = lookup()?.into();
+        *self.0.write().unwrap() = Some(name.clone());
+        Ok(name)
+    }
+    pub(crate) fn set(&self, name: &str) {
+        *self.0.write().unwrap() = Some(name.into());
+    }
+}
+
+/// Sets the DNS servers of one interface, see `DeviceImpl::set_dns_servers`.
+#[allow(dead_code)]
+pub(crate) type DnsSetter = Box<dyn Fn(&[std::net::IpAddr]) -> crate::Result<()> + Send>;
+
+/// Puts the saved DNS servers of an interface back, see `DnsRestore`.
+type DnsRestoreFn = Box<dyn FnOnce() -> crate::Result<()> + Send>;
+
+/// The DNS servers an interface had before this device first changed them, put back on drop
+/// if the device was built with
+/// [`restore_dns_on_drop`](crate::DeviceBuilder::restore_dns_on_drop).
+///
+/// It is the first field of every `DeviceImpl`, so it is dropped before the interface is
+/// closed.
+#[allow(dead_code)]
+#[derive(Default)]
+pub(crate) struct DnsRestore {
+    enabled: std::sync::atomic::AtomicBool,
+    saved: std::sync::Mutex<Option<DnsRestoreFn>>,
+}
+
+#[allow(dead_code)]
+impl DnsRestore {
+    pub(crate) fn enable(&self) {
+        self.enabled
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+    /// Keeps `servers`, to be set again with `setter` on drop, unless restoring is off or
+    /// servers were kept before.
+    pub(crate) fn save(
+        &self,
+        servers: impl FnOnce() -> crate::Result<Vec<std::net::IpAddr>>,
+        setter: impl FnOnce() -> crate::Result<DnsSetter>,
+    ) -> crate::Result<()> {
+        if !self.enabled.load(std::sync::atomic::Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut saved = self.saved.lock().unwrap();
+        if saved.is_none() {
+            let servers = servers()?;
+            let setter = setter()?;
+            *saved = Some(Box::new(move || setter(&servers)));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DnsRestore {
+    fn drop(&mut self) {
+        if let Some(restore) = self.saved.get_mut().unwrap().take() {
+            if let Err(e) = restore() {
+                log::warn!("failed to restore the DNS servers on drop: {e}");
+            }
+        }
+    }
+}
+
+/// An I/O event reported to the hook set with [`SyncDevice::set_io_hook`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoEvent {
+    /// A packet of `len` bytes was received.
+    Recv { len: usize },
+    /// A packet of `len` bytes was sent.
+    Send { len: usize },
+    /// A receive or send failed. `WouldBlock` is not reported.
+    Error { kind: std::io::ErrorKind },
+    /// The offloads accepted by the kernel changed, see
+    /// [`DeviceImpl::set_offloads`](crate::DeviceImpl::set_offloads).
+    #[cfg(all(target_os = "linux", not(target_env = "ohos")))]
+    OffloadsChanged { offloads: crate::OffloadFlags },
+}
+
+type IoHookFn = dyn Fn(IoEvent) + Send + Sync;
+
+/// The hook installed with [`SyncDevice::set_io_hook`], checked with a single atomic load
+/// when unset.
+#[derive(Default)]
+pub(crate) struct IoHook {
+    set: std::sync::atomic::AtomicBool,
+    hook: std::sync::RwLock<Option<std::sync::Arc<IoHookFn>>>,
+}
+
+#[allow(dead_code)]
+impl IoHook {
+    pub(crate) fn set(&self, hook: Option<std::sync::Arc<IoHookFn>>) {
+        let mut guard = self.hook.write().unwrap();
+        self.set
+            .store(hook.is_some(), std::sync::atomic::Ordering::Relaxed);
+        *guard = hook;
+    }
+    pub(crate) fn is_set(&self) -> bool {
+        self.set.load(std::sync::atomic::Ordering::Relaxed)
+    }
+    pub(crate) fn emit(&self, event: IoEvent) {
+        if !self.is_set() {
+            return;
+        }
+        // Called outside the lock, so the hook may replace itself.
+        let hook = self.hook.read().unwrap().clone();
+        if let Some(hook) = hook {
+            hook(event);
+        }
+    }
+    pub(crate) fn error(&self, err: &std::io::Error) {
+        if err.kind() != std::io::ErrorKind::WouldBlock {
+            self.emit(IoEvent::Error { kind: err.kind() });
+        }
+    }
+    pub(crate) fn recv<T>(
+        &self,
+        res: std::io::Result<T>,
+        len: impl Fn(&T) -> usize,
+    ) -> std::io::Result<T> {
+        match &res {
+            Ok(v) => self.emit(IoEvent::Recv { len: len(v) }),
+            Err(e) => self.error(e),
+        }
+        res
+    }
+    pub(crate) fn send(&self, res: std::io::Result<usize>) -> std::io::Result<usize> {
+        match &res {
+            Ok(len) => self.emit(IoEvent::Send { len: *len }),
+            Err(e) => self.error(e),
+        }
+        res
+    }
+}
+
+/// How many reads `AsyncDevice` completes in a row before it yields to the executor, see
+/// `AsyncDevice::set_max_reads_per_wake`.
+#[allow(dead_code)]
+#[derive(Default)]
+pub(crate) struct ReadBudget {
+    /// 0 for no limit.
+    max: std::sync::atomic::AtomicUsize,
+    reads: std::sync::atomic::AtomicUsize,
+}
+
+#[allow(dead_code)]
+impl ReadBudget {
+    pub(crate) fn set_max(&self, max: usize) {
+        self.max.store(max, std::sync::atomic::Ordering::Relaxed);
+    }
+    pub(crate) fn max(&self) -> usize {
+        self.max.load(std::sync::atomic::Ordering::Relaxed)
+    }
+    /// Counts a read and returns whether the caller must yield before doing it.
+    pub(crate) fn should_yield(&self) -> bool {
+        use std::sync::atomic::Ordering;
+        let max = self.max();
+        if max == 0 || self.reads.fetch_add(1, Ordering::Relaxed) < max {
+            return false;
+        }
+        // This read comes first after the yield.
+        self.reads.store(1, Ordering::Relaxed);
+        true
+    }
+    /// Starts over after the caller had to wait for the device anyway.
+    pub(crate) fn would_block<T>(&self, rs: &std::io::Result<T>) {
+        if matches!(rs, Err(e) if e.kind() == std::io::ErrorKind::WouldBlock) {
+            self.reads.store(0, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+}
+
+/// A route whose output interface is the device, see `DeviceImpl::routes`.
+#[cfg(any(
+    target_os = "windows",
+    all(target_os = "linux", not(target_env = "ohos"))
+))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RouteEntry {
+    /// The destination network, the unspecified address for a default route.
+    pub destination: std::net::IpAddr,
+    /// The prefix length of `destination`.
+    pub prefix: u8,
+    /// The next hop, `None` if the destination is on-link.
+    pub gateway: Option<std::net::IpAddr>,
+    /// The metric of the route. On Windows the interface metric is added to it when routes
+    /// are compared.
+    pub metric: u32,
+    /// The routing table the route is in, `main` is 254.
+    #[cfg(target_os = "linux")]
+    pub table: u32,
+    /// The source address preferred for packets that take the route (`src` of `ip route`).
+    #[cfg(target_os = "linux")]
+    pub pref_src: Option<std::net::IpAddr>,
+}
+
+/// Returns the prefix length `address` is assigned with, if it is among `addrs`.
+#[allow(dead_code)]
+pub(crate) fn assigned_prefix(addrs: &[Interface], address: std::net::IpAddr) -> Option<u8> {
+    addrs
+        .iter()
+        .find(|v| v.address == address)
+        .and_then(|v| v.netmask)
+        .and_then(|netmask| ipnet::ip_mask_to_prefix(netmask).ok())
+}
+
+#[cfg(all(
+    any(
+        target_os = "windows",
+        all(target_os = "linux", not(target_env = "ohos")),
+        target_os = "macos",
+        target_os = "freebsd"
+    ),
+    feature = "config"
+))]
+impl DeviceImpl {
+    /// Captures what is needed to recreate the interface with
+    /// [`DeviceBuilder::from_identity`](crate::DeviceBuilder::from_identity).
+    ///
+    /// Link-local IPv6 addresses are left out, the OS assigns them again. Routes are not
+    /// captured.
+    pub fn identity(&self) -> crate::Result<crate::DeviceIdentity> {
+        use std::net::IpAddr;
+        let name = self.name()?;
+        let mut ipv4 = Vec::new();
+        let mut ipv6 = Vec::new();
+        for (address, prefix) in self.list_addresses()? {
+            match address {
+                IpAddr::V4(address) => ipv4.push((address, prefix)),
+                IpAddr::V6(address) if !is_link_local(&address) => {
+                    ipv6.push((address, prefix));
+                }
+                IpAddr::V6(_) => {}
+            }
+        }
+        let layer = self.detect_layer()?;
+        Ok(crate::DeviceIdentity {
+            name,
+            layer,
+            mtu: self.mtu()?,
+            #[cfg(windows)]
+            mtu_v6: self.mtu_v6().ok(),
+            ipv4,
+            ipv6,
+            #[cfg(any(target_os = "windows", target_os = "linux", target_os = "freebsd"))]
+            mac_addr: if layer == crate::Layer::L2 {
+                Some(self.mac_address()?)
+            } else {
+                None
+            },
+            #[cfg(windows)]
+            device_guid: self.device_guid().ok(),
+        })
+    }
+    /// Makes the interface have exactly the given addresses.
+    ///
+    /// Only the difference to the current addresses is applied. New addresses are added
+    /// before stale ones are removed, so traffic on the addresses that stay is not
+    /// interrupted. An address whose prefix changes is removed and added again. IPv6
+    /// link-local addresses are left alone. If a step fails, the steps already applied are
+    /// rolled back and the error is returned.
+    pub fn set_addresses(
+        &self,
+        v4: &[(std::net::Ipv4Addr, u8)],
+        v6: &[(std::net::Ipv6Addr, u8)],
+    ) -> crate::Result<()> {
+        self.update_addresses(v4, v6).map(|_| ())
+    }
+    /// Removes `address` from the interface.
+    ///
+    /// An address that is not assigned, also because the OS or another process removed it,
+    /// is an error of kind [`NotFound`](std::io::ErrorKind::NotFound), so that a renumbering
+    /// client notices stale state. Removing the primary address keeps the interface and its
+    /// other addresses; on Linux the next IPv4 address of the subnet is promoted.
+    pub fn remove_address(&self, address: std::net::IpAddr) -> crate::Result<()> {
+        if !self.addresses()?.contains(&address) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{address} is not assigned to the interface"),
+            )
+            .into());
+        }
+        self.remove_address_unchecked(address)
+    }
+    /// Removes the IPv4 address `address` from the interface, see
+    /// [`remove_address`](Self::remove_address).
+    pub fn remove_address_v4(&self, address: std::net::Ipv4Addr) -> crate::Result<()> {
+        self.remove_address(address.into())
+    }
+    /// Removes the IPv6 address `address` from the interface, see
+    /// [`remove_address`](Self::remove_address).
+    pub fn remove_address_v6(&self, address: std::net::Ipv6Addr) -> crate::Result<()> {
+        self.remove_address(address.into())
+    }
+    /// Sets the DNS servers of the interface, IPv4 and IPv6 mixed, in order of preference.
+    /// An empty list removes the servers set before.
+    ///
+    /// On Linux this goes through systemd-resolved with `resolvectl`, without it
+    /// [`Error::Unsupported`](crate::Error::Unsupported) is returned. macOS publishes them
+    /// with `scutil` as a resolver for all domains, Windows sets them with `netsh`. FreeBSD
+    /// is not supported.
+    ///
+    /// If the device was built with
+    /// [`restore_dns_on_drop`](crate::DeviceBuilder::restore_dns_on_drop), the servers the
+    /// interface had before the first call are set again when the device is dropped.
+    pub fn set_dns_servers(&self, servers: &[std::net::IpAddr]) -> crate::Result<()> {
+        let setter = self.dns_setter()?;
+        self.dns_restore
+            .save(|| self.dns_servers(), || self.dns_setter())?;
+        setter(servers)
+    }
+    /// Applies `spec` to the interface, changing only what differs from the current state,
+    /// and reports the changes made.
+    ///
+    /// Re-applying the same spec makes no changes, so a controller can call this
+    /// periodically without addresses or routes flapping. The MTU is set before the
+    /// addresses, which are updated as by [`set_addresses`](Self::set_addresses). If updating
+    /// the addresses fails, the MTU is restored as well.
+    pub fn apply(&self, spec: &crate::DeviceSpec) -> crate::Result<crate::ApplyReport> {
+        let mut report = crate::ApplyReport::default();
+        if let Some(mtu) = spec.mtu {
+            let old = self.mtu()?;
+            if old != mtu {
+                self.set_mtu(mtu)?;
+                report.mtu = Some((old, mtu));
+            }
+        }
+        #[cfg(windows)]
+        if let Some(mtu) = spec.mtu_v6 {
+            let old = self.mtu_v6()?;
+            if old != mtu {
+                if let Err(e) = self.set_mtu_v6(mtu) {
+                    self.restore_mtu(&report);
+                    return Err(e);
+                }
+                report.mtu_v6 = Some((old, mtu));
+            }
+        }
+        match self.update_addresses(&spec.ipv4, &spec.ipv6) {
+            Ok((added, removed)) => {
+                report.added = added;
+                report.removed = removed;
+                Ok(report)
+            }
+            Err(e) => {
+                self.restore_mtu(&report);
+                Err(e)
+            }
+        }
+    }
+    fn restore_mtu(&self, report: &crate::ApplyReport) {
+        if let Some((old, _)) = report.mtu {
+            if let Err(e) = self.set_mtu(old) {
+                log::warn!("failed to restore the MTU {old}: {e}");
+            }
+        }
+        #[cfg(windows)]
+        if let Some((old, _)) = report.mtu_v6 {
+            if let Err(e) = self.set_mtu_v6(old) {
+                log::warn!("failed to restore the IPv6 MTU {old}: {e}");
+            }
+        }
+    }
+    /// Returns the added and the removed addresses.
+    #[allow(clippy::type_complexity)]
+    fn update_addresses(
+        &self,
+        v4: &[(std::net::Ipv4Addr, u8)],
+        v6: &[(std::net::Ipv6Addr, u8)],
+    ) -> crate::Result<(Vec<(std::net::IpAddr, u8)>, Vec<(std::net::IpAddr, u8)>)> {
+        use std::net::IpAddr;
+        for (_, prefix) in v4 {
+            crate::ToIpv4Netmask::prefix(prefix)?;
+        }
+        for (_, prefix) in v6 {
+            crate::ToIpv6Netmask::prefix(prefix)?;
+        }
+        let wanted: Vec<(IpAddr, u8)> = v4
+            .iter()
+            .map(|&(address, prefix)| (address.into(), prefix))
+            .chain(v6.iter().map(|&(address, prefix)| (address.into(), prefix)))
+            .collect();
+        let current: Vec<(IpAddr, u8)> = self
+            .list_addresses()?
+            .into_iter()
+            .filter(|(address, _)| !matches!(address, IpAddr::V6(v6) if is_link_local(v6)))
+            .collect();
+        let mut applied = Vec::new();
+        if let Err(e) = self.apply_address_changes(&current, &wanted, &mut applied) {
+            for change in applied.iter().rev() {
+                let rollback = match *change {
+                    AddressChange::Added(address) => self.remove_address(address),
+                    AddressChange::Removed(address, prefix) => self.add_address(address, prefix),
+                };
+                if let Err(rollback) = rollback {
+                    log::warn!("failed to roll back {change:?}: {rollback}");
+                }
+            }
+            return Err(e);
+        }
+        let added = wanted.iter().filter(|v| !current.contains(v)).copied();
+        let removed = current.iter().filter(|v| !wanted.contains(v)).copied();
+        Ok((added.collect(), removed.collect()))
+    }
+    fn apply_address_changes(
+        &self,
+        current: &[(std::net::IpAddr, u8)],
+        wanted: &[(std::net::IpAddr, u8)],
+        applied: &mut Vec<AddressChange>,
+    ) -> crate::Result<()> {
+        let new: Vec<_> = wanted.iter().filter(|v| !current.contains(v)).collect();
+        let stale: Vec<_> = current.iter().filter(|v| !wanted.contains(v)).collect();
+        let is_new = |address| new.iter().any(|(v, _)| *v == address);
+        // An address cannot be added again with another prefix before it is removed.
+        for &&(address, prefix) in stale.iter().filter(|(address, _)| is_new(*address)) {
+            self.remove_address(address)?;
+            applied.push(AddressChange::Removed(address, prefix));
+        }
+        for &&(address, prefix) in &new {
+            self.add_address(address, prefix)?;
+            applied.push(AddressChange::Added(address));
+        }
+        let assigned = self.list_addresses()?;
+        for &&(address, prefix) in &stale {
+            if is_new(address) || !assigned.contains(&(address, prefix)) {
+                continue;
+            }
+            self.remove_address(address)?;
+            applied.push(AddressChange::Removed(address, prefix));
+        }
+        Ok(())
+    }
+    fn add_address(&self, address: std::net::IpAddr, prefix: u8) -> crate::Result<()> {
+        match address {
+            std::net::IpAddr::V4(address) => self.add_address_v4(address, prefix, None),
+            std::net::IpAddr::V6(address) => self.add_address_v6(address, prefix),
+        }
+    }
+}
+
+#[cfg(any(
+    target_os = "windows",
+    all(target_os = "linux", not(target_env = "ohos")),
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+impl DeviceImpl {
+    /// Returns every IPv4 and IPv6 address the OS currently has on the interface, with its
+    /// prefix length.
+    ///
+    /// The addresses are read from the system on every call, so addresses added or removed
+    /// after the device was created, also by other processes, are included. IPv6 link-local
+    /// addresses are included as well. An address reported without a netmask, e.g. on
+    /// some point-to-point links, has the full prefix length.
+    pub fn list_addresses(&self) -> crate::Result<Vec<(std::net::IpAddr, u8)>> {
+        #[cfg(windows)]
+        let addrs = self.interface_addrs()?;
+        #[cfg(unix)]
+        let addrs = get_if_addrs_by_name(self.name()?)?;
+        Ok(addrs
+            .iter()
+            .filter_map(|v| {
+                let prefix = match v.netmask {
+                    Some(netmask) => ipnet::ip_mask_to_prefix(netmask).ok()?,
+                    None if v.address.is_ipv4() => 32,
+                    None => 128,
+                };
+                Some((v.address, prefix))
+            })
+            .collect())
+    }
+}
+
+/// Returns the network of `destination/prefix`, its host bits cleared.
+#[cfg(all(
+    any(target_os = "windows", target_os = "macos", target_os = "freebsd"),
+    feature = "config"
+))]
+pub(crate) fn route_network(
+    destination: std::net::IpAddr,
+    prefix: u8,
+) -> crate::Result<std::net::IpAddr> {
+    ipnet::IpNet::new(destination, prefix)
+        .map(|net| net.network())
+        .map_err(|_| crate::Error::InvalidConfig("invalid route prefix length"))
+}
+
+/// Fails with [`Error::InvalidConfig`](crate::Error::InvalidConfig) if `gateway` is not of
+/// the family of `destination`.
+#[cfg(all(
+    any(
+        target_os = "windows",
+        all(target_os = "linux", not(target_env = "ohos")),
+        target_os = "macos",
+        target_os = "freebsd"
+    ),
+    feature = "config"
+))]
+pub(crate) fn check_gateway(
+    destination: std::net::IpAddr,
+    gateway: Option<std::net::IpAddr>,
+) -> crate::Result<()> {
+    if gateway.is_some_and(|gateway| gateway.is_ipv4() != destination.is_ipv4()) {
+        return Err(crate::Error::InvalidConfig(
+            "the gateway must be of the family of the destination",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(all(any(target_os = "macos", target_os = "freebsd"), feature = "config"))]
+impl DeviceImpl {
+    /// Routes `destination/prefix` out of this device with `route -n add -iface`.
+    ///
+    /// The host bits of `destination` are cleared. Route metrics are not supported here,
+    /// `Some` metric fails with [`Error::Unsupported`](crate::Error::Unsupported). An
+    /// existing route to the network is not replaced, an error of kind
+    /// [`AlreadyExists`](std::io::ErrorKind::AlreadyExists) is returned instead.
+    pub fn add_route_with_metric(
+        &self,
+        destination: std::net::IpAddr,
+        prefix: u8,
+        metric: Option<u32>,
+    ) -> crate::Result<()> {
+        if metric.is_some() {
+            return Err(crate::Error::Unsupported("route metrics"));
+        }
+        self.change_route(true, destination, prefix, None)
+    }
+    /// Routes `destination/prefix` out of this device through `gateway` with
+    /// `route -n add <gateway> -ifp`, on-link with `-iface` for `None`.
+    ///
+    /// The gateway must be of the family of `destination`. An existing route to the network
+    /// fails with an error of kind [`AlreadyExists`](std::io::ErrorKind::AlreadyExists).
+    pub fn add_route_via(
+        &self,
+        destination: std::net::IpAddr,
+        prefix: u8,
+        gateway: Option<std::net::IpAddr>,
+    ) -> crate::Result<()> {
+        check_gateway(destination, gateway)?;
+        self.change_route(true, destination, prefix, gateway)
+    }
+    /// Removes the route to `destination/prefix` out of this device with `route -n delete`.
+    /// Fails with an error of kind [`NotFound`](std::io::ErrorKind::NotFound) if there is
+    /// no such route.
+    pub fn remove_route(&self, destination: std::net::IpAddr, prefix: u8) -> crate::Result<()> {
+        self.change_route(false, destination, prefix, None)
+    }
+    fn change_route(
+        &self,
+        add: bool,
+        destination: std::net::IpAddr,
+        prefix: u8,
+        gateway: Option<std::net::IpAddr>,
+    ) -> crate::Result<()> {
+        let network = route_network(destination, prefix)?;
+        let if_name = self.name()?;
+        let network = format!("{network}/{prefix}");
+        let gateway = gateway.map(|gateway| gateway.to_string());
+        // command: route -n add -net 10.0.0.0/24 -iface utun3
+        // command: route -n add -net 10.0.0.0/24 10.1.0.1 -ifp utun3
+        let mut args = vec![
+            "-n",
+            if add { "add" } else { "delete" },
+            if destination.is_ipv4() {
+                "-net"
+            } else {
+                "-inet6"
+            },
+            &network,
+        ];
+        match &gateway {
+            Some(gateway) => args.extend([gateway.as_str(), "-ifp"]),
+            None => args.push("-iface"),
+        }
+        args.push(&if_name);
+        match crate::run_command("route", &args) {
+            Ok(_) => {
+                log::info!("route {}", args.join(" "));
+                Ok(())
+            }
+            Err(e) => {
+                let message = e.to_string();
+                let kind = if message.contains("File exists") {
+                    std::io::ErrorKind::AlreadyExists
+                } else if message.contains("not in table") {
+                    std::io::ErrorKind::NotFound
+                } else {
+                    return Err(e.into());
+                };
+                Err(std::io::Error::new(kind, message).into())
+            }
+        }
+    }
+}
+
+/// A step applied by `set_addresses`, kept to roll it back.
+#[cfg(all(
+    any(
+        target_os = "windows",
+        all(target_os = "linux", not(target_env = "ohos")),
+        target_os = "macos",
+        target_os = "freebsd"
+    ),
+    feature = "config"
+))]
+#[derive(Debug)]
+enum AddressChange {
+    Added(std::net::IpAddr),
+    Removed(std::net::IpAddr, u8),
+}
+
+#[cfg(all(
+    any(
+        target_os = "windows",
+        all(target_os = "linux", not(target_env = "ohos")),
+        target_os = "macos",
+        target_os = "freebsd"
+    ),
+    feature = "config"
+))]
+fn is_link_local(address: &std::net::Ipv6Addr) -> bool {
+    address.segments()[0] & 0xffc0 == 0xfe80
+}
+
 /// A transparent wrapper around DeviceImpl, providing synchronous I/O operations.
+///
+/// `SyncDevice` is `Send` and `Sync` on every platform. All methods take `&self`, so a device
+/// shared through an `Arc` can receive on one thread while other threads send or change the
+/// configuration. Concurrent `recv` calls each get a different packet; on Windows TAP devices
+/// they are serialized by an internal lock.
 #[repr(transparent)]
 pub struct SyncDevice(pub(crate) DeviceImpl);
 
 impl SyncDevice {
     /// Creates a new SyncDevice from a raw file descriptor.
     ///
+    /// The device owns the descriptor and closes it on drop,
+    /// [`into_raw_fd`](IntoRawFd::into_raw_fd) hands it back. No framing is read from the
+    /// descriptor, [`DeviceBuilder::build_from_fd`](crate::DeviceBuilder::build_from_fd)
+    /// checks it where the builder is available. On a descriptor from Android's
+    /// `VpnService`, setters that change the interface, such as addresses or the MTU, fail
+    /// with a permission error; they are optional, the framework configures the interface.
+    ///
     /// # Safety
     /// - The file descriptor (`fd`) must be an owned file descriptor.
     /// - It must be valid and open.
@@ -63,31 +971,199 @@ impl SyncDevice {
     pub unsafe fn from_fd(fd: RawFd) -> Self {
         SyncDevice(DeviceImpl::from_fd(fd))
     }
+    /// Undoes the configuration applied through the device and closes it.
+    ///
+    /// The routes added with `add_route` or `add_peer_routes` are removed first, then the
+    /// addresses the device assigned, then the interface is brought down if the device
+    /// brought it up. Every step is attempted, the failed ones are in the report so that a
+    /// supervisor can escalate. Configuration that was in place before is left alone.
+    ///
+    /// Without `close` the same happens on drop for devices built with
+    /// [`cleanup_on_drop`](crate::DeviceBuilder::cleanup_on_drop), with failures logged,
+    /// unless [`leak_on_drop`](DeviceImpl::leak_on_drop) is set. Returns an error if the
+    /// interface cannot be looked up.
+    #[cfg(all(target_os = "linux", not(target_env = "ohos"), feature = "config"))]
+    pub fn close(self) -> std::io::Result<TeardownReport> {
+        Ok(self.0.teardown()?)
+    }
     /// Receives data from the device into the provided buffer.
     ///
     /// Returns the number of bytes read, or an I/O error.
+    ///
+    /// If the packet does not fit into `buf`, it is consumed and an error of kind `InvalidInput`
+    /// wrapping [`Error::Truncated`](crate::Error::Truncated) is returned. Unix kernels cut
+    /// the packet silently, so a filled `buf` is checked against the length in the IP header.
+    /// This works for TUN devices on Linux and macOS, TAP devices, devices with a virtio-net
+    /// header and FreeBSD return the cut packet, so size `buf` for the MTU plus
+    /// [`header_overhead`](crate::DeviceImpl::header_overhead) or use [`recv_owned`](Self::recv_owned).
+    ///
+    /// On Windows (wintun) the error kind tells how to recover:
+    /// - `NotFound` ([`Error::DeviceGone`](crate::Error::DeviceGone)): the adapter was removed,
+    ///   create the device again.
+    /// - `UnexpectedEof`: the device was shut down with `shutdown`, stop reading.
+    /// - `NotConnected`: the device is disabled with `set_enabled(false)`, only returned by
+    ///   `try_recv`, `recv` waits for it to be enabled again.
+    /// - `InvalidData`: the receive ring is corrupt, restart the session by disabling and
+    ///   enabling the device.
+    /// - anything else is an OS error that may be retried.
     pub fn recv(&self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.0.recv(buf)
+        let res = self.0.mirror.recv(self.0.recv(buf), buf, |n| *n);
+        self.0.io_hook.recv(res, |n| *n)
+    }
+    /// Receives a packet like [`recv`](Self::recv), with the time it was read.
+    ///
+    /// TUN devices have no kernel receive timestamps (`SIOCGSTAMPNS` and `SO_TIMESTAMPING`
+    /// only exist for sockets), so the time is taken with the realtime clock right after the
+    /// read returns, on Windows right after the packet is taken from the wintun ring or the
+    /// TAP driver. It is accurate to the scheduling of the receiving thread, typically a few
+    /// microseconds, and is not affected by how late the caller processes the packet.
+    pub fn recv_with_timestamp(&self, buf: &mut [u8]) -> std::io::Result<(usize, SystemTime)> {
+        let res = self
+            .0
+            .mirror
+            .recv(self.0.recv_with_timestamp(buf), buf, |(n, _)| *n);
+        self.0.io_hook.recv(res, |(n, _)| *n)
+    }
+    /// Receives a packet into a newly allocated buffer that can hold any packet.
+    pub fn recv_owned(&self) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0; MAX_PACKET_SIZE];
+        let res = self.0.mirror.recv(self.0.recv(&mut buf), &buf, |n| *n);
+        let n = self.0.io_hook.recv(res, |n| *n)?;
+        buf.truncate(n);
+        Ok(buf)
     }
     /// Sends data from the provided buffer to the device.
     ///
     /// Returns the number of bytes written, or an I/O error.
+    ///
+    /// Packets are all-or-nothing: if only part of the packet could be written, an error of
+    /// kind `WriteZero` wrapping [`Error::PartialSend`](crate::Error::PartialSend) is returned.
+    ///
+    /// On Linux and macOS L3 devices, a packet without an IP header at the expected position
+    /// is rejected with [`Error::InvalidPacket`](crate::Error::InvalidPacket) before it is
+    /// written, which catches a missing packet information header.
     pub fn send(&self, buf: &[u8]) -> std::io::Result<usize> {
-        self.0.send(buf)
+        self.0
+            .io_hook
+            .send(self.0.mirror.send(self.0.send(buf), buf))
+    }
+    /// Sends an IP packet of the given protocol.
+    ///
+    /// If the device uses packet information headers (`packet_information(true)` on Linux,
+    /// `ignore_packet_info` off on macOS), the header for `protocol` is prepended, so `buf`
+    /// is always a plain IP packet. Returns the length of `buf`.
+    ///
+    /// [`send`](Self::send) instead expects the header to be part of `buf` and fails with
+    /// [`Error::InvalidPacket`](crate::Error::InvalidPacket) if no IP header follows it.
+    pub fn send_packet(&self, buf: &[u8], protocol: Protocol) -> std::io::Result<usize> {
+        #[cfg(unix)]
+        let res = self.0.send_packet(buf, protocol);
+        #[cfg(windows)]
+        let res = {
+            _ = protocol;
+            self.0.send(buf)
+        };
+        self.0.io_hook.send(self.0.mirror.send(res, buf))
+    }
+    /// Receives an IP packet and its protocol, without the packet information header.
+    ///
+    /// Fails with [`Error::InvalidPacket`](crate::Error::InvalidPacket) if the packet is
+    /// neither IPv4 nor IPv6, e.g. on L2 devices.
+    pub fn recv_packet(&self, buf: &mut [u8]) -> std::io::Result<(usize, Protocol)> {
+        #[cfg(unix)]
+        let res = self.0.recv_packet(buf);
+        #[cfg(windows)]
+        let res = self.0.recv(buf).and_then(|len| {
+            let protocol = Protocol::of(&buf[..len]).ok_or_else(|| {
+                std::io::Error::from(crate::Error::InvalidPacket("not an IPv4 or IPv6 packet"))
+            })?;
+            Ok((len, protocol))
+        });
+        let res = self.0.mirror.recv(res, buf, |(n, _)| *n);
+        self.0.io_hook.recv(res, |(n, _)| *n)
+    }
+    /// Sends an IP packet with its DSCP set to `dscp`, keeping the ECN bits, see
+    /// [`packet::set_dscp`](crate::packet::set_dscp).
+    ///
+    /// Only the copy handed to the OS is changed, `buf` stays as it is. The IPv4 header
+    /// checksum is updated accordingly. For IPv6 this sets the upper six bits of the traffic
+    /// class; the flow label is left alone.
+    ///
+    /// The DSCP of the outer packet of a tunnel is set on the socket that carries it, e.g.
+    /// with `IP_TOS` or `IPV6_TCLASS`. A TUN device has no such option: its file descriptor
+    /// is not a socket, so `setsockopt` fails with `ENOTSOCK`, and per-packet values have to
+    /// be written into the packets as done here.
+    ///
+    /// Where the position of the IP header is not known, on TAP devices, with a virtio-net
+    /// header and on Unix platforms other than Linux and macOS, an error of kind
+    /// `Unsupported` is returned.
+    pub fn send_with_dscp(&self, buf: &[u8], dscp: u8) -> std::io::Result<usize> {
+        #[cfg(unix)]
+        let res = self.0.send_with_dscp(buf, dscp);
+        #[cfg(windows)]
+        let res = {
+            let mut packet = buf.to_vec();
+            crate::packet::set_dscp(&mut packet, dscp)
+                .map_err(std::io::Error::from)
+                .and_then(|_| self.0.send(&packet))
+        };
+        self.0.io_hook.send(self.0.mirror.send(res, buf))
     }
     /// Attempts to receive data from the device in a non-blocking fashion.
     ///
-    /// Returns the number of bytes read or an error if the operation would block.
-    #[cfg(target_os = "windows")]
+    /// Returns the number of bytes read. If no packet is available, an error of kind
+    /// `WouldBlock` is returned on every platform, never `Ok(0)`.
+    ///
+    /// On Unix a device in blocking mode is polled before reading. If other threads receive
+    /// from the same device, use [`set_nonblocking`](Self::set_nonblocking) so that a packet
+    /// taken by another thread cannot make this call block.
     pub fn try_recv(&self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.0.try_recv(buf)
+        #[cfg(unix)]
+        let res = (|| {
+            if !self.0.is_nonblocking()? && !self.0.poll_ready(libc::POLLIN)? {
+                return Err(std::io::Error::from(std::io::ErrorKind::WouldBlock));
+            }
+            self.0.recv(buf)
+        })();
+        #[cfg(windows)]
+        let res = self.0.try_recv(buf);
+        let res = self.0.mirror.recv(res, buf, |n| *n);
+        self.0.io_hook.recv(res, |n| *n)
     }
     /// Attempts to send data to the device in a non-blocking fashion.
     ///
-    /// Returns the number of bytes written or an error if the operation would block.
-    #[cfg(target_os = "windows")]
+    /// Returns the number of bytes written. If the device cannot accept the packet right
+    /// now, an error of kind `WouldBlock` is returned on every platform.
     pub fn try_send(&self, buf: &[u8]) -> std::io::Result<usize> {
-        self.0.try_send(buf)
+        #[cfg(unix)]
+        let res = (|| {
+            if !self.0.is_nonblocking()? && !self.0.poll_ready(libc::POLLOUT)? {
+                return Err(std::io::Error::from(std::io::ErrorKind::WouldBlock));
+            }
+            self.0.send(buf)
+        })();
+        #[cfg(windows)]
+        let res = self.0.try_send(buf);
+        self.0.io_hook.send(self.0.mirror.send(res, buf))
+    }
+    /// Blocks until the device has a packet to receive, for at most `timeout` (`None` waits
+    /// indefinitely). Returns `false` if the timeout elapsed first.
+    ///
+    /// Pairs with [`try_recv`](Self::try_recv): call it after `try_recv` returned
+    /// `WouldBlock`. With TAP this waits on the overlapped read event, with wintun on the
+    /// session's read event.
+    #[cfg(target_os = "windows")]
+    pub fn wait_readable(&self, timeout: Option<std::time::Duration>) -> std::io::Result<bool> {
+        self.0.wait_readable(timeout)
+    }
+    /// Blocks until the device can accept a packet, for at most `timeout` (`None` waits
+    /// indefinitely). Returns `false` if the timeout elapsed first.
+    ///
+    /// Pairs with [`try_send`](Self::try_send). With TAP this waits for the pending
+    /// overlapped write to finish. Wintun has no such event and returns immediately.
+    #[cfg(target_os = "windows")]
+    pub fn wait_writable(&self, timeout: Option<std::time::Duration>) -> std::io::Result<bool> {
+        self.0.wait_writable(timeout)
     }
     /// Shuts down the device on Windows.
     ///
@@ -111,7 +1187,7 @@ impl SyncDevice {
     /// Returns the total number of bytes read from the packet, or an error.
     #[cfg(unix)]
     pub fn recv_vectored(&self, bufs: &mut [IoSliceMut<'_>]) -> std::io::Result<usize> {
-        self.0.recv_vectored(bufs)
+        self.0.io_hook.recv(self.0.recv_vectored(bufs), |n| *n)
     }
     /// Sends data to the device from multiple buffers using vectored I/O.
     ///
@@ -119,9 +1195,24 @@ impl SyncDevice {
     /// the provided buffers as one packet.
     ///
     /// Returns the total number of bytes written for the packet, or an error.
+    /// As with [`send`](Self::send), a partially written packet is reported as an error.
     #[cfg(unix)]
     pub fn send_vectored(&self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
-        self.0.send_vectored(bufs)
+        self.0.io_hook.send(self.0.send_vectored(bufs))
+    }
+    /// Calls `hook` for every packet received or sent through this `SyncDevice` and for every
+    /// failed receive or send, replacing any previous hook.
+    ///
+    /// Batched calls such as `recv_multiple` and `send_multiple` on Linux and Windows report
+    /// one event per packet. I/O through [`AsyncDevice`](crate::AsyncDevice) is not reported. The hook runs
+    /// on the I/O path and should return quickly; while none is set the cost is one atomic
+    /// load per call.
+    pub fn set_io_hook(&self, hook: impl Fn(IoEvent) + Send + Sync + 'static) {
+        self.0.io_hook.set(Some(std::sync::Arc::new(hook)));
+    }
+    /// Removes the hook set with [`set_io_hook`](Self::set_io_hook).
+    pub fn clear_io_hook(&self) {
+        self.0.io_hook.set(None);
     }
     /// Checks whether the device is currently operating in nonblocking mode.
     ///
@@ -167,6 +1258,30 @@ impl AsFd for SyncDevice {
         unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) }
     }
 }
+/// See the implementation for `DeviceImpl`.
+#[cfg_attr(docsrs, doc(cfg(feature = "mio")))]
+#[cfg(all(unix, feature = "mio"))]
+impl mio::event::Source for SyncDevice {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        self.0.register(registry, token, interests)
+    }
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        self.0.reregister(registry, token, interests)
+    }
+    fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+        self.0.deregister(registry)
+    }
+}
 #[cfg(unix)]
 impl IntoRawFd for SyncDevice {
     fn into_raw_fd(self) -> RawFd {
@@ -180,7 +1295,7 @@ impl IntoRawFd for SyncDevice {
     target_os = "macos",
     target_os = "freebsd",
 ))]
-#[cfg(test)]
+#[cfg(all(test, feature = "config"))]
 mod test {
     use crate::DeviceBuilder;
     use std::net::Ipv4Addr;
@@ -204,3 +1319,59 @@ mod test {
         assert_eq!("utun6", dev.name().unwrap());
     }
 }
+
+#[cfg(test)]
+mod mac {
+    use super::{check_mac, random_mac};
+    use crate::Error;
+
+    #[test]
+    fn rejects_all_zero_mac() {
+        let err = check_mac(&[0; 6]).unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)));
+    }
+
+    #[test]
+    fn rejects_multicast_mac() {
+        for mac in [[0x01, 0, 0x5e, 0, 0, 1], [0xff; 6], [0x03, 1, 2, 3, 4, 5]] {
+            let err = check_mac(&mac).unwrap_err();
+            assert!(matches!(err, Error::InvalidConfig(_)));
+        }
+    }
+
+    #[test]
+    fn accepts_unicast_mac() {
+        assert!(check_mac(&[0x02, 0, 0, 0, 0, 1]).is_ok());
+        assert!(check_mac(&[0x00, 0x1b, 0x21, 0, 0, 1]).is_ok());
+    }
+
+    #[test]
+    fn random_mac_is_valid() {
+        for _ in 0..64 {
+            let mac = random_mac();
+            assert_eq!(0x02, mac[0] & 0x03);
+            assert!(check_mac(&mac).is_ok());
+        }
+    }
+}
+
+/// Fails to compile if a device type loses `Send` or `Sync`.
+#[cfg(test)]
+mod send_sync {
+    fn assert_send_sync<T: Send + Sync>() {}
+    #[allow(dead_code)]
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn device_types_are_send_and_sync() {
+        assert_send_sync::<super::DeviceImpl>();
+        assert_send_sync::<super::SyncDevice>();
+        #[cfg(any(feature = "async_std", feature = "async_tokio"))]
+        assert_send_sync::<crate::AsyncDevice>();
+        #[cfg(all(
+            feature = "async_framed",
+            any(feature = "async_std", feature = "async_tokio")
+        ))]
+        assert_send::<crate::async_framed::DeviceFramed<crate::async_framed::BytesCodec>>();
+    }
+}
@@ -0,0 +1,126 @@
+use crate::DeviceBuilder;
+
+/// Whether a feature works on this host, see [`probe`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Support {
+    /// The feature works.
+    Available,
+    /// The platform has the feature but it failed here, e.g. for lack of permissions or a
+    /// missing driver. Holds the error.
+    Failed(String),
+    /// The platform does not have the feature.
+    NotSupported,
+}
+
+impl Support {
+    /// Returns whether the feature works.
+    pub fn is_available(&self) -> bool {
+        matches!(self, Support::Available)
+    }
+}
+
+impl<T> From<crate::Result<T>> for Support {
+    fn from(result: crate::Result<T>) -> Self {
+        match result {
+            Ok(_) => Support::Available,
+            Err(e) => Support::Failed(e.to_string()),
+        }
+    }
+}
+
+/// What this host supports, as reported by [`probe`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PlatformCapabilities {
+    /// Creating L3 devices. On macOS these are utun interfaces, on Windows this tells whether
+    /// wintun can be loaded.
+    pub tun: Support,
+    /// Creating L2 devices, which macOS does not have. On Windows this tells whether the
+    /// tap0901 driver is installed.
+    pub tap: Support,
+    /// Creating devices with [`offload`](DeviceBuilder::offload), which needs the kernel to
+    /// accept TCP segmentation offload, on Linux.
+    pub offload: Support,
+    /// Whether the kernel also accepts UDP segmentation offload (Linux 6.2 and later).
+    pub udp_gso: bool,
+    /// Creating multi-queue devices on Linux.
+    pub multi_queue: Support,
+    /// The version of the running wintun driver on Windows. `None` while no wintun adapter
+    /// exists, the driver is only loaded with the first one.
+    pub wintun_version: Option<String>,
+    /// The version of the installed tap0901 driver on Windows.
+    pub tap_driver_version: Option<String>,
+}
+
+/// Reports what this host supports, for deciding the configuration and for support bundles.
+///
+/// On Unix this creates a few disabled, non-persistent devices without addresses, which
+/// disappear again before `probe` returns; it needs the same permissions as creating a
+/// device. On Windows no adapter is created: wintun is loaded and the installed tap0901
+/// driver is looked up, so whether creating an adapter succeeds also depends on running
+/// elevated.
+pub fn probe() -> PlatformCapabilities {
+    let mut caps = PlatformCapabilities {
+        tun: Support::NotSupported,
+        tap: Support::NotSupported,
+        offload: Support::NotSupported,
+        udp_gso: false,
+        multi_queue: Support::NotSupported,
+        wintun_version: None,
+        tap_driver_version: None,
+    };
+    #[cfg(unix)]
+    {
+        caps.tun = DeviceBuilder::new()
+            .enable(false)
+            .layer(crate::Layer::L3)
+            .build_sync()
+            .into();
+    }
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    {
+        caps.tap = DeviceBuilder::new()
+            .enable(false)
+            .layer(crate::Layer::L2)
+            .build_sync()
+            .into();
+    }
+    #[cfg(target_os = "linux")]
+    {
+        match DeviceBuilder::new()
+            .enable(false)
+            .offload(true)
+            .build_sync()
+        {
+            // Without TSO the device silently falls back to no offload.
+            Ok(device) if !device.tcp_gso() => {
+                caps.offload = Support::Failed("the kernel refused TSO".to_string())
+            }
+            Ok(device) => {
+                caps.offload = Support::Available;
+                caps.udp_gso = device.udp_gso();
+            }
+            Err(e) => caps.offload = Support::Failed(e.to_string()),
+        }
+        caps.multi_queue = DeviceBuilder::new()
+            .enable(false)
+            .num_queues(2)
+            .build_sync_multi_queue()
+            .into();
+    }
+    #[cfg(windows)]
+    {
+        const WINTUN_FILE: &str = "wintun.dll";
+        caps.tun = crate::platform::windows::check_wintun(WINTUN_FILE).into();
+        caps.wintun_version = crate::wintun_version(WINTUN_FILE).ok();
+        match crate::platform::windows::tap_driver_version("tap0901") {
+            Ok(version) => {
+                caps.tap = Support::Available;
+                caps.tap_driver_version = Some(version);
+            }
+            Err(e) => caps.tap = Support::Failed(e.to_string()),
+        }
+    }
+    caps
+}
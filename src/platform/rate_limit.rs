@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A token bucket limit for the bytes sent to a device, see
+/// [`AsyncDevice::set_send_rate_limit`](crate::AsyncDevice::set_send_rate_limit).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The sustained rate in bytes per second.
+    pub bytes_per_sec: u64,
+    /// How many bytes may be sent at once after the device was idle. With 0 every packet
+    /// waits for its share of time.
+    pub burst: u64,
+}
+
+struct Bucket {
+    limit: RateLimit,
+    /// The bytes that may be sent right now, negative while sends are reserved ahead.
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens =
+            (self.tokens + elapsed * self.limit.bytes_per_sec as f64).min(self.limit.burst as f64);
+    }
+    /// The bytes that must be available before `len` bytes go out. A packet larger than the
+    /// burst needs the full burst and leaves the bucket in debt.
+    fn needed(&self, len: usize) -> f64 {
+        (len as u64).min(self.limit.burst) as f64
+    }
+}
+
+/// The send pacing of a device, checked with a single atomic load when no limit is set.
+#[allow(dead_code)]
+#[derive(Default)]
+pub(crate) struct SendPacer {
+    set: AtomicBool,
+    bucket: Mutex<Option<Bucket>>,
+}
+
+#[allow(dead_code)]
+impl SendPacer {
+    pub(crate) fn set(&self, limit: Option<RateLimit>, now: Instant) -> crate::Result<()> {
+        if limit.is_some_and(|limit| limit.bytes_per_sec == 0) {
+            return Err(crate::Error::InvalidConfig(
+                "the rate limit must allow at least one byte per second",
+            ));
+        }
+        let mut guard = self.bucket.lock().unwrap();
+        self.set.store(limit.is_some(), Ordering::Relaxed);
+        *guard = limit.map(|limit| Bucket {
+            limit,
+            tokens: limit.burst as f64,
+            last: now,
+        });
+        Ok(())
+    }
+    /// Charges `len` bytes and returns how long to wait before sending them, `None` to send
+    /// right away.
+    ///
+    /// The bytes are charged immediately, so concurrent senders queue up behind each other.
+    pub(crate) fn reserve(&self, len: usize, now: Instant) -> Option<Duration> {
+        if !self.set.load(Ordering::Relaxed) {
+            return None;
+        }
+        let mut guard = self.bucket.lock().unwrap();
+        let bucket = guard.as_mut()?;
+        bucket.refill(now);
+        let missing = bucket.needed(len) - bucket.tokens;
+        bucket.tokens -= len as f64;
+        (missing > 0.0)
+            .then(|| Duration::from_secs_f64(missing / bucket.limit.bytes_per_sec as f64))
+    }
+    /// Charges `len` bytes if they may be sent right now, otherwise fails with `WouldBlock`
+    /// and charges nothing.
+    pub(crate) fn try_reserve(&self, len: usize, now: Instant) -> std::io::Result<()> {
+        if !self.set.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut guard = self.bucket.lock().unwrap();
+        let Some(bucket) = guard.as_mut() else {
+            return Ok(());
+        };
+        bucket.refill(now);
+        if bucket.tokens < bucket.needed(len) {
+            return Err(std::io::Error::from(std::io::ErrorKind::WouldBlock));
+        }
+        bucket.tokens -= len as f64;
+        Ok(())
+    }
+    /// Returns bytes that were reserved but not sent, e.g. because the send failed.
+    pub(crate) fn refund(&self, len: usize) {
+        if !self.set.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Some(bucket) = self.bucket.lock().unwrap().as_mut() {
+            bucket.tokens = (bucket.tokens + len as f64).min(bucket.limit.burst as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `total` bytes in packets of `len` as fast as the pacer allows and returns the
+    /// time it took.
+    fn pace(limit: RateLimit, len: usize, total: usize) -> Duration {
+        let pacer = SendPacer::default();
+        let start = Instant::now();
+        pacer.set(Some(limit), start).unwrap();
+        let mut now = start;
+        for _ in 0..total / len {
+            if let Some(delay) = pacer.reserve(len, now) {
+                now += delay;
+            }
+        }
+        now - start
+    }
+
+    #[test]
+    fn rates() {
+        // 100 kbit/s up to 1 Gbit/s, one second of traffic after the burst.
+        for (bytes_per_sec, len) in [(12_500, 100), (1_250_000, 1400), (125_000_000, 1400)] {
+            let burst = 10 * len as u64;
+            let limit = RateLimit {
+                bytes_per_sec,
+                burst,
+            };
+            let elapsed = pace(limit, len, (burst + bytes_per_sec) as usize);
+            let error = (elapsed.as_secs_f64() - 1.0).abs();
+            assert!(error < 0.02, "{bytes_per_sec} B/s took {elapsed:?}");
+        }
+    }
+
+    #[test]
+    fn try_reserve() {
+        let pacer = SendPacer::default();
+        let now = Instant::now();
+        assert!(pacer.try_reserve(1000, now).is_ok());
+        let limit = RateLimit {
+            bytes_per_sec: 1000,
+            burst: 1500,
+        };
+        pacer.set(Some(limit), now).unwrap();
+        assert!(pacer.try_reserve(1000, now).is_ok());
+        let err = pacer.try_reserve(1000, now).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+        assert!(pacer
+            .try_reserve(1000, now + Duration::from_millis(500))
+            .is_ok());
+        // Larger than the burst, it goes out once the bucket is full.
+        assert!(pacer
+            .try_reserve(2000, now + Duration::from_secs(1))
+            .is_err());
+        assert!(pacer
+            .try_reserve(2000, now + Duration::from_secs(2))
+            .is_ok());
+    }
+}
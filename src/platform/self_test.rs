@@ -0,0 +1,256 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::platform::is_link_local;
+use crate::{Layer, SyncDevice};
+
+/// The port the probes are addressed to on the peer, the discard service.
+const PEER_PORT: u16 = 9;
+
+/// The outcome of one direction of [`SyncDevice::self_test`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Probe {
+    /// The probe arrived after the given time.
+    Passed(Duration),
+    /// The probe could not be sent, e.g. `send` on the device failed with `EIO` or `EINVAL`.
+    Failed(io::ErrorKind),
+    /// The probe was sent but did not arrive before the timeout.
+    TimedOut,
+}
+
+impl Probe {
+    /// Returns whether the probe arrived.
+    pub fn passed(&self) -> bool {
+        matches!(self, Probe::Passed(_))
+    }
+}
+
+/// The result of [`SyncDevice::self_test`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SelfTestReport {
+    /// The UDP socket on an address of the device that sent and received the probes.
+    pub local: SocketAddr,
+    /// The address in the subnet of the device the probes were exchanged with.
+    pub peer: SocketAddr,
+    /// A datagram sent from `local` to `peer` was routed into the device and returned by
+    /// `recv`. A failure points at routing or the interface state.
+    pub inbound: Probe,
+    /// A datagram from `peer` to `local` written with `send` was delivered to the socket.
+    /// `Failed` means the kernel rejected the packet, e.g. because of a packet information
+    /// or offload header mismatch, `TimedOut` that it was accepted but dropped, e.g. by a
+    /// firewall or reverse path filtering.
+    pub outbound: Probe,
+}
+
+impl SelfTestReport {
+    /// Returns whether traffic flows in both directions.
+    pub fn is_ok(&self) -> bool {
+        self.inbound.passed() && self.outbound.passed()
+    }
+}
+
+impl SyncDevice {
+    /// Checks that packets flow between the device and the network stack of the OS.
+    ///
+    /// A UDP socket is bound to an address of the device, preferring IPv4, and exchanges a
+    /// datagram with another address in its subnet in both directions: one sent from the
+    /// socket must come out of [`recv`](Self::recv), one written with [`send`](Self::send)
+    /// must arrive at the socket. Each direction waits up to `timeout`.
+    ///
+    /// The device must be up with an address whose prefix leaves room for another host,
+    /// and must be a TUN (L3) device. Packets received during the test that are not the
+    /// probe are discarded, so run it before the device carries traffic.
+    ///
+    /// Errors are returned when the test cannot be set up, e.g. without a suitable address
+    /// or when the socket cannot be bound. Failures of the probes are in the report.
+    ///
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// use std::time::Duration;
+    /// let dev = tun_rs::DeviceBuilder::new()
+    ///     .ipv4("10.0.0.2", 24, None)
+    ///     .build_sync()?;
+    /// let report = dev.self_test(Duration::from_secs(1))?;
+    /// assert!(report.is_ok(), "{report:?}");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn self_test(&self, timeout: Duration) -> io::Result<SelfTestReport> {
+        if self.detect_layer()? != Layer::L3 {
+            return Err(crate::Error::Unsupported("the self test needs an L3 device").into());
+        }
+        let (address, peer) = self
+            .0
+            .list_addresses()?
+            .into_iter()
+            .filter_map(|(address, prefix)| Some((address, peer_address(address, prefix)?)))
+            .min_by_key(|(address, _)| address.is_ipv6())
+            .ok_or(crate::Error::InvalidConfig(
+                "the device has no address with room for another host in its subnet",
+            ))?;
+        let socket = UdpSocket::bind(SocketAddr::new(address, 0))?;
+        let local = socket.local_addr()?;
+        let peer = SocketAddr::new(peer, PEER_PORT);
+        let token = token();
+        let inbound = self.probe_inbound(&socket, peer, &token, timeout)?;
+        let outbound = self.probe_outbound(&socket, local, peer, &token, timeout)?;
+        Ok(SelfTestReport {
+            local,
+            peer,
+            inbound,
+            outbound,
+        })
+    }
+    fn probe_inbound(
+        &self,
+        socket: &UdpSocket,
+        peer: SocketAddr,
+        token: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Probe> {
+        let start = Instant::now();
+        if let Err(e) = socket.send_to(token, peer) {
+            return Ok(Probe::Failed(e.kind()));
+        }
+        let mut buf = vec![0; crate::platform::MAX_PACKET_SIZE];
+        loop {
+            match self.try_recv(&mut buf) {
+                Ok(len) => {
+                    if buf[..len].windows(token.len()).any(|v| v == token) {
+                        return Ok(Probe::Passed(start.elapsed()));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if start.elapsed() >= timeout {
+                        return Ok(Probe::TimedOut);
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err(e) => return Ok(Probe::Failed(e.kind())),
+            }
+        }
+    }
+    fn probe_outbound(
+        &self,
+        socket: &UdpSocket,
+        local: SocketAddr,
+        peer: SocketAddr,
+        token: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Probe> {
+        let start = Instant::now();
+        let packet = crate::packet::udp_packet(peer, local, token);
+        let overhead = self.header_overhead()?;
+        let sent = if overhead == crate::PACKET_INFORMATION_LENGTH {
+            let protocol = if local.is_ipv4() {
+                crate::Protocol::Ipv4
+            } else {
+                crate::Protocol::Ipv6
+            };
+            self.send_packet(&packet, protocol)
+        } else {
+            // A zeroed virtio-net header asks for neither checksum offload nor segmentation.
+            let mut buf = vec![0; overhead];
+            buf.extend_from_slice(&packet);
+            self.send(&buf)
+        };
+        if let Err(e) = sent {
+            return Ok(Probe::Failed(e.kind()));
+        }
+        let mut buf = [0; 64];
+        loop {
+            let left = timeout.saturating_sub(start.elapsed());
+            if left.is_zero() {
+                return Ok(Probe::TimedOut);
+            }
+            socket.set_read_timeout(Some(left))?;
+            match socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == peer && buf[..len] == *token => {
+                    return Ok(Probe::Passed(start.elapsed()));
+                }
+                Ok(_) => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(Probe::TimedOut)
+                }
+                // An ICMP error for the inbound probe, which the peer did not answer.
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Returns another host address in the subnet of `address`, `None` if there is none.
+fn peer_address(address: IpAddr, prefix: u8) -> Option<IpAddr> {
+    match address {
+        IpAddr::V4(address) => {
+            if prefix >= 32 {
+                return None;
+            }
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            let network = u32::from(address) & mask;
+            let broadcast = network | !mask;
+            // A /31 has no network and broadcast address (RFC 3021).
+            let (first, last) = if prefix == 31 {
+                (network, broadcast)
+            } else {
+                (network + 1, broadcast - 1)
+            };
+            (first..=last)
+                .map(Ipv4Addr::from)
+                .find(|v| *v != address)
+                .map(IpAddr::V4)
+        }
+        IpAddr::V6(address) => {
+            if prefix >= 128 || is_link_local(&address) {
+                return None;
+            }
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            let network = u128::from(address) & mask;
+            // The subnet-router anycast address at the start is not a host.
+            (network + 1..=network | !mask)
+                .map(Ipv6Addr::from)
+                .find(|v| *v != address)
+                .map(IpAddr::V6)
+        }
+    }
+}
+
+/// A payload that tells the probes of this test apart from other traffic.
+fn token() -> Vec<u8> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let mut token = b"tun-rs self test ".to_vec();
+    token.extend_from_slice(&nanos.to_be_bytes());
+    token.extend_from_slice(&std::process::id().to_be_bytes());
+    token
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_addresses() {
+        let peer = |address: &str, prefix| peer_address(address.parse().unwrap(), prefix);
+        let ip = |address: &str| Some(address.parse::<IpAddr>().unwrap());
+        assert_eq!(ip("10.0.0.1"), peer("10.0.0.2", 24));
+        assert_eq!(ip("10.0.0.2"), peer("10.0.0.1", 24));
+        assert_eq!(ip("10.0.0.1"), peer("10.0.0.0", 31));
+        assert_eq!(ip("10.0.0.2"), peer("10.0.0.1", 30));
+        assert_eq!(None, peer("10.0.0.1", 32));
+        assert_eq!(ip("0.0.0.1"), peer("10.0.0.1", 0));
+        assert_eq!(ip("fd00::2"), peer("fd00::1", 64));
+        assert_eq!(None, peer("fd00::1", 128));
+        assert_eq!(None, peer("fe80::1", 64));
+    }
+}
@@ -0,0 +1,203 @@
+use std::net::IpAddr;
+
+use crate::platform::DeviceImpl;
+
+/// A configuration change queued on a [`ConfigTxn`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TxnStep {
+    /// Adds an address with its prefix length, keeping the addresses already assigned.
+    AddAddress(IpAddr, u8),
+    /// Removes an address.
+    RemoveAddress(IpAddr),
+    /// Sets the MTU, on Windows the IPv4 MTU.
+    SetMtu(u16),
+    /// Sets the IPv6 MTU.
+    #[cfg(windows)]
+    SetMtuV6(u16),
+}
+
+/// How to undo an applied step.
+enum Undo {
+    /// The step changed nothing, e.g. the address was already assigned.
+    Nothing,
+    RemoveAddress(IpAddr),
+    AddAddress(IpAddr, u8),
+    SetMtu(u16),
+    #[cfg(windows)]
+    SetMtuV6(u16),
+}
+
+/// Configuration changes that are applied together, created by
+/// [`DeviceImpl::transaction`].
+///
+/// The steps are applied in the order they were queued. If one fails, the steps already
+/// applied are undone in reverse order, as far as possible, and the device is left as it
+/// was before [`commit`](Self::commit).
+#[must_use = "the changes are only applied by `commit`"]
+pub struct ConfigTxn<'a> {
+    device: &'a DeviceImpl,
+    steps: Vec<TxnStep>,
+}
+
+impl DeviceImpl {
+    /// Starts a [`ConfigTxn`] on this device.
+    ///
+    /// ```no_run
+    /// # fn main() -> tun_rs::Result<()> {
+    /// let dev = tun_rs::DeviceBuilder::new().build_sync()?;
+    /// dev.transaction()
+    ///     .add_address("10.0.0.2".parse().unwrap(), 24)
+    ///     .add_address("fd00::2".parse().unwrap(), 64)
+    ///     .mtu(1380)
+    ///     .commit()
+    ///     .into_result()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn transaction(&self) -> ConfigTxn<'_> {
+        ConfigTxn {
+            device: self,
+            steps: Vec::new(),
+        }
+    }
+}
+
+impl ConfigTxn<'_> {
+    /// Queues adding `address` with the prefix length `prefix`.
+    pub fn add_address(self, address: IpAddr, prefix: u8) -> Self {
+        self.step(TxnStep::AddAddress(address, prefix))
+    }
+    /// Queues removing `address`.
+    pub fn remove_address(self, address: IpAddr) -> Self {
+        self.step(TxnStep::RemoveAddress(address))
+    }
+    /// Queues setting the MTU, on Windows the IPv4 MTU.
+    pub fn mtu(self, mtu: u16) -> Self {
+        self.step(TxnStep::SetMtu(mtu))
+    }
+    /// Queues setting the IPv6 MTU.
+    #[cfg(windows)]
+    pub fn mtu_v6(self, mtu: u16) -> Self {
+        self.step(TxnStep::SetMtuV6(mtu))
+    }
+    /// Queues `step`.
+    pub fn step(mut self, step: TxnStep) -> Self {
+        self.steps.push(step);
+        self
+    }
+    /// Applies the queued steps, rolling them back if one fails.
+    ///
+    /// Prefix lengths are checked before anything is changed.
+    pub fn commit(self) -> TxnReport {
+        let mut report = TxnReport::default();
+        if let Some((step, e)) = self.validate() {
+            report.failed = Some((step, e));
+            return report;
+        }
+        let mut undo = Vec::new();
+        for step in self.steps {
+            match self.device.apply_step(&step) {
+                Ok(u) => {
+                    undo.push(u);
+                    report.applied.push(step);
+                }
+                Err(e) => {
+                    report.failed = Some((step, e));
+                    break;
+                }
+            }
+        }
+        if report.failed.is_none() {
+            return report;
+        }
+        for (step, u) in report.applied.iter().zip(undo).rev() {
+            match self.device.undo_step(u) {
+                Ok(()) => report.rolled_back.push(step.clone()),
+                Err(e) => log::warn!("failed to roll back {step:?}: {e}"),
+            }
+        }
+        report
+    }
+    fn validate(&self) -> Option<(TxnStep, crate::Error)> {
+        self.steps.iter().find_map(|step| {
+            let result = match *step {
+                TxnStep::AddAddress(IpAddr::V4(_), prefix) => crate::ToIpv4Netmask::prefix(&prefix),
+                TxnStep::AddAddress(IpAddr::V6(_), prefix) => crate::ToIpv6Netmask::prefix(&prefix),
+                _ => Ok(0),
+            };
+            result.err().map(|e| (step.clone(), e.into()))
+        })
+    }
+}
+
+/// The outcome of [`ConfigTxn::commit`].
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct TxnReport {
+    /// The steps that were applied, in order.
+    pub applied: Vec<TxnStep>,
+    /// The applied steps that were undone after a failure, in the order they were undone.
+    /// An applied step missing here could not be undone.
+    pub rolled_back: Vec<TxnStep>,
+    /// The step that failed and its error.
+    pub failed: Option<(TxnStep, crate::Error)>,
+}
+
+impl TxnReport {
+    /// Returns whether every step was applied.
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_none()
+    }
+    /// Returns the applied steps, or the error of the step that failed.
+    pub fn into_result(self) -> crate::Result<Vec<TxnStep>> {
+        match self.failed {
+            None => Ok(self.applied),
+            Some((_, e)) => Err(e),
+        }
+    }
+}
+
+impl DeviceImpl {
+    fn apply_step(&self, step: &TxnStep) -> crate::Result<Undo> {
+        match *step {
+            TxnStep::AddAddress(address, prefix) => {
+                if self.list_addresses()?.contains(&(address, prefix)) {
+                    return Ok(Undo::Nothing);
+                }
+                self.add_address(address, prefix)?;
+                Ok(Undo::RemoveAddress(address))
+            }
+            TxnStep::RemoveAddress(address) => {
+                let prefix = self
+                    .list_addresses()?
+                    .into_iter()
+                    .find(|(v, _)| *v == address)
+                    .map(|(_, prefix)| prefix);
+                self.remove_address(address)?;
+                Ok(prefix.map_or(Undo::Nothing, |prefix| Undo::AddAddress(address, prefix)))
+            }
+            TxnStep::SetMtu(mtu) => {
+                let old = self.mtu()?;
+                self.set_mtu(mtu)?;
+                Ok(Undo::SetMtu(old))
+            }
+            #[cfg(windows)]
+            TxnStep::SetMtuV6(mtu) => {
+                let old = self.mtu_v6()?;
+                self.set_mtu_v6(mtu)?;
+                Ok(Undo::SetMtuV6(old))
+            }
+        }
+    }
+    fn undo_step(&self, undo: Undo) -> crate::Result<()> {
+        match undo {
+            Undo::Nothing => Ok(()),
+            Undo::RemoveAddress(address) => self.remove_address(address),
+            Undo::AddAddress(address, prefix) => self.add_address(address, prefix),
+            Undo::SetMtu(mtu) => self.set_mtu(mtu),
+            #[cfg(windows)]
+            Undo::SetMtuV6(mtu) => self.set_mtu_v6(mtu),
+        }
+    }
+}
@@ -1,5 +1,12 @@
+use crate::error::check_truncated;
 use crate::platform::unix::{Fd, Tun};
-use crate::platform::DeviceImpl;
+use crate::platform::{DeviceImpl, Protocol};
+#[cfg(any(
+    all(target_os = "linux", not(target_env = "ohos")),
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+use crate::platform::{ErrorCounter, InterfaceStats};
 #[cfg(any(
     all(target_os = "linux", not(target_env = "ohos")),
     target_os = "macos",
@@ -26,9 +33,41 @@ impl AsFd for DeviceImpl {
     }
 }
 
+/// Registers the file descriptor of the device with a [`mio::Poll`].
+///
+/// mio is edge-triggered, so the device must be in non-blocking mode (`set_nonblocking(true)`)
+/// and read until `WouldBlock` after every readable event.
+#[cfg(feature = "mio")]
+impl mio::event::Source for DeviceImpl {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+    fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).deregister(registry)
+    }
+}
+
 impl IntoRawFd for DeviceImpl {
-    fn into_raw_fd(self) -> RawFd {
-        self.tun.into_raw_fd()
+    fn into_raw_fd(mut self) -> RawFd {
+        // The caller takes over the interface, so nothing is undone when `self` is dropped.
+        #[cfg(all(target_os = "linux", not(target_env = "ohos")))]
+        {
+            self.cleanup_on_drop = false;
+        }
+        std::mem::replace(&mut self.tun.fd.inner, -1)
     }
 }
 impl DeviceImpl {
@@ -38,6 +77,28 @@ impl DeviceImpl {
         let tun = Fd::new_unchecked(fd);
         DeviceImpl::from_tun(Tun::new(tun))
     }
+    /// Like `from_fd`, but checks that `fd` is open, and on Linux that it is a TUN/TAP
+    /// queue, before taking it over. On error the descriptor stays with the caller.
+    ///
+    /// # Safety
+    /// The fd passed in must be owned by the caller and not be used after a successful return.
+    #[cfg_attr(not(feature = "config"), allow(dead_code))]
+    pub(crate) unsafe fn adopt_fd(fd: RawFd) -> io::Result<Self> {
+        if fd < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid file descriptor: {fd}"),
+            ));
+        }
+        if libc::fcntl(fd, libc::F_GETFD) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        #[cfg(all(target_os = "linux", not(target_env = "ohos")))]
+        let device = DeviceImpl::from_tun_fd(fd)?;
+        #[cfg(not(all(target_os = "linux", not(target_env = "ohos"))))]
+        let device = DeviceImpl::from_fd(fd);
+        Ok(device)
+    }
     pub(crate) fn is_nonblocking(&self) -> io::Result<bool> {
         self.tun.is_nonblocking()
     }
@@ -48,7 +109,19 @@ impl DeviceImpl {
 
     /// Recv a packet from tun device
     pub(crate) fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
-        self.tun.recv(buf)
+        let n = self.tun.recv(buf)?;
+        let n = check_truncated(buf, n, self.ip_header_offset())?;
+        #[cfg(feature = "packet_information_check")]
+        self.check_packet_information(&buf[..n]);
+        Ok(n)
+    }
+    /// Like `recv`, with the time the read returned.
+    pub(crate) fn recv_with_timestamp(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, std::time::SystemTime)> {
+        let n = self.recv(buf)?;
+        Ok((n, std::time::SystemTime::now()))
     }
     pub(crate) fn recv_vectored(&self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
         self.tun.recv_vectored(bufs)
@@ -56,8 +129,173 @@ impl DeviceImpl {
 
     /// Send a packet to tun device
     pub(crate) fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(offset) = self.ip_header_offset() {
+            if Protocol::of(buf.get(offset..).unwrap_or_default()).is_none() {
+                return Err(crate::Error::InvalidPacket(if offset == 0 {
+                    "expected an IPv4 or IPv6 header, packet_information is disabled"
+                } else {
+                    "expected an IPv4 or IPv6 header after the packet information header, \
+                     use send_packet to have it prepended"
+                })
+                .into());
+            }
+        }
         self.tun.send(buf)
     }
+    /// Sends an IP packet, prepending the packet information header if the device uses one.
+    pub(crate) fn send_packet(&self, buf: &[u8], protocol: Protocol) -> io::Result<usize> {
+        #[cfg(any(
+            target_os = "macos",
+            target_os = "ios",
+            all(target_os = "linux", not(target_env = "ohos"))
+        ))]
+        if self.ip_header_offset() == Some(crate::PACKET_INFORMATION_LENGTH) {
+            let header =
+                crate::platform::unix::tun::generate_packet_information(protocol == Protocol::Ipv6);
+            let len = self
+                .tun
+                .send_vectored(&[IoSlice::new(&header), IoSlice::new(buf)])?;
+            return Ok(len - header.len());
+        }
+        _ = protocol;
+        self.send(buf)
+    }
+    /// Sends a packet with the DSCP of its IP header set to `dscp`.
+    ///
+    /// The start of the header is rewritten in a copy and sent together with the rest of
+    /// `buf`, so the packet is not copied as a whole.
+    pub(crate) fn send_with_dscp(&self, buf: &[u8], dscp: u8) -> io::Result<usize> {
+        use crate::packet::{set_dscp, TOS_HEADER_LEN};
+        let offset = self.ip_header_offset().ok_or(crate::Error::Unsupported(
+            "the position of the IP header is not known",
+        ))?;
+        let split = offset + TOS_HEADER_LEN;
+        if buf.len() < split {
+            return Err(crate::Error::InvalidPacket("too short for an IP header").into());
+        }
+        let mut head = [0u8; crate::PACKET_INFORMATION_LENGTH + TOS_HEADER_LEN];
+        let head = &mut head[..split];
+        head.copy_from_slice(&buf[..split]);
+        set_dscp(&mut head[offset..], dscp)?;
+        self.tun
+            .send_vectored(&[IoSlice::new(head), IoSlice::new(&buf[split..])])
+    }
+    /// Receives an IP packet without the packet information header, if the device uses one.
+    pub(crate) fn recv_packet(&self, buf: &mut [u8]) -> io::Result<(usize, Protocol)> {
+        let not_ip = || io::Error::from(crate::Error::InvalidPacket("not an IPv4 or IPv6 packet"));
+        if self.ip_header_offset() == Some(crate::PACKET_INFORMATION_LENGTH) {
+            let mut header = [0u8; crate::PACKET_INFORMATION_LENGTH];
+            let len = self
+                .tun
+                .recv_vectored(&mut [IoSliceMut::new(&mut header), IoSliceMut::new(buf)])?;
+            let len = len.saturating_sub(header.len());
+            let protocol = Protocol::of(&buf[..len]).ok_or_else(not_ip)?;
+            return Ok((len, protocol));
+        }
+        let len = self.recv(buf)?;
+        let protocol = Protocol::of(&buf[..len]).ok_or_else(not_ip)?;
+        Ok((len, protocol))
+    }
+    /// Returns how many bytes the device puts in front of the IP packet in the buffers of
+    /// `recv` and `send`: [`PACKET_INFORMATION_LENGTH`](crate::PACKET_INFORMATION_LENGTH) for a
+    /// packet information header, `VIRTIO_NET_HDR_LEN` with offload on Linux and
+    /// [`ETHERNET_HEADER_LEN`](crate::ETHERNET_HEADER_LEN) for L2 devices.
+    ///
+    /// A receive buffer of the MTU plus this holds any packet.
+    pub fn header_overhead(&self) -> crate::Result<usize> {
+        #[cfg(all(target_os = "linux", not(target_env = "ohos")))]
+        {
+            let flags = self.flags as libc::c_int;
+            let mut overhead = 0;
+            // The flags are 0 when the kind of the device is not known.
+            if flags & (libc::IFF_TUN | libc::IFF_TAP) != 0 && flags & libc::IFF_NO_PI == 0 {
+                overhead += crate::PACKET_INFORMATION_LENGTH;
+            }
+            if self.vnet_hdr {
+                overhead += crate::VIRTIO_NET_HDR_LEN;
+            }
+            if flags & libc::IFF_TAP != 0 {
+                overhead += crate::ETHERNET_HEADER_LEN;
+            }
+            Ok(overhead)
+        }
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        {
+            // With ignore_packet_info the header is added and removed by `Tun`.
+            if self.tun.ignore_packet_info() {
+                Ok(0)
+            } else {
+                Ok(crate::PACKET_INFORMATION_LENGTH)
+            }
+        }
+        #[cfg(target_os = "freebsd")]
+        {
+            if self.detect_layer()? == crate::Layer::L2 {
+                Ok(crate::ETHERNET_HEADER_LEN)
+            } else {
+                Ok(0)
+            }
+        }
+        #[cfg(not(any(
+            all(target_os = "linux", not(target_env = "ohos")),
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd"
+        )))]
+        Ok(0)
+    }
+    /// Where the IP header starts in the buffers passed to `send`, `None` if the device does
+    /// not carry plain IP packets or the layout is unknown.
+    pub(crate) fn ip_header_offset(&self) -> Option<usize> {
+        #[cfg(all(target_os = "linux", not(target_env = "ohos")))]
+        {
+            let flags = self.flags as libc::c_int;
+            if self.vnet_hdr || flags & libc::IFF_TUN == 0 {
+                return None;
+            }
+            if flags & libc::IFF_NO_PI != 0 {
+                Some(0)
+            } else {
+                Some(crate::PACKET_INFORMATION_LENGTH)
+            }
+        }
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        {
+            // With ignore_packet_info the header is added and removed by `Tun`.
+            if self.tun.ignore_packet_info() {
+                Some(0)
+            } else {
+                Some(crate::PACKET_INFORMATION_LENGTH)
+            }
+        }
+        #[cfg(not(any(
+            all(target_os = "linux", not(target_env = "ohos")),
+            target_os = "macos",
+            target_os = "ios"
+        )))]
+        None
+    }
+    /// Checks without waiting whether `events` are ready on the device.
+    ///
+    /// Errors and hangups count as ready, so that the following I/O reports them.
+    pub(crate) fn poll_ready(&self, events: libc::c_short) -> io::Result<bool> {
+        let mut fd = libc::pollfd {
+            fd: self.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        loop {
+            match unsafe { libc::poll(&mut fd, 1, 0) } {
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                n => return Ok(n > 0),
+            }
+        }
+    }
     pub(crate) fn send_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
         self.tun.send_vectored(bufs)
     }
@@ -77,22 +315,250 @@ impl DeviceImpl {
     ///
     /// This function converts the interface name (obtained via `self.name()`) into a
     /// C-compatible string (CString) and then calls the libc function `if_nametoindex`
-    /// to retrieve the corresponding interface index.
-    pub fn if_index(&self) -> io::Result<u32> {
-        let if_name = std::ffi::CString::new(self.name()?)?;
-        unsafe { Ok(libc::if_nametoindex(if_name.as_ptr())) }
+    /// to retrieve the corresponding interface index. The name is queried on every call,
+    /// so the index is still found after the interface has been renamed.
+    pub fn if_index(&self) -> crate::Result<u32> {
+        let if_name = std::ffi::CString::new(self.name()?).map_err(io::Error::from)?;
+        match unsafe { libc::if_nametoindex(if_name.as_ptr()) } {
+            0 => Err(io::Error::last_os_error().into()),
+            index => Ok(index),
+        }
     }
     /// Retrieves all IP addresses associated with the network interface.
     ///
     /// This function calls `getifaddrs` with the interface name,
     /// then iterates over the returned list of interface addresses, extracting and collecting
     /// the IP addresses into a vector.
-    pub fn addresses(&self) -> io::Result<Vec<std::net::IpAddr>> {
+    pub fn addresses(&self) -> crate::Result<Vec<std::net::IpAddr>> {
         Ok(crate::platform::get_if_addrs_by_name(self.name()?)?
             .iter()
             .map(|v| v.address)
             .collect())
     }
+    /// Returns the number of receive errors counted by the kernel for the interface.
+    pub fn rx_errors(&self) -> crate::Result<u64> {
+        self.error_counter(ErrorCounter::RxErrors)
+    }
+    /// Returns the number of transmit errors counted by the kernel for the interface.
+    pub fn tx_errors(&self) -> crate::Result<u64> {
+        self.error_counter(ErrorCounter::TxErrors)
+    }
+    /// Returns the number of received packets dropped by the interface.
+    pub fn rx_dropped(&self) -> crate::Result<u64> {
+        self.error_counter(ErrorCounter::RxDropped)
+    }
+    /// Returns the number of packets dropped on transmit by the interface.
+    ///
+    /// Not available on macOS, whose `if_data` has no output drop counter.
+    pub fn tx_dropped(&self) -> crate::Result<u64> {
+        self.error_counter(ErrorCounter::TxDropped)
+    }
+    /// Clears the interface counters.
+    ///
+    /// Neither Linux nor the BSDs allow resetting the counters of a live interface, so this
+    /// always fails with [`Error::Unsupported`](crate::Error::Unsupported). Compute deltas
+    /// between two readings instead.
+    pub fn reset_statistics(&self) -> crate::Result<()> {
+        Err(crate::Error::Unsupported("resetting interface statistics"))
+    }
+    /// Returns the packet, byte, error and drop counters of the interface in one snapshot.
+    ///
+    /// On Linux they are read from `/sys/class/net/<name>/statistics`.
+    #[cfg(target_os = "linux")]
+    pub fn stats(&self) -> crate::Result<InterfaceStats> {
+        let name = self.name()?;
+        let read = |file| read_statistic(&name, file).map(Some);
+        Ok(InterfaceStats {
+            rx_packets: read("rx_packets")?,
+            tx_packets: read("tx_packets")?,
+            rx_bytes: read("rx_bytes")?,
+            tx_bytes: read("tx_bytes")?,
+            rx_errors: read("rx_errors")?,
+            tx_errors: read("tx_errors")?,
+            rx_dropped: read("rx_dropped")?,
+            tx_dropped: read("tx_dropped")?,
+        })
+    }
+    /// Returns the packet, byte, error and drop counters of the interface in one snapshot.
+    ///
+    /// On macOS and FreeBSD they are copied from the `if_data` of the interface's link-level
+    /// address. macOS keeps no transmit drop counter, so `tx_dropped` is `None` there.
+    #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+    pub fn stats(&self) -> crate::Result<InterfaceStats> {
+        let data = link_data(&self.name()?)?;
+        #[cfg(target_os = "freebsd")]
+        let tx_dropped = Some(data.ifi_oqdrops as u64);
+        #[cfg(target_os = "macos")]
+        let tx_dropped = None;
+        Ok(InterfaceStats {
+            rx_packets: Some(data.ifi_ipackets as u64),
+            tx_packets: Some(data.ifi_opackets as u64),
+            rx_bytes: Some(data.ifi_ibytes as u64),
+            tx_bytes: Some(data.ifi_obytes as u64),
+            rx_errors: Some(data.ifi_ierrors as u64),
+            tx_errors: Some(data.ifi_oerrors as u64),
+            rx_dropped: Some(data.ifi_iqdrops as u64),
+            tx_dropped,
+        })
+    }
+    /// Reads a counter from `/sys/class/net/<name>/statistics`.
+    #[cfg(target_os = "linux")]
+    fn error_counter(&self, counter: ErrorCounter) -> crate::Result<u64> {
+        let file = match counter {
+            ErrorCounter::RxErrors => "rx_errors",
+            ErrorCounter::TxErrors => "tx_errors",
+            ErrorCounter::RxDropped => "rx_dropped",
+            ErrorCounter::TxDropped => "tx_dropped",
+        };
+        Ok(read_statistic(&self.name()?, file)?)
+    }
+    /// Reads a counter from the `if_data` of the interface's link-level address.
+    #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+    fn error_counter(&self, counter: ErrorCounter) -> crate::Result<u64> {
+        let data = link_data(&self.name()?)?;
+        let value = match counter {
+            ErrorCounter::RxErrors => data.ifi_ierrors,
+            ErrorCounter::TxErrors => data.ifi_oerrors,
+            ErrorCounter::RxDropped => data.ifi_iqdrops,
+            #[cfg(target_os = "freebsd")]
+            ErrorCounter::TxDropped => data.ifi_oqdrops,
+            #[cfg(target_os = "macos")]
+            ErrorCounter::TxDropped => {
+                return Err(crate::Error::Unsupported("transmit drop counter on macOS"))
+            }
+        };
+        Ok(value as u64)
+    }
+    /// Routes all IPv6 traffic through the interface.
+    ///
+    /// `server` is the tunnel endpoint, it first gets a host route via the interface and
+    /// gateway it is currently reached through, so the encapsulated traffic does not loop
+    /// back into the tunnel. The default route is then overridden with `::/1` and `8000::/1`,
+    /// which are more specific than `::/0` without replacing it, so the previous default
+    /// takes over again once the interface is gone. The host route to `server` is not
+    /// removed when the device is dropped.
+    #[cfg(feature = "config")]
+    pub fn set_ipv6_default_route(&self, server: std::net::Ipv6Addr) -> crate::Result<()> {
+        use crate::platform::IPV6_DEFAULT_HALVES;
+        let name = self.name()?;
+        let server = server.to_string();
+        #[cfg(target_os = "linux")]
+        let out = crate::run_command("ip", &["-6", "route", "get", &server])?;
+        #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+        let out = crate::run_command("route", &["-n", "get", "-inet6", &server])?;
+        let (gateway, dev) = parse_route_get(&String::from_utf8_lossy(&out));
+        let dev = dev.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no interface in the route to {server}"),
+            )
+        })?;
+        if dev == name {
+            return Err(crate::Error::InvalidConfig(
+                "the server is already routed through the tunnel, \
+                 set the default route before redirecting traffic to the device",
+            ));
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let host = format!("{server}/128");
+            let mut args = vec!["-6", "route", "replace", host.as_str()];
+            if let Some(gateway) = &gateway {
+                args.extend(["via", gateway.as_str()]);
+            }
+            args.extend(["dev", dev.as_str()]);
+            crate::run_command("ip", &args)?;
+            for prefix in IPV6_DEFAULT_HALVES {
+                crate::run_command("ip", &["-6", "route", "replace", prefix, "dev", &name])?;
+            }
+        }
+        #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+        {
+            let mut args = vec!["-n", "add", "-inet6", "-host", server.as_str()];
+            match &gateway {
+                Some(gateway) => args.push(gateway),
+                None => args.extend(["-interface", dev.as_str()]),
+            }
+            crate::run_command("route", &args)?;
+            for prefix in IPV6_DEFAULT_HALVES {
+                crate::run_command(
+                    "route",
+                    &["-n", "add", "-inet6", "-net", prefix, "-interface", &name],
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Extracts the gateway and the interface from the output of `ip -6 route get`.
+#[cfg(all(target_os = "linux", not(target_env = "ohos")))]
+fn parse_route_get(output: &str) -> (Option<String>, Option<String>) {
+    let mut gateway = None;
+    let mut dev = None;
+    let mut tokens = output.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "via" => gateway = tokens.next().map(str::to_string),
+            "dev" => dev = tokens.next().map(str::to_string),
+            _ => {}
+        }
+    }
+    (gateway, dev)
+}
+
+/// Extracts the gateway and the interface from the output of `route -n get`.
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+fn parse_route_get(output: &str) -> (Option<String>, Option<String>) {
+    let mut gateway = None;
+    let mut dev = None;
+    for line in output.lines() {
+        match line.trim().split_once(':') {
+            Some(("gateway", value)) => gateway = Some(value.trim().to_string()),
+            Some(("interface", value)) => dev = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+    (gateway, dev)
+}
+
+/// Reads the counter `file` of the named interface from `/sys/class/net/<name>/statistics`.
+#[cfg(all(target_os = "linux", not(target_env = "ohos")))]
+fn read_statistic(name: &str, file: &str) -> io::Result<u64> {
+    let path = format!("/sys/class/net/{name}/statistics/{file}");
+    std::fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Copies the `if_data` statistics of the named interface out of `getifaddrs`.
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+pub(crate) fn link_data(name: &str) -> io::Result<libc::if_data> {
+    unsafe {
+        let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+        if libc::getifaddrs(&mut addrs) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut result = Err(io::Error::from(io::ErrorKind::NotFound));
+        let mut cur = addrs;
+        while !cur.is_null() {
+            let ifa = &*cur;
+            cur = ifa.ifa_next;
+            if ifa.ifa_addr.is_null()
+                || (*ifa.ifa_addr).sa_family as libc::c_int != libc::AF_LINK
+                || ifa.ifa_data.is_null()
+            {
+                continue;
+            }
+            if std::ffi::CStr::from_ptr(ifa.ifa_name).to_bytes() == name.as_bytes() {
+                result = Ok(*(ifa.ifa_data as *const libc::if_data));
+                break;
+            }
+        }
+        libc::freeifaddrs(addrs);
+        result
+    }
 }
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 impl DeviceImpl {
@@ -136,3 +602,94 @@ pub(crate) unsafe fn ctl() -> io::Result<Fd> {
 pub(crate) unsafe fn ctl_v6() -> io::Result<Fd> {
     Fd::new(libc::socket(AF_INET6, SOCK_DGRAM, 0))
 }
+
+/// How many packets are inspected by the `packet_information_check` feature, per process.
+#[cfg(feature = "packet_information_check")]
+const PACKET_INFORMATION_CHECKS: usize = 16;
+#[cfg(feature = "packet_information_check")]
+static PACKET_INFORMATION_CHECKED: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
+
+#[cfg(feature = "packet_information_check")]
+impl DeviceImpl {
+    /// Warns if the first received packets do not match the `packet_information` setting,
+    /// e.g. because the fd was created elsewhere with a different setting.
+    fn check_packet_information(&self, packet: &[u8]) {
+        use std::sync::atomic::Ordering;
+        if PACKET_INFORMATION_CHECKED.fetch_add(1, Ordering::Relaxed) >= PACKET_INFORMATION_CHECKS {
+            return;
+        }
+        let Some(expected) = self.expects_packet_information() else {
+            return;
+        };
+        let is_ip = |p: &[u8]| p.first().is_some_and(|b| matches!(b >> 4, 4 | 6));
+        let has_header = packet.len() > crate::PACKET_INFORMATION_LENGTH
+            && is_packet_information(&packet[..crate::PACKET_INFORMATION_LENGTH])
+            && is_ip(&packet[crate::PACKET_INFORMATION_LENGTH..]);
+        if expected && !has_header && is_ip(packet) {
+            log::warn!(
+                "packet_information is enabled, but the packet starts with an IP header; \
+                 was the device created with packet_information disabled?"
+            );
+            PACKET_INFORMATION_CHECKED.store(PACKET_INFORMATION_CHECKS, Ordering::Relaxed);
+        } else if !expected && has_header && !is_ip(packet) {
+            log::warn!(
+                "packet_information is disabled, but the packet starts with a {}-byte \
+                 packet information header; was the device created with it enabled?",
+                crate::PACKET_INFORMATION_LENGTH
+            );
+            PACKET_INFORMATION_CHECKED.store(PACKET_INFORMATION_CHECKS, Ordering::Relaxed);
+        }
+    }
+    /// Whether received packets should start with a packet information header, `None` if the
+    /// packets are not plain IP packets or the setting is unknown.
+    fn expects_packet_information(&self) -> Option<bool> {
+        self.ip_header_offset().map(|offset| offset != 0)
+    }
+}
+
+/// Whether `header` is a packet information header carrying IPv4 or IPv6.
+#[cfg(feature = "packet_information_check")]
+fn is_packet_information(header: &[u8]) -> bool {
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    let protocols = [libc::AF_INET as u32, libc::AF_INET6 as u32];
+    // ETH_P_IP and ETH_P_IPV6
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    let protocols = [0x0800, 0x86DD];
+    let value = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    // Linux puts the flags in the upper half, macOS has the address family there.
+    protocols.contains(&(value & 0xffff)) || protocols.contains(&value)
+}
+
+#[cfg(test)]
+#[cfg(any(
+    all(target_os = "linux", not(target_env = "ohos")),
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+mod test {
+    use super::parse_route_get;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parses_route_get() {
+        let out = "2001:db8::1 from :: via fe80::1 dev eth0 proto ra src 2001:db8::2 metric 100 pref medium\n";
+        assert_eq!(
+            (Some("fe80::1".to_string()), Some("eth0".to_string())),
+            parse_route_get(out)
+        );
+        let out =
+            "2001:db8::1 from :: dev eth0 proto kernel src 2001:db8::2 metric 256 pref medium\n";
+        assert_eq!((None, Some("eth0".to_string())), parse_route_get(out));
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+    #[test]
+    fn parses_route_get() {
+        let out = "   route to: 2001:db8::1\ndestination: default\n       mask: default\n    gateway: fe80::1%en0\n  interface: en0\n      flags: <UP,GATEWAY,DONE,STATIC,PRCLONING>\n";
+        assert_eq!(
+            (Some("fe80::1%en0".to_string()), Some("en0".to_string())),
+            parse_route_get(out)
+        );
+    }
+}
@@ -58,55 +58,79 @@ impl Fd {
     #[inline]
     fn read0(&self, buf: &mut [u8]) -> io::Result<usize> {
         let fd = self.as_raw_fd();
-        let amount = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut _, buf.len()) };
-        if amount < 0 {
-            return Err(io::Error::last_os_error());
-        }
-        Ok(amount as usize)
+        retry_on_interrupt(|| unsafe { libc::read(fd, buf.as_mut_ptr() as *mut _, buf.len()) })
+            .map_err(|e| self.map_gone(e))
     }
     #[inline]
     fn readv0(&self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
         if bufs.len() > max_iov() {
             return Err(io::Error::from(io::ErrorKind::InvalidInput));
         }
-        let amount = unsafe {
+        retry_on_interrupt(|| unsafe {
             libc::readv(
                 self.as_raw_fd(),
                 bufs.as_mut_ptr() as *mut libc::iovec as *const libc::iovec,
                 bufs.len() as libc::c_int,
             )
-        };
-        if amount < 0 {
-            return Err(io::Error::last_os_error());
-        }
-        Ok(amount as usize)
+        })
+        .map_err(|e| self.map_gone(e))
     }
 
     #[inline]
     pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
         let fd = self.as_raw_fd();
-        let amount = unsafe { libc::write(fd, buf.as_ptr() as *const _, buf.len()) };
-        if amount < 0 {
-            return Err(io::Error::last_os_error());
-        }
-        Ok(amount as usize)
+        retry_on_interrupt(|| unsafe { libc::write(fd, buf.as_ptr() as *const _, buf.len()) })
+            .map_err(|e| self.map_gone(e))
     }
     #[inline]
     pub fn writev(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
         if bufs.len() > max_iov() {
             return Err(io::Error::from(io::ErrorKind::InvalidInput));
         }
-        let amount = unsafe {
+        retry_on_interrupt(|| unsafe {
             libc::writev(
                 self.as_raw_fd(),
                 bufs.as_ptr() as *const libc::iovec,
                 bufs.len() as libc::c_int,
             )
-        };
-        if amount < 0 {
-            return Err(io::Error::last_os_error());
+        })
+        .map_err(|e| self.map_gone(e))
+    }
+    fn map_gone(&self, err: io::Error) -> io::Error {
+        map_gone(self.inner, err)
+    }
+}
+/// Reports a removed interface as [`Error::DeviceGone`](crate::Error::DeviceGone).
+///
+/// On Linux a reader blocked while the interface is deleted is woken with `EFAULT`, and
+/// writes to a persistent device can fail with `EIO`. Both are only treated as removal if
+/// the file descriptor is no longer attached to an interface, later calls get `EBADFD`.
+pub(crate) fn map_gone(fd: RawFd, err: io::Error) -> io::Error {
+    #[cfg(all(target_os = "linux", not(target_env = "ohos")))]
+    if matches!(err.raw_os_error(), Some(libc::EFAULT | libc::EIO)) {
+        let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+        let rs =
+            unsafe { crate::platform::linux::sys::tungetiff(fd, &mut req as *mut _ as *mut _) };
+        if rs == Err(nix::errno::Errno::EBADFD) {
+            return crate::Error::DeviceGone.into();
+        }
+    }
+    #[cfg(not(all(target_os = "linux", not(target_env = "ohos"))))]
+    let _ = fd;
+    crate::error::map_device_gone(err)
+}
+/// Runs the syscall again while it fails with `EINTR`, like std does.
+#[inline]
+fn retry_on_interrupt<F: FnMut() -> libc::ssize_t>(mut f: F) -> io::Result<usize> {
+    loop {
+        let amount = f();
+        if amount >= 0 {
+            return Ok(amount as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
         }
-        Ok(amount as usize)
     }
 }
 #[cfg(any(
@@ -164,30 +188,37 @@ impl Fd {
         let fd = self.as_raw_fd() as libc::c_int;
 
         let event_fd = self.event_fd.as_event_fd();
-        let mut readfds: libc::fd_set = unsafe { std::mem::zeroed() };
-        unsafe {
-            libc::FD_SET(fd, &mut readfds);
-            libc::FD_SET(event_fd, &mut readfds);
-        }
-        let result = unsafe {
-            libc::select(
-                fd.max(event_fd) + 1,
-                &mut readfds,
-                std::ptr::null_mut(),
-                std::ptr::null_mut(),
-                std::ptr::null_mut(),
-            )
-        };
-        if self.is_shutdown.load(Ordering::Relaxed) {
-            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "close"));
-        }
-        if result == -1 {
-            return Err(io::Error::last_os_error());
-        }
-        if result == 0 {
-            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        loop {
+            let mut readfds: libc::fd_set = unsafe { std::mem::zeroed() };
+            unsafe {
+                libc::FD_SET(fd, &mut readfds);
+                libc::FD_SET(event_fd, &mut readfds);
+            }
+            let result = unsafe {
+                libc::select(
+                    fd.max(event_fd) + 1,
+                    &mut readfds,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                )
+            };
+            // A shutdown always wins over a retry after a signal.
+            if self.is_shutdown.load(Ordering::Relaxed) {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "close"));
+            }
+            if result == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if result == 0 {
+                return Err(io::Error::from(io::ErrorKind::TimedOut));
+            }
+            return Ok(());
         }
-        Ok(())
     }
     pub fn shutdown(&self) -> io::Result<()> {
         self.is_shutdown.store(true, Ordering::Relaxed);
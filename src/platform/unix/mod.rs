@@ -1,9 +1,12 @@
+// Without `config` the address conversions and control sockets are unused.
+#[cfg_attr(not(feature = "config"), allow(dead_code, unused_imports))]
 mod sockaddr;
 #[cfg(any(
     all(target_os = "linux", not(target_env = "ohos")),
     target_os = "freebsd",
     target_os = "macos"
 ))]
+#[cfg_attr(not(feature = "config"), allow(unused_imports))]
 pub(crate) use sockaddr::sockaddr_union;
 
 #[cfg(any(
@@ -15,11 +18,14 @@ pub(crate) use sockaddr::sockaddr_union;
 pub(crate) use sockaddr::ipaddr_to_sockaddr;
 
 mod fd;
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+pub(crate) use self::fd::map_gone;
 pub(crate) use self::fd::Fd;
 
 mod tun;
 pub(crate) use self::tun::Tun;
 
+#[cfg_attr(not(feature = "config"), allow(dead_code, unused_imports))]
 pub(crate) mod device;
 
 #[cfg(all(
@@ -34,6 +40,12 @@ pub(crate) mod device;
 /// A TUN device for Android/iOS/...
 pub struct DeviceImpl {
     pub(crate) tun: Tun,
+    pub(crate) io_hook: crate::platform::IoHook,
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    pub(crate) send_pacer: crate::platform::SendPacer,
+    pub(crate) mirror: crate::platform::Mirror,
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    pub(crate) read_budget: crate::platform::ReadBudget,
 }
 #[cfg(all(
     unix,
@@ -46,6 +58,14 @@ pub struct DeviceImpl {
 ))]
 impl DeviceImpl {
     pub(crate) fn from_tun(tun: Tun) -> Self {
-        Self { tun }
+        Self {
+            tun,
+            io_hook: Default::default(),
+            #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+            send_pacer: Default::default(),
+            mirror: Default::default(),
+            #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+            read_budget: Default::default(),
+        }
     }
 }
@@ -1,11 +1,21 @@
+use crate::error::check_sent;
 use crate::platform::unix::Fd;
-#[cfg(any(target_os = "macos", target_os = "ios"))]
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    all(target_os = "linux", not(target_env = "ohos"))
+))]
 use crate::PACKET_INFORMATION_LENGTH as PIL;
 use std::io::{self, IoSlice, IoSliceMut, Read, Write};
 use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 use std::sync::atomic::{AtomicBool, Ordering};
 
+/// The length of the packet gathered from `bufs`.
+fn total_len(bufs: &[IoSlice<'_>]) -> usize {
+    bufs.iter().map(|b| b.len()).sum()
+}
+
 /// Infer the protocol based on the first nibble in the packet buffer.
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 pub(crate) fn is_ipv6(buf: &[u8]) -> std::io::Result<bool> {
@@ -19,7 +29,11 @@ pub(crate) fn is_ipv6(buf: &[u8]) -> std::io::Result<bool> {
         p => Err(Error::new(InvalidData, format!("IP version {}", p))),
     }
 }
-#[cfg(any(target_os = "macos", target_os = "ios"))]
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    all(target_os = "linux", not(target_env = "ohos"))
+))]
 pub(crate) fn generate_packet_information(_ipv6: bool) -> [u8; PIL] {
     #[cfg(any(target_os = "linux", target_os = "android"))]
     const TUN_PROTO_IP6: [u8; PIL] = (libc::ETH_P_IPV6 as u32).to_be_bytes();
@@ -67,7 +81,7 @@ impl Tun {
     #[cfg(not(any(target_os = "macos", target_os = "ios")))]
     #[inline]
     pub(crate) fn send(&self, buf: &[u8]) -> io::Result<usize> {
-        self.fd.write(buf)
+        check_sent(self.fd.write(buf)?, buf.len())
     }
     #[cfg(any(target_os = "macos", target_os = "ios"))]
     pub(crate) fn send(&self, buf: &[u8]) -> io::Result<usize> {
@@ -77,14 +91,14 @@ impl Tun {
             let len = self
                 .fd
                 .writev(&[IoSlice::new(&header), IoSlice::new(buf)])?;
-            return Ok(len.saturating_sub(PIL));
+            return check_sent(len.saturating_sub(PIL), buf.len());
         }
-        self.fd.write(buf)
+        check_sent(self.fd.write(buf)?, buf.len())
     }
     #[cfg(not(any(target_os = "macos", target_os = "ios")))]
     #[inline]
     pub(crate) fn send_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
-        self.fd.writev(bufs)
+        check_sent(self.fd.writev(bufs)?, total_len(bufs))
     }
     #[cfg(any(target_os = "macos", target_os = "ios"))]
     #[inline]
@@ -104,9 +118,9 @@ impl Tun {
                 iov_block[index + 1] = *buf
             }
             let len = self.fd.writev(&iov_block[..bufs.len() + 1])?;
-            Ok(len.saturating_sub(PIL))
+            check_sent(len.saturating_sub(PIL), total_len(bufs))
         } else {
-            self.fd.writev(bufs)
+            check_sent(self.fd.writev(bufs)?, total_len(bufs))
         }
     }
     #[cfg(not(any(target_os = "macos", target_os = "ios")))]
@@ -1,15 +1,23 @@
 use std::collections::HashSet;
 use std::io;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
 
 use getifaddrs::Interface;
+use windows_sys::Win32::Foundation::ERROR_FILE_NOT_FOUND;
+use windows_sys::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_ARM64;
 
 use crate::builder::DeviceConfig;
-use crate::platform::windows::netsh;
+use crate::error::{check_sent, map_device_gone};
 use crate::platform::windows::tap::TapDevice;
 use crate::platform::windows::tun::TunDevice;
-use crate::platform::ETHER_ADDR_LEN;
-use crate::{Layer, ToIpv4Address, ToIpv4Netmask, ToIpv6Address, ToIpv6Netmask};
+use crate::platform::windows::{ffi, netsh};
+use crate::platform::{
+    DnsRestore, ErrorCounter, InterfaceStats, IoEvent, IoHook, Mirror, NameCache, ETHER_ADDR_LEN,
+};
+#[cfg(any(feature = "async_tokio", feature = "async_std"))]
+use crate::platform::{ReadBudget, SendPacer};
+use crate::{Error, Layer, Result, ToIpv4Address, ToIpv4Netmask, ToIpv6Address, ToIpv6Netmask};
 
 pub(crate) enum Driver {
     Tun(TunDevice),
@@ -18,7 +26,15 @@ pub(crate) enum Driver {
 
 /// A TUN device using the wintun driver.
 pub struct DeviceImpl {
+    pub(crate) dns_restore: DnsRestore,
     pub(crate) driver: Driver,
+    name_cache: NameCache,
+    pub(crate) io_hook: IoHook,
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    pub(crate) send_pacer: SendPacer,
+    pub(crate) mirror: Mirror,
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    pub(crate) read_budget: ReadBudget,
 }
 
 fn hash_name(input_str: &str) -> u128 {
@@ -35,9 +51,20 @@ fn hash_name(input_str: &str) -> u128 {
     (u128::from(front) << 64) | u128::from(back)
 }
 
+/// tap-windows6 ships no ARM64 build of tap0901, so on ARM64 Windows creating an adapter
+/// fails for want of a compatible driver.
+fn tap_create_error(e: io::Error) -> Error {
+    let no_driver = e.kind() == io::ErrorKind::NotFound
+        || e.raw_os_error() == Some(ERROR_FILE_NOT_FOUND as i32);
+    if no_driver && ffi::native_machine().ok() == Some(IMAGE_FILE_MACHINE_ARM64) {
+        return Error::Unsupported("tap0901 driver not available for ARM64; use Layer::L3/wintun");
+    }
+    e.into()
+}
+
 impl DeviceImpl {
     /// Create a new `Device` for the given `Configuration`.
-    pub(crate) fn new(config: DeviceConfig) -> io::Result<Self> {
+    pub(crate) fn new(config: DeviceConfig) -> Result<Self> {
         let layer = config.layer.unwrap_or(Layer::L3);
         let mut count = 0;
         let interfaces: HashSet<String> = Self::get_all_adapter_address()?
@@ -45,11 +72,17 @@ impl DeviceImpl {
             .map(|v| v.name)
             .collect();
         let device = if layer == Layer::L3 {
-            let wintun_file = config.wintun_file.as_deref().unwrap_or("wintun.dll");
+            let wintun_file = config
+                .wintun_file
+                .clone()
+                .unwrap_or_else(crate::platform::windows::default_wintun_file);
             let ring_capacity = config.ring_capacity.unwrap_or(0x20_0000);
+            // Fail before wintun does, which only reports ERROR_ACCESS_DENIED.
+            crate::platform::windows::check_privileges()?;
             let mut attempts = 0;
             let tun_device = loop {
-                let default_name = format!("tun{count}");
+                let default_name =
+                    format!("{}{count}", config.name_prefix.as_deref().unwrap_or("tun"));
                 count += 1;
                 let name = config.dev_name.as_deref().unwrap_or(&default_name);
 
@@ -63,7 +96,7 @@ impl DeviceImpl {
                     ))?
                 }
                 let guid = config.device_guid.unwrap_or_else(|| hash_name(name));
-                match TunDevice::create(wintun_file, name, name, guid, ring_capacity) {
+                match TunDevice::create(&wintun_file, name, name, guid, ring_capacity) {
                     Ok(tun_device) => break tun_device,
                     Err(e) => {
                         if attempts > 3 {
@@ -75,14 +108,24 @@ impl DeviceImpl {
             };
 
             DeviceImpl {
+                dns_restore: DnsRestore::default(),
                 driver: Driver::Tun(tun_device),
+                name_cache: NameCache::default(),
+                io_hook: IoHook::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                send_pacer: SendPacer::default(),
+                mirror: Mirror::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                read_budget: ReadBudget::default(),
             }
         } else if layer == Layer::L2 {
             const HARDWARE_ID: &str = "tap0901";
             let tap = loop {
-                let default_name = format!("tap{count}");
+                let default_name =
+                    format!("{}{count}", config.name_prefix.as_deref().unwrap_or("tap"));
                 let name = config.dev_name.as_deref().unwrap_or(&default_name);
                 if interfaces.contains(name) && config.dev_name.is_none() {
+                    count += 1;
                     continue;
                 }
                 if let Ok(tap) = TapDevice::open(HARDWARE_ID, name) {
@@ -92,7 +135,8 @@ impl DeviceImpl {
                     }
                     break tap;
                 } else {
-                    let tap = TapDevice::create(HARDWARE_ID)?;
+                    crate::platform::windows::check_privileges()?;
+                    let tap = TapDevice::create(HARDWARE_ID).map_err(tap_create_error)?;
                     if let Err(e) = tap.set_name(name) {
                         if config.dev_name.is_some() {
                             Err(e)?
@@ -102,7 +146,15 @@ impl DeviceImpl {
                 }
             };
             DeviceImpl {
+                dns_restore: DnsRestore::default(),
                 driver: Driver::Tap(tap),
+                name_cache: NameCache::default(),
+                io_hook: IoHook::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                send_pacer: SendPacer::default(),
+                mirror: Mirror::default(),
+                #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+                read_budget: ReadBudget::default(),
             }
         } else {
             panic!("unknown layer {:?}", layer);
@@ -113,28 +165,144 @@ impl DeviceImpl {
     /// Recv a packet from tun device
     pub(crate) fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
         match &self.driver {
-            Driver::Tap(tap) => tap.read(buf),
+            Driver::Tap(tap) => tap.read(buf).map_err(map_device_gone),
             Driver::Tun(tun) => tun.recv(buf),
         }
     }
+    /// Like `recv`, with the time the packet was taken from the driver.
+    pub(crate) fn recv_with_timestamp(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, std::time::SystemTime)> {
+        let n = self.recv(buf)?;
+        Ok((n, std::time::SystemTime::now()))
+    }
     pub(crate) fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize> {
         match &self.driver {
-            Driver::Tap(tap) => tap.try_read(buf),
+            Driver::Tap(tap) => tap.try_read(buf).map_err(map_device_gone),
             Driver::Tun(tun) => tun.try_recv(buf),
         }
     }
 
     /// Send a packet to tun device
     pub(crate) fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let sent = match &self.driver {
+            Driver::Tap(tap) => tap.write(buf).map_err(map_device_gone)?,
+            Driver::Tun(tun) => tun.send(buf)?,
+        };
+        check_sent(sent, buf.len())
+    }
+    pub(crate) fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
+        let sent = match &self.driver {
+            Driver::Tap(tap) => tap.try_write(buf).map_err(map_device_gone)?,
+            Driver::Tun(tun) => tun.try_send(buf)?,
+        };
+        check_sent(sent, buf.len())
+    }
+    /// Receives a burst of packets, `bufs[i][offset..]` is filled with packet `i` and
+    /// `sizes[i]` set to its length.
+    ///
+    /// Blocks until the first packet arrives, then takes the packets already queued in the
+    /// wintun ring (or the TAP driver) without waiting, until `bufs` is full. Returns the
+    /// number of packets received. An error after the first packet ends the batch early,
+    /// it is not returned. `bufs` and `sizes` must have the same, non-zero length.
+    ///
+    /// This mirrors `recv_multiple` on Linux without offload, where each call returns one
+    /// packet, so the same receive loop works on both.
+    pub fn recv_multiple<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        bufs: &mut [B],
+        sizes: &mut [usize],
+        offset: usize,
+    ) -> io::Result<usize> {
+        Self::check_batch(bufs, sizes, offset)?;
+        let rs = self.recv(&mut bufs[0].as_mut()[offset..]);
+        let rs = rs.map(|len| {
+            sizes[0] = len;
+            1 + self.drain(&mut bufs[1..], &mut sizes[1..], offset)
+        });
+        match &rs {
+            Ok(n) => sizes[..*n]
+                .iter()
+                .for_each(|&len| self.io_hook.emit(IoEvent::Recv { len })),
+            Err(e) => self.io_hook.error(e),
+        }
+        rs
+    }
+    /// Checks the buffers of a batched receive.
+    pub(crate) fn check_batch<B: AsRef<[u8]>>(
+        bufs: &[B],
+        sizes: &[usize],
+        offset: usize,
+    ) -> io::Result<()> {
+        if bufs.is_empty() || bufs.len() != sizes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "bufs and sizes must have the same, non-zero length",
+            ));
+        }
+        if bufs.iter().any(|buf| buf.as_ref().len() <= offset) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset leaves no room in a buffer",
+            ));
+        }
+        Ok(())
+    }
+    /// Fills `bufs` with the packets that can be received without waiting, returns how many.
+    pub(crate) fn drain<B: AsMut<[u8]>>(
+        &self,
+        bufs: &mut [B],
+        sizes: &mut [usize],
+        offset: usize,
+    ) -> usize {
+        for (i, (buf, size)) in bufs.iter_mut().zip(sizes.iter_mut()).enumerate() {
+            match self.try_recv(&mut buf.as_mut()[offset..]) {
+                Ok(len) => *size = len,
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::WouldBlock {
+                        log::debug!("ending the receive batch early: {e}");
+                    }
+                    return i;
+                }
+            }
+        }
+        bufs.len()
+    }
+    /// Sends the packets `bufs[i][offset..]` in order, returns the number of bytes sent.
+    ///
+    /// Each packet goes into the wintun ring (or to the TAP driver) without waking the
+    /// reader in between. An error is returned only if the first packet could not be sent, a
+    /// later error ends the batch early. Mirrors `send_multiple` on Linux without offload.
+    pub fn send_multiple<B: AsRef<[u8]>>(&self, bufs: &[B], offset: usize) -> io::Result<usize> {
+        let mut total = 0;
+        for (i, buf) in bufs.iter().enumerate() {
+            let rs = buf
+                .as_ref()
+                .get(offset..)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "offset out of buffer"))
+                .and_then(|packet| self.send(packet));
+            match self.io_hook.send(rs) {
+                Ok(n) => total += n,
+                Err(e) if i == 0 => return Err(e),
+                Err(e) => {
+                    log::debug!("ending the send batch early: {e}");
+                    break;
+                }
+            }
+        }
+        Ok(total)
+    }
+    pub(crate) fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<bool> {
         match &self.driver {
-            Driver::Tap(tap) => tap.write(buf),
-            Driver::Tun(tun) => tun.send(buf),
+            Driver::Tap(tap) => tap.wait_readable(timeout).map_err(map_device_gone),
+            Driver::Tun(tun) => tun.wait_readable(timeout),
         }
     }
-    pub(crate) fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
+    pub(crate) fn wait_writable(&self, timeout: Option<Duration>) -> io::Result<bool> {
         match &self.driver {
-            Driver::Tap(tap) => tap.try_write(buf),
-            Driver::Tun(tun) => tun.try_send(buf),
+            Driver::Tap(tap) => tap.wait_writable(timeout).map_err(map_device_gone),
+            Driver::Tun(tun) => tun.wait_writable(timeout),
         }
     }
     pub(crate) fn shutdown(&self) -> io::Result<()> {
@@ -143,76 +311,112 @@ impl DeviceImpl {
             Driver::Tap(tap) => tap.down(),
         }
     }
+    /// Returns how many bytes the device puts in front of the IP packet in the buffers of
+    /// `recv` and `send`: [`ETHERNET_HEADER_LEN`](crate::ETHERNET_HEADER_LEN) for TAP
+    /// devices, nothing for wintun.
+    ///
+    /// A receive buffer of the MTU plus this holds any packet.
+    pub fn header_overhead(&self) -> Result<usize> {
+        match &self.driver {
+            Driver::Tun(_) => Ok(0),
+            Driver::Tap(_) => Ok(crate::ETHERNET_HEADER_LEN),
+        }
+    }
     fn get_all_adapter_address() -> io::Result<Vec<Interface>> {
         Ok(getifaddrs::getifaddrs()?.collect())
     }
     /// Retrieves the name of the device.
     ///
     /// Calls the appropriate method on the underlying driver (TUN or TAP) to obtain the device name.
-    pub fn name(&self) -> io::Result<String> {
-        match &self.driver {
-            Driver::Tun(tun) => tun.get_name(),
-            Driver::Tap(tap) => tap.get_name(),
-        }
+    pub fn name(&self) -> Result<String> {
+        let name = match &self.driver {
+            Driver::Tun(tun) => tun.get_name()?,
+            Driver::Tap(tap) => tap.get_name()?,
+        };
+        Ok(name)
     }
     /// Sets a new name for the device.
     ///
     /// This method first checks if the current name is different from the desired one. If it is,
     /// it uses the `netsh` command to update the interface name.
-    pub fn set_name(&self, value: &str) -> io::Result<()> {
+    pub fn set_name(&self, value: &str) -> Result<()> {
         let name = self.name()?;
         if value == name {
             return Ok(());
         }
-        netsh::set_interface_name(&name, value)
+        netsh::set_interface_name(&name, value)?;
+        self.name_cache.set(value);
+        Ok(())
+    }
+    /// Returns the name of the device without querying the system.
+    ///
+    /// The name is looked up once and then only updated by [`set_name`](Self::set_name),
+    /// so it is cheap enough for hot logging paths. A rename by another process is not
+    /// noticed, use [`name`](Self::name) for the authoritative value.
+    pub fn cached_name(&self) -> Result<std::sync::Arc<str>> {
+        self.name_cache.get_or(|| self.name())
     }
     /// Retrieves the interface index (if_index) of the device.
     ///
-    /// This is used for various network configuration commands.
-    pub fn if_index(&self) -> io::Result<u32> {
+    /// This is used for various network configuration commands, and for `IP_UNICAST_IF` to
+    /// bind a socket to the interface. Windows keeps the index of an adapter for its
+    /// lifetime, a rename with [`set_name`](Self::set_name) does not change it.
+    pub fn if_index(&self) -> Result<u32> {
         match &self.driver {
             Driver::Tun(tun) => Ok(tun.index()),
             Driver::Tap(tap) => Ok(tap.index()),
         }
     }
+    /// Retrieves the locally unique identifier (`NET_LUID`) of the adapter, as the `Value`
+    /// of the union.
+    ///
+    /// Some IP Helper functions take the LUID instead of the interface index. Like the
+    /// index, it does not change when the adapter is renamed.
+    pub fn luid(&self) -> Result<u64> {
+        match &self.driver {
+            Driver::Tun(tun) => Ok(tun.luid()),
+            Driver::Tap(tap) => Ok(tap.luid()),
+        }
+    }
     /// Enables or disables the device.
     ///
     /// For a TUN device, disabling is not supported and will return an error.
     /// For a TAP device, this calls the appropriate method to set the device status.
-    pub fn enabled(&self, value: bool) -> io::Result<()> {
+    pub fn enabled(&self, value: bool) -> Result<()> {
         match &self.driver {
-            Driver::Tun(_tun) => {
-                if value {
-                    Ok(())
-                } else {
-                    Err(io::Error::from(io::ErrorKind::Unsupported))
-                }
-            }
-            Driver::Tap(tap) => tap.set_status(value),
+            Driver::Tun(tun) => Ok(tun.set_enabled(value)?),
+            Driver::Tap(tap) => Ok(tap.set_status(value)?),
         }
     }
     /// Retrieves all IP addresses associated with this device.
     ///
     /// Filters the adapter addresses by matching the device's interface index.
-    pub fn addresses(&self) -> io::Result<Vec<IpAddr>> {
-        let index = self.if_index()?;
-        let r = Self::get_all_adapter_address()?
+    pub fn addresses(&self) -> Result<Vec<IpAddr>> {
+        let r = self
+            .interface_addrs()?
             .into_iter()
-            .filter(|v| v.index == Some(index))
             .map(|v| v.address)
             .collect();
         Ok(r)
     }
+    /// The adapter addresses of this device.
+    pub(crate) fn interface_addrs(&self) -> Result<Vec<Interface>> {
+        let index = self.if_index()?;
+        Ok(Self::get_all_adapter_address()?
+            .into_iter()
+            .filter(|v| v.index == Some(index))
+            .collect())
+    }
     /// Sets the IPv4 network address for the device.
     ///
     /// This method configures the IP address, netmask, and an optional destination for the interface
-    /// using the `netsh` command.
+    /// through the IP Helper API, falling back to the `netsh` command if that fails.
     pub fn set_network_address<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
         &self,
         address: IPv4,
         netmask: Netmask,
         destination: Option<IPv4>,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
         netsh::set_interface_ip(
             self.if_index()?,
             address.ipv4()?.into(),
@@ -220,25 +424,66 @@ impl DeviceImpl {
             destination.map(|v| v.ipv4()).transpose()?.map(|v| v.into()),
         )
     }
-    /// Removes the specified IP address from the device.
-    pub fn remove_address(&self, addr: IpAddr) -> io::Result<()> {
+    /// Adds an IPv4 address to the device, keeping the addresses already assigned, unlike
+    /// [`set_network_address`](Self::set_network_address) which replaces them.
+    pub fn add_address_v4<IPv4: ToIpv4Address, Netmask: ToIpv4Netmask>(
+        &self,
+        address: IPv4,
+        netmask: Netmask,
+        destination: Option<IPv4>,
+    ) -> Result<()> {
+        netsh::add_interface_ipv4(
+            self.if_index()?,
+            address.ipv4()?,
+            netmask.netmask()?,
+            destination.map(|v| v.ipv4()).transpose()?,
+        )
+    }
+    /// Removes the specified IP address from the device without checking that it is
+    /// assigned.
+    pub(crate) fn remove_address_unchecked(&self, addr: IpAddr) -> Result<()> {
         netsh::delete_interface_ip(self.if_index()?, addr)
     }
     /// Adds an IPv6 address to the device.
     ///
     /// Configures the IPv6 address and netmask (converted from prefix) for the interface.
+    ///
+    /// Adding an address that is already assigned with the same prefix succeeds without
+    /// changing the interface, so a configuration can be re-applied. If it is assigned with a
+    /// different prefix, [`Error::InvalidConfig`] is returned.
     pub fn add_address_v6<IPv6: ToIpv6Address, Netmask: ToIpv6Netmask>(
         &self,
         addr: IPv6,
         netmask: Netmask,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
+        let index = self.if_index()?;
+        let addr = addr.ipv6()?;
+        let prefix = netmask.prefix()?;
+        // The netsh fallback reports "The object already exists" as localized text, so
+        // check beforehand.
+        let addrs = self.interface_addrs()?;
+        match crate::platform::assigned_prefix(&addrs, addr.into()) {
+            Some(assigned) if assigned == prefix => {
+                log::debug!("{addr}/{prefix} is already assigned to the interface");
+                return Ok(());
+            }
+            Some(_) => {
+                return Err(Error::InvalidConfig(
+                    "the address is already assigned with a different prefix",
+                ))
+            }
+            None => {}
+        }
         let mask = netmask.netmask()?;
-        netsh::set_interface_ip(self.if_index()?, addr.ipv6()?.into(), mask.into(), None)
+        netsh::set_interface_ip(index, addr.into(), mask.into(), None)
     }
     /// Retrieves the MTU for the device (IPv4).
     ///
-    /// This method uses a Windows-specific FFI function to query the MTU by interface index.
-    pub fn mtu(&self) -> io::Result<u16> {
+    /// This method uses a Windows-specific FFI function to query the MTU by interface index,
+    /// so it returns the value the OS stores for the IPv4 subinterface, not the fixed packet
+    /// size limit of wintun. Windows keeps a separate MTU per address family, see
+    /// [`mtu_v6`](Self::mtu_v6).
+    pub fn mtu(&self) -> Result<u16> {
         let index = self.if_index()?;
         let mtu = crate::platform::windows::ffi::get_mtu_by_index(index, true)?;
         Ok(mtu as _)
@@ -246,55 +491,375 @@ impl DeviceImpl {
     /// Retrieves the MTU for the device (IPv6).
     ///
     /// This method uses a Windows-specific FFI function to query the IPv6 MTU by interface index.
-    pub fn mtu_v6(&self) -> io::Result<u16> {
+    pub fn mtu_v6(&self) -> Result<u16> {
         let index = self.if_index()?;
         let mtu = crate::platform::windows::ffi::get_mtu_by_index(index, false)?;
         Ok(mtu as _)
     }
-    /// Sets the MTU for the device (IPv4) using the `netsh` command.
-    pub fn set_mtu(&self, mtu: u16) -> io::Result<()> {
-        netsh::set_interface_mtu(self.if_index()?, mtu as _)
+    /// Checks that the IPv4 and IPv6 MTUs are equal.
+    ///
+    /// Catches a misconfiguration where only one family's MTU got applied. Succeeds if IPv6
+    /// is disabled on the interface.
+    pub fn assert_mtu_consistent(&self) -> Result<()> {
+        let mtu = self.mtu()?;
+        let mtu_v6 = match self.mtu_v6() {
+            Ok(mtu_v6) => mtu_v6,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if mtu != mtu_v6 {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the IPv4 MTU ({mtu}) differs from the IPv6 MTU ({mtu_v6})"),
+            )));
+        }
+        Ok(())
+    }
+    /// Sets the MTU for the device (IPv4) with `SetIpInterfaceEntry`, falling back to the
+    /// `netsh` command if that fails.
+    ///
+    /// Values below 576 are rejected with [`Error::InvalidConfig`], use
+    /// [`set_mtu_unchecked`](Self::set_mtu_unchecked) to apply them anyway. Fails if the
+    /// OS applied a different value than requested.
+    pub fn set_mtu(&self, mtu: u16) -> Result<()> {
+        crate::platform::check_mtu(mtu, false, false)?;
+        self.set_mtu_unchecked(mtu)
+    }
+    /// Sets the IPv4 MTU without the lower bound checked by [`set_mtu`](Self::set_mtu).
+    pub fn set_mtu_unchecked(&self, mtu: u16) -> Result<()> {
+        netsh::set_interface_mtu(self.if_index()?, mtu as _)?;
+        crate::platform::check_mtu_applied(mtu, self.mtu()?)
+    }
+    /// Sets the MTU for the device (IPv6), see [`set_mtu`](Self::set_mtu).
+    ///
+    /// Values below 1280 are rejected with [`Error::InvalidConfig`], use
+    /// [`set_mtu_v6_unchecked`](Self::set_mtu_v6_unchecked) to apply them anyway. Fails if
+    /// the OS applied a different value than requested.
+    pub fn set_mtu_v6(&self, mtu: u16) -> Result<()> {
+        crate::platform::check_mtu(mtu, true, false)?;
+        self.set_mtu_v6_unchecked(mtu)
+    }
+    /// Sets the IPv6 MTU without the lower bound checked by [`set_mtu_v6`](Self::set_mtu_v6).
+    pub fn set_mtu_v6_unchecked(&self, mtu: u16) -> Result<()> {
+        netsh::set_interface_mtu_v6(self.if_index()?, mtu as _)?;
+        crate::platform::check_mtu_applied(mtu, self.mtu_v6()?)
     }
-    /// Sets the MTU for the device (IPv6) using the `netsh` command.
-    pub fn set_mtu_v6(&self, mtu: u16) -> io::Result<()> {
-        netsh::set_interface_mtu_v6(self.if_index()?, mtu as _)
+    /// Routes all IPv6 traffic through the device.
+    ///
+    /// `server` is the tunnel endpoint, it first gets a host route via the interface and
+    /// next hop it is currently reached through, so the encapsulated traffic does not loop
+    /// back into the tunnel. The default route is then overridden with `::/1` and `8000::/1`,
+    /// which are more specific than `::/0` without replacing it. All routes are added with
+    /// `store=active` and the host route to `server` is not removed when the device is dropped.
+    pub fn set_ipv6_default_route(&self, server: Ipv6Addr) -> Result<()> {
+        let index = self.if_index()?;
+        let (server_index, next_hop) = crate::platform::windows::ffi::best_route_v6(server)?;
+        if server_index == index {
+            return Err(Error::InvalidConfig(
+                "the server is already routed through the tunnel, \
+                 set the default route before redirecting traffic to the device",
+            ));
+        }
+        netsh::add_route_v6(server_index, &format!("{server}/128"), next_hop)?;
+        for prefix in crate::platform::IPV6_DEFAULT_HALVES {
+            netsh::add_route_v6(index, prefix, None)?;
+        }
+        Ok(())
     }
     /// Sets the MAC address for the device.
     ///
     /// This operation is only supported for TAP devices; attempting to set a MAC address on a TUN device
-    /// will result in an error.
-    pub fn set_mac_address(&self, eth_addr: [u8; ETHER_ADDR_LEN as usize]) -> io::Result<()> {
+    /// will result in an error. All-zero and multicast addresses are rejected with
+    /// [`Error::InvalidConfig`].
+    pub fn set_mac_address(&self, eth_addr: [u8; ETHER_ADDR_LEN as usize]) -> Result<()> {
         match &self.driver {
-            Driver::Tun(_tun) => Err(io::Error::from(io::ErrorKind::Unsupported)),
-            Driver::Tap(tap) => tap.set_mac(&eth_addr),
+            Driver::Tun(_tun) => Err(Error::Unsupported(
+                "setting the MAC address of a TUN device",
+            )),
+            Driver::Tap(tap) => {
+                crate::platform::check_mac(&eth_addr)?;
+                Ok(tap.set_mac(&eth_addr)?)
+            }
         }
     }
     /// Retrieves the MAC address of the device.
     ///
     /// This operation is only supported for TAP devices.
-    pub fn mac_address(&self) -> io::Result<[u8; ETHER_ADDR_LEN as usize]> {
+    pub fn mac_address(&self) -> Result<[u8; ETHER_ADDR_LEN as usize]> {
         match &self.driver {
-            Driver::Tun(_tun) => Err(io::Error::from(io::ErrorKind::Unsupported)),
-            Driver::Tap(tap) => tap.get_mac(),
+            Driver::Tun(_tun) => Err(Error::Unsupported(
+                "reading the MAC address of a TUN device",
+            )),
+            Driver::Tap(tap) => Ok(tap.get_mac()?),
+        }
+    }
+    /// The GUID of a wintun adapter, see [`DeviceBuilder::device_guid`](crate::DeviceBuilder::device_guid).
+    pub(crate) fn device_guid(&self) -> Result<u128> {
+        match &self.driver {
+            Driver::Tun(tun) => Ok(tun.guid()?),
+            Driver::Tap(_) => Err(Error::Unsupported("reading the GUID of a TAP device")),
+        }
+    }
+    /// Returns whether the device operates at L2 (tap-windows) or L3 (wintun).
+    pub fn detect_layer(&self) -> Result<Layer> {
+        match &self.driver {
+            Driver::Tun(_) => Ok(Layer::L3),
+            Driver::Tap(_) => Ok(Layer::L2),
+        }
+    }
+    /// Sets the interface metric (routing cost) of both IPv4 and IPv6 via `SetIpInterfaceEntry`.
+    pub fn set_metric(&self, metric: u16) -> Result<()> {
+        let index = self.if_index()?;
+        crate::platform::windows::ffi::set_interface_metric(index, true, metric as _)?;
+        match crate::platform::windows::ffi::set_interface_metric(index, false, metric as _) {
+            // IPv6 is disabled on the adapter.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            rs => Ok(rs?),
         }
     }
-    /// Sets the interface metric (routing cost) using the `netsh` command.
-    pub fn set_metric(&self, metric: u16) -> io::Result<()> {
-        netsh::set_interface_metric(self.if_index()?, metric)
+    /// Enables or disables IPv6 router discovery on the adapter.
+    ///
+    /// Statically addressed tunnels usually turn it off, so that the adapter does not send
+    /// router solicitations or pick up routes and SLAAC prefixes from advertisements. This
+    /// is the `RouterDiscovery` setting of `Set-NetIPInterface`.
+    pub fn set_router_discovery(&self, enabled: bool) -> Result<()> {
+        let index = self.if_index()?;
+        crate::platform::windows::ffi::set_router_discovery(index, enabled)?;
+        Ok(())
+    }
+    /// Sets whether the adapter follows the managed (M) and other configuration (O) flags of
+    /// IPv6 router advertisements, i.e. starts DHCPv6 when a router asks for it.
+    ///
+    /// Stateless addresses from advertised prefixes are only configured while router
+    /// discovery is enabled, see [`set_router_discovery`](Self::set_router_discovery).
+    pub fn set_slaac(&self, enabled: bool) -> Result<()> {
+        let index = self.if_index()?;
+        crate::platform::windows::ffi::set_ra_autoconf(index, enabled)?;
+        Ok(())
+    }
+    /// Returns the number of receive errors counted for the interface (`InErrors`).
+    pub fn rx_errors(&self) -> Result<u64> {
+        self.error_counter(ErrorCounter::RxErrors)
+    }
+    /// Returns the number of transmit errors counted for the interface (`OutErrors`).
+    pub fn tx_errors(&self) -> Result<u64> {
+        self.error_counter(ErrorCounter::TxErrors)
+    }
+    /// Returns the number of received packets discarded by the interface (`InDiscards`).
+    pub fn rx_dropped(&self) -> Result<u64> {
+        self.error_counter(ErrorCounter::RxDropped)
+    }
+    /// Returns the number of outgoing packets discarded by the interface (`OutDiscards`).
+    pub fn tx_dropped(&self) -> Result<u64> {
+        self.error_counter(ErrorCounter::TxDropped)
+    }
+    /// Clears the interface counters.
+    ///
+    /// Windows offers no way to reset the counters of an interface, so this always fails with
+    /// [`Error::Unsupported`]. Compute deltas between two readings instead.
+    pub fn reset_statistics(&self) -> Result<()> {
+        Err(Error::Unsupported("resetting interface statistics"))
+    }
+    /// Returns the packet, byte, error and drop counters of the interface in one snapshot,
+    /// from a single `GetIfEntry2` call. The packet counters add up unicast and non-unicast
+    /// packets.
+    pub fn stats(&self) -> Result<InterfaceStats> {
+        let row = ffi::get_if_entry(self.if_index()?)?;
+        Ok(InterfaceStats {
+            rx_packets: Some(row.InUcastPkts + row.InNUcastPkts),
+            tx_packets: Some(row.OutUcastPkts + row.OutNUcastPkts),
+            rx_bytes: Some(row.InOctets),
+            tx_bytes: Some(row.OutOctets),
+            rx_errors: Some(row.InErrors),
+            tx_errors: Some(row.OutErrors),
+            rx_dropped: Some(row.InDiscards),
+            tx_dropped: Some(row.OutDiscards),
+        })
+    }
+    /// Returns the DNS servers of the adapter, IPv4 and IPv6, from `GetAdaptersAddresses`.
+    pub fn dns_servers(&self) -> Result<Vec<IpAddr>> {
+        Ok(crate::platform::windows::ffi::dns_servers(
+            self.if_index()?,
+        )?)
+    }
+    pub(crate) fn dns_setter(&self) -> Result<crate::platform::DnsSetter> {
+        let index = self.if_index()?;
+        Ok(Box::new(move |servers| {
+            netsh::set_dns_servers(index, servers)
+        }))
+    }
+    /// Returns the routes whose output interface is this device, from `GetIpForwardTable2`.
+    pub fn routes(&self) -> Result<Vec<crate::platform::RouteEntry>> {
+        Ok(crate::platform::windows::ffi::routes(self.if_index()?)?)
+    }
+    /// Routes the host `peer` out of this device, see
+    /// [`add_peer_routes`](Self::add_peer_routes).
+    pub fn add_peer_route(&self, peer: Ipv4Addr) -> Result<()> {
+        self.add_peer_routes(&[peer])
+    }
+    /// Routes each host of `peers` out of this device as an on-link /32 route.
+    ///
+    /// The routes are created with `CreateIpForwardEntry2` from one prepared row rather than
+    /// one `netsh` process each. Existing routes are kept. On an error the routes before it
+    /// have been added.
+    pub fn add_peer_routes(&self, peers: &[Ipv4Addr]) -> Result<()> {
+        let index = self.if_index()?;
+        Ok(crate::platform::windows::ffi::change_host_routes(
+            true, index, peers,
+        )?)
+    }
+    /// Removes the route to the host `peer` out of this device.
+    pub fn remove_peer_route(&self, peer: Ipv4Addr) -> Result<()> {
+        self.remove_peer_routes(&[peer])
+    }
+    /// Removes the routes to the hosts `peers` out of this device with
+    /// `DeleteIpForwardEntry2`. Peers without a route are skipped.
+    pub fn remove_peer_routes(&self, peers: &[Ipv4Addr]) -> Result<()> {
+        let index = self.if_index()?;
+        Ok(crate::platform::windows::ffi::change_host_routes(
+            false, index, peers,
+        )?)
+    }
+    /// Routes `destination/prefix` on-link out of this device with `CreateIpForwardEntry2`.
+    ///
+    /// The host bits of `destination` are cleared. `metric` is the route metric, Windows adds
+    /// the interface metric to it; `None` keeps the default. An existing route to the same
+    /// network out of this device is not replaced, an error of kind
+    /// [`AlreadyExists`](io::ErrorKind::AlreadyExists) is returned instead.
+    pub fn add_route_with_metric(
+        &self,
+        destination: IpAddr,
+        prefix: u8,
+        metric: Option<u32>,
+    ) -> Result<()> {
+        let network = crate::platform::route_network(destination, prefix)?;
+        Ok(crate::platform::windows::ffi::change_route(
+            true,
+            self.if_index()?,
+            network,
+            prefix,
+            None,
+            metric,
+        )?)
+    }
+    /// Routes `destination/prefix` out of this device through `gateway`, on-link for `None`,
+    /// with `CreateIpForwardEntry2`.
+    ///
+    /// The gateway must be of the family of `destination`. An existing route to the same
+    /// network through the same gateway fails with an error of kind
+    /// [`AlreadyExists`](io::ErrorKind::AlreadyExists).
+    pub fn add_route_via(
+        &self,
+        destination: IpAddr,
+        prefix: u8,
+        gateway: Option<IpAddr>,
+    ) -> Result<()> {
+        let network = crate::platform::route_network(destination, prefix)?;
+        crate::platform::check_gateway(network, gateway)?;
+        Ok(crate::platform::windows::ffi::change_route(
+            true,
+            self.if_index()?,
+            network,
+            prefix,
+            gateway,
+            None,
+        )?)
+    }
+    /// Removes the routes to `destination/prefix` out of this device with
+    /// `DeleteIpForwardEntry2`, whatever their gateway. Fails with an error of kind
+    /// [`NotFound`](io::ErrorKind::NotFound) if there is no such route.
+    pub fn remove_route(&self, destination: IpAddr, prefix: u8) -> Result<()> {
+        let network = crate::platform::route_network(destination, prefix)?;
+        let index = self.if_index()?;
+        let gateways: Vec<_> = self
+            .routes()?
+            .into_iter()
+            .filter(|route| route.destination == network && route.prefix == prefix)
+            .map(|route| route.gateway)
+            .collect();
+        if gateways.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no route to {network}/{prefix}"),
+            )
+            .into());
+        }
+        for gateway in gateways {
+            crate::platform::windows::ffi::change_route(
+                false, index, network, prefix, gateway, None,
+            )?;
+        }
+        Ok(())
+    }
+    /// Returns the hosts with an on-link /32 route out of this device.
+    ///
+    /// The host routes Windows adds for the addresses of the device and the broadcast
+    /// addresses of their subnets are left out.
+    pub fn peer_routes(&self) -> Result<Vec<Ipv4Addr>> {
+        let mut own = vec![Ipv4Addr::BROADCAST];
+        for (address, prefix) in self.list_addresses()? {
+            if let IpAddr::V4(address) = address {
+                own.push(address);
+                if let Ok(net) = ipnet::Ipv4Net::new(address, prefix) {
+                    own.push(net.broadcast());
+                }
+            }
+        }
+        Ok(self
+            .routes()?
+            .into_iter()
+            .filter(|route| route.prefix == 32 && route.gateway.is_none())
+            .filter_map(|route| match route.destination {
+                IpAddr::V4(peer) => Some(peer),
+                IpAddr::V6(_) => None,
+            })
+            .filter(|peer| !own.contains(peer))
+            .collect())
+    }
+    /// Checks whether the IP packet `packet`, about to be sent on a socket, would be routed
+    /// back into this device, e.g. the encapsulated traffic of the tunnel itself.
+    ///
+    /// The destination of the packet is looked up with `GetBestRoute2`, the source address
+    /// of the packet is not taken into account. An unreachable destination does not loop.
+    pub fn would_loop(&self, packet: &[u8]) -> Result<bool> {
+        let destination = crate::platform::packet_destination(packet)?;
+        let index = crate::platform::windows::ffi::best_route_interface(destination)?;
+        Ok(index == Some(self.if_index()?))
+    }
+    /// Checks whether the adapter reports its media as connected, independently of whether it
+    /// is enabled.
+    ///
+    /// This is `MediaConnectState` of the interface. Wintun is connected while a session is
+    /// open, TAP while the media status was set with `TAP_IOCTL_SET_MEDIA_STATUS`, which `enabled` does.
+    pub fn has_carrier(&self) -> Result<bool> {
+        use windows_sys::Win32::NetworkManagement::Ndis::MediaConnectStateConnected;
+        let row = crate::platform::windows::ffi::get_if_entry(self.if_index()?)?;
+        Ok(row.MediaConnectState == MediaConnectStateConnected)
+    }
+    fn error_counter(&self, counter: ErrorCounter) -> Result<u64> {
+        let row = crate::platform::windows::ffi::get_if_entry(self.if_index()?)?;
+        Ok(match counter {
+            ErrorCounter::RxErrors => row.InErrors,
+            ErrorCounter::TxErrors => row.OutErrors,
+            ErrorCounter::RxDropped => row.InDiscards,
+            ErrorCounter::TxDropped => row.OutDiscards,
+        })
     }
     /// Retrieves the version of the underlying driver.
     ///
-    /// For TUN devices, this directly queries the driver version.
+    /// For TUN devices, this directly queries the running wintun driver version,
+    /// see also [`wintun_version`](crate::wintun_version).
     /// For TAP devices, the version is composed of several components joined by dots.
-    pub fn version(&self) -> io::Result<String> {
-        match &self.driver {
-            Driver::Tun(tun) => tun.version(),
-            Driver::Tap(tap) => tap.get_version().map(|v| {
-                v.iter()
-                    .map(|v| v.to_string())
-                    .collect::<Vec<String>>()
-                    .join(".")
-            }),
-        }
+    pub fn version(&self) -> Result<String> {
+        let version = match &self.driver {
+            Driver::Tun(tun) => tun.version()?,
+            Driver::Tap(tap) => tap
+                .get_version()?
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join("."),
+        };
+        Ok(version)
     }
 }
@@ -1,11 +1,24 @@
 use std::os::windows::io::{FromRawHandle, OwnedHandle};
+use std::time::Duration;
 use std::{io, mem, ptr};
 
-use windows_sys::Win32::Foundation::{ERROR_IO_PENDING, NO_ERROR};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use windows_sys::Win32::Foundation::{
+    ERROR_BUFFER_OVERFLOW, ERROR_HOST_UNREACHABLE, ERROR_IO_INCOMPLETE, ERROR_IO_PENDING,
+    ERROR_NETWORK_UNREACHABLE, ERROR_NOT_FOUND, ERROR_OBJECT_ALREADY_EXISTS, NO_ERROR,
+};
 use windows_sys::Win32::NetworkManagement::IpHelper::{
-    GetIpInterfaceTable, MIB_IPINTERFACE_ROW, MIB_IPINTERFACE_TABLE,
+    CreateIpForwardEntry2, DeleteIpForwardEntry2, FreeMibTable, GetAdaptersAddresses,
+    GetBestRoute2, GetIfEntry2, GetIpForwardTable2, GetIpInterfaceEntry, GetIpInterfaceTable,
+    InitializeIpForwardEntry, InitializeIpInterfaceEntry, SetIpInterfaceEntry,
+    GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_MULTICAST, GAA_FLAG_SKIP_UNICAST, IP_ADAPTER_ADDRESSES_LH,
+    MIB_IF_ROW2, MIB_IPFORWARD_ROW2, MIB_IPFORWARD_TABLE2, MIB_IPINTERFACE_ROW,
+    MIB_IPINTERFACE_TABLE,
+};
+use windows_sys::Win32::Networking::WinSock::{
+    RouterDiscoveryDisabled, RouterDiscoveryEnabled, AF_INET, AF_INET6, AF_UNSPEC, SOCKADDR_IN,
+    SOCKADDR_IN6, SOCKADDR_INET, SOCKET_ADDRESS,
 };
-use windows_sys::Win32::Networking::WinSock::{AF_INET, AF_INET6};
 use windows_sys::Win32::System::IO::{GetOverlappedResult, OVERLAPPED};
 use windows_sys::{
     core::GUID,
@@ -19,7 +32,10 @@ use windows_sys::{
             SetupDiSetSelectedDriverW, HDEVINFO, MAX_CLASS_NAME_LEN, SP_DEVINFO_DATA,
             SP_DRVINFO_DATA_V2_W, SP_DRVINFO_DETAIL_DATA_W,
         },
-        Foundation::{GetLastError, BOOL, ERROR_NO_MORE_ITEMS, FALSE, FILETIME, HANDLE, TRUE},
+        Foundation::{
+            GetLastError, BOOL, ERROR_INSUFFICIENT_BUFFER, ERROR_NO_MORE_ITEMS, FALSE, FILETIME,
+            HANDLE, LUID, TRUE,
+        },
         NetworkManagement::{
             IpHelper::{
                 ConvertInterfaceAliasToLuid, ConvertInterfaceLuidToAlias,
@@ -27,14 +43,26 @@ use windows_sys::{
             },
             Ndis::NET_LUID_LH,
         },
+        Security::{
+            GetTokenInformation, LookupPrivilegeValueW, TokenElevation, TokenPrivileges,
+            LUID_AND_ATTRIBUTES, TOKEN_ELEVATION, TOKEN_PRIVILEGES, TOKEN_QUERY,
+        },
         Storage::FileSystem::{
-            CreateFileW, ReadFile, WriteFile, FILE_CREATION_DISPOSITION, FILE_FLAGS_AND_ATTRIBUTES,
-            FILE_SHARE_MODE,
+            CreateFileW, GetFileVersionInfoSizeW, GetFileVersionInfoW, ReadFile, VerQueryValueW,
+            WriteFile, FILE_CREATION_DISPOSITION, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_MODE,
+            VS_FIXEDFILEINFO,
         },
         System::{
             Com::StringFromGUID2,
             Registry::{RegNotifyChangeKeyValue, HKEY},
-            Threading::{CreateEventW, WaitForSingleObject},
+            SystemInformation::{
+                IMAGE_FILE_MACHINE, IMAGE_FILE_MACHINE_AMD64, IMAGE_FILE_MACHINE_ARM64,
+                IMAGE_FILE_MACHINE_I386,
+            },
+            Threading::{
+                CreateEventW, GetCurrentProcess, IsWow64Process2, OpenProcessToken,
+                WaitForSingleObject,
+            },
             IO::DeviceIoControl,
         },
     },
@@ -111,9 +139,10 @@ pub fn luid_to_alias(luid: &NET_LUID_LH) -> io::Result<String> {
     }
 }
 
-pub fn create_event() -> io::Result<OwnedHandle> {
+pub fn create_event(manual_reset: bool) -> io::Result<OwnedHandle> {
     unsafe {
-        let read_event_handle = CreateEventW(ptr::null_mut(), 0, 0, ptr::null_mut());
+        let read_event_handle =
+            CreateEventW(ptr::null_mut(), manual_reset as _, 0, ptr::null_mut());
         if read_event_handle.is_null() {
             Err(io::Error::last_os_error())?
         }
@@ -121,6 +150,19 @@ pub fn create_event() -> io::Result<OwnedHandle> {
     }
 }
 
+/// Waits for `event` to be signalled. Returns `false` if `timeout` elapsed first.
+pub fn wait_event(event: HANDLE, timeout: Option<Duration>) -> io::Result<bool> {
+    const INFINITE: u32 = u32::MAX;
+    let milliseconds = timeout.map_or(INFINITE, |t| {
+        t.as_millis().min((INFINITE - 1) as u128) as u32
+    });
+    match unsafe { WaitForSingleObject(event, milliseconds) } {
+        0 => Ok(true),
+        0x102 => Ok(false),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
 pub fn create_file(
     file_name: &str,
     desired_access: u32,
@@ -216,7 +258,13 @@ pub fn try_io_overlapped(handle: HANDLE, io_overlapped: &OVERLAPPED) -> io::Resu
     let mut ret = 0;
     unsafe {
         if 0 == GetOverlappedResult(handle, io_overlapped, &mut ret, 0) {
-            Err(io::Error::from(io::ErrorKind::WouldBlock))
+            let e = io::Error::last_os_error();
+            if e.raw_os_error().unwrap_or(0) == ERROR_IO_INCOMPLETE as i32 {
+                // Still in progress, anything else means the operation failed.
+                Err(io::Error::from(io::ErrorKind::WouldBlock))
+            } else {
+                Err(e)
+            }
         } else {
             Ok(ret)
         }
@@ -613,3 +661,476 @@ pub fn get_mtu_by_index(index: u32, is_v4: bool) -> io::Result<u32> {
         Err(io::Error::from(io::ErrorKind::NotFound))
     }
 }
+
+pub fn set_interface_metric(index: u32, is_v4: bool, metric: u32) -> io::Result<()> {
+    update_ip_interface(index, is_v4, |row| {
+        row.UseAutomaticMetric = 0;
+        row.Metric = metric;
+    })
+}
+
+/// Enables or disables IPv6 router discovery, i.e. sending router solicitations and
+/// acting on router advertisements.
+pub fn set_router_discovery(index: u32, enabled: bool) -> io::Result<()> {
+    update_ip_interface(index, false, |row| {
+        row.RouterDiscoveryBehavior = if enabled {
+            RouterDiscoveryEnabled
+        } else {
+            RouterDiscoveryDisabled
+        };
+    })
+}
+
+/// Sets whether the managed and other configuration flags of router advertisements are
+/// honoured on IPv6.
+pub fn set_ra_autoconf(index: u32, enabled: bool) -> io::Result<()> {
+    update_ip_interface(index, false, |row| {
+        row.ManagedAddressConfigurationSupported = enabled as _;
+        row.OtherStatefulConfigurationSupported = enabled as _;
+    })
+}
+
+/// Reads the `MIB_IPINTERFACE_ROW` of an interface, lets `update` change it and writes it back.
+pub fn update_ip_interface(
+    index: u32,
+    is_v4: bool,
+    update: impl FnOnce(&mut MIB_IPINTERFACE_ROW),
+) -> io::Result<()> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-setipinterfaceentry
+    unsafe {
+        let mut row: MIB_IPINTERFACE_ROW = mem::zeroed();
+        InitializeIpInterfaceEntry(&mut row);
+        row.Family = if is_v4 { AF_INET } else { AF_INET6 };
+        row.InterfaceIndex = index;
+        let rs = GetIpInterfaceEntry(&mut row);
+        if rs != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(rs as i32));
+        }
+        update(&mut row);
+        if is_v4 {
+            // Must be zero when setting an IPv4 entry.
+            row.SitePrefixLength = 0;
+        }
+        let rs = SetIpInterfaceEntry(&mut row);
+        if rs != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(rs as i32));
+        }
+    }
+    Ok(())
+}
+
+pub fn get_if_entry(index: u32) -> io::Result<MIB_IF_ROW2> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-getifentry2
+    unsafe {
+        let mut row: MIB_IF_ROW2 = mem::zeroed();
+        row.InterfaceIndex = index;
+        let rs = GetIfEntry2(&mut row);
+        if rs != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(rs as i32));
+        }
+        Ok(row)
+    }
+}
+
+/// Returns the routes whose output interface is `index`, from `GetIpForwardTable2`.
+pub fn routes(index: u32) -> io::Result<Vec<crate::platform::RouteEntry>> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-getipforwardtable2
+    unsafe {
+        let mut table: *mut MIB_IPFORWARD_TABLE2 = ptr::null_mut();
+        let rs = GetIpForwardTable2(AF_UNSPEC, &mut table);
+        if rs != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(rs as i32));
+        }
+        let rows =
+            std::slice::from_raw_parts((*table).Table.as_ptr(), (*table).NumEntries as usize);
+        let routes = rows
+            .iter()
+            .filter(|row| row.InterfaceIndex == index)
+            .filter_map(|row| {
+                let destination = sockaddr_inet_to_ip(&row.DestinationPrefix.Prefix)?;
+                let gateway =
+                    sockaddr_inet_to_ip(&row.NextHop).filter(|addr| !addr.is_unspecified());
+                Some(crate::platform::RouteEntry {
+                    destination,
+                    prefix: row.DestinationPrefix.PrefixLength,
+                    gateway,
+                    metric: row.Metric,
+                })
+            })
+            .collect();
+        FreeMibTable(table as _);
+        Ok(routes)
+    }
+}
+
+/// Adds (`CreateIpForwardEntry2`) or removes (`DeleteIpForwardEntry2`) an on-link /32 route
+/// to each of `hosts` out of interface `index`. Adding an existing route or removing a
+/// missing one is not an error, the first other error is returned.
+pub fn change_host_routes(add: bool, index: u32, hosts: &[Ipv4Addr]) -> io::Result<()> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-createipforwardentry2
+    unsafe {
+        let mut row: MIB_IPFORWARD_ROW2 = mem::zeroed();
+        InitializeIpForwardEntry(&mut row);
+        row.InterfaceIndex = index;
+        row.DestinationPrefix.Prefix.si_family = AF_INET;
+        row.DestinationPrefix.PrefixLength = 32;
+        row.NextHop.si_family = AF_INET;
+        for host in hosts {
+            row.DestinationPrefix.Prefix.Ipv4.sin_addr.S_un.S_addr = u32::from(*host).to_be();
+            let rs = if add {
+                CreateIpForwardEntry2(&row)
+            } else {
+                DeleteIpForwardEntry2(&row)
+            };
+            match rs {
+                NO_ERROR => {}
+                ERROR_OBJECT_ALREADY_EXISTS if add => {}
+                ERROR_NOT_FOUND if !add => {}
+                rs => return Err(io::Error::from_raw_os_error(rs as i32)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adds (`CreateIpForwardEntry2`) or removes (`DeleteIpForwardEntry2`) a route to
+/// `destination/prefix` out of interface `index` through `gateway`, on-link for `None`.
+/// Adding an existing route is an error of kind `AlreadyExists`, removing a missing one of
+/// kind `NotFound`.
+pub fn change_route(
+    add: bool,
+    index: u32,
+    destination: IpAddr,
+    prefix: u8,
+    gateway: Option<IpAddr>,
+    metric: Option<u32>,
+) -> io::Result<()> {
+    unsafe {
+        let mut row: MIB_IPFORWARD_ROW2 = mem::zeroed();
+        InitializeIpForwardEntry(&mut row);
+        row.InterfaceIndex = index;
+        row.DestinationPrefix.PrefixLength = prefix;
+        match destination {
+            IpAddr::V4(destination) => {
+                row.DestinationPrefix.Prefix.si_family = AF_INET;
+                row.DestinationPrefix.Prefix.Ipv4.sin_addr.S_un.S_addr =
+                    u32::from(destination).to_be();
+                row.NextHop.si_family = AF_INET;
+            }
+            IpAddr::V6(destination) => {
+                row.DestinationPrefix.Prefix.si_family = AF_INET6;
+                row.DestinationPrefix.Prefix.Ipv6.sin6_addr.u.Byte = destination.octets();
+                row.NextHop.si_family = AF_INET6;
+            }
+        }
+        match gateway {
+            Some(IpAddr::V4(gateway)) => {
+                row.NextHop.Ipv4.sin_addr.S_un.S_addr = u32::from(gateway).to_be();
+            }
+            Some(IpAddr::V6(gateway)) => row.NextHop.Ipv6.sin6_addr.u.Byte = gateway.octets(),
+            None => {}
+        }
+        if let Some(metric) = metric {
+            row.Metric = metric;
+        }
+        let rs = if add {
+            CreateIpForwardEntry2(&row)
+        } else {
+            DeleteIpForwardEntry2(&row)
+        };
+        match rs {
+            NO_ERROR => Ok(()),
+            ERROR_OBJECT_ALREADY_EXISTS => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("a route to {destination}/{prefix} already exists"),
+            )),
+            ERROR_NOT_FOUND => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no route to {destination}/{prefix}"),
+            )),
+            rs => Err(io::Error::from_raw_os_error(rs as i32)),
+        }
+    }
+}
+
+pub unsafe fn sockaddr_inet_to_ip(addr: &SOCKADDR_INET) -> Option<IpAddr> {
+    match addr.si_family {
+        AF_INET => Some(Ipv4Addr::from(u32::from_be(addr.Ipv4.sin_addr.S_un.S_addr)).into()),
+        AF_INET6 => Some(Ipv6Addr::from(addr.Ipv6.sin6_addr.u.Byte).into()),
+        _ => None,
+    }
+}
+
+/// Returns the DNS servers of interface `index`, read with `GetAdaptersAddresses`.
+pub fn dns_servers(index: u32) -> io::Result<Vec<IpAddr>> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/iphlpapi/nf-iphlpapi-getadaptersaddresses
+    let flags = GAA_FLAG_SKIP_UNICAST | GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST;
+    let mut size = 16 * 1024u32;
+    loop {
+        // u64 elements keep the buffer aligned for IP_ADAPTER_ADDRESSES_LH.
+        let mut buf = vec![0u64; (size as usize).div_ceil(8)];
+        let first = buf.as_mut_ptr() as *mut IP_ADAPTER_ADDRESSES_LH;
+        match unsafe {
+            GetAdaptersAddresses(AF_UNSPEC as u32, flags, ptr::null(), first, &mut size)
+        } {
+            NO_ERROR => {}
+            ERROR_BUFFER_OVERFLOW => continue,
+            rs => return Err(io::Error::from_raw_os_error(rs as i32)),
+        }
+        let mut servers = Vec::new();
+        let mut adapter = first;
+        unsafe {
+            while !adapter.is_null() {
+                let a = &*adapter;
+                if a.Anonymous1.Anonymous.IfIndex == index || a.Ipv6IfIndex == index {
+                    let mut server = a.FirstDnsServerAddress;
+                    while !server.is_null() {
+                        if let Some(ip) = socket_address_to_ip(&(*server).Address) {
+                            servers.push(ip);
+                        }
+                        server = (*server).Next;
+                    }
+                    return Ok(servers);
+                }
+                adapter = a.Next;
+            }
+        }
+        return Err(io::Error::from(io::ErrorKind::NotFound));
+    }
+}
+
+unsafe fn socket_address_to_ip(addr: &SOCKET_ADDRESS) -> Option<IpAddr> {
+    if addr.lpSockaddr.is_null() {
+        return None;
+    }
+    match (*addr.lpSockaddr).sa_family {
+        AF_INET => {
+            let addr = &*(addr.lpSockaddr as *const SOCKADDR_IN);
+            Some(Ipv4Addr::from(u32::from_be(addr.sin_addr.S_un.S_addr)).into())
+        }
+        AF_INET6 => {
+            let addr = &*(addr.lpSockaddr as *const SOCKADDR_IN6);
+            Some(Ipv6Addr::from(addr.sin6_addr.u.Byte).into())
+        }
+        _ => None,
+    }
+}
+
+/// Returns the index of the interface used to reach `destination`, `None` if it is
+/// unreachable.
+pub fn best_route_interface(destination: IpAddr) -> io::Result<Option<u32>> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-getbestroute2
+    unsafe {
+        let mut dest: SOCKADDR_INET = mem::zeroed();
+        match destination {
+            IpAddr::V4(addr) => {
+                dest.Ipv4.sin_family = AF_INET;
+                dest.Ipv4.sin_addr.S_un.S_addr = u32::from(addr).to_be();
+            }
+            IpAddr::V6(addr) => {
+                dest.Ipv6.sin6_family = AF_INET6;
+                dest.Ipv6.sin6_addr.u.Byte = addr.octets();
+            }
+        }
+        let mut row: MIB_IPFORWARD_ROW2 = mem::zeroed();
+        let mut source: SOCKADDR_INET = mem::zeroed();
+        let rs = GetBestRoute2(ptr::null(), 0, ptr::null(), &dest, 0, &mut row, &mut source);
+        match rs {
+            NO_ERROR => Ok(Some(row.InterfaceIndex)),
+            ERROR_NETWORK_UNREACHABLE | ERROR_HOST_UNREACHABLE => Ok(None),
+            rs => Err(io::Error::from_raw_os_error(rs as i32)),
+        }
+    }
+}
+
+/// Returns the interface index and next hop used to reach `destination`, the next hop is
+/// `None` if the destination is on-link.
+pub fn best_route_v6(destination: Ipv6Addr) -> io::Result<(u32, Option<Ipv6Addr>)> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-getbestroute2
+    unsafe {
+        let mut dest: SOCKADDR_INET = mem::zeroed();
+        dest.Ipv6.sin6_family = AF_INET6;
+        dest.Ipv6.sin6_addr.u.Byte = destination.octets();
+        let mut row: MIB_IPFORWARD_ROW2 = mem::zeroed();
+        let mut source: SOCKADDR_INET = mem::zeroed();
+        let rs = GetBestRoute2(ptr::null(), 0, ptr::null(), &dest, 0, &mut row, &mut source);
+        if rs != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(rs as i32));
+        }
+        let next_hop = Ipv6Addr::from(row.NextHop.Ipv6.sin6_addr.u.Byte);
+        Ok((
+            row.InterfaceIndex,
+            (!next_hop.is_unspecified()).then_some(next_hop),
+        ))
+    }
+}
+
+/// Returns the file version of `path` from its version resource, most significant part first.
+pub fn file_version(path: &str) -> io::Result<[u16; 4]> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/winver/nf-winver-verqueryvaluew
+    let path = encode_utf16(path);
+    unsafe {
+        let len = GetFileVersionInfoSizeW(path.as_ptr(), ptr::null_mut());
+        if len == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut data = vec![0u8; len as usize];
+        if GetFileVersionInfoW(path.as_ptr(), 0, len, data.as_mut_ptr().cast()) == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+        let root = encode_utf16("\\");
+        let mut info: *mut std::ffi::c_void = ptr::null_mut();
+        let mut info_len = 0;
+        if VerQueryValueW(
+            data.as_ptr().cast(),
+            root.as_ptr(),
+            &mut info,
+            &mut info_len,
+        ) == FALSE
+            || (info_len as usize) < mem::size_of::<VS_FIXEDFILEINFO>()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the file has no version resource",
+            ));
+        }
+        let info = &*(info as *const VS_FIXEDFILEINFO);
+        Ok([
+            (info.dwFileVersionMS >> 16) as u16,
+            info.dwFileVersionMS as u16,
+            (info.dwFileVersionLS >> 16) as u16,
+            info.dwFileVersionLS as u16,
+        ])
+    }
+}
+
+/// The machine type of the running process, which a loaded DLL must match.
+pub const PROCESS_MACHINE: IMAGE_FILE_MACHINE = if cfg!(target_arch = "aarch64") {
+    IMAGE_FILE_MACHINE_ARM64
+} else if cfg!(target_arch = "x86") {
+    IMAGE_FILE_MACHINE_I386
+} else {
+    IMAGE_FILE_MACHINE_AMD64
+};
+
+/// Returns the machine type of the OS, which kernel drivers must match. It differs from
+/// [`PROCESS_MACHINE`] for a process running under emulation.
+pub fn native_machine() -> io::Result<IMAGE_FILE_MACHINE> {
+    let mut process = 0;
+    let mut native = 0;
+    if unsafe { IsWow64Process2(GetCurrentProcess(), &mut process, &mut native) } == FALSE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(native)
+}
+
+/// Reads the machine type from the PE header of the executable image at `path`.
+pub fn image_machine(path: &std::path::Path) -> io::Result<IMAGE_FILE_MACHINE> {
+    use std::io::{Read, Seek, SeekFrom};
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a PE image");
+    let mut file = std::fs::File::open(path)?;
+    let mut dos = [0u8; 64];
+    file.read_exact(&mut dos)?;
+    if &dos[..2] != b"MZ" {
+        return Err(invalid());
+    }
+    // e_lfanew, the offset of the "PE\0\0" signature followed by IMAGE_FILE_HEADER.Machine
+    let offset = u32::from_le_bytes([dos[60], dos[61], dos[62], dos[63]]);
+    file.seek(SeekFrom::Start(offset as u64))?;
+    let mut header = [0u8; 6];
+    file.read_exact(&mut header)?;
+    if &header[..4] != b"PE\0\0" {
+        return Err(invalid());
+    }
+    Ok(u16::from_le_bytes([header[4], header[5]]))
+}
+
+/// Names a machine type the way driver downloads do.
+pub fn machine_name(machine: IMAGE_FILE_MACHINE) -> &'static str {
+    match machine {
+        IMAGE_FILE_MACHINE_AMD64 => "x64",
+        IMAGE_FILE_MACHINE_ARM64 => "ARM64",
+        IMAGE_FILE_MACHINE_I386 => "x86",
+        _ => "unknown",
+    }
+}
+
+/// Opens the access token of this process for querying.
+fn process_token() -> io::Result<OwnedHandle> {
+    let mut token: HANDLE = ptr::null_mut();
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == FALSE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(token) })
+}
+
+/// Returns whether the token of this process is elevated, which is also the case for
+/// services running as LocalSystem.
+pub fn is_elevated() -> io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    let token = process_token()?;
+    let mut elevation: TOKEN_ELEVATION = unsafe { mem::zeroed() };
+    let mut len = 0;
+    if unsafe {
+        GetTokenInformation(
+            token.as_raw_handle(),
+            TokenElevation,
+            &mut elevation as *mut _ as *mut _,
+            mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut len,
+        )
+    } == FALSE
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(elevation.TokenIsElevated != 0)
+}
+
+/// Returns whether the token of this process holds the privilege `name`, e.g.
+/// `SeLoadDriverPrivilege`, enabled or not.
+pub fn has_privilege(name: &str) -> io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    let name = encode_utf16(name);
+    let mut luid: LUID = unsafe { mem::zeroed() };
+    if unsafe { LookupPrivilegeValueW(ptr::null(), name.as_ptr(), &mut luid) } == FALSE {
+        return Err(io::Error::last_os_error());
+    }
+    let token = process_token()?;
+    let mut len = 0;
+    unsafe {
+        GetTokenInformation(
+            token.as_raw_handle(),
+            TokenPrivileges,
+            ptr::null_mut(),
+            0,
+            &mut len,
+        )
+    };
+    if unsafe { GetLastError() } != ERROR_INSUFFICIENT_BUFFER {
+        return Err(io::Error::last_os_error());
+    }
+    // u64 keeps the buffer aligned for TOKEN_PRIVILEGES.
+    let mut buf = vec![0u64; (len as usize).div_ceil(8)];
+    if unsafe {
+        GetTokenInformation(
+            token.as_raw_handle(),
+            TokenPrivileges,
+            buf.as_mut_ptr().cast(),
+            len,
+            &mut len,
+        )
+    } == FALSE
+    {
+        return Err(io::Error::last_os_error());
+    }
+    let privileges = unsafe { &*(buf.as_ptr() as *const TOKEN_PRIVILEGES) };
+    let privileges: &[LUID_AND_ATTRIBUTES] = unsafe {
+        std::slice::from_raw_parts(
+            privileges.Privileges.as_ptr(),
+            privileges.PrivilegeCount as usize,
+        )
+    };
+    Ok(privileges
+        .iter()
+        .any(|v| v.Luid.LowPart == luid.LowPart && v.Luid.HighPart == luid.HighPart))
+}
@@ -0,0 +1,168 @@
+//! Interface configuration through the IP Helper API, without spawning `netsh`.
+//!
+//! The functions here fail with the Win32 error code the API returned. With the
+//! `netsh_fallback` feature the callers in [`netsh`](super::netsh) run `netsh` instead.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::{io, mem, ptr};
+
+use windows_sys::Win32::Foundation::{ERROR_NOT_FOUND, ERROR_OBJECT_ALREADY_EXISTS, NO_ERROR};
+use windows_sys::Win32::NetworkManagement::IpHelper::{
+    CreateIpForwardEntry2, CreateUnicastIpAddressEntry, DeleteUnicastIpAddressEntry, FreeMibTable,
+    GetUnicastIpAddressTable, InitializeIpForwardEntry, InitializeUnicastIpAddressEntry,
+    MIB_IPFORWARD_ROW2, MIB_UNICASTIPADDRESS_ROW, MIB_UNICASTIPADDRESS_TABLE,
+};
+use windows_sys::Win32::Networking::WinSock::{
+    IpPrefixOriginManual, IpSuffixOriginManual, AF_INET, AF_INET6, SOCKADDR_INET,
+};
+
+use crate::platform::windows::ffi;
+
+/// Assigns `address/prefix` to interface `index` with `CreateUnicastIpAddressEntry`.
+///
+/// Like `netsh interface ipv4 set address`, an IPv4 address replaces the IPv4 addresses
+/// already assigned; an IPv6 address is added to the others. With a `gateway` a default
+/// route through it is added as well.
+pub fn set_interface_ip(
+    index: u32,
+    address: IpAddr,
+    prefix: u8,
+    gateway: Option<IpAddr>,
+) -> io::Result<()> {
+    if address.is_ipv4() {
+        for assigned in unicast_addresses(index, AF_INET)? {
+            if assigned.1 != (address, prefix) {
+                delete_unicast_address(&assigned.0)?;
+            }
+        }
+    }
+    add_interface_ip(index, address, prefix, gateway)
+}
+
+/// Adds `address/prefix` to interface `index`, keeping the addresses already assigned.
+/// Adding an address that is already assigned with the same prefix succeeds.
+pub fn add_interface_ip(
+    index: u32,
+    address: IpAddr,
+    prefix: u8,
+    gateway: Option<IpAddr>,
+) -> io::Result<()> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-createunicastipaddressentry
+    unsafe {
+        let mut row: MIB_UNICASTIPADDRESS_ROW = mem::zeroed();
+        InitializeUnicastIpAddressEntry(&mut row);
+        row.InterfaceIndex = index;
+        row.Address = sockaddr_inet(address);
+        row.OnLinkPrefixLength = prefix;
+        row.PrefixOrigin = IpPrefixOriginManual;
+        row.SuffixOrigin = IpSuffixOriginManual;
+        match CreateUnicastIpAddressEntry(&row) {
+            NO_ERROR => {}
+            ERROR_OBJECT_ALREADY_EXISTS
+                if unicast_addresses(index, row.Address.si_family)?
+                    .iter()
+                    .any(|(_, assigned)| *assigned == (address, prefix)) => {}
+            rs => return Err(io::Error::from_raw_os_error(rs as i32)),
+        }
+    }
+    if let Some(gateway) = gateway {
+        add_default_route(index, gateway)?;
+    }
+    Ok(())
+}
+
+/// Removes `address` from interface `index` with `DeleteUnicastIpAddressEntry`. Fails with
+/// an error of kind `NotFound` if it is not assigned.
+pub fn delete_interface_ip(index: u32, address: IpAddr) -> io::Result<()> {
+    let family = if address.is_ipv4() { AF_INET } else { AF_INET6 };
+    let row = unicast_addresses(index, family)?
+        .into_iter()
+        .find(|(_, (assigned, _))| *assigned == address)
+        .map(|(row, _)| row)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{address} is not assigned to the interface"),
+            )
+        })?;
+    delete_unicast_address(&row)
+}
+
+/// Sets the MTU of the IPv4 or IPv6 subinterface of `index` with `SetIpInterfaceEntry`.
+pub fn set_interface_mtu(index: u32, is_v4: bool, mtu: u32) -> io::Result<()> {
+    ffi::update_ip_interface(index, is_v4, |row| row.NlMtu = mtu)
+}
+
+/// Adds a default route out of interface `index` through `gateway`, keeping an existing one.
+fn add_default_route(index: u32, gateway: IpAddr) -> io::Result<()> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-createipforwardentry2
+    unsafe {
+        let mut row: MIB_IPFORWARD_ROW2 = mem::zeroed();
+        InitializeIpForwardEntry(&mut row);
+        row.InterfaceIndex = index;
+        row.DestinationPrefix.Prefix = sockaddr_inet(match gateway {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        });
+        row.DestinationPrefix.PrefixLength = 0;
+        row.NextHop = sockaddr_inet(gateway);
+        match CreateIpForwardEntry2(&row) {
+            NO_ERROR | ERROR_OBJECT_ALREADY_EXISTS => Ok(()),
+            rs => Err(io::Error::from_raw_os_error(rs as i32)),
+        }
+    }
+}
+
+/// Returns the unicast address rows of `family` on interface `index` with their address
+/// and prefix length.
+fn unicast_addresses(
+    index: u32,
+    family: u16,
+) -> io::Result<Vec<(MIB_UNICASTIPADDRESS_ROW, (IpAddr, u8))>> {
+    // https://learn.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-getunicastipaddresstable
+    let mut table: *mut MIB_UNICASTIPADDRESS_TABLE = ptr::null_mut();
+    unsafe {
+        let rs = GetUnicastIpAddressTable(family, &mut table);
+        if rs != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(rs as i32));
+        }
+        let rows = std::slice::from_raw_parts::<MIB_UNICASTIPADDRESS_ROW>(
+            &(*table).Table[0],
+            (*table).NumEntries as usize,
+        );
+        let addresses = rows
+            .iter()
+            .filter(|row| row.InterfaceIndex == index)
+            .filter_map(|row| {
+                let address = ffi::sockaddr_inet_to_ip(&row.Address)?;
+                Some((*row, (address, row.OnLinkPrefixLength)))
+            })
+            .collect();
+        FreeMibTable(table as _);
+        Ok(addresses)
+    }
+}
+
+fn delete_unicast_address(row: &MIB_UNICASTIPADDRESS_ROW) -> io::Result<()> {
+    match unsafe { DeleteUnicastIpAddressEntry(row) } {
+        NO_ERROR | ERROR_NOT_FOUND => Ok(()),
+        rs => Err(io::Error::from_raw_os_error(rs as i32)),
+    }
+}
+
+fn sockaddr_inet(address: IpAddr) -> SOCKADDR_INET {
+    unsafe {
+        let mut addr: SOCKADDR_INET = mem::zeroed();
+        match address {
+            IpAddr::V4(address) => {
+                addr.si_family = AF_INET;
+                addr.Ipv4.sin_addr.S_un.S_addr = u32::from(address).to_be();
+            }
+            IpAddr::V6(address) => {
+                addr.si_family = AF_INET6;
+                addr.Ipv6.sin6_addr.u.Byte = address.octets();
+            }
+        }
+        addr
+    }
+}
@@ -1,7 +1,51 @@
 mod device;
 mod ffi;
+mod iphlpapi;
 mod netsh;
 mod tap;
 mod tun;
 
 pub use device::DeviceImpl;
+pub(crate) use tap::driver_version as tap_driver_version;
+pub(crate) use tun::{check_wintun, default_wintun_file, MAX_RING_CAPACITY, MIN_RING_CAPACITY};
+pub use tun::{reload_wintun, wintun_dll_version, wintun_version};
+
+/// Returns whether this process runs elevated, as an administrator or as a service under
+/// LocalSystem. Creating or configuring an adapter fails otherwise.
+pub fn is_elevated() -> bool {
+    ffi::is_elevated().unwrap_or(false)
+}
+
+/// Returns whether this process may create wintun and tap0901 adapters: it runs elevated
+/// and holds `SeLoadDriverPrivilege`, which installing the driver of a new adapter needs.
+///
+/// Services under LocalSystem have both, services under LocalService or NetworkService
+/// have neither. Call it at startup to fail with an actionable message instead of on the
+/// first device.
+pub fn has_required_privileges() -> bool {
+    check_privileges().is_ok()
+}
+
+/// Fails with [`Error::PermissionDenied`](crate::Error::PermissionDenied) explaining what
+/// is missing to create an adapter.
+pub(crate) fn check_privileges() -> crate::Result<()> {
+    let denied = |msg: &str| {
+        crate::Error::PermissionDenied(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            msg.to_string(),
+        ))
+    };
+    if !is_elevated() {
+        return Err(denied(
+            "creating an adapter needs an elevated process; run as administrator or as a \
+             service under LocalSystem",
+        ));
+    }
+    if !ffi::has_privilege("SeLoadDriverPrivilege").unwrap_or(false) {
+        return Err(denied(
+            "creating an adapter needs SeLoadDriverPrivilege; run the service under \
+             LocalSystem or grant the privilege to its account",
+        ));
+    }
+    Ok(())
+}
@@ -1,38 +1,41 @@
 use std::io;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::os::windows::process::CommandExt;
 use std::process::{Command, Output};
 
+use crate::platform::windows::iphlpapi;
+use crate::{Error, Result};
 use encoding_rs::GBK;
 use windows_sys::Win32::System::Threading::CREATE_NO_WINDOW;
 
-pub fn set_interface_name(old_name: &str, new_name: &str) -> io::Result<()> {
-    let cmd = format!(
-        " netsh interface set interface name={:?} newname={:?}",
-        old_name, new_name
-    );
-    exe_cmd(&cmd)
-}
-pub fn set_interface_metric(index: u32, metric: u16) -> io::Result<()> {
-    let cmd = format!(
-        "netsh interface ip set interface {} metric={}",
-        index, metric
-    );
-    exe_cmd(&cmd)
-}
-pub fn exe_cmd(cmd: &str) -> io::Result<()> {
-    let out = Command::new("cmd")
-        .creation_flags(CREATE_NO_WINDOW)
-        .arg("/C")
-        .arg(cmd)
-        .output()?;
-    output(cmd, out)
+pub fn set_interface_name(old_name: &str, new_name: &str) -> Result<()> {
+    exe_netsh(&[
+        "interface",
+        "set",
+        "interface",
+        &format!("name={old_name}"),
+        &format!("newname={new_name}"),
+    ])
+}
+/// Returns a command running `netsh.exe` from the system directory.
+///
+/// `netsh` is started directly, not through `cmd.exe`, which policies often block for
+/// services, and by its full path, so a service does not depend on its `PATH`.
+fn netsh() -> Command {
+    let root = std::env::var_os("SystemRoot").unwrap_or_else(|| "C:\\Windows".into());
+    let mut path = std::path::PathBuf::from(root);
+    path.push("System32");
+    path.push("netsh.exe");
+    Command::new(path)
+}
+fn exe_netsh(args: &[&str]) -> Result<()> {
+    exe_command(netsh().args(args))
 }
 fn gbk_to_utf8(bytes: &[u8]) -> String {
     let (msg, _, _) = GBK.decode(bytes);
     msg.to_string()
 }
-fn output(cmd: &str, out: Output) -> io::Result<()> {
+fn output(cmd: &str, out: Output) -> Result<()> {
     if !out.status.success() {
         let msg = if !out.stderr.is_empty() {
             match std::str::from_utf8(&out.stderr) {
@@ -47,14 +50,33 @@ fn output(cmd: &str, out: Output) -> io::Result<()> {
         } else {
             String::new()
         };
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("cmd=\"{}\",out=\"{}\"", cmd, msg.trim()),
-        ));
+        return Err(Error::Netsh {
+            op: cmd.to_string(),
+            code: out.status.code(),
+            output: msg.trim().to_string(),
+        });
     }
     Ok(())
 }
-pub fn exe_command(cmd: &mut Command) -> io::Result<()> {
+/// Returns `Ok` if the IP Helper call `api` succeeded. Otherwise, with the
+/// `netsh_fallback` feature, runs `netsh` instead.
+///
+/// If `netsh` fails as well, the error of the API call is returned: its Win32 error code
+/// is more useful than the localized console output, which is only logged.
+fn with_fallback(api: io::Result<()>, fallback: impl FnOnce() -> Result<()>) -> Result<()> {
+    let Err(e) = api else {
+        return Ok(());
+    };
+    if !cfg!(feature = "netsh_fallback") {
+        return Err(Error::Io(e));
+    }
+    log::debug!("IP Helper call failed, falling back to netsh: {e}");
+    fallback().map_err(|netsh_err| {
+        log::warn!("{netsh_err}");
+        Error::Io(e)
+    })
+}
+pub fn exe_command(cmd: &mut Command) -> Result<()> {
     let out = cmd.creation_flags(CREATE_NO_WINDOW).output()?;
     let command = cmd
         .get_args()
@@ -62,12 +84,58 @@ pub fn exe_command(cmd: &mut Command) -> io::Result<()> {
         .collect::<Vec<String>>();
     output(&command.join(" ").to_string(), out)
 }
-pub fn delete_interface_ip(index: u32, address: IpAddr) -> io::Result<()> {
-    let cmd = format!(
-        "netsh interface {} delete address {index} {address}",
-        if address.is_ipv4() { "ip" } else { "ipv6" }
-    );
-    exe_cmd(&cmd)
+pub fn delete_interface_ip(index: u32, address: IpAddr) -> Result<()> {
+    let api = iphlpapi::delete_interface_ip(index, address);
+    with_fallback(api, || delete_interface_ip_netsh(index, address))
+}
+fn delete_interface_ip_netsh(index: u32, address: IpAddr) -> Result<()> {
+    exe_netsh(&[
+        "interface",
+        if address.is_ipv4() { "ip" } else { "ipv6" },
+        "delete",
+        "address",
+        &index.to_string(),
+        &address.to_string(),
+    ])
+}
+
+/// Adds an IPv4 address, keeping the addresses already assigned.
+pub fn add_interface_ipv4(
+    index: u32,
+    address: Ipv4Addr,
+    netmask: Ipv4Addr,
+    gateway: Option<Ipv4Addr>,
+) -> Result<()> {
+    let prefix = ipnet::ipv4_mask_to_prefix(netmask)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+    let api = iphlpapi::add_interface_ip(index, address.into(), prefix, gateway.map(Into::into));
+    with_fallback(api, || {
+        add_interface_ipv4_netsh(index, address, netmask, gateway)
+    })
+}
+fn add_interface_ipv4_netsh(
+    index: u32,
+    address: Ipv4Addr,
+    netmask: Ipv4Addr,
+    gateway: Option<Ipv4Addr>,
+) -> Result<()> {
+    let index = index.to_string();
+    let address = format!("address={address}");
+    let mask = format!("mask={netmask}");
+    let gateway = gateway.map(|v| format!("gateway={v}"));
+    let mut args = vec![
+        "interface",
+        "ipv4",
+        "add",
+        "address",
+        &index,
+        &address,
+        &mask,
+    ];
+    if let Some(gateway) = &gateway {
+        args.push(gateway);
+    }
+    exe_netsh(&args)
 }
 
 /// 设置网卡ip
@@ -76,8 +144,21 @@ pub fn set_interface_ip(
     address: IpAddr,
     netmask: IpAddr,
     gateway: Option<IpAddr>,
-) -> io::Result<()> {
-    let mut binding = Command::new("netsh");
+) -> Result<()> {
+    let prefix = ipnet::ip_mask_to_prefix(netmask)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+    let api = iphlpapi::set_interface_ip(index, address, prefix, gateway);
+    with_fallback(api, || {
+        set_interface_ip_netsh(index, address, netmask, gateway)
+    })
+}
+fn set_interface_ip_netsh(
+    index: u32,
+    address: IpAddr,
+    netmask: IpAddr,
+    gateway: Option<IpAddr>,
+) -> Result<()> {
+    let mut binding = netsh();
 
     let cmd = if address.is_ipv4() {
         binding
@@ -107,17 +188,72 @@ pub fn set_interface_ip(
     exe_command(cmd)
 }
 
-pub fn set_interface_mtu(index: u32, mtu: u32) -> io::Result<()> {
-    let cmd = format!(
-        "netsh interface ipv4 set subinterface {}  mtu={} store=persistent",
-        index, mtu
-    );
-    exe_cmd(&cmd)
-}
-pub fn set_interface_mtu_v6(index: u32, mtu: u32) -> io::Result<()> {
-    let cmd = format!(
-        "netsh interface ipv6 set subinterface {}  mtu={} store=persistent",
-        index, mtu
-    );
-    exe_cmd(&cmd)
+pub fn set_interface_mtu(index: u32, mtu: u32) -> Result<()> {
+    let api = iphlpapi::set_interface_mtu(index, true, mtu);
+    with_fallback(api, || set_subinterface_mtu("ipv4", index, mtu))
+}
+pub fn set_interface_mtu_v6(index: u32, mtu: u32) -> Result<()> {
+    let api = iphlpapi::set_interface_mtu(index, false, mtu);
+    with_fallback(api, || set_subinterface_mtu("ipv6", index, mtu))
+}
+fn set_subinterface_mtu(family: &str, index: u32, mtu: u32) -> Result<()> {
+    exe_netsh(&[
+        "interface",
+        family,
+        "set",
+        "subinterface",
+        &index.to_string(),
+        &format!("mtu={mtu}"),
+        "store=persistent",
+    ])
+}
+/// Replaces the static DNS servers of interface `index`, both IPv4 and IPv6, with `servers`
+/// in order. An empty list removes them.
+pub fn set_dns_servers(index: u32, servers: &[IpAddr]) -> Result<()> {
+    let index = index.to_string();
+    for (family, is_v4) in [("ipv4", true), ("ipv6", false)] {
+        let servers: Vec<&IpAddr> = servers.iter().filter(|v| v.is_ipv4() == is_v4).collect();
+        let cleared = exe_netsh(&[
+            "interface",
+            family,
+            "set",
+            "dnsservers",
+            &index,
+            "source=static",
+            "address=none",
+        ]);
+        match cleared {
+            // IPv6 may be disabled on the adapter, which matters only with IPv6 servers.
+            Err(e) if !is_v4 && servers.is_empty() => {
+                log::debug!("{e}");
+                continue;
+            }
+            rs => rs?,
+        }
+        for (i, server) in servers.iter().enumerate() {
+            exe_netsh(&[
+                "interface",
+                family,
+                "add",
+                "dnsservers",
+                &index,
+                &format!("address={server}"),
+                &format!("index={}", i + 1),
+                "validate=no",
+            ])?;
+        }
+    }
+    Ok(())
+}
+/// Adds an IPv6 route that only lives until the next reboot.
+pub fn add_route_v6(index: u32, prefix: &str, next_hop: Option<Ipv6Addr>) -> Result<()> {
+    let mut cmd = netsh();
+    cmd.args(["interface", "ipv6", "add", "route"])
+        .arg(format!("prefix={prefix}"))
+        .arg(format!("interface={index}"))
+        .arg("store=active");
+    if let Some(next_hop) = next_hop {
+        cmd.arg(format!("nexthop={next_hop}"));
+    }
+    exe_command(&mut cmd)
 }
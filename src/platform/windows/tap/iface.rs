@@ -10,7 +10,7 @@ use windows_sys::{
         Devices::DeviceAndDriverInstallation::{
             DICD_GENERATE_ID, DICS_FLAG_GLOBAL, DIF_INSTALLDEVICE, DIF_INSTALLINTERFACES,
             DIF_REGISTERDEVICE, DIF_REGISTER_COINSTALLERS, DIF_REMOVE, DIGCF_PRESENT, DIREG_DRV,
-            SPDIT_COMPATDRIVER, SPDRP_HARDWAREID,
+            HDEVINFO, SPDIT_COMPATDRIVER, SPDRP_HARDWAREID, SP_DEVINFO_DATA,
         },
         Foundation::{GENERIC_READ, GENERIC_WRITE, TRUE},
         NetworkManagement::Ndis::NET_LUID_LH,
@@ -66,41 +66,7 @@ pub fn create_interface(component_id: &str) -> io::Result<NET_LUID_LH> {
         let _ = ffi::destroy_driver_info_list(devinfo, &devinfo_data, SPDIT_COMPATDRIVER);
     });
 
-    let mut driver_version = 0;
-    let mut member_index = 0;
-
-    while let Some(drvinfo_data) =
-        ffi::enum_driver_info(devinfo, &devinfo_data, SPDIT_COMPATDRIVER, member_index)
-    {
-        member_index += 1;
-
-        if drvinfo_data.is_err() {
-            continue;
-        }
-        let drvinfo_data = drvinfo_data?;
-        if drvinfo_data.DriverVersion <= driver_version {
-            continue;
-        }
-
-        let drvinfo_detail =
-            match ffi::get_driver_info_detail(devinfo, &devinfo_data, &drvinfo_data) {
-                Ok(drvinfo_detail) => drvinfo_detail,
-                _ => continue,
-            };
-
-        let hardware_id = decode_utf16(&drvinfo_detail.HardwareID);
-        if !hardware_id.eq_ignore_ascii_case(component_id) {
-            continue;
-        }
-
-        if ffi::set_selected_driver(devinfo, &devinfo_data, &drvinfo_data).is_err() {
-            continue;
-        }
-
-        driver_version = drvinfo_data.DriverVersion;
-    }
-
-    if driver_version == 0 {
+    if select_driver(devinfo, &devinfo_data, component_id) == 0 {
         return Err(io::Error::new(io::ErrorKind::NotFound, "No driver found"));
     }
 
@@ -151,6 +117,85 @@ pub fn create_interface(component_id: &str) -> io::Result<NET_LUID_LH> {
     Ok(luid)
 }
 
+/// Selects the newest installed driver for `component_id` and returns its version, 0 if there
+/// is none.
+fn select_driver(devinfo: HDEVINFO, devinfo_data: &SP_DEVINFO_DATA, component_id: &str) -> u64 {
+    let mut driver_version = 0;
+    let mut member_index = 0;
+
+    while let Some(drvinfo_data) =
+        ffi::enum_driver_info(devinfo, devinfo_data, SPDIT_COMPATDRIVER, member_index)
+    {
+        member_index += 1;
+
+        let Ok(drvinfo_data) = drvinfo_data else {
+            continue;
+        };
+        if drvinfo_data.DriverVersion <= driver_version {
+            continue;
+        }
+
+        let drvinfo_detail = match ffi::get_driver_info_detail(devinfo, devinfo_data, &drvinfo_data)
+        {
+            Ok(drvinfo_detail) => drvinfo_detail,
+            _ => continue,
+        };
+
+        let hardware_id = decode_utf16(&drvinfo_detail.HardwareID);
+        if !hardware_id.eq_ignore_ascii_case(component_id) {
+            continue;
+        }
+
+        if ffi::set_selected_driver(devinfo, devinfo_data, &drvinfo_data).is_err() {
+            continue;
+        }
+
+        driver_version = drvinfo_data.DriverVersion;
+    }
+    driver_version
+}
+
+/// Returns the version of the installed driver for `component_id` as `a.b.c.d`, without
+/// creating an interface.
+pub fn driver_version(component_id: &str) -> io::Result<String> {
+    let devinfo = ffi::create_device_info_list(&GUID_NETWORK_ADAPTER)?;
+
+    let _guard = guard((), |_| {
+        let _ = ffi::destroy_device_info_list(devinfo);
+    });
+
+    let class_name = ffi::class_name_from_guid(&GUID_NETWORK_ADAPTER)?;
+
+    // The device info element is only registered by DIF_REGISTERDEVICE, it is dropped with
+    // the list.
+    let mut devinfo_data = ffi::create_device_info(
+        devinfo,
+        &class_name,
+        &GUID_NETWORK_ADAPTER,
+        "",
+        DICD_GENERATE_ID,
+    )?;
+    ffi::set_device_registry_property(devinfo, &devinfo_data, SPDRP_HARDWAREID, component_id)?;
+    ffi::build_driver_info_list(devinfo, &mut devinfo_data, SPDIT_COMPATDRIVER)?;
+
+    let _guard = guard((), |_| {
+        let _ = ffi::destroy_driver_info_list(devinfo, &devinfo_data, SPDIT_COMPATDRIVER);
+    });
+
+    let version = select_driver(devinfo, &devinfo_data, component_id);
+    if version == 0 {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No driver found"));
+    }
+    let v = version.to_be_bytes();
+    Ok(format!(
+        "{}.{}.{}.{}",
+        u16::from_be_bytes([v[0], v[1]]),
+        u16::from_be_bytes([v[2], v[3]]),
+        u16::from_be_bytes([v[4], v[5]]),
+        u16::from_be_bytes([v[6], v[7]])
+    ))
+}
+
 /// Check if the given interface exists and is a valid network device
 pub fn check_interface(component_id: &str, luid: &NET_LUID_LH) -> io::Result<()> {
     let devinfo = ffi::get_class_devs(&GUID_NETWORK_ADAPTER, DIGCF_PRESENT)?;
@@ -1,7 +1,9 @@
+use crate::error::check_received;
 use crate::platform::windows::{ffi, netsh};
 use std::ops::DerefMut;
 use std::os::windows::io::{AsRawHandle, OwnedHandle};
 use std::sync::Mutex;
+use std::time::Duration;
 use std::{io, time};
 use windows_sys::Win32::Foundation::HANDLE;
 use windows_sys::Win32::NetworkManagement::Ndis::NET_LUID_LH;
@@ -9,6 +11,7 @@ use windows_sys::Win32::System::Ioctl::{FILE_ANY_ACCESS, FILE_DEVICE_UNKNOWN, ME
 use windows_sys::Win32::System::IO::OVERLAPPED;
 
 mod iface;
+pub(crate) use iface::driver_version;
 
 pub struct TapDevice {
     luid: NET_LUID_LH,
@@ -18,10 +21,15 @@ pub struct TapDevice {
     need_delete: bool,
     read_io_overlapped: Mutex<(Option<Box<OVERLAPPED>>, Vec<u8>)>,
     write_io_overlapped: Mutex<Option<(Box<OVERLAPPED>, Vec<u8>)>>,
+    /// Signalled when the pending overlapped read completes.
+    read_event: OwnedHandle,
+    /// Signalled when the pending overlapped write completes.
+    write_event: OwnedHandle,
 }
-const READ_BUFFER_SIZE: usize = 14 + 65536;
+const READ_BUFFER_SIZE: usize = crate::platform::MAX_PACKET_SIZE;
+// SAFETY: the OVERLAPPED structures hold raw pointers but are only touched under their
+// mutexes, and the file handle may be used from any thread.
 unsafe impl Send for TapDevice {}
-
 unsafe impl Sync for TapDevice {}
 
 impl Drop for TapDevice {
@@ -43,6 +51,9 @@ impl TapDevice {
     pub fn index(&self) -> u32 {
         self.index
     }
+    pub fn luid(&self) -> u64 {
+        unsafe { self.luid.Value }
+    }
     /// Creates a new tap-windows device
     pub fn create(component_id: &str) -> io::Result<Self> {
         let luid = iface::create_interface(component_id)?;
@@ -89,6 +100,8 @@ impl TapDevice {
             need_delete: true,
             read_io_overlapped: Mutex::new((None, vec![0; READ_BUFFER_SIZE])),
             write_io_overlapped: Mutex::new(None),
+            read_event: ffi::create_event(true)?,
+            write_event: ffi::create_event(true)?,
         })
     }
 
@@ -107,6 +120,8 @@ impl TapDevice {
             need_delete: false,
             read_io_overlapped: Mutex::new((None, vec![0; READ_BUFFER_SIZE])),
             write_io_overlapped: Mutex::new(None),
+            read_event: ffi::create_event(true)?,
+            write_event: ffi::create_event(true)?,
         })
     }
 
@@ -152,7 +167,7 @@ impl TapDevice {
     /// Set the name of the interface
     pub fn set_name(&self, newname: &str) -> io::Result<()> {
         let name = self.get_name()?;
-        netsh::set_interface_name(&name, newname)
+        netsh::set_interface_name(&name, newname).map_err(io::Error::from)
     }
 
     // /// Set the ip of the interface
@@ -179,23 +194,28 @@ impl TapDevice {
             &mut out_status,
         )
     }
-    pub fn try_read(&self, mut buf: &mut [u8]) -> io::Result<usize> {
+    pub fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
         let Ok(mut guard) = self.read_io_overlapped.try_lock() else {
             return Err(io::Error::from(io::ErrorKind::WouldBlock));
         };
         let (overlapped, read_buffer) = guard.deref_mut();
-        let n = if let Some(overlapped) = overlapped {
-            ffi::try_io_overlapped(self.handle.as_raw_handle(), overlapped)?
+        let rs = if let Some(overlapped) = overlapped {
+            ffi::try_io_overlapped(self.handle.as_raw_handle(), overlapped)
         } else {
-            let overlapped = overlapped.insert(Box::new(ffi::io_overlapped()));
-            ffi::try_read_file(self.handle.as_raw_handle(), overlapped, read_buffer)?
+            let overlapped = overlapped.insert(Box::new(overlapped_with(&self.read_event)));
+            ffi::try_read_file(self.handle.as_raw_handle(), overlapped, read_buffer)
         };
-        _ = overlapped.take();
-        let n = n as usize;
-        match io::copy(&mut &read_buffer[..n], &mut buf) {
-            Ok(n) => Ok(n as usize),
-            Err(e) => Err(e),
-        }
+        let n = match rs {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Err(e),
+            rs => {
+                // The read is finished, successfully or not, so the next call starts a new one.
+                _ = overlapped.take();
+                rs?
+            }
+        };
+        let n = check_received(n as usize, buf.len())?;
+        buf[..n].copy_from_slice(&read_buffer[..n]);
+        Ok(n)
     }
     pub fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
         let Ok(mut guard) = self.write_io_overlapped.try_lock() else {
@@ -205,7 +225,7 @@ impl TapDevice {
             ffi::try_io_overlapped(self.handle.as_raw_handle(), overlapped)?;
         }
         let (overlapped, write_buffer) =
-            guard.insert((Box::new(ffi::io_overlapped()), buf.to_vec()));
+            guard.insert((Box::new(overlapped_with(&self.write_event)), buf.to_vec()));
         let rs = ffi::try_write_file(self.handle.as_raw_handle(), overlapped, write_buffer);
         match rs {
             Ok(len) => {
@@ -216,7 +236,37 @@ impl TapDevice {
             Err(e) => Err(e),
         }
     }
-    pub fn read(&self, mut buf: &mut [u8]) -> io::Result<usize> {
+    /// Waits until `try_read` can return a packet. Returns `false` if `timeout` elapsed first.
+    ///
+    /// Starts an overlapped read if none is pending, so the packet is kept for `try_read`.
+    pub fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        {
+            let mut guard = self.read_io_overlapped.lock().unwrap();
+            let (pending, read_buffer) = guard.deref_mut();
+            if pending.is_none() {
+                let overlapped = pending.insert(Box::new(overlapped_with(&self.read_event)));
+                match ffi::try_read_file(self.handle.as_raw_handle(), overlapped, read_buffer) {
+                    // Finished right away, the result is collected by the next `try_read`.
+                    Ok(_) => return Ok(true),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => {
+                        *pending = None;
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        ffi::wait_event(self.read_event.as_raw_handle(), timeout)
+    }
+    /// Waits until the pending overlapped write, if any, has completed. Returns `false` if
+    /// `timeout` elapsed first.
+    pub fn wait_writable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        if self.write_io_overlapped.lock().unwrap().is_none() {
+            return Ok(true);
+        }
+        ffi::wait_event(self.write_event.as_raw_handle(), timeout)
+    }
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
         let mut guard = self.read_io_overlapped.lock().unwrap();
         let (overlapped, read_buffer) = guard.deref_mut();
         let n = if let Some(overlapped) = overlapped.take() {
@@ -224,10 +274,9 @@ impl TapDevice {
         } else {
             return ffi::read_file(self.handle.as_raw_handle(), buf).map(|res| res as _);
         };
-        match io::copy(&mut &read_buffer[..n], &mut buf) {
-            Ok(n) => Ok(n as usize),
-            Err(e) => Err(e),
-        }
+        let n = check_received(n, buf.len())?;
+        buf[..n].copy_from_slice(&read_buffer[..n]);
+        Ok(n)
     }
     pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
         let mut guard = self.write_io_overlapped.lock().unwrap();
@@ -238,6 +287,12 @@ impl TapDevice {
     }
 }
 
+fn overlapped_with(event: &OwnedHandle) -> OVERLAPPED {
+    let mut overlapped = ffi::io_overlapped();
+    overlapped.hEvent = event.as_raw_handle();
+    overlapped
+}
+
 #[allow(non_snake_case)]
 #[inline]
 const fn CTL_CODE(DeviceType: u32, Function: u32, Method: u32, Access: u32) -> u32 {
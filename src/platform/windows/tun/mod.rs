@@ -1,16 +1,19 @@
 use std::os::windows::io::{AsRawHandle, OwnedHandle};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use std::{io, ptr};
 
 use windows_sys::Win32::Foundation::{
     GetLastError, ERROR_BUFFER_OVERFLOW, ERROR_HANDLE_EOF, ERROR_INVALID_DATA, ERROR_NO_MORE_ITEMS,
-    FALSE, WAIT_FAILED, WAIT_OBJECT_0,
+    FALSE, WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT,
 };
 use windows_sys::Win32::NetworkManagement::Ndis::NET_LUID_LH;
-use windows_sys::Win32::System::Threading::{SetEvent, WaitForMultipleObjects, INFINITE};
+use windows_sys::Win32::System::Threading::{ResetEvent, SetEvent, WaitForMultipleObjects};
 
 use crate::platform::windows::ffi;
 use crate::platform::windows::ffi::encode_utf16;
+use crate::Error;
 
 mod wintun_log;
 mod wintun_raw;
@@ -24,16 +27,110 @@ pub const MIN_RING_CAPACITY: u32 = 0x2_0000;
 /// Maximum pool name length including zero terminator
 pub const MAX_POOL: usize = 256;
 
+/// Returns the version of the running wintun driver, loading wintun from `wintun_path`.
+///
+/// The driver is only running while at least one wintun adapter exists.
+pub fn wintun_version(wintun_path: &str) -> crate::Result<String> {
+    let win_tun = unsafe { wintun_raw::wintun::new(wintun_path) }
+        .map_err(|e| Error::Wintun(e.to_string()))?;
+    Ok(running_driver_version(&win_tun)?)
+}
+
+/// Returns the file version of the wintun library at `wintun_path`, e.g. `0.14.1.0`.
+///
+/// The file is read without loading it, so this also reports a library that was just
+/// replaced on disk. The first two parts are the version of the driver it installs, compare
+/// them with [`wintun_version`] to decide whether a [`reload_wintun`] is needed.
+pub fn wintun_dll_version(wintun_path: &str) -> crate::Result<String> {
+    let [major, minor, patch, build] = ffi::file_version(wintun_path)?;
+    Ok(format!("{major}.{minor}.{patch}.{build}"))
+}
+
+/// The number of adapters alive in this process, each keeps the wintun library it was
+/// created with loaded.
+static ADAPTERS: Mutex<usize> = Mutex::new(0);
+/// The wintun library for devices built without a `wintun_file`, see [`reload_wintun`].
+static DEFAULT_WINTUN_FILE: RwLock<Option<String>> = RwLock::new(None);
+
+pub(crate) fn default_wintun_file() -> String {
+    DEFAULT_WINTUN_FILE
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| "wintun.dll".into())
+}
+
+/// Loads wintun from `wintun_path` for the devices built from now on without
+/// [`DeviceBuilder::wintun_file`](crate::DeviceBuilder::wintun_file).
+///
+/// Windows hands out the library that is already loaded for the same path, even if the file
+/// was replaced since, until every adapter of this process using it is dropped. This fails
+/// while any device exists; once they are gone the old library has been released and the
+/// next build loads `wintun_path` afresh.
+pub fn reload_wintun(wintun_path: &str) -> crate::Result<()> {
+    let adapters = ADAPTERS.lock().unwrap();
+    if *adapters > 0 {
+        return Err(Error::Wintun(format!(
+            "wintun is still used by {} adapters, drop them before reloading",
+            *adapters
+        )));
+    }
+    check_wintun(wintun_path)?;
+    *DEFAULT_WINTUN_FILE.write().unwrap() = Some(wintun_path.into());
+    Ok(())
+}
+
+/// Returns whether the library version `dll` installs a different driver than `running`.
+fn driver_mismatch(dll: &str, running: &str) -> bool {
+    let driver = dll.splitn(3, '.').take(2).collect::<Vec<_>>().join(".");
+    driver != running
+}
+
+/// Checks that wintun can be loaded from `wintun_path`, without creating an adapter.
+pub(crate) fn check_wintun(wintun_path: &str) -> crate::Result<()> {
+    unsafe { wintun_raw::wintun::new(wintun_path) }
+        .map(|_| ())
+        .map_err(|e| Error::Wintun(e.to_string()))
+}
+
+fn running_driver_version(win_tun: &wintun_raw::wintun) -> io::Result<String> {
+    let version = unsafe { win_tun.WintunGetRunningDriverVersion() };
+    if version == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let v = version.to_be_bytes();
+    Ok(format!(
+        "{}.{}",
+        u16::from_be_bytes([v[0], v[1]]),
+        u16::from_be_bytes([v[2], v[3]])
+    ))
+}
+
 pub struct TunDevice {
     index: u32,
     luid: NET_LUID_LH,
-    session: SessionHandle,
+    adapter: Arc<AdapterHandle>,
+    /// The running session, `None` while the device is disabled.
+    session: RwLock<Option<SessionHandle>>,
+    enabled: Mutex<bool>,
+    enabled_cond: Condvar,
+}
+/// How often a blocked reader checks that the adapter still exists.
+const GONE_CHECK_INTERVAL_MS: u32 = 1000;
+enum ReadWait {
+    Readable,
+    Timeout,
+    /// The session is about to be ended.
+    Paused,
 }
 struct AdapterHandle {
     win_tun: wintun_raw::wintun,
     handle: wintun_raw::WINTUN_ADAPTER_HANDLE,
+    luid: NET_LUID_LH,
     shutdown_state: AtomicBool,
     shutdown_event: OwnedHandle,
+    /// Signalled while the session is being ended, so that waiting readers let go of it.
+    pause_event: OwnedHandle,
     ring_capacity: u32,
 }
 impl Drop for AdapterHandle {
@@ -42,19 +139,14 @@ impl Drop for AdapterHandle {
             self.win_tun.WintunCloseAdapter(self.handle);
             self.win_tun.WintunDeleteDriver();
         }
+        *ADAPTERS.lock().unwrap() -= 1;
     }
 }
 impl AdapterHandle {
     fn version(&self) -> io::Result<String> {
-        let version = unsafe { self.win_tun.WintunGetRunningDriverVersion() };
-        let v = version.to_be_bytes();
-        Ok(format!(
-            "{}.{}",
-            u16::from_be_bytes([v[0], v[1]]),
-            u16::from_be_bytes([v[2], v[3]])
-        ))
-    }
-    fn start_session(self) -> io::Result<SessionHandle> {
+        running_driver_version(&self.win_tun)
+    }
+    fn start_session(self: &Arc<Self>) -> io::Result<SessionHandle> {
         unsafe {
             let session = self
                 .win_tun
@@ -68,7 +160,7 @@ impl AdapterHandle {
             }
 
             let session = SessionHandle {
-                adapter: self,
+                adapter: self.clone(),
                 handle: session,
                 read_event: read_event_handle,
             };
@@ -88,10 +180,12 @@ impl AdapterHandle {
         self.shutdown_state.load(Ordering::SeqCst)
     }
 }
+// SAFETY: the adapter handle is an opaque pointer owned by this value, and wintun allows
+// using it from any thread. The shutdown state is atomic and the events are kernel objects.
 unsafe impl Send for AdapterHandle {}
 unsafe impl Sync for AdapterHandle {}
 struct SessionHandle {
-    adapter: AdapterHandle,
+    adapter: Arc<AdapterHandle>,
     handle: wintun_raw::WINTUN_SESSION_HANDLE,
     read_event: wintun_raw::HANDLE,
 }
@@ -102,6 +196,8 @@ impl Drop for SessionHandle {
         }
     }
 }
+// SAFETY: wintun sessions are thread safe, packets may be sent and received concurrently
+// from any thread. The read event is a kernel object that is only waited on.
 unsafe impl Send for SessionHandle {}
 unsafe impl Sync for SessionHandle {}
 impl TunDevice {
@@ -128,10 +224,31 @@ impl TunDevice {
             Err(io::Error::new(io::ErrorKind::Other, "tunnel type too long"))?;
         }
         unsafe {
-            let shutdown_event = ffi::create_event()?;
+            let shutdown_event = ffi::create_event(false)?;
+            let pause_event = ffi::create_event(true)?;
 
-            let win_tun = wintun_raw::wintun::new(wintun_path)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            // A path with a directory is loaded as an absolute path, so that Windows does not
+            // hand out a wintun.dll loaded earlier from elsewhere.
+            let wintun_path = if wintun_path.contains(['\\', '/']) {
+                std::env::current_dir()?.join(wintun_path)
+            } else {
+                wintun_path.into()
+            };
+            // Loading a DLL built for another architecture fails with a bare
+            // ERROR_BAD_EXE_FORMAT, name the mismatch instead.
+            if let Ok(machine) = ffi::image_machine(&wintun_path) {
+                if machine != ffi::PROCESS_MACHINE {
+                    Err(io::Error::from(Error::Wintun(format!(
+                        "{} is built for {}, this process needs the {} wintun.dll",
+                        wintun_path.display(),
+                        ffi::machine_name(machine),
+                        ffi::machine_name(ffi::PROCESS_MACHINE)
+                    ))))?;
+                }
+            }
+            let win_tun = wintun_raw::wintun::new(&wintun_path)
+                .map_err(|e| io::Error::from(Error::Wintun(e.to_string())))?;
+            let dll_version = wintun_dll_version(&wintun_path.to_string_lossy()).ok();
 
             //SAFETY: guid is a unique integer so transmuting either all zeroes or the user's preferred
             //guid to the wintun_raw guid type is safe and will allow the windows kernel to see our GUID
@@ -148,26 +265,54 @@ impl TunDevice {
                 guid_ptr,
             );
             if adapter.is_null() {
-                Err(io::Error::last_os_error())?
+                let e = io::Error::last_os_error();
+                if let (Some(dll), Ok(running)) = (&dll_version, running_driver_version(&win_tun)) {
+                    if driver_mismatch(dll, &running) {
+                        Err(Error::Wintun(format!(
+                            "wintun {dll} cannot replace the running driver {running} while \
+                             adapters use it, close them (in any process) first: {e}"
+                        )))?
+                    }
+                }
+                Err(e)?
             }
+            *ADAPTERS.lock().unwrap() += 1;
             let mut luid: wintun_raw::NET_LUID = std::mem::zeroed();
             win_tun.WintunGetAdapterLUID(adapter, &mut luid as *mut wintun_raw::NET_LUID);
+            let luid = std::mem::transmute::<wintun_raw::_NET_LUID_LH, NET_LUID_LH>(luid);
 
             let adapter = AdapterHandle {
                 win_tun,
                 handle: adapter,
+                luid,
                 ring_capacity,
                 shutdown_event,
+                pause_event,
                 shutdown_state: AtomicBool::new(false),
             };
-            let luid = std::mem::transmute::<wintun_raw::_NET_LUID_LH, NET_LUID_LH>(luid);
+            let adapter = Arc::new(adapter);
             let index = ffi::luid_to_index(&luid)?;
+            match adapter.version() {
+                Ok(version) => {
+                    log::info!("wintun driver version {version}");
+                    if let Some(dll) = dll_version.filter(|dll| driver_mismatch(dll, &version)) {
+                        log::warn!(
+                            "wintun {dll} runs with the driver {version}, \
+                             which is upgraded once no adapter uses it"
+                        );
+                    }
+                }
+                Err(e) => log::warn!("failed to query the wintun driver version: {e}"),
+            }
             let session = adapter.start_session()?;
 
             let tun = Self {
                 luid,
                 index,
-                session,
+                adapter,
+                session: RwLock::new(Some(session)),
+                enabled: Mutex::new(true),
+                enabled_cond: Condvar::new(),
             };
             Ok(tun)
         }
@@ -175,26 +320,111 @@ impl TunDevice {
     pub fn index(&self) -> u32 {
         self.index
     }
+    pub fn luid(&self) -> u64 {
+        unsafe { self.luid.Value }
+    }
     pub fn get_name(&self) -> io::Result<String> {
         ffi::luid_to_alias(&self.luid)
     }
+    /// The adapter GUID, in the form passed to `create`.
+    pub fn guid(&self) -> io::Result<u128> {
+        let guid = ffi::luid_to_guid(&self.luid)?;
+        // SAFETY: the reverse of the transmute in `create`, both types are 16 plain bytes.
+        Ok(unsafe { std::mem::transmute::<windows_sys::core::GUID, u128>(guid) })
+    }
+    fn with_session<R>(&self, f: impl FnOnce(&SessionHandle) -> io::Result<R>) -> io::Result<R> {
+        match self.session.read().unwrap().as_ref() {
+            Some(session) => f(session),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the device is disabled",
+            )),
+        }
+    }
     pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
-        self.session.send(buf)
+        self.with_session(|session| session.send(buf))
     }
+    /// Blocks until a packet arrives. While the device is disabled, waits for it to be
+    /// enabled again.
     pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
-        self.session.recv(buf)
+        loop {
+            {
+                let guard = self.session.read().unwrap();
+                if let Some(session) = guard.as_ref() {
+                    if let Some(n) = session.recv(buf)? {
+                        return Ok(n);
+                    }
+                    // The session is being ended, release it and wait below.
+                }
+            }
+            self.wait_enabled()?;
+        }
     }
     pub fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
-        self.session.try_send(buf)
+        self.with_session(|session| session.try_send(buf))
     }
     pub fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize> {
-        self.session.try_recv(buf)
+        self.with_session(|session| session.try_recv(buf))
+    }
+    /// Waits on the wintun read event. Returns `false` if `timeout` elapsed first.
+    ///
+    /// The event is only armed once the ring is empty, so call this after `try_recv`
+    /// returned `WouldBlock`.
+    pub fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        self.with_session(|session| session.wait_readable_timeout(timeout))
+    }
+    /// Wintun has no event for free space in the send ring, so this only checks that a
+    /// session is running.
+    pub fn wait_writable(&self, _timeout: Option<Duration>) -> io::Result<bool> {
+        self.with_session(|session| session.check_shutdown().map(|_| true))
+    }
+    /// Ends or restarts the wintun session. The adapter and its configuration are kept,
+    /// so the device can be paused and resumed.
+    pub fn set_enabled(&self, value: bool) -> io::Result<()> {
+        let mut enabled = self.enabled.lock().unwrap();
+        if value {
+            let mut session = self.session.write().unwrap();
+            if session.is_none() {
+                *session = Some(self.adapter.start_session()?);
+            }
+        } else {
+            unsafe {
+                if FALSE == SetEvent(self.adapter.pause_event.as_raw_handle()) {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            // Ending the session waits for readers blocked in `recv` to let go of it.
+            self.session.write().unwrap().take();
+            unsafe {
+                if FALSE == ResetEvent(self.adapter.pause_event.as_raw_handle()) {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        *enabled = value;
+        self.enabled_cond.notify_all();
+        Ok(())
+    }
+    fn wait_enabled(&self) -> io::Result<()> {
+        let mut enabled = self.enabled.lock().unwrap();
+        loop {
+            if self.adapter.is_shutdown() {
+                return Err(shutdown_error());
+            }
+            if *enabled && self.session.read().unwrap().is_some() {
+                return Ok(());
+            }
+            enabled = self.enabled_cond.wait(enabled).unwrap();
+        }
     }
     pub fn shutdown(&self) -> io::Result<()> {
-        self.session.shutdown()
+        self.adapter.shutdown()?;
+        let _guard = self.enabled.lock().unwrap();
+        self.enabled_cond.notify_all();
+        Ok(())
     }
     pub fn version(&self) -> io::Result<String> {
-        self.session.adapter.version()
+        self.adapter.version()
     }
 }
 
@@ -216,11 +446,12 @@ impl SessionHandle {
             };
         }
     }
-    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+    /// Returns `None` if the session is about to be ended.
+    fn recv(&self, buf: &mut [u8]) -> io::Result<Option<usize>> {
         loop {
             for i in 0..64 {
                 return match self.try_recv(buf) {
-                    Ok(n) => Ok(n),
+                    Ok(n) => Ok(Some(n)),
                     Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                         if i > 32 {
                             std::thread::yield_now()
@@ -232,7 +463,9 @@ impl SessionHandle {
                     Err(e) => Err(e),
                 };
             }
-            self.wait_readable()?;
+            if !self.wait_readable()? {
+                return Ok(None);
+            }
         }
     }
     fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
@@ -243,7 +476,8 @@ impl SessionHandle {
         let bytes_ptr = unsafe { win_tun.WintunAllocateSendPacket(handle, buf.len() as u32) };
         if bytes_ptr.is_null() {
             match unsafe { GetLastError() } {
-                ERROR_HANDLE_EOF => Err(std::io::Error::from(io::ErrorKind::WriteZero)),
+                // The adapter is terminating.
+                ERROR_HANDLE_EOF => Err(Error::DeviceGone.into()),
                 ERROR_BUFFER_OVERFLOW => Err(std::io::Error::from(io::ErrorKind::WouldBlock)),
                 ERROR_INVALID_DATA => Err(std::io::Error::from(io::ErrorKind::InvalidData)),
                 e => Err(io::Error::from_raw_os_error(e as i32)),
@@ -265,53 +499,104 @@ impl SessionHandle {
         if ptr.is_null() {
             // Wintun returns ERROR_NO_MORE_ITEMS instead of blocking if packets are not available
             return match unsafe { GetLastError() } {
-                ERROR_HANDLE_EOF => Err(std::io::Error::from(io::ErrorKind::UnexpectedEof)),
+                ERROR_HANDLE_EOF => Err(Error::DeviceGone.into()),
                 ERROR_NO_MORE_ITEMS => Err(std::io::Error::from(io::ErrorKind::WouldBlock)),
+                // The ring is corrupt, only a new session recovers from it.
+                ERROR_INVALID_DATA => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the wintun receive ring is corrupt",
+                )),
                 e => Err(io::Error::from_raw_os_error(e as i32)),
             };
         }
         let size = size as usize;
         if size > buf.len() {
             unsafe { win_tun.WintunReleaseReceivePacket(handle, ptr) };
-            use std::io::{Error, ErrorKind::InvalidInput};
-            return Err(Error::new(InvalidInput, "destination buffer too small"));
+            return Err(Error::Truncated { needed: size }.into());
         }
         unsafe { ptr::copy_nonoverlapping(ptr, buf.as_mut_ptr(), size) };
         unsafe { win_tun.WintunReleaseReceivePacket(handle, ptr) };
         Ok(size)
     }
-    fn wait_readable(&self) -> io::Result<()> {
+    /// Returns `false` if the session is about to be ended.
+    fn wait_readable(&self) -> io::Result<bool> {
+        match self.wait_read_event(GONE_CHECK_INTERVAL_MS)? {
+            ReadWait::Readable => Ok(true),
+            ReadWait::Timeout => {
+                self.check_gone()?;
+                Ok(true)
+            }
+            ReadWait::Paused => Ok(false),
+        }
+    }
+    fn wait_readable_timeout(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let mut interval = GONE_CHECK_INTERVAL_MS;
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                interval = interval.min(remaining.as_millis() as u32);
+            }
+            match self.wait_read_event(interval)? {
+                ReadWait::Readable => return Ok(true),
+                ReadWait::Timeout if interval < GONE_CHECK_INTERVAL_MS => return Ok(false),
+                ReadWait::Timeout => self.check_gone()?,
+                ReadWait::Paused => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotConnected,
+                        "the device is disabled",
+                    ))
+                }
+            }
+        }
+    }
+    fn wait_read_event(&self, milliseconds: u32) -> io::Result<ReadWait> {
         self.check_shutdown()?;
-        //Wait on both the read handle and the shutdown handle so that we stop when requested
-        let handles = [self.read_event, self.adapter.shutdown_event.as_raw_handle()];
+        //Wait on the read handle, the shutdown handle and the pause handle so that we stop when requested
+        let handles = [
+            self.read_event,
+            self.adapter.shutdown_event.as_raw_handle(),
+            self.adapter.pause_event.as_raw_handle(),
+        ];
         let result = unsafe {
             //SAFETY: We abide by the requirements of WaitForMultipleObjects, handles is a
             //pointer to valid, aligned, stack memory
-            WaitForMultipleObjects(2, &handles as _, 0, INFINITE)
+            WaitForMultipleObjects(3, &handles as _, 0, milliseconds)
         };
         match result {
             WAIT_FAILED => Err(io::Error::last_os_error()),
+            WAIT_TIMEOUT => Ok(ReadWait::Timeout),
             _ => {
                 if result == WAIT_OBJECT_0 {
                     //We have data!
-                    Ok(())
+                    Ok(ReadWait::Readable)
+                } else if result == WAIT_OBJECT_0 + 2 {
+                    Ok(ReadWait::Paused)
                 } else {
                     //Shutdown event triggered
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Shutdown event triggered {}", io::Error::last_os_error()),
-                    ))
+                    Err(shutdown_error())
                 }
             }
         }
     }
-    fn shutdown(&self) -> io::Result<()> {
-        self.adapter.shutdown()
+    /// The read event is not signalled when the adapter is removed, so this is checked
+    /// periodically while waiting.
+    fn check_gone(&self) -> io::Result<()> {
+        if ffi::luid_to_index(&self.adapter.luid).is_err() {
+            return Err(Error::DeviceGone.into());
+        }
+        Ok(())
     }
     fn check_shutdown(&self) -> io::Result<()> {
         if self.adapter.is_shutdown() {
-            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            return Err(shutdown_error());
         }
         Ok(())
     }
 }
+
+/// The error of I/O on a device after `shutdown`, distinct from the `NotFound` of
+/// [`Error::DeviceGone`] so that callers know not to recreate the adapter.
+fn shutdown_error() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "the device was shut down")
+}
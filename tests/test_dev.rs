@@ -167,3 +167,1195 @@ async fn test_unix_fd() {
     let fd = device.into_raw_fd();
     assert_eq!(fd, 1)
 }
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_tap_jumbo_mtu() {
+    let device = DeviceBuilder::new()
+        .layer(tun_rs::Layer::L2)
+        .mtu(9000)
+        .build_sync()
+        .unwrap();
+    assert_eq!(9000, device.mtu().unwrap());
+    // The largest MTU leaves room for the Ethernet header in a 65535-byte frame.
+    device.set_mtu(65521).unwrap();
+    assert_eq!(65521, device.mtu().unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_set_broadcast_address() {
+    use std::net::Ipv4Addr;
+    let device = DeviceBuilder::new()
+        .layer(tun_rs::Layer::L2)
+        .ipv4("10.26.9.1", 24, None)
+        .build_sync()
+        .unwrap();
+    assert_eq!(device.broadcast().unwrap(), Ipv4Addr::new(10, 26, 9, 255));
+    let broadcast = Ipv4Addr::new(10, 26, 9, 127);
+    device.set_broadcast_address(broadcast).unwrap();
+    assert_eq!(device.broadcast().unwrap(), broadcast);
+
+    let tun = DeviceBuilder::new()
+        .ipv4("10.26.10.1", 24, None)
+        .build_sync()
+        .unwrap();
+    let err = tun.set_broadcast_address(broadcast).unwrap_err();
+    assert!(matches!(err, tun_rs::Error::Unsupported(_)));
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_recv_with_timestamp() {
+    use std::time::SystemTime;
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.11.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let udp_socket = std::net::UdpSocket::bind("10.26.11.100:0").unwrap();
+    let before = SystemTime::now();
+    udp_socket.send_to(b"stamp", "10.26.11.101:8080").unwrap();
+    let mut buf = [0; 1500];
+    loop {
+        let (n, timestamp) = device.recv_with_timestamp(&mut buf).unwrap();
+        if buf[..n].ends_with(b"stamp") {
+            assert!(timestamp >= before && timestamp <= SystemTime::now());
+            break;
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_recv_truncated() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.3.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let udp_socket = std::net::UdpSocket::bind("10.26.3.100:0").unwrap();
+    udp_socket.send_to(&[0; 1000], "10.26.3.101:8080").unwrap();
+    let mut buf = [0; 100];
+    // Small unrelated packets (e.g. neighbor discovery) may arrive first.
+    let err = (0..16)
+        .find_map(|_| device.recv(&mut buf).err())
+        .expect("the packet was not reported as truncated");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(matches!(
+        tun_rs::Error::from(err),
+        tun_rs::Error::Truncated { needed } if needed >= 1028
+    ));
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_protocol_filter() {
+    let tun = DeviceBuilder::new()
+        .ipv4("10.26.27.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let err = tun
+        .set_protocol_filter(Some(tun_rs::Protocol::Ipv4))
+        .unwrap_err();
+    assert_eq!(
+        std::io::ErrorKind::Unsupported,
+        std::io::Error::from(err).kind()
+    );
+
+    let device = DeviceBuilder::new()
+        .layer(tun_rs::Layer::L2)
+        .ipv4("10.26.28.100", 24, None)
+        .protocol_filter(tun_rs::Protocol::Ipv4)
+        .build_sync()
+        .unwrap();
+    device.set_nonblocking(true).unwrap();
+    let udp_socket = std::net::UdpSocket::bind("10.26.28.100:0").unwrap();
+    udp_socket.send_to(&[0; 100], "10.26.28.101:8080").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let mut buf = [0; 1514];
+    // The ARP request for the peer is not IPv4 and is dropped.
+    let err = device.recv(&mut buf).unwrap_err();
+    assert_eq!(std::io::ErrorKind::WouldBlock, err.kind());
+    device.set_protocol_filter(None).unwrap();
+    // Another peer, as the ARP request for the first one is only repeated after a second.
+    udp_socket.send_to(&[0; 100], "10.26.28.102:8080").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    device.recv(&mut buf).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_recv_retries_on_eintr() {
+    extern "C" fn noop(_: libc::c_int) {}
+    unsafe {
+        // No SA_RESTART, so blocking reads are interrupted with EINTR.
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = noop as *const () as libc::sighandler_t;
+        libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
+    }
+    let test_msg = "test eintr";
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.4.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let reader = std::thread::spawn(move || {
+        tx.send(unsafe { libc::pthread_self() }).unwrap();
+        let mut buf = [0; 65535];
+        loop {
+            let len = device.recv(&mut buf)?;
+            if buf[..len].ends_with(test_msg.as_bytes()) {
+                return Ok::<(), std::io::Error>(());
+            }
+        }
+    });
+    let thread = rx.recv().unwrap();
+    for _ in 0..200 {
+        unsafe { libc::pthread_kill(thread, libc::SIGUSR1) };
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let udp_socket = std::net::UdpSocket::bind("10.26.4.100:0").unwrap();
+    udp_socket
+        .send_to(test_msg.as_bytes(), "10.26.4.101:8080")
+        .unwrap();
+    reader.join().unwrap().unwrap();
+}
+
+#[cfg(target_os = "windows")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_disable_enable() {
+    let test_msg = "test enable";
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.5.100", 24, None)
+        .build_sync()
+        .unwrap();
+    device.enabled(false).unwrap();
+    let mut buf = [0; 65535];
+    assert_eq!(
+        device.try_recv(&mut buf).unwrap_err().kind(),
+        std::io::ErrorKind::NotConnected
+    );
+    device.enabled(true).unwrap();
+    let udp_socket = std::net::UdpSocket::bind("10.26.5.100:0").unwrap();
+    udp_socket
+        .send_to(test_msg.as_bytes(), "10.26.5.101:8080")
+        .unwrap();
+    loop {
+        let len = device.recv(&mut buf).unwrap();
+        if buf[..len].ends_with(test_msg.as_bytes()) {
+            break;
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_external_rename() {
+    let device = DeviceBuilder::new()
+        .name("tunrename0")
+        .ipv4("10.26.6.100", 24, None)
+        .enable(false)
+        .build_sync()
+        .unwrap();
+    let status = std::process::Command::new("ip")
+        .args(["link", "set", "dev", "tunrename0", "name", "tunrenamed0"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!("tunrenamed0", device.name().unwrap());
+    device.set_mtu(1400).unwrap();
+    assert_eq!(1400, device.mtu().unwrap());
+    assert_ne!(0, device.if_index().unwrap());
+    device.enabled(true).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_send_packet_information() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.6.120", 24, None)
+        .packet_information(true)
+        .build_sync()
+        .unwrap();
+    // A minimal IPv4 header without the packet information header in front of it.
+    let mut packet = [0u8; 20];
+    packet[0] = 0x45;
+    let err = device.send(&packet).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(matches!(
+        tun_rs::Error::from(err),
+        tun_rs::Error::InvalidPacket(_)
+    ));
+    assert_eq!(
+        packet.len(),
+        device.send_packet(&packet, tun_rs::Protocol::Ipv4).unwrap()
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_header_overhead() {
+    let overhead =
+        |builder: DeviceBuilder| builder.build_sync().unwrap().header_overhead().unwrap();
+    assert_eq!(0, overhead(DeviceBuilder::new()));
+    assert_eq!(
+        tun_rs::PACKET_INFORMATION_LENGTH,
+        overhead(DeviceBuilder::new().packet_information(true))
+    );
+    assert_eq!(
+        tun_rs::VIRTIO_NET_HDR_LEN,
+        overhead(DeviceBuilder::new().offload(true))
+    );
+    assert_eq!(
+        tun_rs::ETHERNET_HEADER_LEN,
+        overhead(DeviceBuilder::new().layer(tun_rs::Layer::L2))
+    );
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_detect_layer() {
+    for layer in [tun_rs::Layer::L2, tun_rs::Layer::L3] {
+        let device = DeviceBuilder::new().layer(layer).build_sync().unwrap();
+        assert_eq!(layer, device.detect_layer().unwrap());
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(feature = "async_tokio")]
+#[tokio::test]
+async fn test_async_write() {
+    use tokio::io::AsyncWriteExt;
+    let mut device = DeviceBuilder::new()
+        .ipv4("10.26.26.100", 24, None)
+        .build_async()
+        .unwrap();
+    let mut packet = vec![0; 100];
+    packet[0] = 0x45;
+    assert_eq!(100, device.write(&packet).await.unwrap());
+    device.flush().await.unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(feature = "async_tokio")]
+#[tokio::test(start_paused = true)]
+async fn test_send_rate_limit() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.11.100", 24, None)
+        .build_async()
+        .unwrap();
+    device
+        .set_send_rate_limit(Some(tun_rs::RateLimit {
+            bytes_per_sec: 10_000,
+            burst: 2_000,
+        }))
+        .unwrap();
+    let mut packet = vec![0; 1000];
+    packet[0] = 0x45;
+    let start = tokio::time::Instant::now();
+    // The burst covers two packets, the other 20 take 2s.
+    for _ in 0..22 {
+        device.send(&packet).await.unwrap();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    assert!((elapsed - 2.0).abs() < 0.02, "took {elapsed}s");
+    let err = device.try_send(&packet).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+    device.set_send_rate_limit(None).unwrap();
+    device.try_send(&packet).unwrap();
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_would_loop() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.13.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let mut packet = [0u8; 20];
+    packet[0] = 0x45;
+    packet[12..16].copy_from_slice(&[10, 26, 13, 100]);
+    packet[16..20].copy_from_slice(&[10, 26, 13, 1]);
+    assert!(device.would_loop(&packet).unwrap());
+    packet[16..20].copy_from_slice(&[127, 0, 0, 1]);
+    assert!(!device.would_loop(&packet).unwrap());
+    assert!(device.would_loop(&[0; 20]).is_err());
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_routes() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.12.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let routes = device.routes().unwrap();
+    let subnet: std::net::IpAddr = "10.26.12.0".parse().unwrap();
+    assert!(routes
+        .iter()
+        .any(|route| route.destination == subnet && route.prefix == 24));
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_peer_routes() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.14.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let peers: Vec<std::net::Ipv4Addr> = (1..=200)
+        .map(|i| std::net::Ipv4Addr::new(10, 27, i / 100, i % 100))
+        .collect();
+    device.add_peer_routes(&peers).unwrap();
+    let mut routes = device.peer_routes().unwrap();
+    routes.sort();
+    assert_eq!(peers, routes);
+    device.remove_peer_routes(&peers[..100]).unwrap();
+    device.remove_peer_route(peers[0]).unwrap();
+    assert_eq!(100, device.peer_routes().unwrap().len());
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_close() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.15.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let destination: std::net::IpAddr = "10.27.15.0".parse().unwrap();
+    device.add_route(destination, 24, None).unwrap();
+    let report = device.close().unwrap();
+    assert!(report.is_clean(), "{report:?}");
+    assert_eq!(
+        Some(&tun_rs::TeardownStep::RemoveRoute(destination, 24)),
+        report.completed.first()
+    );
+    assert!(report
+        .completed
+        .contains(&tun_rs::TeardownStep::RemoveAddress(
+            "10.26.15.100".parse().unwrap()
+        )));
+    assert_eq!(Some(&tun_rs::TeardownStep::Down), report.completed.last());
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_multiple_ipv4() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.16.100", 24, None)
+        .ipv4_tuple(&[("10.26.17.100", 24, None), ("10.26.18.100", 24, None)])
+        .build_sync()
+        .unwrap();
+    let addresses = device.addresses().unwrap();
+    let expected: [std::net::IpAddr; 3] = [
+        "10.26.16.100".parse().unwrap(),
+        "10.26.17.100".parse().unwrap(),
+        "10.26.18.100".parse().unwrap(),
+    ];
+    assert!(
+        expected.iter().all(|v| addresses.contains(v)),
+        "{addresses:?}"
+    );
+    assert_eq!(Some(&expected[0]), addresses.iter().find(|v| v.is_ipv4()));
+    device.remove_address(expected[1]).unwrap();
+    let addresses = device.addresses().unwrap();
+    assert!(addresses.contains(&expected[0]) && !addresses.contains(&expected[1]));
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_probe() {
+    let caps = tun_rs::probe();
+    assert!(caps.tun.is_available());
+    assert!(caps.tap.is_available());
+    assert!(caps.multi_queue.is_available());
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_has_carrier() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.10.100", 24, None)
+        .build_sync()
+        .unwrap();
+    assert!(device.has_carrier().unwrap());
+    #[cfg(target_os = "linux")]
+    {
+        device.set_carrier(false).unwrap();
+        assert!(!device.has_carrier().unwrap());
+        device.set_carrier(true).unwrap();
+        assert!(device.has_carrier().unwrap());
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_offload_enabled() {
+    let device = DeviceBuilder::new().offload(true).build_sync().unwrap();
+    assert_eq!(device.tcp_gso(), device.offload_enabled().unwrap());
+    let device = DeviceBuilder::new().build_sync().unwrap();
+    assert!(!device.offload_enabled().unwrap());
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_recreate_from_identity() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.6.130", 24, None)
+        .ipv6("CDCD:910A:2222:5498:8475:1111:3900:2030", 64)
+        .mtu(1400)
+        .build_sync()
+        .unwrap();
+    let identity = device.identity().unwrap();
+    assert_eq!(vec![("10.26.6.130".parse().unwrap(), 24)], identity.ipv4);
+    drop(device);
+    let device = DeviceBuilder::from_identity(&identity)
+        .build_sync()
+        .unwrap();
+    assert_eq!(identity, device.identity().unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_config_lock() {
+    let device = DeviceBuilder::new()
+        .name("tunlock0")
+        .multi_queue(true)
+        .config_lock(true)
+        .build_sync()
+        .unwrap();
+    assert!(device.is_config_locked());
+    let err = DeviceBuilder::new()
+        .name("tunlock0")
+        .multi_queue(true)
+        .config_lock(true)
+        .build_sync()
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_build_multi_queue() {
+    let queues = DeviceBuilder::new()
+        .ipv4("10.26.6.140", 24, None)
+        .num_queues(4)
+        .build_sync_multi_queue()
+        .unwrap();
+    assert_eq!(4, queues.len());
+    let name = queues[0].name().unwrap();
+    for queue in &queues {
+        assert_eq!(name, queue.name().unwrap());
+    }
+    queues[1].set_queue_enabled(false).unwrap();
+    // A detached queue can still be queried, but no longer receives packets.
+    assert_eq!(name, queues[1].name().unwrap());
+    let mut buf = [0; 1500];
+    for queue in &queues {
+        queue.set_nonblocking(true).unwrap();
+    }
+    while queues[1].recv(&mut buf).is_ok() {}
+    let udp_socket = std::net::UdpSocket::bind("10.26.6.140:0").unwrap();
+    // Every port is a flow of its own, hashed to one of the attached queues.
+    for port in 8000..8064 {
+        udp_socket
+            .send_to(&[0; 100], ("10.26.6.141", port))
+            .unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let err = queues[1].recv(&mut buf).unwrap_err();
+    assert_eq!(std::io::ErrorKind::WouldBlock, err.kind());
+    let received: usize = [&queues[0], &queues[2], &queues[3]]
+        .iter()
+        .map(|queue| std::iter::from_fn(|| queue.recv(&mut buf).ok()).count())
+        .sum();
+    assert!(received >= 64, "{received}");
+    queues[1].set_queue_enabled(true).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_set_addresses() {
+    use std::net::IpAddr;
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.7.1", 24, None)
+        .ipv6("CDCD:910A:2222:5498:8475:1111:3900:7001", 64)
+        .build_sync()
+        .unwrap();
+    let v4 = [("10.26.8.1".parse().unwrap(), 24)];
+    let v6 = [
+        (
+            "CDCD:910A:2222:5498:8475:1111:3900:7001".parse().unwrap(),
+            64,
+        ),
+        (
+            "CDCD:910A:2222:5498:8475:1111:3900:7002".parse().unwrap(),
+            64,
+        ),
+    ];
+    device.set_addresses(&v4, &v6).unwrap();
+    let addresses = device.addresses().unwrap();
+    assert!(addresses.contains(&IpAddr::V4(v4[0].0)));
+    assert!(!addresses.contains(&"10.26.7.1".parse().unwrap()));
+    for (address, _) in v6 {
+        assert!(addresses.contains(&IpAddr::V6(address)));
+    }
+    device.set_addresses(&v4, &v6[1..]).unwrap();
+    assert!(!device.addresses().unwrap().contains(&IpAddr::V6(v6[0].0)));
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_apply_spec() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.7.2", 24, None)
+        .mtu(1400)
+        .build_sync()
+        .unwrap();
+    let spec = tun_rs::DeviceSpec {
+        ipv4: vec![("10.26.7.2".parse().unwrap(), 24)],
+        ipv6: vec![(
+            "CDCD:910A:2222:5498:8475:1111:3900:7003".parse().unwrap(),
+            64,
+        )],
+        mtu: Some(1420),
+    };
+    let report = device.apply(&spec).unwrap();
+    assert_eq!(Some((1400, 1420)), report.mtu);
+    assert_eq!(1, report.added.len());
+    assert!(report.removed.is_empty());
+    assert!(device.apply(&spec).unwrap().is_empty());
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_transaction_rollback() {
+    use std::net::IpAddr;
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.7.3", 24, None)
+        .mtu(1400)
+        .build_sync()
+        .unwrap();
+    let v6: IpAddr = "CDCD:910A:2222:5498:8475:1111:3900:7004".parse().unwrap();
+    let report = device
+        .transaction()
+        .mtu(1420)
+        .add_address(v6, 64)
+        // Below the IPv6 minimum once the address is added.
+        .mtu(1000)
+        .commit();
+    assert!(!report.is_ok());
+    assert_eq!(2, report.applied.len());
+    assert_eq!(
+        vec![
+            tun_rs::TxnStep::AddAddress(v6, 64),
+            tun_rs::TxnStep::SetMtu(1420)
+        ],
+        report.rolled_back
+    );
+    assert_eq!(1400, device.mtu().unwrap());
+    assert!(!device.addresses().unwrap().contains(&v6));
+
+    let report = device.transaction().add_address(v6, 64).mtu(1420).commit();
+    assert_eq!(2, report.into_result().unwrap().len());
+    assert_eq!(1420, device.mtu().unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_route_pref_src() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.7.5", 24, None)
+        .build_sync()
+        .unwrap();
+    let destination: std::net::IpAddr = "10.28.0.0".parse().unwrap();
+    device.add_route_via_device(destination, 16).unwrap();
+    let route = device
+        .routes()
+        .unwrap()
+        .into_iter()
+        .find(|v| v.destination == destination)
+        .unwrap();
+    assert_eq!(Some("10.26.7.5".parse().unwrap()), route.pref_src);
+    device.remove_route(destination, 16).unwrap();
+    assert!(!device
+        .routes()
+        .unwrap()
+        .iter()
+        .any(|v| v.destination == destination));
+    assert!(device
+        .add_route_via_device("fd00:28::".parse().unwrap(), 64)
+        .is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_tunnel_routes() {
+    let destination: std::net::IpAddr = "10.27.0.0".parse().unwrap();
+    let tunnel = tun_rs::Tunnel::builder()
+        .ipv4("10.26.7.4", 24, None)
+        .route(destination, 16)
+        .build()
+        .unwrap();
+    let routes = tunnel.routes().unwrap();
+    assert!(routes
+        .iter()
+        .any(|v| v.destination == destination && v.prefix == 16));
+    tunnel.close().unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_io_hook() {
+    use tun_rs::IoEvent;
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.9.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let events_c = events.clone();
+    device.set_io_hook(move |event| events_c.lock().unwrap().push(event));
+    // An empty IPv4 header from 10.26.9.1 to 10.26.9.100.
+    let packet = [
+        0x45, 0, 0, 20, 0, 0, 0, 0, 64, 0xfd, 0, 0, 10, 26, 9, 1, 10, 26, 9, 100,
+    ];
+    assert_eq!(20, device.send(&packet).unwrap());
+    assert_eq!(vec![IoEvent::Send { len: 20 }], *events.lock().unwrap());
+    device.clear_io_hook();
+    device.send(&packet).unwrap();
+    assert_eq!(1, events.lock().unwrap().len());
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_extra_tun_flags_excl() {
+    let _device = DeviceBuilder::new()
+        .name("tunexcl0")
+        .multi_queue(true)
+        .build_sync()
+        .unwrap();
+    let result = DeviceBuilder::new()
+        .name("tunexcl0")
+        .multi_queue(true)
+        .extra_tun_flags(libc::IFF_TUN_EXCL as u16)
+        .build_sync();
+    assert!(matches!(result, Err(tun_rs::Error::InvalidConfig(_))));
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows", target_os = "freebsd"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_cached_name() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.6.110", 24, None)
+        .build_sync()
+        .unwrap();
+    assert_eq!(device.name().unwrap(), &*device.cached_name().unwrap());
+    device.set_name("tuncached0").unwrap();
+    assert_eq!("tuncached0", &*device.cached_name().unwrap());
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows", target_os = "freebsd"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_error_counters() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.7.100", 24, None)
+        .build_sync()
+        .unwrap();
+    assert_eq!(0, device.rx_errors().unwrap());
+    assert_eq!(0, device.tx_errors().unwrap());
+    device.rx_dropped().unwrap();
+    device.tx_dropped().unwrap();
+    assert!(matches!(
+        device.reset_statistics(),
+        Err(tun_rs::Error::Unsupported(_))
+    ));
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows", target_os = "freebsd"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_stats() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.24.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let stats = device.stats().unwrap();
+    assert_eq!(Some(0), stats.rx_errors);
+    assert_eq!(Some(0), stats.tx_errors);
+    assert!(stats.rx_packets.is_some() && stats.tx_packets.is_some());
+    assert!(stats.rx_bytes.is_some() && stats.tx_bytes.is_some());
+    assert!(stats.rx_dropped.is_some() && stats.tx_dropped.is_some());
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_build_unconfigured() {
+    let (device, configurator) = DeviceBuilder::new()
+        .ipv4("10.26.8.100", 24, None)
+        .mtu(1400)
+        .build_sync_unconfigured()
+        .unwrap();
+    let address: std::net::IpAddr = "10.26.8.100".parse().unwrap();
+    assert!(!device.addresses().unwrap().contains(&address));
+    configurator.apply(&device).unwrap();
+    assert_eq!(1400, device.mtu().unwrap());
+    assert!(device.addresses().unwrap().contains(&address));
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_recv_device_gone() {
+    let device = DeviceBuilder::new()
+        .name("tungone0")
+        .ipv4("10.26.9.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut buf = [0; 65535];
+        loop {
+            if let Err(e) = device.recv(&mut buf) {
+                return e;
+            }
+        }
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    let status = std::process::Command::new("ip")
+        .args(["link", "delete", "tungone0"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let err = reader.join().unwrap();
+    assert!(matches!(
+        tun_rs::Error::from(err),
+        tun_rs::Error::DeviceGone
+    ));
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(feature = "async_tokio")]
+#[tokio::test]
+async fn test_recv_device_gone() {
+    let device = DeviceBuilder::new()
+        .name("tungone1")
+        .ipv4("10.26.10.100", 24, None)
+        .build_async()
+        .unwrap();
+    let reader = tokio::spawn(async move {
+        let mut buf = [0; 65535];
+        loop {
+            if let Err(e) = device.recv(&mut buf).await {
+                return e;
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let status = std::process::Command::new("ip")
+        .args(["link", "delete", "tungone1"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let err = tokio::time::timeout(Duration::from_secs(5), reader)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        tun_rs::Error::from(err),
+        tun_rs::Error::DeviceGone
+    ));
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(feature = "async_tokio")]
+#[tokio::test]
+async fn test_addresses_changed() {
+    let device = DeviceBuilder::new()
+        .name("tunaddr1")
+        .ipv4("10.26.11.100", 24, None)
+        .build_async()
+        .unwrap();
+    let mut events = device.addresses_changed().unwrap();
+    device
+        .add_address_v6("CDCD:910A:2222:5498:8475:1112:1900:2030", 64)
+        .unwrap();
+    let added = tun_rs::AddressEvent::Added {
+        address: "CDCD:910A:2222:5498:8475:1112:1900:2030".parse().unwrap(),
+        prefix: 64,
+    };
+    // The kernel may assign a link-local address around the same time.
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .unwrap();
+        if event == Some(added) {
+            break;
+        }
+    }
+    let status = std::process::Command::new("ip")
+        .args(["link", "delete", "tunaddr1"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .unwrap();
+        if event == Some(tun_rs::AddressEvent::DeviceGone) {
+            break;
+        }
+    }
+    assert_eq!(None, events.next().await);
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_add_existing_address_v6() {
+    let device = DeviceBuilder::new()
+        .ipv6("CDCD:910A:2222:5498:8475:1112:1900:2026", 64)
+        .build_sync()
+        .unwrap();
+    device
+        .add_address_v6("CDCD:910A:2222:5498:8475:1112:1900:2026", 64)
+        .unwrap();
+    assert!(matches!(
+        device.add_address_v6("CDCD:910A:2222:5498:8475:1112:1900:2026", 96),
+        Err(tun_rs::Error::InvalidConfig(_))
+    ));
+}
+
+#[cfg(target_os = "windows")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_luid() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.25.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let index = device.if_index().unwrap();
+    let luid = device.luid().unwrap();
+    assert_ne!(0, luid);
+    device.set_name("tun-luid0").unwrap();
+    assert_eq!(index, device.if_index().unwrap());
+    assert_eq!(luid, device.luid().unwrap());
+}
+
+#[cfg(target_os = "windows")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_mtu_consistent() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.11.100", 24, None)
+        .mtu(1400)
+        .build_sync()
+        .unwrap();
+    device.set_mtu_v6(1400).unwrap();
+    device.assert_mtu_consistent().unwrap();
+    device.set_mtu_v6(1380).unwrap();
+    assert!(device.assert_mtu_consistent().is_err());
+}
+
+/// `try_recv` reports an empty device as `WouldBlock` on every platform, in blocking and
+/// nonblocking mode, and never returns `Ok(0)`.
+#[cfg(any(
+    target_os = "windows",
+    target_os = "macos",
+    target_os = "linux",
+    target_os = "freebsd"
+))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_try_recv_would_block() {
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.12.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let mut buf = [0; 65535];
+    let mut drain = || {
+        // Skip packets the OS sends on its own, e.g. router solicitations.
+        for _ in 0..100 {
+            match device.try_recv(&mut buf) {
+                Ok(n) => assert!(n > 0),
+                Err(e) => {
+                    assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+                    return;
+                }
+            }
+        }
+        panic!("try_recv never reported WouldBlock");
+    };
+    drain();
+    #[cfg(unix)]
+    {
+        device.set_nonblocking(true).unwrap();
+        drain();
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_self_test() {
+    for builder in [DeviceBuilder::new(), DeviceBuilder::new().offload(true)] {
+        let device = builder.ipv4("10.26.8.5", 24, None).build_sync().unwrap();
+        let report = device.self_test(Duration::from_secs(2)).unwrap();
+        assert!(report.is_ok(), "{report:?}");
+        assert_eq!(
+            "10.26.8.1:9".parse::<std::net::SocketAddr>().unwrap(),
+            report.peer
+        );
+    }
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.9.5", 32, None)
+        .build_sync()
+        .unwrap();
+    assert!(device.self_test(Duration::from_millis(100)).is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_active_offloads() {
+    use tun_rs::{IoEvent, OffloadFlags};
+    let device = DeviceBuilder::new().build_sync().unwrap();
+    assert!(device.active_offloads().is_empty());
+    assert!(device.set_offloads(OffloadFlags::TCP).is_err());
+
+    let device = DeviceBuilder::new().offload(true).build_sync().unwrap();
+    let negotiated = device.active_offloads();
+    assert!(negotiated.contains(OffloadFlags::TCP));
+    assert_eq!(negotiated.intersects(OffloadFlags::UDP), device.udp_gso());
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen = events.clone();
+    device.set_io_hook(move |event| seen.lock().unwrap().push(event));
+    assert_eq!(
+        OffloadFlags::TCP,
+        device.set_offloads(OffloadFlags::TCP).unwrap()
+    );
+    assert_eq!(OffloadFlags::TCP, device.active_offloads());
+    assert!(!device.udp_gso());
+    device.set_offloads(negotiated).unwrap();
+    assert_eq!(negotiated, device.active_offloads());
+    let expected = if negotiated == OffloadFlags::TCP {
+        vec![]
+    } else {
+        vec![
+            IoEvent::OffloadsChanged {
+                offloads: OffloadFlags::TCP,
+            },
+            IoEvent::OffloadsChanged {
+                offloads: negotiated,
+            },
+        ]
+    };
+    assert_eq!(expected, *events.lock().unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_build_from_fd() {
+    use std::os::fd::{AsRawFd, IntoRawFd};
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.19.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let name = device.name().unwrap();
+    let fd = device.into_raw_fd();
+
+    let err = unsafe {
+        DeviceBuilder::new()
+            .packet_information(true)
+            .build_from_fd(fd)
+    };
+    assert!(matches!(err, Err(tun_rs::Error::InvalidConfig(_))));
+    let err = unsafe { DeviceBuilder::new().offload(true).build_from_fd(fd) };
+    assert!(matches!(err, Err(tun_rs::Error::InvalidConfig(_))));
+    let device = unsafe { DeviceBuilder::new().build_from_fd(fd) }.unwrap();
+    assert_eq!(name, device.name().unwrap());
+    assert_eq!(fd, device.as_raw_fd());
+    assert!(device
+        .addresses()
+        .unwrap()
+        .contains(&"10.26.19.100".parse().unwrap()));
+    assert_eq!(fd, device.into_raw_fd());
+    drop(unsafe { DeviceBuilder::new().build_from_fd(fd) }.unwrap());
+
+    assert!(unsafe { DeviceBuilder::new().build_from_fd(-1) }.is_err());
+    let file = std::fs::File::open("/dev/null").unwrap();
+    assert!(unsafe { DeviceBuilder::new().build_from_fd(file.as_raw_fd()) }.is_err());
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_remove_address_v4_v6() {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    let primary: Ipv4Addr = "10.26.20.100".parse().unwrap();
+    let secondary: Ipv4Addr = "10.26.20.101".parse().unwrap();
+    let v6: Ipv6Addr = "CDCD:910A:2222:5498:8475:1112:1900:2030".parse().unwrap();
+    let device = DeviceBuilder::new()
+        .ipv4(primary, 24, None)
+        .ipv4(secondary, 24, None)
+        .ipv6(v6, 64)
+        .build_sync()
+        .unwrap();
+    device.remove_address_v4(primary).unwrap();
+    let addresses = device.addresses().unwrap();
+    assert!(!addresses.contains(&IpAddr::V4(primary)), "{addresses:?}");
+    assert!(addresses.contains(&IpAddr::V4(secondary)), "{addresses:?}");
+    let err = device.remove_address_v4(primary).unwrap_err();
+    assert_eq!(std::io::ErrorKind::NotFound, err.kind());
+
+    device.remove_address_v6(v6).unwrap();
+    assert!(!device.addresses().unwrap().contains(&IpAddr::V6(v6)));
+    let err = device.remove_address(v6.into()).unwrap_err();
+    assert_eq!(std::io::ErrorKind::NotFound, err.kind());
+    assert!(device.addresses().unwrap().contains(&IpAddr::V4(secondary)));
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_list_addresses() {
+    use std::net::IpAddr;
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.21.100", 24, None)
+        .ipv6("CDCD:910A:2222:5498:8475:1112:1900:2031", 64)
+        .build_sync()
+        .unwrap();
+    let v4: IpAddr = "10.26.21.100".parse().unwrap();
+    let v6: IpAddr = "CDCD:910A:2222:5498:8475:1112:1900:2031".parse().unwrap();
+    let added: IpAddr = "CDCD:910A:2222:5498:8475:1112:1900:2032".parse().unwrap();
+    let addresses = device.list_addresses().unwrap();
+    assert!(addresses.contains(&(v4, 24)), "{addresses:?}");
+    assert!(addresses.contains(&(v6, 64)), "{addresses:?}");
+
+    device.add_address_v6(added, 120).unwrap();
+    let addresses = device.list_addresses().unwrap();
+    assert!(addresses.contains(&(added, 120)), "{addresses:?}");
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_add_route_with_metric() {
+    use std::net::IpAddr;
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.22.100", 24, None)
+        .build_sync()
+        .unwrap();
+    let destination: IpAddr = "10.27.100.0".parse().unwrap();
+    device
+        .add_route_with_metric(destination, 24, Some(50))
+        .unwrap();
+    let err = device
+        .add_route_with_metric(destination, 24, Some(50))
+        .unwrap_err();
+    assert_eq!(std::io::ErrorKind::AlreadyExists, err.kind());
+    let routes = device.routes().unwrap();
+    assert!(
+        routes
+            .iter()
+            .any(|r| r.destination == destination && r.prefix == 24 && r.metric == 50),
+        "{routes:?}"
+    );
+
+    device.remove_route(destination, 24).unwrap();
+    let routes = device.routes().unwrap();
+    assert!(
+        !routes.iter().any(|r| r.destination == destination),
+        "{routes:?}"
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_add_route_via() {
+    use std::net::IpAddr;
+    let device = DeviceBuilder::new()
+        .ipv4("10.26.23.100", 24, None)
+        .build_sync()
+        .unwrap();
+    device.enabled(true).unwrap();
+    let destination: IpAddr = "10.27.101.0".parse().unwrap();
+    let gateway: IpAddr = "10.26.23.1".parse().unwrap();
+    device
+        .add_route_via(destination, 24, Some(gateway))
+        .unwrap();
+    let err = device
+        .add_route_via(destination, 24, Some(gateway))
+        .unwrap_err();
+    assert_eq!(std::io::ErrorKind::AlreadyExists, err.kind());
+    let routes = device.routes().unwrap();
+    assert!(
+        routes
+            .iter()
+            .any(|r| r.destination == destination && r.gateway == Some(gateway)),
+        "{routes:?}"
+    );
+    let v6: IpAddr = "fd00::1".parse().unwrap();
+    assert!(device.add_route_via(destination, 24, Some(v6)).is_err());
+
+    device.remove_route(destination, 24).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(any(feature = "async_tokio", feature = "async_std")))]
+#[test]
+fn test_persist() {
+    let name = "tun-persist0";
+    let exists = || std::path::Path::new("/sys/class/net").join(name).exists();
+    let device = DeviceBuilder::new()
+        .name(name)
+        .persist(true)
+        .enable(false)
+        .build_sync()
+        .unwrap();
+    let index = device.if_index().unwrap();
+    drop(device);
+    assert!(exists());
+
+    let device = DeviceBuilder::new()
+        .name(name)
+        .enable(false)
+        .build_sync()
+        .unwrap();
+    assert_eq!(index, device.if_index().unwrap());
+    device.set_persist(false).unwrap();
+    drop(device);
+    assert!(!exists());
+}